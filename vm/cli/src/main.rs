@@ -5,20 +5,31 @@ use clap::{Parser, Subcommand};
 use colored::Colorize;
 use console::graphics::App;
 use interpreter::{
+    coverage::Coverage,
     env::{Env, ExternalFunction, ExternalGlobal},
-    slow_vm::{DebugEnv, LocalValue, Vm},
+    profile::{ProfileMetric, Profiler},
+    slow_vm::{DebugEnv, LocalValue, RuntimeError, Vm},
+    snapshot::Snapshot,
+    trace::{TraceFormat, TraceRecorder},
 };
 use itertools::Itertools;
 use parser::{
     info::FunctionType,
-    reader::{BytecodeReader, ExportDesc, ValueType, is_wasm_bytecode},
+    printer::PrintMode,
+    reader::{
+        Bytecode, BytecodeReader, ExportDesc, ValueType, is_wasm_bytecode, parse_binary, parse_wat,
+    },
 };
 use std::{
     fs::File,
-    io::{Cursor, Read, Seek},
-    path::PathBuf,
+    io::{BufReader, BufWriter, Cursor, Read, Seek, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use validator::validator::{
+    ReadAndValidateError, ValidateResult, collect_validation_errors, read_and_validate,
+    read_and_validate_wat,
 };
-use validator::validator::{ValidateResult, read_and_validate, read_and_validate_wat};
 
 use crate::env::HeadlessEnv;
 #[derive(Parser, Debug)]
@@ -30,9 +41,48 @@ struct Args {
 }
 #[derive(Debug, Subcommand)]
 enum Commands {
-    Validate,
-    Print,
-    Run { name: String },
+    Validate {
+        /// Keep going after the first error and report every invalid instruction
+        #[arg(long)]
+        all: bool,
+    },
+    Print {
+        /// Print instructions as nested s-expressions
+        #[arg(long)]
+        folded: bool,
+    },
+    Run {
+        name: String,
+        /// Abort after executing this many instructions
+        #[arg(long)]
+        fuel: Option<u64>,
+        /// Record every executed instruction, `.jsonl` files get JSON Lines, others the binary format
+        #[arg(long)]
+        trace: Option<PathBuf>,
+        /// Start from a state saved with `--save` instead of the freshly instantiated module
+        #[arg(long)]
+        restore: Option<PathBuf>,
+        /// Save memory, globals and tables to this file after the function returned
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+    /// Run an exported function and report instructions and time per function
+    Profile {
+        name: String,
+        /// Write folded stacks for flamegraph tools to this file
+        #[arg(long)]
+        folded: Option<PathBuf>,
+        /// Weight folded stacks by executed instructions instead of microseconds
+        #[arg(long)]
+        instructions: bool,
+    },
+    /// Run an exported function and print the module annotated with instruction hit counts
+    Coverage {
+        name: String,
+        /// Print the report as JSON instead of annotated WAT
+        #[arg(long)]
+        json: bool,
+    },
     Console,
 }
 
@@ -47,15 +97,46 @@ pub fn read_and_validate_file(file: &mut impl BytecodeReader) -> Result<Validate
     }
 }
 
+pub fn read_file(file: &mut impl BytecodeReader) -> Result<Bytecode> {
+    if is_wasm_bytecode(file).context("Failed to determine file type")? {
+        parse_binary(file).context("Unable to parse bytecode")
+    } else {
+        let mut code = String::new();
+        file.read_to_string(&mut code)
+            .context("Unable to read wat source code")?;
+        parse_wat(code).context("Error while reading")
+    }
+}
+
+fn open_trace(path: &Path) -> Result<Arc<Mutex<TraceRecorder>>> {
+    let file = File::create(path).context("Unable to create trace file")?;
+    let format = match path.extension().and_then(|e| e.to_str()) {
+        Some("jsonl") => TraceFormat::JsonLines,
+        _ => TraceFormat::Binary,
+    };
+    let recorder = TraceRecorder::new(BufWriter::new(file), format);
+    Ok(Arc::new(Mutex::new(recorder)))
+}
+
 pub fn execute_run_command(
     func_name: &str,
     params: impl IntoIterator<Item = LocalValue> + Clone,
+    fuel: Option<u64>,
+    trace: Option<&Path>,
+    restore: Option<&Path>,
+    save: Option<&Path>,
     file: &mut File,
 ) -> Result<()> {
     let validate_result = read_and_validate_file(file).context("Unable to parse file")?;
     let mut env = HeadlessEnv {};
     let mut vm =
         Vm::init_from_validation_result(&validate_result).context("Unable to instantiate")?;
+    if let Some(path) = restore {
+        let mut reader = BufReader::new(File::open(path).context("Unable to open snapshot")?);
+        let snapshot = Snapshot::read(&mut reader).context("Unable to read snapshot")?;
+        vm.restore(&snapshot)
+            .context("Unable to restore snapshot")?;
+    }
 
     if let Some(exported) = validate_result.bytecode.get_exports_as_map() {
         let func = exported.get_function_id(func_name);
@@ -69,10 +150,33 @@ pub fn execute_run_command(
 
         vm.set_func(func_id, params.clone())
             .context("Unable to load function")?;
+        vm.set_fuel(fuel);
+        let recorder = trace.map(open_trace).transpose()?;
+        vm.set_tracer(recorder.clone());
 
-        let result = vm
-            .run_func(&validate_result.bytecode, &validate_result.info, &mut env)
-            .context("Error while executing {func_name}")?;
+        let result = match vm.run_func(&validate_result.bytecode, &validate_result.info, &mut env) {
+            Err(RuntimeError::OutOfFuel) => {
+                bail!(
+                    "{func_name} did not finish within {} instructions",
+                    fuel.unwrap_or_default()
+                )
+            }
+            result => result.with_context(|| format!("Error while executing {func_name}"))?,
+        };
+        if let Some(recorder) = recorder {
+            recorder
+                .lock()
+                .unwrap()
+                .finish()
+                .context("Unable to write trace")?;
+        }
+        if let Some(path) = save {
+            let mut w = BufWriter::new(File::create(path).context("Unable to create snapshot")?);
+            vm.snapshot(false)
+                .write(&mut w)
+                .and_then(|_| w.flush())
+                .context("Unable to write snapshot")?;
+        }
 
         if result.len() == 1 {
             println!("{}", result[0])
@@ -85,35 +189,152 @@ pub fn execute_run_command(
     }
 }
 
+pub fn execute_profile_command(
+    func_name: &str,
+    folded: Option<&Path>,
+    metric: ProfileMetric,
+    file: &mut File,
+) -> Result<()> {
+    let validate_result = read_and_validate_file(file).context("Unable to parse file")?;
+    let mut env = HeadlessEnv {};
+    let mut vm =
+        Vm::init_from_validation_result(&validate_result).context("Unable to instantiate")?;
+    let func_id = validate_result
+        .bytecode
+        .get_exports_as_map()
+        .and_then(|e| e.get_function_id(func_name))
+        .context("This function does not exist or is not exported by the module")?;
+
+    vm.set_func(func_id, [])
+        .context("Unable to load function")?;
+    vm.set_profiler(Some(Profiler::new()));
+    vm.run_func(&validate_result.bytecode, &validate_result.info, &mut env)
+        .with_context(|| format!("Error while executing {func_name}"))?;
+    let profiler = vm.take_profiler().unwrap();
+
+    print!("{}", profiler.report(vm.function_names()));
+    if let Some(path) = folded {
+        let mut w =
+            BufWriter::new(File::create(path).context("Unable to create folded stacks file")?);
+        profiler
+            .write_folded(&mut w, vm.function_names(), metric)
+            .and_then(|_| w.flush())
+            .context("Unable to write folded stacks")?;
+    }
+    Ok(())
+}
+
+pub fn execute_coverage_command(func_name: &str, json: bool, file: &mut File) -> Result<()> {
+    let validate_result = read_and_validate_file(file).context("Unable to parse file")?;
+    let mut env = HeadlessEnv {};
+    let mut vm =
+        Vm::init_from_validation_result(&validate_result).context("Unable to instantiate")?;
+    let func_id = validate_result
+        .bytecode
+        .get_exports_as_map()
+        .and_then(|e| e.get_function_id(func_name))
+        .context("This function does not exist or is not exported by the module")?;
+
+    vm.set_func(func_id, [])
+        .context("Unable to load function")?;
+    vm.set_coverage(Some(Coverage::new()));
+    //NOTE: Coverage up to a trap is still useful, so the error is reported after the report
+    let res = vm.run_func(&validate_result.bytecode, &validate_result.info, &mut env);
+    let report = vm
+        .take_coverage()
+        .unwrap()
+        .report(&validate_result.bytecode, &validate_result.info);
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        print!("{}", report.annotated_wat());
+    }
+    res.with_context(|| format!("Error while executing {func_name}"))?;
+    Ok(())
+}
+
 pub fn main() -> Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Commands::Validate => {
+        Commands::Validate { all: true } => {
+            let mut file = File::open(args.path)?;
+            let bytecode = read_file(&mut file)?;
+            let errors = collect_validation_errors(&bytecode);
+            for error in &errors {
+                eprintln!("{}", error.to_string().red());
+                if let Some(context) = error.context() {
+                    eprintln!("{}", context.format_stacks().yellow());
+                }
+            }
+            ensure!(
+                errors.is_empty(),
+                "Found {} validation errors",
+                errors.len()
+            );
+            println!("OK!");
+            Ok(())
+        }
+        Commands::Validate { all: false } => {
             let mut file = File::open(args.path)?;
-            let _validate_result = read_and_validate_file(&mut file)?;
+            let validate_result = read_and_validate_file(&mut file);
+            if let Some(context) = validate_result.as_ref().err().and_then(|e| {
+                e.downcast_ref::<ReadAndValidateError>()?
+                    .validation_context()
+            }) {
+                eprintln!("{}", context.format_stacks().yellow());
+            }
+            validate_result?;
             println!("OK!");
             Ok(())
         }
-        Commands::Print => {
+        Commands::Print { folded } => {
             let mut file = File::open(args.path)?;
             let validate_result = read_and_validate_file(&mut file)?;
-            println!(
-                "{}",
-                validate_result
-                    .info
-                    .functions
-                    .iter()
-                    .map(|f| f.format(&validate_result.bytecode))
-                    .format("\n")
-            );
+            let mode = if folded {
+                PrintMode::Folded
+            } else {
+                PrintMode::Flat
+            };
+            println!("{}", validate_result.bytecode.to_wat(mode));
             Ok(())
         }
         Commands::Console => App::run(args.path).context("Unable to run console"),
 
-        Commands::Run { name } => {
+        Commands::Run {
+            name,
+            fuel,
+            trace,
+            restore,
+            save,
+        } => {
             let mut file = File::open(args.path).unwrap();
-            execute_run_command(&name, Vec::new(), &mut file)
+            execute_run_command(
+                &name,
+                Vec::new(),
+                fuel,
+                trace.as_deref(),
+                restore.as_deref(),
+                save.as_deref(),
+                &mut file,
+            )
+        }
+        Commands::Profile {
+            name,
+            folded,
+            instructions,
+        } => {
+            let mut file = File::open(args.path)?;
+            let metric = if instructions {
+                ProfileMetric::Instructions
+            } else {
+                ProfileMetric::Microseconds
+            };
+            execute_profile_command(&name, folded.as_deref(), metric, &mut file)
+        }
+        Commands::Coverage { name, json } => {
+            let mut file = File::open(args.path)?;
+            execute_coverage_command(&name, json, &mut file)
         }
         _ => bail!("Unknown command: {:?}", args.command),
     }
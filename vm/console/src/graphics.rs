@@ -1,16 +1,18 @@
 use bytemuck::*;
 use core::sync;
 use interpreter::{
-    env::{Env, ExternalFunction},
-    slow_vm::{InstanceError, LocalValue, RuntimeError, Vm},
+    instance::Instance,
+    linker::{Caller, Host, LinkError, Linker},
+    slow_vm::{LocalValue, RuntimeError},
+    snapshot::{Snapshot, SnapshotError},
 };
 use notify::Watcher;
-use parser::reader::{BytecodeReader, ParserError, ValueType, is_wasm_bytecode};
+use parser::reader::{BytecodeReader, ParserError, is_wasm_bytecode};
 use rand::{Rng, rngs::ThreadRng};
 use std::{
     collections::HashMap,
     fs::File,
-    io::{self, BufReader, Read},
+    io::{self, BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
     sync::{
         Arc,
@@ -64,8 +66,11 @@ pub enum ConsoleError {
     #[error("Runtime error occured: {0}")]
     RuntimeError(#[from] RuntimeError),
 
-    #[error("Unable to virtual machine: {0}")]
-    UnableToInitVirtualMachine(#[from] InstanceError),
+    #[error("Unable to save or load state: {0}")]
+    Snapshot(#[from] SnapshotError),
+
+    #[error("Unable to link module: {0}")]
+    Link(#[from] LinkError),
     #[error("Unable to create wgpu surface: {0}")]
     CreateSurface(#[from] wgpu::CreateSurfaceError),
 
@@ -77,6 +82,20 @@ pub enum ConsoleError {
     #[error("Unable to create event loop: {0}")]
     UnableToCreateEventLoop(#[from] EventLoopError),
 }
+
+impl ConsoleError {
+    /// The error message, for validation errors followed by the validator stacks
+    pub fn report(&self) -> String {
+        match self {
+            ConsoleError::UnableToParseFile(e) => match e.validation_context() {
+                Some(context) => format!("{e}\n{}", context.format_stacks()),
+                None => e.to_string(),
+            },
+            e => e.to_string(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum ConsoleKey {
     Up,
@@ -174,6 +193,10 @@ const VERTICES: &[Vertex] = &[
 const INDICES: &[u16] = &[0, 1, 3, 1, 2, 3];
 */
 const FB_SIZE: (u32, u32) = (640, 360);
+/// Instruction budget of a single `run` call, frames that take longer are aborted
+const FRAME_FUEL: u64 = 50_000_000;
+/// Upper bound for memory.grow, 256 pages are 16MiB
+const MAX_MEMORY_PAGES: u32 = 256;
 #[derive(Debug)]
 struct State {
     window: Arc<Window>,
@@ -191,8 +214,6 @@ struct State {
     texture: wgpu::Texture,
     texture_size: wgpu::Extent3d,
     diffuse_bind_group: wgpu::BindGroup,
-    start_time: Instant,
-    rng: ThreadRng,
     clip_rect: (u32, u32, u32, u32),
 }
 
@@ -432,8 +453,6 @@ impl State {
             diffuse_bind_group,
             texture: diffuse_texture,
             texture_size,
-            start_time: Instant::now(),
-            rng: rand::rng(),
             clip_rect: matrix.clip_rect(),
             uniform_buffer,
         })
@@ -566,151 +585,119 @@ impl State {
         }
     }
 }
-impl Env for State {
-    fn get_func(env: &str, name: &str) -> Option<ExternalFunction> {
-        if env != "env" {
-            return None;
-        }
-        //TODO:
-        match name {
-            "io_print_string" => Some(ExternalFunction {
-                params: vec![ValueType::I32, ValueType::I32],
-                result: vec![],
-                id: 0,
-            }),
-            "gfx_paint" => Some(ExternalFunction {
-                params: vec![ValueType::I32, ValueType::I32, ValueType::I32],
-                result: vec![],
-                id: 1,
-            }),
-            "io_print_sint" => Some(ExternalFunction {
-                params: vec![ValueType::I32],
-                result: vec![],
-                id: 2,
-            }),
-            "gfx_clear_buffer_rgb" => Some(ExternalFunction {
-                params: vec![
-                    ValueType::I32, //buffer
-                    ValueType::I32, //r
-                    ValueType::I32, //g
-                    ValueType::I32, //b
-                ],
-                result: vec![],
-                id: 3,
-            }),
-            "gfx_draw_rect_rgb" => Some(ExternalFunction {
-                params: vec![
-                    ValueType::I32, //buffer
-                    ValueType::I32, //x
-                    ValueType::I32, //y
-                    ValueType::I32, //width
-                    ValueType::I32, //height
-                    ValueType::I32, //r
-                    ValueType::I32, //g
-                    ValueType::I32, //b
-                ],
-                result: vec![],
-                id: 4,
-            }),
-            "clock_get_time_passed_ms" => Some(ExternalFunction {
-                params: vec![],
-                result: vec![ValueType::I64],
-                id: 5,
-            }),
+/// The host data of the console's imports, a painted frame waits here until it is uploaded
+#[derive(Debug)]
+struct Console {
+    start_time: Instant,
+    rng: ThreadRng,
+    frame: Option<Frame>,
+}
 
-            "io_print_sint64" => Some(ExternalFunction {
-                params: vec![ValueType::I64],
-                result: vec![],
-                id: 6,
-            }),
-            "rand_range_sint32" => Some(ExternalFunction {
-                params: vec![ValueType::I32, ValueType::I32],
-                result: vec![ValueType::I32],
-                id: 7,
-            }),
-            _ => None,
-        }
-    }
+#[derive(Debug)]
+struct Frame {
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+}
 
-    fn get_global(env: &str, name: &str) -> Option<interpreter::env::ExternalGlobal> {
-        None
+impl Console {
+    fn new() -> Self {
+        Self {
+            start_time: Instant::now(),
+            rng: rand::rng(),
+            frame: None,
+        }
     }
 
-    fn call(
-        &mut self,
-        vm: &mut Vm<Self>,
-        params: &[LocalValue],
-        results: &mut [LocalValue],
-        func_id: usize,
-    ) -> Result<(), usize> {
-        match func_id {
-            0 => {
-                let ptr = params[0].u32();
-                let count = params[1].u32();
-                let data = vm
-                    .get_bytes_from_mem(ptr as usize, count as usize)
-                    .map_err(|_| 1_usize)?;
-                let str = str::from_utf8(data).map_err(|_| 2_usize)?;
-                print!("{str}");
-                Ok(())
-            }
-            //paint
-            1 => {
-                let (ptr, width, height) = (params[0].u32(), params[1].u32(), params[2].u32());
-                let data = vm
-                    .get_bytes_from_mem(ptr as usize, (width * height * 4) as usize)
-                    .map_err(|_| 1_usize)?;
-                self.update_framebuffer_data(data, width, height);
-
-                Ok(())
-            }
-            2 => Ok(println!("{}", params[0].i32())),
-
-            //gfx_fill_buffer
-            3 => {
-                let ptr = params[0].u32();
-                let (r, g, b, a) = (params[1].u32(), params[2].u32(), params[3].u32(), 0);
-                let data = vm
-                    .get_bytes_from_mem_mut(ptr as usize, (FB_SIZE.0 * FB_SIZE.1 * 4) as usize)
-                    .map_err(|_| 1_usize)?;
-                Self::fill_buffer_with_color(data, r, g, b, a);
-                Ok(())
-            }
-            4 => {
-                let ptr = params[0].u32();
-                let (x, y, w, h, r, g, b, a) = (
-                    params[1].u32(),
-                    params[2].u32(),
-                    params[3].u32(),
-                    params[4].u32(),
-                    params[5].u32(),
-                    params[6].u32(),
-                    params[7].u32(),
-                    0,
-                );
-                let data = vm
-                    .get_bytes_from_mem_mut(ptr as usize, (FB_SIZE.0 * FB_SIZE.1 * 4) as usize)
-                    .map_err(|_| 1_usize)?;
-
-                Self::draw_rectanlge_color(data, x, y, w, h, r, g, b, a);
-                Ok(())
-            }
-            5 => {
-                let duration = self.start_time.elapsed().as_millis();
-                results[0] = LocalValue::I64(duration as u64);
-
-                Ok(())
-            }
-            6 => Ok(println!("{}", params[0].i64())),
-            7 => {
-                let num = self.rng.random_range(params[0].i32()..params[1].i32());
-
-                results[0] = LocalValue::S32(num as i32);
-                Ok(())
-            }
-
-            _ => unreachable!(),
-        }
+    fn linker() -> Result<Linker<Self>, LinkError> {
+        let fb_len = (FB_SIZE.0 * FB_SIZE.1 * 4) as usize;
+        let mut linker = Linker::new();
+        linker
+            .func_wrap(
+                "env",
+                "io_print_string",
+                |caller: Caller<'_, Self>, ptr: u32, count: u32| -> Result<(), usize> {
+                    let data = caller
+                        .memory()
+                        .ok_or(1_usize)?
+                        .range(ptr as usize, count as usize)
+                        .map_err(|_| 1_usize)?;
+                    let str = str::from_utf8(data).map_err(|_| 2_usize)?;
+                    print!("{str}");
+                    Ok(())
+                },
+            )?
+            .func_wrap(
+                "env",
+                "gfx_paint",
+                |mut caller: Caller<'_, Self>, ptr: u32, width: u32, height: u32| {
+                    let pixels = caller
+                        .memory()
+                        .ok_or(1_usize)?
+                        .range(ptr as usize, (width * height * 4) as usize)
+                        .map_err(|_| 1_usize)?
+                        .to_vec();
+                    caller.data_mut().frame = Some(Frame {
+                        pixels,
+                        width,
+                        height,
+                    });
+                    Ok::<_, usize>(())
+                },
+            )?
+            .func_wrap("env", "io_print_sint", |_: Caller<'_, Self>, x: i32| {
+                println!("{x}")
+            })?
+            .func_wrap(
+                "env",
+                "gfx_clear_buffer_rgb",
+                move |mut caller: Caller<'_, Self>, ptr: u32, r: u32, g: u32, b: u32| {
+                    let data = caller
+                        .memory_mut()
+                        .ok_or(1_usize)?
+                        .range_mut(ptr as usize, fb_len)
+                        .map_err(|_| 1_usize)?;
+                    State::fill_buffer_with_color(data, r, g, b, 0);
+                    Ok::<_, usize>(())
+                },
+            )?
+            .func_wrap(
+                "env",
+                "gfx_draw_rect_rgb",
+                move |mut caller: Caller<'_, Self>,
+                      ptr: u32,
+                      x: u32,
+                      y: u32,
+                      w: u32,
+                      h: u32,
+                      r: u32,
+                      g: u32,
+                      b: u32| {
+                    let data = caller
+                        .memory_mut()
+                        .ok_or(1_usize)?
+                        .range_mut(ptr as usize, fb_len)
+                        .map_err(|_| 1_usize)?;
+                    State::draw_rectanlge_color(data, x, y, w, h, r, g, b, 0);
+                    Ok::<_, usize>(())
+                },
+            )?
+            .func_wrap(
+                "env",
+                "clock_get_time_passed_ms",
+                |caller: Caller<'_, Self>| caller.data().start_time.elapsed().as_millis() as u64,
+            )?
+            .func_wrap("env", "io_print_sint64", |_: Caller<'_, Self>, x: i64| {
+                println!("{x}")
+            })?
+            .func_wrap(
+                "env",
+                "rand_range_sint32",
+                |mut caller: Caller<'_, Self>, low: i32, high: i32| {
+                    caller.data_mut().rng.random_range(low..high)
+                },
+            )?;
+        Ok(linker)
     }
 }
 
@@ -740,10 +727,11 @@ impl Funcs {
 #[derive(Debug)]
 pub struct Executor {
     wasm_path: PathBuf,
-    validate_result: ValidateResult,
+    linker: Linker<Console>,
+    host: Host<Console>,
+    instance: Instance<Host<Console>>,
     funcs: Funcs,
     init_func_result: Option<u32>,
-    vm: Vm<State>,
 }
 
 impl Executor {
@@ -761,52 +749,99 @@ impl Executor {
         Ok(res)
     }
 
-    pub fn new(path: PathBuf) -> Result<Self, ConsoleError> {
-        let file = File::open(&path).map_err(|e| ConsoleError::UnableToLoadFile(e))?;
+    fn load(path: &Path) -> Result<(ValidateResult, Funcs), ConsoleError> {
+        let file = File::open(path).map_err(|e| ConsoleError::UnableToLoadFile(e))?;
         let mut reader = BufReader::new(file);
-
         let validate_result = Self::get_validate_result(&mut reader)?;
         let funcs = Funcs::from_validate_result(&validate_result)?;
+        Ok((validate_result, funcs))
+    }
 
-        let vm = Vm::init_from_validation_result(&validate_result)?;
+    fn instantiate(
+        linker: &Linker<Console>,
+        validate_result: ValidateResult,
+        host: &mut Host<Console>,
+    ) -> Result<Instance<Host<Console>>, ConsoleError> {
+        let mut instance = linker.instantiate(validate_result, host)?;
+        instance.vm_mut().set_max_memory_pages(MAX_MEMORY_PAGES);
+        Ok(instance)
+    }
+
+    pub fn new(path: PathBuf) -> Result<Self, ConsoleError> {
+        let (validate_result, funcs) = Self::load(&path)?;
+        let linker = Console::linker()?;
+        let mut host = Host::new(Console::new());
+        let instance = Self::instantiate(&linker, validate_result, &mut host)?;
 
         Ok(Executor {
             wasm_path: path,
-            vm,
-            validate_result,
+            linker,
+            host,
+            instance,
             funcs,
             init_func_result: None,
         })
     }
 
     pub fn reload_all(&mut self, state: &mut State) -> Result<(), ConsoleError> {
-        let file = File::open(&self.wasm_path).map_err(|e| ConsoleError::UnableToLoadFile(e))?;
-        let mut reader = BufReader::new(file);
-        self.validate_result = Self::get_validate_result(&mut reader)?;
-        self.funcs = Funcs::from_validate_result(&self.validate_result)?;
-        self.vm = Vm::init_from_validation_result(&self.validate_result)?;
+        let (validate_result, funcs) = Self::load(&self.wasm_path)?;
+        self.instance = Self::instantiate(&self.linker, validate_result, &mut self.host)?;
+        self.funcs = funcs;
         self.run_init(state)?;
         Ok(())
     }
 
-    pub fn reload_code(&mut self, state: &mut State) -> Result<(), ConsoleError> {
-        let file = File::open(&self.wasm_path).map_err(|e| ConsoleError::UnableToLoadFile(e))?;
+    pub fn reload_code(&mut self) -> Result<(), ConsoleError> {
+        let (validate_result, funcs) = Self::load(&self.wasm_path)?;
+        self.linker
+            .reload_code(&mut self.instance, validate_result)?;
+        self.funcs = funcs;
+        Ok(())
+    }
+
+    /// Save states are stored next to the wasm file
+    fn save_state_path(&self) -> PathBuf {
+        let mut path = self.wasm_path.clone().into_os_string();
+        path.push(".snapshot");
+        path.into()
+    }
+
+    //NOTE: States are only saved between frames, so the call stack is never part of them. The
+    //result of init is stored in front of the snapshot because run needs it.
+    pub fn save_state(&self) -> Result<(), ConsoleError> {
+        let file = File::create(self.save_state_path()).map_err(SnapshotError::from)?;
+        let mut writer = BufWriter::new(file);
+        writer
+            .write_all(&self.init_func_result.unwrap_or_default().to_le_bytes())
+            .and_then(|_| self.instance.vm().snapshot(false).write(&mut writer))
+            .and_then(|_| writer.flush())
+            .map_err(SnapshotError::from)?;
+        Ok(())
+    }
+
+    pub fn load_state(&mut self) -> Result<(), ConsoleError> {
+        let file = File::open(self.save_state_path()).map_err(SnapshotError::from)?;
         let mut reader = BufReader::new(file);
-        self.validate_result = Self::get_validate_result(&mut reader)?;
-        self.funcs = Funcs::from_validate_result(&self.validate_result)?;
-        self.vm
-            .reload_code(&self.validate_result.bytecode, &self.validate_result.info)?;
+        let mut init_result = [0; 4];
+        reader
+            .read_exact(&mut init_result)
+            .map_err(SnapshotError::from)?;
+        let snapshot = Snapshot::read(&mut reader)?;
+        self.instance.vm_mut().restore(&snapshot)?;
+        self.init_func_result = Some(u32::from_le_bytes(init_result));
         Ok(())
     }
 
-    fn run_init(&mut self, state: &mut State) -> Result<(), ConsoleError> {
-        self.vm.set_func(self.funcs.init, vec![])?;
+    /// Uploads the frame painted by the last call to the window
+    fn present(&mut self, state: &mut State) {
+        if let Some(frame) = self.host.data_mut().frame.take() {
+            state.update_framebuffer_data(&frame.pixels, frame.width, frame.height);
+        }
+    }
 
-        let result = self.vm.run_func(
-            &self.validate_result.bytecode,
-            &self.validate_result.info,
-            state,
-        )?;
+    fn run_init(&mut self, state: &mut State) -> Result<(), ConsoleError> {
+        let result = self.instance.call(self.funcs.init, &[], &mut self.host)?;
+        self.present(state);
 
         println!("Init done!\n");
         assert!(result.len() == 1);
@@ -826,13 +861,19 @@ impl Executor {
             LocalValue::I32(width),
             LocalValue::I32(height),
         ];
-        self.vm.set_func(self.funcs.run, args)?;
-        self.vm.run_func(
-            &self.validate_result.bytecode,
-            &self.validate_result.info,
-            state,
-        )?;
-        Ok(())
+        self.instance.vm_mut().set_fuel(Some(FRAME_FUEL));
+        let result = self.instance.call(self.funcs.run, &args, &mut self.host);
+        self.instance.vm_mut().set_fuel(None);
+        self.present(state);
+        match result {
+            Err(RuntimeError::OutOfFuel) => {
+                //NOTE: Most likely an infinite loop, the next frame starts from scratch
+                eprintln!("run did not finish within {FRAME_FUEL} instructions, frame aborted");
+                self.instance.vm_mut().reset_state();
+                Ok(())
+            }
+            result => result.map(|_| ()),
+        }
     }
 
     fn run_input(
@@ -846,12 +887,9 @@ impl Executor {
             LocalValue::I32(key.into()),
             LocalValue::I32(pressed.into()),
         ];
-        self.vm.set_func(self.funcs.input.unwrap(), args)?;
-        self.vm.run_func(
-            &self.validate_result.bytecode,
-            &self.validate_result.info,
-            state,
-        )?;
+        self.instance
+            .call(self.funcs.input.unwrap(), &args, &mut self.host)?;
+        self.present(state);
         Ok(())
     }
 }
@@ -929,12 +967,24 @@ impl ApplicationHandler for App {
             } => match (code, key_state.is_pressed()) {
                 (KeyCode::KeyR, true) => {
                     let state = self.state.as_mut().unwrap();
-                    self.exec.reload_all(state).unwrap();
+                    if let Err(e) = self.exec.reload_all(state) {
+                        eprintln!("Reload failed: {}", e.report());
+                    }
                 }
                 (KeyCode::KeyT, true) => {
                     let state = self.state.as_mut().unwrap();
-                    self.exec.reload_code(state).unwrap();
+                    if let Err(e) = self.exec.reload_code() {
+                        eprintln!("Reload failed: {}", e.report());
+                    }
                 }
+                (KeyCode::F5, true) => match self.exec.save_state() {
+                    Ok(()) => println!("State saved"),
+                    Err(e) => eprintln!("Saving state failed: {}", e.report()),
+                },
+                (KeyCode::F9, true) => match self.exec.load_state() {
+                    Ok(()) => println!("State loaded"),
+                    Err(e) => eprintln!("Loading state failed: {}", e.report()),
+                },
 
                 (key, pressed) => {
                     let state = self.state.as_mut().unwrap();
@@ -965,8 +1015,10 @@ impl ApplicationHandler for App {
                         Ok(e) => match e.kind {
                             notify::EventKind::Modify(_) => {
                                 println!("blub!\n");
-                                if self.auto_hot_reload {
-                                    self.exec.reload_code(state).unwrap();
+                                if self.auto_hot_reload
+                                    && let Err(e) = self.exec.reload_code()
+                                {
+                                    eprintln!("Reload failed: {}", e.report());
                                 }
                             }
                             _ => {}
@@ -0,0 +1,165 @@
+//! Runs compiled console demos on both interpreter tiers and compares their speed.
+//! Pass the demo binaries as arguments, by default the one `just build` writes to
+//! `console/out.wasm` is used.
+
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use interpreter::{
+    env::{Env, ExternalFunction, ExternalGlobal},
+    fast_vm::FastVm,
+    slow_vm::{LocalValue, Vm},
+};
+use parser::reader::ValueType;
+use validator::validator::{ValidateResult, read_and_validate};
+
+const FRAMES: usize = 5;
+const FB_WIDTH: u32 = 800;
+const FB_HEIGHT: u32 = 600;
+
+/// The console's imports without a window, painting only counts the frames
+#[derive(Debug, Default)]
+struct HeadlessEnv {
+    painted: usize,
+}
+
+impl Env for HeadlessEnv {
+    fn get_func(env: &str, name: &str) -> Option<ExternalFunction> {
+        if env != "env" {
+            return None;
+        }
+        match name {
+            "io_print_string" => Some(ExternalFunction {
+                params: vec![ValueType::I32, ValueType::I32],
+                result: vec![],
+                id: 0,
+            }),
+            "gfx_paint" => Some(ExternalFunction {
+                params: vec![ValueType::I32, ValueType::I32, ValueType::I32],
+                result: vec![],
+                id: 1,
+            }),
+            _ => None,
+        }
+    }
+
+    fn get_global(_env: &str, _name: &str) -> Option<ExternalGlobal> {
+        None
+    }
+
+    fn call(
+        &mut self,
+        _vm: &mut Vm<Self>,
+        _params: &[LocalValue],
+        _results: &mut [LocalValue],
+        func_id: usize,
+    ) -> Result<(), usize> {
+        if func_id == 1 {
+            self.painted += 1;
+        }
+        Ok(())
+    }
+}
+
+fn export(module: &ValidateResult, name: &str) -> Option<usize> {
+    module
+        .bytecode
+        .get_exports_as_map()
+        .and_then(|e| e.get_function_id(name))
+}
+
+fn run_slow(
+    module: &ValidateResult,
+    vm: &mut Vm<HeadlessEnv>,
+    env: &mut HeadlessEnv,
+    func_id: usize,
+    params: &[LocalValue],
+) -> Vec<LocalValue> {
+    vm.set_func(func_id, params.iter().copied()).unwrap();
+    vm.run_func(&module.bytecode, &module.info, env).unwrap()
+}
+
+fn time(mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        f();
+    }
+    start.elapsed() / FRAMES as u32
+}
+
+fn bench_demo(path: &Path) {
+    let file = File::open(path).unwrap();
+    let module = read_and_validate(&mut BufReader::new(file)).unwrap();
+    let (mut slow_env, mut fast_env) = (HeadlessEnv::default(), HeadlessEnv::default());
+    let mut slow: Vm<HeadlessEnv> = Vm::init_from_validation_result(&module).unwrap();
+    let mut fast: FastVm<HeadlessEnv> = FastVm::init_from_validation_result(&module).unwrap();
+
+    //NOTE: Like the console, `init` returns the address of the framebuffer
+    let init = export(&module, "init").expect("console demos export init");
+    let framebuffer = run_slow(&module, &mut slow, &mut slow_env, init, &[]);
+    assert_eq!(fast.call(init, &[], &mut fast_env).unwrap(), framebuffer);
+    let framebuffer = framebuffer[0];
+
+    let size = [FB_WIDTH, FB_HEIGHT].map(LocalValue::I32).to_vec();
+    let demos = [
+        ("run", size),
+        (
+            "fill_framebuffer",
+            [0, 200, 0, 0].map(LocalValue::I32).to_vec(),
+        ),
+        (
+            "draw_rectangle",
+            [40, 40, 100, 100, 250, 50, 0].map(LocalValue::I32).to_vec(),
+        ),
+        (
+            "render_weird_gradient",
+            [3, 7].map(LocalValue::I32).to_vec(),
+        ),
+    ];
+    println!("{}:", path.display());
+    for (name, params) in demos {
+        let Some(func_id) = export(&module, name) else {
+            continue;
+        };
+        let params = [vec![framebuffer], params].concat();
+        let slow_time = time(|| _ = run_slow(&module, &mut slow, &mut slow_env, func_id, &params));
+        let fast_time = time(|| _ = fast.call(func_id, &params, &mut fast_env).unwrap());
+        println!(
+            "{name:>22}: slow {slow_time:>10.2?}, fast {fast_time:>10.2?}, {:.1}x",
+            slow_time.as_secs_f64() / fast_time.as_secs_f64()
+        );
+        assert_eq!(
+            fast.vm().memory().unwrap().bytes(),
+            slow.memory().unwrap().bytes()
+        );
+        assert_eq!(fast.vm().globals(), slow.globals());
+    }
+    assert_eq!(fast_env.painted, slow_env.painted);
+}
+
+fn main() {
+    //NOTE: cargo passes `--bench` to benchmarks without the default harness
+    let mut paths = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        paths.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../console/out.wasm"));
+    }
+    println!("{FRAMES} frames per demo, average time per frame:");
+    for path in paths {
+        if !path.exists() {
+            println!(
+                "{} not found, build it with `just build` in console/",
+                path.display()
+            );
+            continue;
+        }
+        bench_demo(&path);
+    }
+}
@@ -0,0 +1,395 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use parser::{
+    dwarf::{LineTable, SourceLine},
+    info::{BytecodeInfo, FunctionType},
+    op::Op,
+    printer::WatPrinter,
+    reader::Bytecode,
+};
+
+use crate::json::json_string;
+
+/// How often a conditional branch (`if` or `br_if`) went either way
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchHits {
+    /// The condition was non zero, `br_if` branched or `if` entered its then block
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl BranchHits {
+    /// Both directions were executed at least once
+    pub fn is_complete(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FunctionHits {
+    hits: Vec<u64>,
+    branches: HashMap<usize, BranchHits>,
+}
+
+/// Counts how often every instruction ran, attached with `Vm::set_coverage`.
+/// Only available with the `coverage` feature.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    functions: HashMap<usize, FunctionHits>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Called before every instruction, `condition` is the operand of a conditional branch
+    pub fn hit(&mut self, func_id: usize, instruction: usize, condition: Option<bool>) {
+        let function = self.functions.entry(func_id).or_default();
+        if function.hits.len() <= instruction {
+            function.hits.resize(instruction + 1, 0);
+        }
+        function.hits[instruction] += 1;
+        if let Some(condition) = condition {
+            let branch = function.branches.entry(instruction).or_default();
+            match condition {
+                true => branch.taken += 1,
+                false => branch.not_taken += 1,
+            }
+        }
+    }
+
+    pub fn hits(&self, func_id: usize, instruction: usize) -> u64 {
+        self.functions
+            .get(&func_id)
+            .and_then(|f| f.hits.get(instruction))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn branch(&self, func_id: usize, instruction: usize) -> Option<BranchHits> {
+        self.functions
+            .get(&func_id)?
+            .branches
+            .get(&instruction)
+            .copied()
+    }
+
+    /// Adds the counts of another run, e.g. to combine several entry points
+    pub fn merge(&mut self, other: &Coverage) {
+        for (func_id, other) in &other.functions {
+            let function = self.functions.entry(*func_id).or_default();
+            if function.hits.len() < other.hits.len() {
+                function.hits.resize(other.hits.len(), 0);
+            }
+            for (hits, other) in function.hits.iter_mut().zip(&other.hits) {
+                *hits += other;
+            }
+            for (instruction, other) in &other.branches {
+                let branch = function.branches.entry(*instruction).or_default();
+                branch.taken += other.taken;
+                branch.not_taken += other.not_taken;
+            }
+        }
+    }
+
+    /// Covers every function defined in the module, imported functions are skipped.
+    /// Instructions are mapped to source lines if the module has a DWARF line table.
+    pub fn report(&self, bytecode: &Bytecode, info: &BytecodeInfo) -> CoverageReport {
+        //NOTE: Like a malformed name section, malformed debug info only loses the annotations
+        let lines = bytecode
+            .parse_line_table()
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        let printer = WatPrinter::new(bytecode);
+        let functions = info
+            .functions
+            .iter()
+            .enumerate()
+            .filter_map(|(func_id, function)| match function.t {
+                FunctionType::Internal { code_id, .. } => {
+                    Some(self.function_report(bytecode, info, &printer, &lines, func_id, code_id))
+                }
+                FunctionType::Imported { .. } => None,
+            })
+            .collect();
+        CoverageReport { functions }
+    }
+
+    fn function_report(
+        &self,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        printer: &WatPrinter,
+        lines: &LineTable,
+        func_id: usize,
+        code_id: usize,
+    ) -> FunctionCoverage {
+        let ops = bytecode
+            .get_code(code_id)
+            .map_or(&[][..], |c| c.code.data.ops());
+        //NOTE: The lines leave out the final `end`, it is not reached by functions that return
+        //with `return` and would only add noise
+        let instructions = printer
+            .instruction_lines(func_id)
+            .into_iter()
+            .zip(ops)
+            .enumerate()
+            .map(|(i, (wat, op))| InstructionCoverage {
+                wat,
+                hits: self.hits(func_id, i),
+                branch: matches!(op.data, Op::If { .. } | Op::BrIf { .. })
+                    .then(|| self.branch(func_id, i).unwrap_or_default()),
+                line: bytecode
+                    .code_address(code_id, i)
+                    .and_then(|address| lines.lookup(address))
+                    .cloned(),
+            })
+            .collect();
+        FunctionCoverage {
+            func_id,
+            name: info.names.functions.describe(func_id),
+            header: printer.function_header(func_id).unwrap_or_default(),
+            instructions,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionCoverage {
+    /// The instruction as printed in flat WAT, indented by its block depth
+    pub wat: String,
+    pub hits: u64,
+    /// Only set for `if` and `br_if`
+    pub branch: Option<BranchHits>,
+    pub line: Option<SourceLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCoverage {
+    pub func_id: usize,
+    pub name: String,
+    /// E.g. `(func $add (param i32) (result i32)`
+    pub header: String,
+    pub instructions: Vec<InstructionCoverage>,
+}
+
+impl FunctionCoverage {
+    /// Number of instructions that ran at least once
+    pub fn covered(&self) -> usize {
+        self.instructions.iter().filter(|i| i.hits > 0).count()
+    }
+
+    pub fn percent(&self) -> f64 {
+        match self.instructions.len() {
+            0 => 100.0,
+            n => self.covered() as f64 * 100.0 / n as f64,
+        }
+    }
+
+    /// Conditional branches that never went one of their ways, with their instruction index
+    pub fn partial_branches(&self) -> impl Iterator<Item = (usize, BranchHits)> {
+        self.instructions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instr)| Some((i, instr.branch?)))
+            .filter(|(_, branch)| !branch.is_complete())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub functions: Vec<FunctionCoverage>,
+}
+
+impl CoverageReport {
+    pub fn covered(&self) -> usize {
+        self.functions.iter().map(FunctionCoverage::covered).sum()
+    }
+
+    pub fn instructions(&self) -> usize {
+        self.functions.iter().map(|f| f.instructions.len()).sum()
+    }
+
+    /// The module with the hit count in front of every instruction. Instructions that never ran
+    /// and branches that never went one of their ways are marked with `!`.
+    pub fn annotated_wat(&self) -> String {
+        let mut out = String::from("(module\n");
+        for function in &self.functions {
+            _ = writeln!(
+                out,
+                "{:>10}  {}  ;; {}/{} instructions, {:.1}%",
+                "",
+                function.header,
+                function.covered(),
+                function.instructions.len(),
+                function.percent()
+            );
+            for instr in &function.instructions {
+                let complete = instr.hits > 0 && instr.branch.is_none_or(|b| b.is_complete());
+                let marker = if complete { ' ' } else { '!' };
+                _ = write!(out, "{marker}{:>9}    {}", instr.hits, instr.wat);
+                let mut comments = vec![];
+                if let Some(branch) = instr.branch {
+                    comments.push(format!(
+                        "taken {}, not taken {}",
+                        branch.taken, branch.not_taken
+                    ));
+                }
+                if let Some(line) = &instr.line {
+                    comments.push(format!("{}:{}", line.file, line.line));
+                }
+                if !comments.is_empty() {
+                    _ = write!(out, "  ;; {}", comments.join(", "));
+                }
+                out.push('\n');
+            }
+            _ = writeln!(out, "{:>10}  )", "");
+        }
+        out.push_str(")\n");
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        _ = write!(
+            out,
+            "{{\"covered\":{},\"instructions\":{},\"functions\":[",
+            self.covered(),
+            self.instructions()
+        );
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            _ = write!(out, "{{\"func_id\":{},\"name\":", function.func_id);
+            json_string(&mut out, &function.name);
+            _ = write!(
+                out,
+                ",\"covered\":{},\"percent\":{:.1},\"instructions\":[",
+                function.covered(),
+                function.percent()
+            );
+            for (i, instr) in function.instructions.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str("{\"op\":");
+                json_string(&mut out, instr.wat.trim_start());
+                _ = write!(out, ",\"hits\":{}", instr.hits);
+                if let Some(branch) = instr.branch {
+                    _ = write!(
+                        out,
+                        ",\"taken\":{},\"not_taken\":{}",
+                        branch.taken, branch.not_taken
+                    );
+                }
+                if let Some(line) = &instr.line {
+                    out.push_str(",\"file\":");
+                    json_string(&mut out, &line.file);
+                    _ = write!(out, ",\"line\":{}", line.line);
+                }
+                out.push('}');
+            }
+            out.push_str("],\"partial_branches\":[");
+            for (i, (instruction, _)) in function.partial_branches().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                _ = write!(out, "{instruction}");
+            }
+            out.push_str("]}");
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::{DebugEnv, LocalValue, Vm};
+
+    const SRC: &str = r#"
+        (module
+            (func $abs (param i32) (result i32)
+                local.get 0
+                i32.const 0
+                i32.lt_s
+                if (result i32)
+                    i32.const 0
+                    local.get 0
+                    i32.sub
+                else
+                    local.get 0
+                end
+            )
+            (func $unused
+                nop
+            )
+        )
+    "#;
+
+    fn report(params: &[i32]) -> CoverageReport {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_coverage(Some(Coverage::new()));
+        for param in params {
+            vm.set_func(0, [LocalValue::I32(*param as u32)]).unwrap();
+            vm.run_func(&res.bytecode, &res.info, &mut DebugEnv {})
+                .unwrap();
+        }
+        vm.take_coverage().unwrap().report(&res.bytecode, &res.info)
+    }
+
+    #[test]
+    fn counts_instructions_and_branches() {
+        let report = report(&[5, 7]);
+        let abs = &report.functions[0];
+        assert_eq!(abs.name, "$abs");
+        assert_eq!(abs.instructions.len(), 10);
+        assert_eq!(abs.instructions[0].hits, 2);
+        //NOTE: The then block never ran
+        assert_eq!(abs.covered(), 6);
+        assert_eq!(
+            abs.partial_branches().collect::<Vec<_>>(),
+            vec![(
+                3,
+                BranchHits {
+                    taken: 0,
+                    not_taken: 2
+                }
+            )]
+        );
+        assert_eq!(report.functions[1].covered(), 0);
+        assert_eq!(report.functions[1].percent(), 0.0);
+
+        let report = self::report(&[5, -3]);
+        assert_eq!(report.functions[0].covered(), 10);
+        assert_eq!(report.functions[0].partial_branches().count(), 0);
+    }
+
+    #[test]
+    fn annotated_wat_and_json() {
+        let report = report(&[5]);
+        let wat = report.annotated_wat();
+        let lines = wat.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[1],
+            "            (func $abs (;0;) (param i32) (result i32)  ;; 6/10 instructions, 60.0%"
+        );
+        assert_eq!(
+            lines[5],
+            "!        1    if (result i32)  ;; taken 0, not taken 1"
+        );
+        assert_eq!(lines[6], "!        0      i32.const 0");
+        assert_eq!(lines[10], "         1      local.get 0");
+
+        let json = report.to_json();
+        assert!(json.starts_with(r#"{"covered":6,"instructions":11,"functions":[{"func_id":0,"name":"$abs","covered":6,"percent":60.0,"instructions":[{"op":"local.get 0","hits":1},"#));
+        assert!(json.contains(r#"{"op":"if (result i32)","hits":1,"taken":0,"not_taken":1}"#));
+        assert!(json.ends_with(r#""partial_branches":[]}]}"#));
+    }
+}
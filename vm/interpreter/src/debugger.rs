@@ -0,0 +1,349 @@
+use std::collections::HashSet;
+
+use parser::{info::BytecodeInfo, reader::Bytecode};
+
+#[cfg(feature = "time-travel")]
+use crate::time_travel::UndoEntry;
+use crate::{
+    env::Env,
+    slow_vm::{LocalValue, RuntimeError, Vm},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Breakpoint {
+    pub func_id: usize,
+    /// Index of the instruction inside the function body
+    pub instruction: usize,
+}
+
+#[derive(Debug)]
+pub enum StopReason {
+    /// Execution is paused before the instruction of the breakpoint
+    Breakpoint(Breakpoint),
+    /// The requested step is done
+    Step,
+    /// Execution is paused at the failing instruction. Its operands are already consumed,
+    /// so the vm has to be reset with [`Vm::reset_state`] before it can run again.
+    Trap(RuntimeError),
+    /// The entered function returned, the vm is reset
+    Finished(Vec<LocalValue>),
+    /// Going backwards reached the oldest instruction in the history
+    HistoryStart,
+}
+
+/// Drives a [`Vm`] whose function was entered with [`Vm::set_func`] instruction by instruction.
+/// Frames, locals, labels, the operand stack and memory can be inspected on the vm after every stop.
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    breakpoints: HashSet<Breakpoint>,
+    pause_on_trap: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: HashSet::new(),
+            pause_on_trap: true,
+        }
+    }
+
+    /// Returns false if the breakpoint was already set.
+    /// Breakpoints are not checked against the code, one that matches no instruction is never hit.
+    pub fn set_breakpoint(&mut self, func_id: usize, instruction: usize) -> bool {
+        self.breakpoints.insert(Breakpoint {
+            func_id,
+            instruction,
+        })
+    }
+
+    /// Returns false if no such breakpoint was set
+    pub fn clear_breakpoint(&mut self, func_id: usize, instruction: usize) -> bool {
+        self.breakpoints.remove(&Breakpoint {
+            func_id,
+            instruction,
+        })
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.iter()
+    }
+
+    /// If set, traps are reported as [`StopReason::Trap`] instead of an error
+    pub fn set_pause_on_trap(&mut self, pause: bool) {
+        self.pause_on_trap = pause;
+    }
+
+    /// Runs until a breakpoint is reached, the function finishes or traps
+    pub fn resume<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        env: &mut E,
+    ) -> Result<StopReason, RuntimeError> {
+        self.run_until(vm, bytecode, info, env, |_| false)
+    }
+
+    /// Executes one instruction, entering called functions
+    pub fn step_into<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        env: &mut E,
+    ) -> Result<StopReason, RuntimeError> {
+        self.run_until(vm, bytecode, info, env, |_| true)
+    }
+
+    /// Executes one instruction, a call runs until it returns unless it hits a breakpoint
+    pub fn step_over<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        env: &mut E,
+    ) -> Result<StopReason, RuntimeError> {
+        let depth = vm.call_depth();
+        self.run_until(vm, bytecode, info, env, |vm| vm.call_depth() <= depth)
+    }
+
+    /// Runs until the current function returned to its caller
+    pub fn step_out<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        env: &mut E,
+    ) -> Result<StopReason, RuntimeError> {
+        let depth = vm.call_depth();
+        self.run_until(vm, bytecode, info, env, |vm| vm.call_depth() < depth)
+    }
+
+    /// Undoes the last executed instruction, needs a history attached with `Vm::set_history`
+    #[cfg(feature = "time-travel")]
+    pub fn step_back<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        env: &mut E,
+    ) -> Result<StopReason, RuntimeError> {
+        self.run_back_until(vm, bytecode, env, |_| true)
+    }
+
+    /// Goes backwards until a breakpoint is reached
+    #[cfg(feature = "time-travel")]
+    pub fn reverse_continue<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        env: &mut E,
+    ) -> Result<StopReason, RuntimeError> {
+        self.run_back_until(vm, bytecode, env, |_| false)
+    }
+
+    /// Goes backwards until the vm is before the instruction that last wrote the byte at `addr`
+    #[cfg(feature = "time-travel")]
+    pub fn reverse_to_write<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        env: &mut E,
+        addr: usize,
+    ) -> Result<StopReason, RuntimeError> {
+        self.run_back_until(vm, bytecode, env, |entry| entry.wrote(addr))
+    }
+
+    #[cfg(feature = "time-travel")]
+    fn run_back_until<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        env: &mut E,
+        done: impl Fn(&UndoEntry) -> bool,
+    ) -> Result<StopReason, RuntimeError> {
+        loop {
+            let Some(entry) = vm.step_back(bytecode, env)? else {
+                return Ok(StopReason::HistoryStart);
+            };
+            if let Some(bp) = self.breakpoint_at(vm) {
+                return Ok(StopReason::Breakpoint(bp));
+            }
+            if done(&entry) {
+                return Ok(StopReason::Step);
+            }
+        }
+    }
+
+    fn breakpoint_at<E: Env>(&self, vm: &Vm<E>) -> Option<Breakpoint> {
+        let (func_id, instruction) = vm.position()?;
+        let bp = Breakpoint {
+            func_id,
+            instruction,
+        };
+        self.breakpoints.contains(&bp).then_some(bp)
+    }
+
+    //NOTE: The first instruction always executes, so resuming from a breakpoint does not stop
+    //at the same breakpoint again.
+    fn run_until<E: Env>(
+        &self,
+        vm: &mut Vm<E>,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        env: &mut E,
+        done: impl Fn(&Vm<E>) -> bool,
+    ) -> Result<StopReason, RuntimeError> {
+        loop {
+            match vm.step(bytecode, env) {
+                Ok(true) => return vm.finish(info).map(StopReason::Finished),
+                Ok(false) => {}
+                Err(RuntimeError::OutOfFuel) => return Err(RuntimeError::OutOfFuel),
+                Err(e) if self.pause_on_trap => return Ok(StopReason::Trap(e)),
+                Err(e) => return Err(e),
+            }
+            if let Some(bp) = self.breakpoint_at(vm) {
+                return Ok(StopReason::Breakpoint(bp));
+            }
+            if done(vm) {
+                return Ok(StopReason::Step);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::{ValidateResult, read_and_validate_wat};
+
+    use super::*;
+    use crate::slow_vm::DebugEnv;
+
+    const SRC: &str = r#"
+        (module
+            (func $double (param i32) (result i32)
+                local.get 0
+                local.get 0
+                i32.add
+            )
+            (func $main (param i32) (result i32)
+                local.get 0
+                call $double
+                i32.const 1
+                i32.add
+            )
+            (func $trap
+                i32.const 1
+                i32.const 0
+                i32.div_u
+                drop
+            )
+        )
+    "#;
+
+    fn start(func_id: usize, params: Vec<LocalValue>) -> (ValidateResult, Vm<DebugEnv>) {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_func(func_id, params).unwrap();
+        (res, vm)
+    }
+
+    #[test]
+    fn breakpoint_pauses_and_exposes_locals() {
+        let (res, mut vm) = start(1, vec![LocalValue::I32(4)]);
+        let mut env = DebugEnv {};
+        let mut debugger = Debugger::new();
+        debugger.set_breakpoint(0, 2);
+
+        let stop = debugger
+            .resume(&mut vm, &res.bytecode, &res.info, &mut env)
+            .unwrap();
+        assert!(matches!(
+            stop,
+            StopReason::Breakpoint(Breakpoint {
+                func_id: 0,
+                instruction: 2
+            })
+        ));
+        let frames = vm.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].func_name, Some("double"));
+        assert_eq!(frames[0].stack.len(), 2);
+        assert_eq!(frames[1].func_name, Some("main"));
+        assert_eq!(frames[1].instruction, 1);
+        assert!(matches!(frames[1].locals, [LocalValue::I32(4)]));
+
+        let stop = debugger
+            .resume(&mut vm, &res.bytecode, &res.info, &mut env)
+            .unwrap();
+        assert!(matches!(stop, StopReason::Finished(r) if r == vec![LocalValue::I32(9)]));
+    }
+
+    #[test]
+    fn step_over_and_out() {
+        let (res, mut vm) = start(1, vec![LocalValue::I32(4)]);
+        let mut env = DebugEnv {};
+        let debugger = Debugger::new();
+        let (bytecode, info) = (&res.bytecode, &res.info);
+
+        debugger
+            .step_into(&mut vm, bytecode, info, &mut env)
+            .unwrap();
+        assert_eq!(vm.position(), Some((1, 1)));
+        debugger
+            .step_over(&mut vm, bytecode, info, &mut env)
+            .unwrap();
+        assert_eq!(vm.position(), Some((1, 2)));
+
+        let (res, mut vm) = start(1, vec![LocalValue::I32(4)]);
+        let (bytecode, info) = (&res.bytecode, &res.info);
+        debugger
+            .step_into(&mut vm, bytecode, info, &mut env)
+            .unwrap();
+        debugger
+            .step_into(&mut vm, bytecode, info, &mut env)
+            .unwrap();
+        assert_eq!(vm.position(), Some((0, 0)));
+        assert_eq!(vm.call_depth(), 2);
+        debugger
+            .step_out(&mut vm, bytecode, info, &mut env)
+            .unwrap();
+        assert_eq!(vm.position(), Some((1, 2)));
+        assert_eq!(vm.call_depth(), 1);
+    }
+
+    #[test]
+    fn pause_on_trap() {
+        let (res, mut vm) = start(2, vec![]);
+        let mut env = DebugEnv {};
+        let mut debugger = Debugger::new();
+        let stop = debugger
+            .resume(&mut vm, &res.bytecode, &res.info, &mut env)
+            .unwrap();
+        assert!(matches!(
+            stop,
+            StopReason::Trap(ref e) if matches!(e.trap_cause(), RuntimeError::IntegerDivideByZero)
+        ));
+        assert_eq!(vm.position(), Some((2, 2)));
+        assert_eq!(vm.frames()[0].func_name, Some("trap"));
+
+        let (res, mut vm) = start(2, vec![]);
+        debugger.set_pause_on_trap(false);
+        let err = debugger
+            .resume(&mut vm, &res.bytecode, &res.info, &mut env)
+            .unwrap_err();
+        assert!(matches!(
+            err.trap_cause(),
+            RuntimeError::IntegerDivideByZero
+        ));
+    }
+}
@@ -1,8 +1,16 @@
 use std::collections::HashMap;
 
-use parser::reader::ValueType;
+use parser::{
+    info::{BytecodeInfo, FunctionType, GlobalInfo},
+    reader::{Bytecode, Import, ValueType},
+};
 
-use crate::slow_vm::{InstanceError, LocalValue, NativeFunctionInstance, Vm};
+use crate::{
+    linker::{ImportKind, LinkError},
+    memory::MemoryInstance,
+    slow_vm::{LocalValue, Vm},
+    table::TableInstance,
+};
 
 #[derive(Debug, Clone)]
 pub struct ExternalFunction {
@@ -21,6 +29,9 @@ pub trait Env: Sized {
         results: &mut [LocalValue],
         func_id: usize,
     ) -> Result<(), usize>;
+
+    /// Called after memory.grow succeeded, sizes are in pages
+    fn memory_grown(&mut self, _old_pages: u32, _new_pages: u32) {}
 }
 
 #[derive(Debug, Clone)]
@@ -28,3 +39,47 @@ pub struct ExternalGlobal {
     pub value: LocalValue,
     pub mutable: bool,
 }
+
+/// Definitions for the imports of a module by import id, used while instantiating it
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Imports {
+    pub functions: HashMap<usize, ExternalFunction>,
+    pub globals: HashMap<usize, ExternalGlobal>,
+    pub memory: Option<MemoryInstance>,
+    pub tables: HashMap<usize, TableInstance>,
+}
+
+impl Imports {
+    /// Looks up the imported functions and globals with [`Env::get_func`] and [`Env::get_global`].
+    /// Imported memories are created from their limits, imported tables are not supported.
+    pub fn from_env<E: Env>(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<Self, LinkError> {
+        let mut imports = Self::default();
+        let import = |import_id| {
+            bytecode
+                .get_import(import_id)
+                .ok_or(LinkError::InvalidImportId(import_id))
+        };
+        let unknown = |import: &Import, kind| LinkError::UnknownImport {
+            module: import.get_mod_name().to_string(),
+            name: import.get_name().to_string(),
+            kind,
+        };
+        for f in &info.functions {
+            if let FunctionType::Imported { import_id } = f.t {
+                let import = import(import_id)?;
+                let func = E::get_func(import.get_mod_name(), import.get_name())
+                    .ok_or_else(|| unknown(import, ImportKind::Function))?;
+                imports.functions.insert(import_id, func);
+            }
+        }
+        for g in &info.globals {
+            if let GlobalInfo::Imported { import_id } = g.info {
+                let import = import(import_id)?;
+                let global = E::get_global(import.get_mod_name(), import.get_name())
+                    .ok_or_else(|| unknown(import, ImportKind::Global))?;
+                imports.globals.insert(import_id, global);
+            }
+        }
+        Ok(imports)
+    }
+}
@@ -0,0 +1,1933 @@
+use parser::{
+    op::{Blocktype, Op},
+    reader::Bytecode,
+};
+use smallvec::SmallVec;
+use validator::validator::ValidateResult;
+
+use crate::{
+    env::Env,
+    float, int,
+    memory::MemoryInstance,
+    slow_vm::{CallStack, FunctionType, InstanceError, LocalValue, RuntimeError, Type, Vm},
+    stack::{NULL_REF, StackValue},
+    table::Ref,
+};
+
+/// Conversion between values and the untyped stack slots, 32 bit values are zero extended
+trait Slot: Sized {
+    fn from_slot(slot: u64) -> Self;
+    fn into_slot(self) -> u64;
+}
+
+macro_rules! impl_slot {
+    ($t: ty, $from: expr, $into: expr) => {
+        impl Slot for $t {
+            #[inline(always)]
+            fn from_slot(slot: u64) -> Self {
+                $from(slot)
+            }
+            #[inline(always)]
+            fn into_slot(self) -> u64 {
+                $into(self)
+            }
+        }
+    };
+}
+
+impl_slot!(u32, |s| s as u32, |v| v as u64);
+impl_slot!(i32, |s| s as u32 as i32, |v| v as u32 as u64);
+impl_slot!(u64, |s| s, |v| v);
+impl_slot!(i64, |s| s as i64, |v| v as u64);
+impl_slot!(f32, |s| f32::from_bits(s as u32), |v: f32| v.to_bits()
+    as u64);
+impl_slot!(f64, f64::from_bits, f64::to_bits);
+impl_slot!(bool, |s| s as u32 != 0, |v| v as u64);
+impl_slot!(
+    Ref,
+    |s| Some(s as u32).filter(|r| *r != NULL_REF),
+    |v: Ref| v.unwrap_or(NULL_REF) as u64
+);
+
+//NOTE: Validated code never pops more values than it pushed, like the slow vm the stack
+//operations skip the checks
+#[inline(always)]
+fn pop<T: Slot>(stack: &mut Vec<u64>) -> T {
+    T::from_slot(unsafe { stack.pop().unwrap_unchecked() })
+}
+
+#[inline(always)]
+fn push<T: Slot>(stack: &mut Vec<u64>, value: T) {
+    stack.push(value.into_slot());
+}
+
+#[inline(always)]
+fn unop<T: Slot, R: Slot>(stack: &mut [u64], op: impl FnOnce(T) -> R) {
+    let top = unsafe { stack.last_mut().unwrap_unchecked() };
+    *top = op(T::from_slot(*top)).into_slot();
+}
+
+#[inline(always)]
+fn binop<T: Slot, R: Slot>(stack: &mut Vec<u64>, op: impl FnOnce(T, T) -> R) {
+    let b = pop::<T>(stack);
+    unop(stack, |a| op(a, b));
+}
+
+#[inline(always)]
+fn try_unop<T: Slot, R: Slot>(
+    stack: &mut [u64],
+    op: impl FnOnce(T) -> Result<R, RuntimeError>,
+) -> Result<(), RuntimeError> {
+    let top = unsafe { stack.last_mut().unwrap_unchecked() };
+    *top = op(T::from_slot(*top))?.into_slot();
+    Ok(())
+}
+
+#[inline(always)]
+fn try_binop<T: Slot, R: Slot>(
+    stack: &mut Vec<u64>,
+    op: impl FnOnce(T, T) -> Result<R, RuntimeError>,
+) -> Result<(), RuntimeError> {
+    let b = pop::<T>(stack);
+    try_unop(stack, |a| op(a, b))
+}
+
+#[inline(always)]
+fn read_memory<const N: usize>(
+    mem: Option<&MemoryInstance>,
+    addr: u32,
+    offset: u32,
+) -> Result<[u8; N], RuntimeError> {
+    let start = addr as usize + offset as usize;
+    let bytes = unsafe { mem.unwrap_unchecked() }
+        .bytes()
+        .get(start..start + N)
+        .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
+    Ok(bytes.try_into().unwrap())
+}
+
+#[inline(always)]
+fn write_memory<const N: usize>(
+    mem: &mut Option<MemoryInstance>,
+    addr: u32,
+    offset: u32,
+    bytes: [u8; N],
+) -> Result<(), RuntimeError> {
+    let start = addr as usize + offset as usize;
+    unsafe { mem.as_mut().unwrap_unchecked() }
+        .bytes_mut()
+        .get_mut(start..start + N)
+        .ok_or(RuntimeError::MemoryAddressOutOfScope)?
+        .copy_from_slice(&bytes);
+    Ok(())
+}
+
+/// An `i32` comparison fused into a conditional jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum I32Cmp {
+    Eq,
+    Ne,
+    LtS,
+    LtU,
+    GtS,
+    GtU,
+    LeS,
+    LeU,
+    GeS,
+    GeU,
+}
+
+impl I32Cmp {
+    fn from_op(op: &Op) -> Option<Self> {
+        let cmp = match op {
+            Op::I32Eq => Self::Eq,
+            Op::I32Ne => Self::Ne,
+            Op::I32Lts => Self::LtS,
+            Op::I32Ltu => Self::LtU,
+            Op::I32Gts => Self::GtS,
+            Op::I32Gtu => Self::GtU,
+            Op::I32Les => Self::LeS,
+            Op::I32Leu => Self::LeU,
+            Op::I32Ges => Self::GeS,
+            Op::I32Geu => Self::GeU,
+            _ => return None,
+        };
+        Some(cmp)
+    }
+
+    /// The comparison that holds exactly when this one does not
+    fn negate(self) -> Self {
+        match self {
+            Self::Eq => Self::Ne,
+            Self::Ne => Self::Eq,
+            Self::LtS => Self::GeS,
+            Self::LtU => Self::GeU,
+            Self::GtS => Self::LeS,
+            Self::GtU => Self::LeU,
+            Self::LeS => Self::GtS,
+            Self::LeU => Self::GtU,
+            Self::GeS => Self::LtS,
+            Self::GeU => Self::LtU,
+        }
+    }
+
+    #[inline(always)]
+    fn holds(self, a: u32, b: u32) -> bool {
+        let (sa, sb) = (a as i32, b as i32);
+        match self {
+            Self::Eq => a == b,
+            Self::Ne => a != b,
+            Self::LtS => sa < sb,
+            Self::LtU => a < b,
+            Self::GtS => sa > sb,
+            Self::GtU => a > b,
+            Self::LeS => sa <= sb,
+            Self::LeU => a <= b,
+            Self::GeS => sa >= sb,
+            Self::GeU => a >= b,
+        }
+    }
+}
+
+/// A branch that moves the values it keeps down to the label
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Branch {
+    target: u32,
+    /// Stack height of the label relative to the frame pointer
+    height: u32,
+    keep: u32,
+}
+
+/// A lowered instruction. Targets are positions in the lowered code, locals are stack slots
+/// relative to the frame pointer of the current call.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Instr {
+    Unreachable,
+    /// A branch whose kept values are already in place
+    Jump(u32),
+    JumpIfZero(u32),
+    JumpIfNonZero(u32),
+    /// `i32.eq` and friends followed by `br_if`, or by `if` with the negated comparison
+    JumpIfI32 {
+        cmp: I32Cmp,
+        target: u32,
+    },
+    Br(Branch),
+    BrIf(Branch),
+    /// The targets are `br_tables[start..start + len]`, the last one is the default
+    BrTable {
+        start: u32,
+        len: u32,
+    },
+    Return,
+    Call(u32),
+    CallHost(u32),
+    CallIndirect {
+        type_id: u32,
+        table: u32,
+    },
+    Drop,
+    Select,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    /// `local.get`, `i32.const`, `i32.add`
+    LocalGetI32AddConst {
+        local: u32,
+        value: i32,
+    },
+    /// `local.get`, `i32.const`, `i32.add`, `local.set` of the same local
+    LocalI32AddConst {
+        local: u32,
+        value: i32,
+    },
+    /// `i32.const`, `i32.add`
+    I32AddConst(i32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    /// Any constant as raw stack slot bits
+    Const(u64),
+    I32Load(u32),
+    I64Load(u32),
+    F32Load(u32),
+    F64Load(u32),
+    I32Load8s(u32),
+    I32Load8u(u32),
+    I32Load16s(u32),
+    I32Load16u(u32),
+    I64Load8s(u32),
+    I64Load8u(u32),
+    I64Load16s(u32),
+    I64Load16u(u32),
+    I64Load32s(u32),
+    I64Load32u(u32),
+    I32Store(u32),
+    I64Store(u32),
+    F32Store(u32),
+    F64Store(u32),
+    I32Store8(u32),
+    I32Store16(u32),
+    I64Store8(u32),
+    I64Store16(u32),
+    I64Store32(u32),
+    MemorySize,
+    MemoryGrow,
+    MemoryCopy,
+    MemoryFill,
+    MemoryInit(u32),
+    TableGet(u32),
+    TableSet(u32),
+    TableSize(u32),
+    TableGrow(u32),
+    TableFill(u32),
+    TableCopy {
+        dst: u32,
+        src: u32,
+    },
+    TableInit {
+        elem_id: u32,
+        table: u32,
+    },
+    ElemDrop(u32),
+    RefIsNull,
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32Lts,
+    I32Ltu,
+    I32Gts,
+    I32Gtu,
+    I32Leu,
+    I32Les,
+    I32Ges,
+    I32Geu,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64Lts,
+    I64Ltu,
+    I64Gts,
+    I64Gtu,
+    I64Les,
+    I64Leu,
+    I64Ges,
+    I64Geu,
+    F32Eq,
+    F32Ne,
+    F32Lt,
+    F32Gt,
+    F32Le,
+    F32Ge,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+    I32Clz,
+    I32Ctz,
+    I32Popcnt,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32Divs,
+    I32Divu,
+    I32Rems,
+    I32Remu,
+    I32And,
+    I32Or,
+    I32Xor,
+    I32Shl,
+    I32Shrs,
+    I32Shru,
+    I32Rotl,
+    I32Rotr,
+    I64Clz,
+    I64Ctz,
+    I64Popcnt,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64Divs,
+    I64Divu,
+    I64Rems,
+    I64Remu,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64Shrs,
+    I64Shru,
+    I64Rotl,
+    I64Rotr,
+    F32Abs,
+    F32Neg,
+    F32Ceil,
+    F32Floor,
+    F32Trunc,
+    F32Nearest,
+    F32Sqrt,
+    F32Add,
+    F32Sub,
+    F32Mul,
+    F32Div,
+    F32Min,
+    F32Max,
+    F32Copysign,
+    F64Abs,
+    F64Neg,
+    F64Ceil,
+    F64Floor,
+    F64Trunc,
+    F64Nearest,
+    F64Sqrt,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Min,
+    F64Max,
+    F64Copysign,
+    I32WrapI64,
+    I32TruncF32s,
+    I32TruncF32u,
+    I32TruncF64s,
+    I32TruncF64u,
+    I64ExtendI32s,
+    I64ExtendI32u,
+    I64TruncF32s,
+    I64TruncF32u,
+    I64TruncF64s,
+    I64TruncF64u,
+    F32ConvertI32s,
+    F32ConvertI32u,
+    F32ConvertI64s,
+    F32ConvertI64u,
+    F32DemoteF64,
+    F64ConvertI32s,
+    F64ConvertI32u,
+    F64ConvertI64s,
+    F64ConvertI64u,
+    F64PromoteF32,
+    I32TruncSatF32s,
+    I32TruncSatF32u,
+    I32TruncSatF64s,
+    I32TruncSatF64u,
+    I64TruncSatF32s,
+    I64TruncSatF32u,
+    I64TruncSatF64s,
+    I64TruncSatF64u,
+}
+
+macro_rules! lower_same {
+    ($op: expr, $($name: ident),+ $(,)?) => {
+        match $op {
+            $(Op::$name => Some(Instr::$name),)+
+            _ => None,
+        }
+    };
+}
+
+impl Instr {
+    /// Instructions without control flow, calls or fusion
+    fn from_op(op: &Op) -> Option<Self> {
+        let instr = match op {
+            Op::Unreachable => Self::Unreachable,
+            Op::Drop => Self::Drop,
+            Op::Select(_) => Self::Select,
+            Op::LocalGet(id) => Self::LocalGet(*id as u32),
+            Op::LocalSet(id) => Self::LocalSet(*id as u32),
+            Op::LocalTee(id) => Self::LocalTee(*id as u32),
+            Op::GlobalGet(id) => Self::GlobalGet(*id as u32),
+            Op::GlobalSet(id) => Self::GlobalSet(*id as u32),
+            Op::I32Const(v) => Self::Const(v.into_slot()),
+            Op::I64Const(v) => Self::Const(v.into_slot()),
+            Op::F32Const(v) => Self::Const(v.into_slot()),
+            Op::F64Const(v) => Self::Const(v.into_slot()),
+            Op::RefNull(_) => Self::Const(None.into_slot()),
+            Op::RefFunc(id) => Self::Const(Some(*id as u32).into_slot()),
+            Op::I32Load(m) => Self::I32Load(m.offset),
+            Op::I64Load(m) => Self::I64Load(m.offset),
+            Op::F32Load(m) => Self::F32Load(m.offset),
+            Op::F64Load(m) => Self::F64Load(m.offset),
+            Op::I32Load8s(m) => Self::I32Load8s(m.offset),
+            Op::I32Load8u(m) => Self::I32Load8u(m.offset),
+            Op::I32Load16s(m) => Self::I32Load16s(m.offset),
+            Op::I32Load16u(m) => Self::I32Load16u(m.offset),
+            Op::I64Load8s(m) => Self::I64Load8s(m.offset),
+            Op::I64Load8u(m) => Self::I64Load8u(m.offset),
+            Op::I64Load16s(m) => Self::I64Load16s(m.offset),
+            Op::I64Load16u(m) => Self::I64Load16u(m.offset),
+            Op::I64Load32s(m) => Self::I64Load32s(m.offset),
+            Op::I64Load32u(m) => Self::I64Load32u(m.offset),
+            Op::I32Store(m) => Self::I32Store(m.offset),
+            Op::I64Store(m) => Self::I64Store(m.offset),
+            Op::F32Store(m) => Self::F32Store(m.offset),
+            Op::F64Store(m) => Self::F64Store(m.offset),
+            Op::I32Store8(m) => Self::I32Store8(m.offset),
+            Op::I32Store16(m) => Self::I32Store16(m.offset),
+            Op::I64Store8(m) => Self::I64Store8(m.offset),
+            Op::I64Store16(m) => Self::I64Store16(m.offset),
+            Op::I64Store32(m) => Self::I64Store32(m.offset),
+            Op::MemorySize { .. } => Self::MemorySize,
+            Op::MemoryGrow { .. } => Self::MemoryGrow,
+            Op::MemoryCopy { .. } => Self::MemoryCopy,
+            Op::MemoryFill { .. } => Self::MemoryFill,
+            Op::MemoryInit { data_id, .. } => Self::MemoryInit(*data_id as u32),
+            Op::TableGet(table) => Self::TableGet(*table as u32),
+            Op::TableSet(table) => Self::TableSet(*table as u32),
+            Op::TableSize(table) => Self::TableSize(*table as u32),
+            Op::TableGrow(table) => Self::TableGrow(*table as u32),
+            Op::TableFill(table) => Self::TableFill(*table as u32),
+            Op::TableCopy { dst, src } => Self::TableCopy {
+                dst: *dst as u32,
+                src: *src as u32,
+            },
+            Op::TableInit { elem_id, table } => Self::TableInit {
+                elem_id: *elem_id as u32,
+                table: *table as u32,
+            },
+            Op::ElemDrop(elem_id) => Self::ElemDrop(*elem_id as u32),
+            op => {
+                return lower_same!(
+                    op,
+                    RefIsNull,
+                    I32Eqz,
+                    I32Eq,
+                    I32Ne,
+                    I32Lts,
+                    I32Ltu,
+                    I32Gts,
+                    I32Gtu,
+                    I32Leu,
+                    I32Les,
+                    I32Ges,
+                    I32Geu,
+                    I64Eqz,
+                    I64Eq,
+                    I64Ne,
+                    I64Lts,
+                    I64Ltu,
+                    I64Gts,
+                    I64Gtu,
+                    I64Les,
+                    I64Leu,
+                    I64Ges,
+                    I64Geu,
+                    F32Eq,
+                    F32Ne,
+                    F32Lt,
+                    F32Gt,
+                    F32Le,
+                    F32Ge,
+                    F64Eq,
+                    F64Ne,
+                    F64Lt,
+                    F64Gt,
+                    F64Le,
+                    F64Ge,
+                    I32Clz,
+                    I32Ctz,
+                    I32Popcnt,
+                    I32Add,
+                    I32Sub,
+                    I32Mul,
+                    I32Divs,
+                    I32Divu,
+                    I32Rems,
+                    I32Remu,
+                    I32And,
+                    I32Or,
+                    I32Xor,
+                    I32Shl,
+                    I32Shrs,
+                    I32Shru,
+                    I32Rotl,
+                    I32Rotr,
+                    I64Clz,
+                    I64Ctz,
+                    I64Popcnt,
+                    I64Add,
+                    I64Sub,
+                    I64Mul,
+                    I64Divs,
+                    I64Divu,
+                    I64Rems,
+                    I64Remu,
+                    I64And,
+                    I64Or,
+                    I64Xor,
+                    I64Shl,
+                    I64Shrs,
+                    I64Shru,
+                    I64Rotl,
+                    I64Rotr,
+                    F32Abs,
+                    F32Neg,
+                    F32Ceil,
+                    F32Floor,
+                    F32Trunc,
+                    F32Nearest,
+                    F32Sqrt,
+                    F32Add,
+                    F32Sub,
+                    F32Mul,
+                    F32Div,
+                    F32Min,
+                    F32Max,
+                    F32Copysign,
+                    F64Abs,
+                    F64Neg,
+                    F64Ceil,
+                    F64Floor,
+                    F64Trunc,
+                    F64Nearest,
+                    F64Sqrt,
+                    F64Add,
+                    F64Sub,
+                    F64Mul,
+                    F64Div,
+                    F64Min,
+                    F64Max,
+                    F64Copysign,
+                    I32WrapI64,
+                    I32TruncF32s,
+                    I32TruncF32u,
+                    I32TruncF64s,
+                    I32TruncF64u,
+                    I64ExtendI32s,
+                    I64ExtendI32u,
+                    I64TruncF32s,
+                    I64TruncF32u,
+                    I64TruncF64s,
+                    I64TruncF64u,
+                    F32ConvertI32s,
+                    F32ConvertI32u,
+                    F32ConvertI64s,
+                    F32ConvertI64u,
+                    F32DemoteF64,
+                    F64ConvertI32s,
+                    F64ConvertI32u,
+                    F64ConvertI64s,
+                    F64ConvertI64u,
+                    F64PromoteF32,
+                    I32TruncSatF32s,
+                    I32TruncSatF32u,
+                    I32TruncSatF64s,
+                    I32TruncSatF64u,
+                    I64TruncSatF32s,
+                    I64TruncSatF32u,
+                    I64TruncSatF64s,
+                    I64TruncSatF64u,
+                );
+            }
+        };
+        Some(instr)
+    }
+
+    fn set_target(&mut self, pc: u32) {
+        match self {
+            Self::Jump(target)
+            | Self::JumpIfZero(target)
+            | Self::JumpIfNonZero(target)
+            | Self::JumpIfI32 { target, .. }
+            | Self::Br(Branch { target, .. })
+            | Self::BrIf(Branch { target, .. }) => *target = pc,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CompiledFunction {
+    t: Type,
+    /// Position of the first instruction, unused for host functions
+    start: u32,
+    /// Initial values of the declared locals, the params are already on the stack
+    locals: Box<[u64]>,
+    /// Id passed to [`Env::call`] for imported functions
+    host: Option<usize>,
+    /// Functions with equal types share a signature, checked by `call_indirect`
+    signature: u32,
+}
+
+/// A caller waiting for its callee to return
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    func_id: u32,
+    /// Behind the call instruction
+    pc: u32,
+    fp: u32,
+}
+
+/// A branch whose target is only known once the end of its block is reached
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    Code(usize),
+    Table(usize),
+}
+
+#[derive(Debug)]
+struct Block {
+    /// Stack height below the block params, relative to the frame pointer
+    height: u32,
+    params: u32,
+    results: u32,
+    /// Set for loops, branches jump back to their start
+    start: Option<u32>,
+    fixups: Vec<Fixup>,
+    /// The conditional jump of an `if` to its else branch or end
+    else_jump: Option<usize>,
+}
+
+impl Block {
+    /// Values kept when branching to the block
+    fn arity(&self) -> u32 {
+        match self.start {
+            Some(_) => self.params,
+            None => self.results,
+        }
+    }
+}
+
+struct Lowering<'a> {
+    types: &'a [Type],
+    functions: &'a [CompiledFunction],
+    code: &'a mut Vec<Instr>,
+    origins: &'a mut Vec<u32>,
+    br_tables: &'a mut Vec<Branch>,
+    blocks: Vec<Block>,
+    height: u32,
+    /// Set after an unconditional branch until the end of the block
+    unreachable: bool,
+    /// Blocks opened in unreachable code, skipped with their body
+    dead_blocks: usize,
+}
+
+impl Lowering<'_> {
+    fn blocktype_arity(&self, bt: &Blocktype) -> (u32, u32) {
+        match bt {
+            Blocktype::Empty => (0, 0),
+            Blocktype::Value(_) => (0, 1),
+            Blocktype::TypeIndex(id) => {
+                let t = &self.types[*id as usize];
+                (t.params.len() as u32, t.results.len() as u32)
+            }
+        }
+    }
+
+    fn emit(&mut self, instr: Instr, ip: usize) -> usize {
+        self.code.push(instr);
+        self.origins.push(ip as u32);
+        self.code.len() - 1
+    }
+
+    fn pc(&self) -> u32 {
+        self.code.len() as u32
+    }
+
+    fn open_block(&mut self, bt: &Blocktype, start: Option<u32>, else_jump: Option<usize>) {
+        let (params, results) = self.blocktype_arity(bt);
+        self.blocks.push(Block {
+            height: self.height - params,
+            params,
+            results,
+            start,
+            fixups: vec![],
+            else_jump,
+        });
+    }
+
+    fn patch(&mut self, fixup: Fixup, pc: u32) {
+        match fixup {
+            Fixup::Code(i) => self.code[i].set_target(pc),
+            Fixup::Table(i) => self.br_tables[i].target = pc,
+        }
+    }
+
+    /// The branch to the label `depth` blocks up, the target is patched later for forward jumps
+    fn branch(&mut self, depth: usize, fixup: Fixup) -> Branch {
+        let index = self.blocks.len() - 1 - depth;
+        let block = &self.blocks[index];
+        let mut branch = Branch {
+            target: 0,
+            height: block.height,
+            keep: block.arity(),
+        };
+        match block.start {
+            Some(start) => branch.target = start,
+            None => self.blocks[index].fixups.push(fixup),
+        }
+        branch
+    }
+
+    /// True if the kept values of a branch are already at the height of its label
+    fn is_in_place(&self, depth: usize) -> bool {
+        let block = &self.blocks[self.blocks.len() - 1 - depth];
+        self.height == block.height + block.arity()
+    }
+
+    fn emit_branch(&mut self, depth: usize, conditional: bool, ip: usize) {
+        //NOTE: The outermost block is the function body, branching there returns
+        if !conditional && depth == self.blocks.len() - 1 {
+            self.emit(Instr::Return, ip);
+            return;
+        }
+        let pos = self.code.len();
+        let in_place = self.is_in_place(depth);
+        let branch = self.branch(depth, Fixup::Code(pos));
+        let instr = match (conditional, in_place) {
+            (false, true) => Instr::Jump(branch.target),
+            (true, true) => Instr::JumpIfNonZero(branch.target),
+            (false, false) => Instr::Br(branch),
+            (true, false) => Instr::BrIf(branch),
+        };
+        self.emit(instr, ip);
+    }
+
+    /// Emits a single instruction for a common sequence at the start of `ops`, returns how
+    /// many ops it replaced
+    fn fuse(&mut self, ops: &[Op], ip: usize) -> usize {
+        match ops {
+            [
+                Op::LocalGet(a),
+                Op::I32Const(value),
+                Op::I32Add,
+                Op::LocalSet(b),
+                ..,
+            ] if a == b => {
+                let local = *a as u32;
+                self.emit(
+                    Instr::LocalI32AddConst {
+                        local,
+                        value: *value,
+                    },
+                    ip,
+                );
+                4
+            }
+            [Op::LocalGet(local), Op::I32Const(value), Op::I32Add, ..] => {
+                let local = *local as u32;
+                self.emit(
+                    Instr::LocalGetI32AddConst {
+                        local,
+                        value: *value,
+                    },
+                    ip,
+                );
+                self.height += 1;
+                3
+            }
+            [Op::I32Const(value), Op::I32Add, ..] => {
+                self.emit(Instr::I32AddConst(*value), ip);
+                2
+            }
+            [Op::I32Eqz, Op::BrIf { label, .. }, ..] => {
+                self.height -= 1;
+                if !self.is_in_place(*label) {
+                    self.height += 1;
+                    return 0;
+                }
+                let pos = self.code.len();
+                let branch = self.branch(*label, Fixup::Code(pos));
+                self.emit(Instr::JumpIfZero(branch.target), ip);
+                2
+            }
+            [Op::I32Eqz, Op::If { bt, .. }, ..] => {
+                self.height -= 1;
+                let pos = self.emit(Instr::JumpIfNonZero(0), ip);
+                self.open_block(bt, None, Some(pos));
+                2
+            }
+            [cmp, Op::BrIf { label, .. }, ..] if I32Cmp::from_op(cmp).is_some() => {
+                self.height -= 2;
+                if !self.is_in_place(*label) {
+                    self.height += 2;
+                    return 0;
+                }
+                let pos = self.code.len();
+                let branch = self.branch(*label, Fixup::Code(pos));
+                let cmp = I32Cmp::from_op(cmp).unwrap();
+                let target = branch.target;
+                self.emit(Instr::JumpIfI32 { cmp, target }, ip);
+                2
+            }
+            [cmp, Op::If { bt, .. }, ..] if I32Cmp::from_op(cmp).is_some() => {
+                self.height -= 2;
+                let cmp = I32Cmp::from_op(cmp).unwrap().negate();
+                let pos = self.emit(Instr::JumpIfI32 { cmp, target: 0 }, ip);
+                self.open_block(bt, None, Some(pos));
+                2
+            }
+            _ => 0,
+        }
+    }
+
+    /// Lowers the body of a function starting at `ip` in the slow vm's code
+    fn function(&mut self, ops: &[Op], mut ip: usize, locals: u32, results: u32) {
+        self.height = locals;
+        self.blocks.push(Block {
+            height: locals,
+            params: 0,
+            results,
+            start: None,
+            fixups: vec![],
+            else_jump: None,
+        });
+        while !self.blocks.is_empty() {
+            let op = &ops[ip];
+            if self.unreachable {
+                let live = match op {
+                    Op::Block(_) | Op::Loop(_) | Op::If { .. } => {
+                        self.dead_blocks += 1;
+                        false
+                    }
+                    Op::End(_) if self.dead_blocks > 0 => {
+                        self.dead_blocks -= 1;
+                        false
+                    }
+                    Op::End(_) | Op::Else(_) => self.dead_blocks == 0,
+                    _ => false,
+                };
+                if !live {
+                    ip += 1;
+                    continue;
+                }
+            }
+            let fused = self.fuse(&ops[ip..], ip);
+            if fused > 0 {
+                ip += fused;
+                continue;
+            }
+            self.op(op, ip);
+            ip += 1;
+        }
+    }
+
+    fn op(&mut self, op: &Op, ip: usize) {
+        match op {
+            Op::Nop => {}
+            //NOTE: Floats are stored as their bits, reinterpreting leaves the slot unchanged
+            Op::I32ReinterpretF32
+            | Op::I64ReinterpretF64
+            | Op::F32ReinterpretI32
+            | Op::F64ReinterpretI64 => {}
+            Op::Block(bt) => self.open_block(bt, None, None),
+            Op::Loop(bt) => self.open_block(bt, Some(self.pc()), None),
+            Op::If { bt, .. } => {
+                self.height -= 1;
+                let pos = self.emit(Instr::JumpIfZero(0), ip);
+                self.open_block(bt, None, Some(pos));
+            }
+            Op::Else(_) => {
+                let was_unreachable = self.unreachable;
+                let pos = self.code.len();
+                let block = self.blocks.last_mut().unwrap();
+                if !was_unreachable {
+                    block.fixups.push(Fixup::Code(pos));
+                }
+                let else_jump = block.else_jump.take().unwrap();
+                self.height = block.height + block.params;
+                if !was_unreachable {
+                    self.emit(Instr::Jump(0), ip);
+                }
+                let pc = self.pc();
+                self.code[else_jump].set_target(pc);
+                self.unreachable = false;
+            }
+            Op::End(_) => {
+                let block = self.blocks.pop().unwrap();
+                let pc = self.pc();
+                if let Some(else_jump) = block.else_jump {
+                    self.code[else_jump].set_target(pc);
+                }
+                for fixup in block.fixups {
+                    self.patch(fixup, pc);
+                }
+                self.height = block.height + block.results;
+                self.unreachable = false;
+                if self.blocks.is_empty() {
+                    self.emit(Instr::Return, ip);
+                }
+            }
+            Op::Br { label, .. } => {
+                self.emit_branch(*label, false, ip);
+                self.unreachable = true;
+            }
+            Op::BrIf { label, .. } => {
+                self.height -= 1;
+                self.emit_branch(*label, true, ip);
+            }
+            Op::BrTable { labels, default } => {
+                self.height -= 1;
+                let start = self.br_tables.len();
+                for target in labels.iter().chain([default]) {
+                    let pos = self.br_tables.len();
+                    let branch = self.branch(target.label, Fixup::Table(pos));
+                    self.br_tables.push(branch);
+                }
+                let len = (self.br_tables.len() - start) as u32;
+                let start = start as u32;
+                self.emit(Instr::BrTable { start, len }, ip);
+                self.unreachable = true;
+            }
+            Op::Return => {
+                self.emit(Instr::Return, ip);
+                self.unreachable = true;
+            }
+            Op::Call(id) => {
+                let f = &self.functions[*id];
+                self.height = self.height - f.t.params.len() as u32 + f.t.results.len() as u32;
+                let instr = match f.host {
+                    Some(_) => Instr::CallHost(*id as u32),
+                    None => Instr::Call(*id as u32),
+                };
+                self.emit(instr, ip);
+            }
+            Op::CallIndirect { type_id, table } => {
+                let t = &self.types[*type_id];
+                self.height = self.height - 1 - t.params.len() as u32 + t.results.len() as u32;
+                let instr = Instr::CallIndirect {
+                    type_id: *type_id as u32,
+                    table: *table as u32,
+                };
+                self.emit(instr, ip);
+            }
+            op => {
+                let (pops, pushes) = op.stack_effect().unwrap();
+                self.height = self.height - pops as u32 + pushes as u32;
+                self.emit(Instr::from_op(op).unwrap(), ip);
+                if matches!(op, Op::Unreachable) {
+                    self.unreachable = true;
+                }
+            }
+        }
+    }
+}
+
+/// A faster interpreter tier. Validated functions are lowered into a compact bytecode with
+/// resolved branch targets, locals in stack slots and fused common instruction sequences.
+///
+/// Memory, globals and tables stay in the wrapped [`Vm`], so host functions get the same
+/// `&mut Vm` in [`Env::call`] and both tiers can be used on the same instance. Fuel, tracing,
+/// profiling, coverage and time travel are only supported by the slow vm.
+#[derive(Debug, Clone)]
+pub struct FastVm<E: Env> {
+    vm: Vm<E>,
+    code: Vec<Instr>,
+    /// Position of the original instruction in the slow vm's code, used to report traps
+    origins: Vec<u32>,
+    br_tables: Vec<Branch>,
+    functions: Vec<CompiledFunction>,
+    /// Signature of every type in the type section
+    type_signatures: Vec<u32>,
+    /// Passive data segments, empty for active ones
+    data: Vec<Vec<u8>>,
+    stack: Vec<u64>,
+    frames: Vec<Frame>,
+}
+
+impl<E: Env> FastVm<E> {
+    pub fn init_from_validation_result(res: &ValidateResult) -> Result<Self, InstanceError> {
+        Ok(Self::new(
+            Vm::init_from_validation_result(res)?,
+            &res.bytecode,
+        ))
+    }
+
+    /// Lowers the code of an instantiated module, `bytecode` has to be the module of `vm`
+    pub fn new(vm: Vm<E>, bytecode: &Bytecode) -> Self {
+        let types = vm.types().to_vec();
+        let mut signatures: Vec<&Type> = vec![];
+        let mut signature = |t| match signatures.iter().position(|s| *s == t) {
+            Some(i) => i as u32,
+            None => {
+                signatures.push(t);
+                signatures.len() as u32 - 1
+            }
+        };
+        let type_signatures = types.iter().map(&mut signature).collect();
+        let mut functions = vm
+            .functions()
+            .iter()
+            .map(|f| {
+                let (locals, host) = match &f.kind {
+                    FunctionType::Wasm(w) => {
+                        let locals = w
+                            .locals
+                            .iter()
+                            .map(|t| StackValue::from(LocalValue::init_from_type(*t)).bits())
+                            .collect();
+                        (locals, None)
+                    }
+                    FunctionType::Native(n) => (Box::default(), Some(n.id)),
+                };
+                CompiledFunction {
+                    t: f.t.clone(),
+                    start: 0,
+                    locals,
+                    host,
+                    signature: signature(&f.t),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let (mut code, mut origins, mut br_tables) = (vec![], vec![], vec![]);
+        let mut starts = vec![];
+        for (f, function) in vm.functions().iter().zip(&functions) {
+            starts.push(code.len() as u32);
+            let FunctionType::Wasm(w) = &f.kind else {
+                continue;
+            };
+            let mut lowering = Lowering {
+                types: &types,
+                functions: &functions,
+                code: &mut code,
+                origins: &mut origins,
+                br_tables: &mut br_tables,
+                blocks: vec![],
+                height: 0,
+                unreachable: false,
+                dead_blocks: 0,
+            };
+            let locals = (f.t.params.len() + w.locals.len()) as u32;
+            let results = function.t.results.len() as u32;
+            lowering.function(vm.instructions(), w.code_offset, locals, results);
+        }
+        for (function, start) in functions.iter_mut().zip(starts) {
+            function.start = start;
+        }
+        let data = bytecode
+            .iter_data()
+            .map(|data| {
+                data.map(|d| match d.is_passive() {
+                    true => d.get_data().to_vec(),
+                    false => vec![],
+                })
+                .collect()
+            })
+            .unwrap_or_default();
+
+        Self {
+            vm,
+            code,
+            origins,
+            br_tables,
+            functions,
+            type_signatures,
+            data,
+            stack: Vec::with_capacity(1024),
+            frames: Vec::with_capacity(64),
+        }
+    }
+
+    /// The instance state shared with the slow vm
+    pub fn vm(&self) -> &Vm<E> {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm<E> {
+        &mut self.vm
+    }
+
+    pub fn into_vm(self) -> Vm<E> {
+        self.vm
+    }
+
+    /// Runs a function to completion and returns its results. Errors are reported like the slow
+    /// vm reports them, traps carry the position of the original instruction.
+    /// Every lowered instruction consumes one unit of the fuel set on the vm, unlike the slow vm
+    /// a call that ran out of fuel cannot be resumed.
+    pub fn call(
+        &mut self,
+        func_id: usize,
+        params: &[LocalValue],
+        env: &mut E,
+    ) -> Result<Vec<LocalValue>, RuntimeError> {
+        self.stack.clear();
+        self.frames.clear();
+        let function = self
+            .functions
+            .get(func_id)
+            .ok_or(RuntimeError::UnknownFunction(func_id))?;
+        let params = params.iter().map(|p| StackValue::from(*p).bits());
+        self.stack.extend(params);
+        if let Some(id) = function.host {
+            let t = function.t.clone();
+            call_host(&mut self.vm, env, &t, id, &mut self.stack)?;
+        } else {
+            if self.stack.len() + function.locals.len() > self.vm.max_stack_size() {
+                return Err(RuntimeError::StackOverflow {
+                    depth: 0,
+                    call_stack: CallStack::default(),
+                });
+            }
+            self.stack.extend_from_slice(&function.locals);
+            self.execute(func_id, env)?;
+        }
+        let results = &self.functions[func_id].t.results;
+        Ok(results
+            .iter()
+            .zip(&self.stack)
+            .map(|(t, v)| LocalValue::init_from_type_and_val(*t, (*v).into()))
+            .collect())
+    }
+
+    fn execute(&mut self, func_id: usize, env: &mut E) -> Result<(), RuntimeError> {
+        let Self {
+            vm,
+            code,
+            origins,
+            br_tables,
+            functions,
+            type_signatures,
+            data,
+            stack,
+            frames,
+        } = self;
+        let mut func_id = func_id;
+        let mut pc = functions[func_id].start as usize;
+        let mut fp = 0;
+
+        macro_rules! trap {
+            ($res: expr) => {
+                match $res {
+                    Ok(v) => v,
+                    Err(e) => return Err(vm.trap_at(func_id, origins[pc - 1] as usize, e)),
+                }
+            };
+        }
+        macro_rules! load {
+            ($offset: expr, $storage: ty => $target: ty) => {{
+                let addr = pop::<u32>(stack);
+                let bytes = trap!(read_memory(vm.memory(), addr, $offset));
+                push::<$target>(stack, <$storage>::from_le_bytes(bytes).into());
+            }};
+        }
+        macro_rules! store {
+            ($offset: expr, $pop: ty, $convert: expr) => {{
+                let value: $pop = pop(stack);
+                let addr = pop::<u32>(stack);
+                let bytes = $convert(value).to_le_bytes();
+                trap!(write_memory(vm.memory_slot(), addr, $offset, bytes));
+            }};
+        }
+        macro_rules! enter {
+            ($callee: expr) => {{
+                let callee = $callee;
+                let function = &functions[callee];
+                if let Some(id) = function.host {
+                    trap!(call_host(vm, env, &function.t, id, stack));
+                } else {
+                    let depth = frames.len() + 1;
+                    if depth >= vm.max_call_depth()
+                        || stack.len() + function.locals.len() > vm.max_stack_size()
+                    {
+                        let call_stack = call_stack(vm, origins, frames, func_id, pc);
+                        trap!(Err(RuntimeError::StackOverflow { depth, call_stack }));
+                    }
+                    frames.push(Frame {
+                        func_id: func_id as u32,
+                        pc: pc as u32,
+                        fp: fp as u32,
+                    });
+                    fp = stack.len() - function.t.params.len();
+                    stack.extend_from_slice(&function.locals);
+                    func_id = callee;
+                    pc = function.start as usize;
+                }
+            }};
+        }
+
+        loop {
+            vm.consume_fuel()?;
+            let instr = code[pc];
+            pc += 1;
+            match instr {
+                Instr::Unreachable => trap!(Err(RuntimeError::UnreachableReached)),
+                Instr::Jump(target) => pc = target as usize,
+                Instr::JumpIfZero(target) => {
+                    if !pop::<bool>(stack) {
+                        pc = target as usize;
+                    }
+                }
+                Instr::JumpIfNonZero(target) => {
+                    if pop::<bool>(stack) {
+                        pc = target as usize;
+                    }
+                }
+                Instr::JumpIfI32 { cmp, target } => {
+                    let b = pop::<u32>(stack);
+                    let a = pop::<u32>(stack);
+                    if cmp.holds(a, b) {
+                        pc = target as usize;
+                    }
+                }
+                Instr::Br(branch) => pc = jump(stack, fp, branch),
+                Instr::BrIf(branch) => {
+                    if pop::<bool>(stack) {
+                        pc = jump(stack, fp, branch);
+                    }
+                }
+                Instr::BrTable { start, len } => {
+                    let index = pop::<u32>(stack).min(len - 1);
+                    pc = jump(stack, fp, br_tables[(start + index) as usize]);
+                }
+                Instr::Return => {
+                    let results = functions[func_id].t.results.len();
+                    let len = stack.len();
+                    stack.copy_within(len - results..len, fp);
+                    stack.truncate(fp + results);
+                    match frames.pop() {
+                        Some(frame) => {
+                            func_id = frame.func_id as usize;
+                            pc = frame.pc as usize;
+                            fp = frame.fp as usize;
+                        }
+                        None => return Ok(()),
+                    }
+                }
+                Instr::Call(callee) => enter!(callee as usize),
+                Instr::CallHost(callee) => {
+                    let function = &functions[callee as usize];
+                    trap!(call_host(
+                        vm,
+                        env,
+                        &function.t,
+                        function.host.unwrap(),
+                        stack
+                    ));
+                }
+                Instr::CallIndirect { type_id, table } => {
+                    let i = pop::<u32>(stack);
+                    let callee = trap!(
+                        vm.tables_mut()[table as usize]
+                            .get(i)
+                            .map_err(|_| RuntimeError::UndefinedElement)
+                            .and_then(|r| r.ok_or(RuntimeError::UninitializedElement))
+                    ) as usize;
+                    //NOTE: Functions appended to the vm after lowering, like those a store
+                    //adds for references into other instances, are host functions
+                    if callee >= functions.len() {
+                        let function = &vm.functions()[callee];
+                        let FunctionType::Native(native) = &function.kind else {
+                            unreachable!("wasm functions are lowered");
+                        };
+                        let (t, id) = (function.t.clone(), native.id);
+                        if t != vm.types()[type_id as usize] {
+                            trap!(Err(RuntimeError::IndirectCallTypeMismatch {
+                                type_id: type_id as usize,
+                                func_id: callee,
+                            }));
+                        }
+                        trap!(call_host(vm, env, &t, id, stack));
+                        continue;
+                    }
+                    if functions[callee].signature != type_signatures[type_id as usize] {
+                        trap!(Err(RuntimeError::IndirectCallTypeMismatch {
+                            type_id: type_id as usize,
+                            func_id: callee,
+                        }));
+                    }
+                    enter!(callee);
+                }
+                Instr::Drop => _ = pop::<u64>(stack),
+                Instr::Select => {
+                    let cond = pop::<bool>(stack);
+                    let b = pop::<u64>(stack);
+                    if !cond {
+                        unop(stack, |_: u64| b);
+                    }
+                }
+                Instr::LocalGet(id) => stack.push(stack[fp + id as usize]),
+                Instr::LocalSet(id) => stack[fp + id as usize] = pop(stack),
+                Instr::LocalTee(id) => stack[fp + id as usize] = stack[stack.len() - 1],
+                Instr::LocalGetI32AddConst { local, value } => {
+                    let a = u32::from_slot(stack[fp + local as usize]);
+                    push(stack, a.wrapping_add(value as u32));
+                }
+                Instr::LocalI32AddConst { local, value } => {
+                    let slot = &mut stack[fp + local as usize];
+                    *slot = u32::from_slot(*slot).wrapping_add(value as u32).into_slot();
+                }
+                Instr::I32AddConst(value) => unop(stack, |a: u32| a.wrapping_add(value as u32)),
+                Instr::GlobalGet(id) => {
+                    stack.push(StackValue::from(vm.globals()[id as usize]).bits());
+                }
+                Instr::GlobalSet(id) => {
+                    let value = StackValue::from(pop::<u64>(stack));
+                    unsafe { vm.globals_mut()[id as usize].set_inner_from_stack_val(value) };
+                }
+                Instr::Const(bits) => stack.push(bits),
+                Instr::I32Load(offset) => load!(offset, u32 => u32),
+                Instr::I64Load(offset) => load!(offset, u64 => u64),
+                Instr::F32Load(offset) => load!(offset, f32 => f32),
+                Instr::F64Load(offset) => load!(offset, f64 => f64),
+                Instr::I32Load8s(offset) => load!(offset, i8 => i32),
+                Instr::I32Load8u(offset) => load!(offset, u8 => u32),
+                Instr::I32Load16s(offset) => load!(offset, i16 => i32),
+                Instr::I32Load16u(offset) => load!(offset, u16 => u32),
+                Instr::I64Load8s(offset) => load!(offset, i8 => i64),
+                Instr::I64Load8u(offset) => load!(offset, u8 => u64),
+                Instr::I64Load16s(offset) => load!(offset, i16 => i64),
+                Instr::I64Load16u(offset) => load!(offset, u16 => u64),
+                Instr::I64Load32s(offset) => load!(offset, i32 => i64),
+                Instr::I64Load32u(offset) => load!(offset, u32 => u64),
+                Instr::I32Store(offset) => store!(offset, u32, |v| v),
+                Instr::I64Store(offset) => store!(offset, u64, |v| v),
+                Instr::F32Store(offset) => store!(offset, f32, |v| v),
+                Instr::F64Store(offset) => store!(offset, f64, |v| v),
+                Instr::I32Store8(offset) => store!(offset, u32, |v| v as u8),
+                Instr::I32Store16(offset) => store!(offset, u32, |v| v as u16),
+                Instr::I64Store8(offset) => store!(offset, u64, |v| v as u8),
+                Instr::I64Store16(offset) => store!(offset, u64, |v| v as u16),
+                Instr::I64Store32(offset) => store!(offset, u64, |v| v as u32),
+                Instr::MemorySize => {
+                    push(stack, vm.memory().unwrap().size());
+                }
+                Instr::MemoryGrow => {
+                    let n = pop::<u32>(stack);
+                    let mem = vm.memory_slot().as_mut().unwrap();
+                    match mem.grow(n) {
+                        Some(old_size) => {
+                            push(stack, old_size);
+                            env.memory_grown(old_size, mem.size());
+                        }
+                        None => push(stack, u32::MAX),
+                    }
+                }
+                Instr::MemoryCopy => {
+                    let n = pop::<u32>(stack) as usize;
+                    let s = pop::<u32>(stack) as usize;
+                    let d = pop::<u32>(stack) as usize;
+                    trap!(vm.memory_slot().as_mut().unwrap().copy_within(d, s, n));
+                }
+                Instr::MemoryFill => {
+                    let n = pop::<u32>(stack) as usize;
+                    let value = pop::<u32>(stack);
+                    let dest = pop::<u32>(stack) as usize;
+                    let mem = vm.memory_slot().as_mut().unwrap();
+                    trap!(mem.range_mut(dest, n)).fill(value as u8);
+                }
+                Instr::MemoryInit(data_id) => {
+                    let size = pop::<u32>(stack) as usize;
+                    let source = pop::<u32>(stack) as usize;
+                    let dest = pop::<u32>(stack) as usize;
+                    let src = trap!(
+                        data[data_id as usize]
+                            .get(source..source + size)
+                            .ok_or(RuntimeError::MemoryAddressOutOfScope)
+                    );
+                    let mem = vm.memory_slot().as_mut().unwrap();
+                    trap!(mem.range_mut(dest, size)).copy_from_slice(src);
+                }
+                Instr::TableGet(table) => {
+                    let i = pop::<u32>(stack);
+                    push(stack, trap!(vm.tables_mut()[table as usize].get(i)));
+                }
+                Instr::TableSet(table) => {
+                    let value = pop::<Ref>(stack);
+                    let i = pop::<u32>(stack);
+                    trap!(vm.tables_mut()[table as usize].set(i, value));
+                }
+                Instr::TableSize(table) => push(stack, vm.tables_mut()[table as usize].size()),
+                Instr::TableGrow(table) => {
+                    let n = pop::<u32>(stack);
+                    let init = pop::<Ref>(stack);
+                    //NOTE: A failed grow pushes -1
+                    let res = vm.tables_mut()[table as usize].grow(n, init);
+                    push(stack, res.unwrap_or(u32::MAX));
+                }
+                Instr::TableFill(table) => {
+                    let n = pop::<u32>(stack);
+                    let value = pop::<Ref>(stack);
+                    let i = pop::<u32>(stack);
+                    trap!(vm.tables_mut()[table as usize].fill(i, value, n));
+                }
+                Instr::TableCopy { dst, src } => {
+                    let n = pop::<u32>(stack);
+                    let s = pop::<u32>(stack);
+                    let d = pop::<u32>(stack);
+                    trap!(vm.copy_table(dst as usize, src as usize, d, s, n));
+                }
+                Instr::TableInit { elem_id, table } => {
+                    let n = pop::<u32>(stack) as usize;
+                    let s = pop::<u32>(stack) as usize;
+                    let d = pop::<u32>(stack);
+                    trap!(vm.init_table(elem_id as usize, table as usize, d, s, n));
+                }
+                Instr::ElemDrop(elem_id) => vm.elements_mut()[elem_id as usize] = Vec::new(),
+                Instr::RefIsNull => unop(stack, |r: Ref| r.is_none()),
+                Instr::I32Eqz => unop(stack, |val: u32| val == 0),
+                Instr::I32Eq => binop(stack, |a: u32, b: u32| a == b),
+                Instr::I32Ne => binop(stack, |a: u32, b: u32| a != b),
+                Instr::I32Lts => binop(stack, |a: i32, b: i32| a < b),
+                Instr::I32Ltu => binop(stack, |a: u32, b: u32| a < b),
+                Instr::I32Gts => binop(stack, |a: i32, b: i32| a > b),
+                Instr::I32Gtu => binop(stack, |a: u32, b: u32| a > b),
+                Instr::I32Leu => binop(stack, |a: u32, b: u32| a <= b),
+                Instr::I32Les => binop(stack, |a: i32, b: i32| a <= b),
+                Instr::I32Geu => binop(stack, |a: u32, b: u32| a >= b),
+                Instr::I32Ges => binop(stack, |a: i32, b: i32| a >= b),
+                Instr::I64Eqz => unop(stack, |val: u64| val == 0),
+                Instr::I64Eq => binop(stack, |a: u64, b: u64| a == b),
+                Instr::I64Ne => binop(stack, |a: u64, b: u64| a != b),
+                Instr::I64Lts => binop(stack, |a: i64, b: i64| a < b),
+                Instr::I64Ltu => binop(stack, |a: u64, b: u64| a < b),
+                Instr::I64Gts => binop(stack, |a: i64, b: i64| a > b),
+                Instr::I64Gtu => binop(stack, |a: u64, b: u64| a > b),
+                Instr::I64Les => binop(stack, |a: i64, b: i64| a <= b),
+                Instr::I64Leu => binop(stack, |a: u64, b: u64| a <= b),
+                Instr::I64Geu => binop(stack, |a: u64, b: u64| a >= b),
+                Instr::I64Ges => binop(stack, |a: i64, b: i64| a >= b),
+                Instr::F32Eq => binop(stack, |a: f32, b: f32| a == b),
+                Instr::F32Ne => binop(stack, |a: f32, b: f32| a != b),
+                Instr::F32Lt => binop(stack, |a: f32, b: f32| a < b),
+                Instr::F32Gt => binop(stack, |a: f32, b: f32| a > b),
+                Instr::F32Le => binop(stack, |a: f32, b: f32| a <= b),
+                Instr::F32Ge => binop(stack, |a: f32, b: f32| a >= b),
+                Instr::F64Eq => binop(stack, |a: f64, b: f64| a == b),
+                Instr::F64Ne => binop(stack, |a: f64, b: f64| a != b),
+                Instr::F64Lt => binop(stack, |a: f64, b: f64| a < b),
+                Instr::F64Gt => binop(stack, |a: f64, b: f64| a > b),
+                Instr::F64Le => binop(stack, |a: f64, b: f64| a <= b),
+                Instr::F64Ge => binop(stack, |a: f64, b: f64| a >= b),
+                Instr::I32Clz => unop(stack, |a: u32| a.leading_zeros()),
+                Instr::I32Ctz => unop(stack, |a: u32| a.trailing_zeros()),
+                Instr::I32Popcnt => unop(stack, |a: u32| a.count_ones()),
+                Instr::I64Clz => unop(stack, |a: u64| a.leading_zeros() as u64),
+                Instr::I64Ctz => unop(stack, |a: u64| a.trailing_zeros() as u64),
+                Instr::I64Popcnt => unop(stack, |a: u64| a.count_ones() as u64),
+                Instr::I32Add => binop(stack, |a: u32, b: u32| a.wrapping_add(b)),
+                Instr::I32Sub => binop(stack, |a: u32, b: u32| a.wrapping_sub(b)),
+                Instr::I32Mul => binop(stack, |a: u32, b: u32| a.wrapping_mul(b)),
+                Instr::I32Divs => trap!(try_binop(stack, int::i32_div_s)),
+                Instr::I32Divu => trap!(try_binop(stack, int::i32_div_u)),
+                Instr::I32Rems => trap!(try_binop(stack, int::i32_rem_s)),
+                Instr::I32Remu => trap!(try_binop(stack, int::i32_rem_u)),
+                Instr::I32And => binop(stack, |a: u32, b: u32| a & b),
+                Instr::I32Or => binop(stack, |a: u32, b: u32| a | b),
+                Instr::I32Xor => binop(stack, |a: u32, b: u32| a ^ b),
+                Instr::I32Shl => binop(stack, |a: u32, b: u32| a.wrapping_shl(b)),
+                Instr::I32Shrs => binop(stack, |a: i32, b: i32| a.wrapping_shr(b as u32)),
+                Instr::I32Shru => binop(stack, |a: u32, b: u32| a.wrapping_shr(b)),
+                Instr::I32Rotl => binop(stack, |a: u32, b: u32| a.rotate_left(b % 32)),
+                Instr::I32Rotr => binop(stack, |a: u32, b: u32| a.rotate_right(b % 32)),
+                Instr::I64Add => binop(stack, |a: u64, b: u64| a.wrapping_add(b)),
+                Instr::I64Sub => binop(stack, |a: u64, b: u64| a.wrapping_sub(b)),
+                Instr::I64Mul => binop(stack, |a: u64, b: u64| a.wrapping_mul(b)),
+                Instr::I64Divs => trap!(try_binop(stack, int::i64_div_s)),
+                Instr::I64Divu => trap!(try_binop(stack, int::i64_div_u)),
+                Instr::I64Rems => trap!(try_binop(stack, int::i64_rem_s)),
+                Instr::I64Remu => trap!(try_binop(stack, int::i64_rem_u)),
+                Instr::I64And => binop(stack, |a: u64, b: u64| a & b),
+                Instr::I64Or => binop(stack, |a: u64, b: u64| a | b),
+                Instr::I64Xor => binop(stack, |a: u64, b: u64| a ^ b),
+                Instr::I64Shl => binop(stack, |a: u64, b: u64| a.wrapping_shl(b as u32)),
+                Instr::I64Shrs => binop(stack, |a: i64, b: i64| a.wrapping_shr(b as u32)),
+                Instr::I64Shru => binop(stack, |a: u64, b: u64| a.wrapping_shr(b as u32)),
+                Instr::I64Rotl => binop(stack, |a: u64, b: u64| a.rotate_left((b % 64) as u32)),
+                Instr::I64Rotr => binop(stack, |a: u64, b: u64| a.rotate_right((b % 64) as u32)),
+                Instr::F32Abs => unop(stack, |a: f32| a.abs()),
+                Instr::F32Neg => unop(stack, |a: f32| -a),
+                Instr::F32Ceil => unop(stack, |a: f32| a.ceil()),
+                Instr::F32Floor => unop(stack, |a: f32| a.floor()),
+                Instr::F32Trunc => unop(stack, |a: f32| a.trunc()),
+                Instr::F32Nearest => unop(stack, |a: f32| a.round_ties_even()),
+                Instr::F32Sqrt => unop(stack, |a: f32| a.sqrt()),
+                Instr::F32Add => binop(stack, |a: f32, b: f32| a + b),
+                Instr::F32Sub => binop(stack, |a: f32, b: f32| a - b),
+                Instr::F32Mul => binop(stack, |a: f32, b: f32| a * b),
+                Instr::F32Div => binop(stack, |a: f32, b: f32| a / b),
+                Instr::F32Min => binop(stack, float::f32_min),
+                Instr::F32Max => binop(stack, float::f32_max),
+                Instr::F32Copysign => binop(stack, |a: f32, b: f32| a.copysign(b)),
+                Instr::F64Abs => unop(stack, |a: f64| a.abs()),
+                Instr::F64Neg => unop(stack, |a: f64| -a),
+                Instr::F64Ceil => unop(stack, |a: f64| a.ceil()),
+                Instr::F64Floor => unop(stack, |a: f64| a.floor()),
+                Instr::F64Trunc => unop(stack, |a: f64| a.trunc()),
+                Instr::F64Nearest => unop(stack, |a: f64| a.round_ties_even()),
+                Instr::F64Sqrt => unop(stack, |a: f64| a.sqrt()),
+                Instr::F64Add => binop(stack, |a: f64, b: f64| a + b),
+                Instr::F64Sub => binop(stack, |a: f64, b: f64| a - b),
+                Instr::F64Mul => binop(stack, |a: f64, b: f64| a * b),
+                Instr::F64Div => binop(stack, |a: f64, b: f64| a / b),
+                Instr::F64Min => binop(stack, float::f64_min),
+                Instr::F64Max => binop(stack, float::f64_max),
+                Instr::F64Copysign => binop(stack, |a: f64, b: f64| a.copysign(b)),
+                Instr::I32WrapI64 => unop(stack, |a: u64| a as u32),
+                Instr::I32TruncF32s => trap!(try_unop(stack, |a: f32| float::trunc_i32(a as f64))),
+                Instr::I32TruncF32u => trap!(try_unop(stack, |a: f32| float::trunc_u32(a as f64))),
+                Instr::I32TruncF64s => trap!(try_unop(stack, float::trunc_i32)),
+                Instr::I32TruncF64u => trap!(try_unop(stack, float::trunc_u32)),
+                Instr::I64ExtendI32s => unop(stack, |a: i32| a as i64),
+                Instr::I64ExtendI32u => unop(stack, |a: u32| a as u64),
+                Instr::I64TruncF32s => trap!(try_unop(stack, |a: f32| float::trunc_i64(a as f64))),
+                Instr::I64TruncF32u => trap!(try_unop(stack, |a: f32| float::trunc_u64(a as f64))),
+                Instr::I64TruncF64s => trap!(try_unop(stack, float::trunc_i64)),
+                Instr::I64TruncF64u => trap!(try_unop(stack, float::trunc_u64)),
+                Instr::F32ConvertI32s => unop(stack, |a: i32| a as f32),
+                Instr::F32ConvertI32u => unop(stack, |a: u32| a as f32),
+                Instr::F32ConvertI64s => unop(stack, |a: i64| a as f32),
+                Instr::F32ConvertI64u => unop(stack, |a: u64| a as f32),
+                Instr::F32DemoteF64 => unop(stack, |a: f64| a as f32),
+                Instr::F64ConvertI32s => unop(stack, |a: i32| a as f64),
+                Instr::F64ConvertI32u => unop(stack, |a: u32| a as f64),
+                Instr::F64ConvertI64s => unop(stack, |a: i64| a as f64),
+                Instr::F64ConvertI64u => unop(stack, |a: u64| a as f64),
+                Instr::F64PromoteF32 => unop(stack, |a: f32| a as f64),
+                Instr::I32TruncSatF32s => unop(stack, |a: f32| a as i32),
+                Instr::I32TruncSatF32u => unop(stack, |a: f32| a as u32),
+                Instr::I32TruncSatF64s => unop(stack, |a: f64| a as i32),
+                Instr::I32TruncSatF64u => unop(stack, |a: f64| a as u32),
+                Instr::I64TruncSatF32s => unop(stack, |a: f32| a as i64),
+                Instr::I64TruncSatF32u => unop(stack, |a: f32| a as u64),
+                Instr::I64TruncSatF64s => unop(stack, |a: f64| a as i64),
+                Instr::I64TruncSatF64u => unop(stack, |a: f64| a as u64),
+            }
+        }
+    }
+}
+
+/// Moves the kept values of a branch down to its label and returns the target
+#[inline(always)]
+fn jump(stack: &mut Vec<u64>, fp: usize, branch: Branch) -> usize {
+    let base = fp + branch.height as usize;
+    let keep = branch.keep as usize;
+    let len = stack.len();
+    stack.copy_within(len - keep..len, base);
+    stack.truncate(base + keep);
+    branch.target as usize
+}
+
+fn call_host<E: Env>(
+    vm: &mut Vm<E>,
+    env: &mut E,
+    t: &Type,
+    id: usize,
+    stack: &mut Vec<u64>,
+) -> Result<(), RuntimeError> {
+    let start = stack.len() - t.params.len();
+    let params: SmallVec<[LocalValue; 16]> = stack
+        .drain(start..)
+        .zip(&t.params)
+        .map(|(v, t)| LocalValue::init_from_type_and_val(*t, v.into()))
+        .collect();
+    let mut results: SmallVec<[LocalValue; 4]> = t
+        .results
+        .iter()
+        .map(|t| LocalValue::init_from_type(*t))
+        .collect();
+    env.call(vm, &params, &mut results, id)
+        .map_err(RuntimeError::NativeFuncCallError)?;
+    stack.extend(results.iter().map(|r| StackValue::from(*r).bits()));
+    Ok(())
+}
+
+/// The call stack at the call instruction before `pc`, innermost call first
+fn call_stack<E: Env>(
+    vm: &Vm<E>,
+    origins: &[u32],
+    frames: &[Frame],
+    func_id: usize,
+    pc: usize,
+) -> CallStack {
+    let callers = frames
+        .iter()
+        .rev()
+        .map(|f| vm.call_frame(f.func_id as usize, origins[f.pc as usize - 1] as usize));
+    let current = vm.call_frame(func_id, origins[pc - 1] as usize);
+    CallStack([current].into_iter().chain(callers).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::DebugEnv;
+
+    /// Runs a function on both tiers, errors are compared by their message
+    fn run_both(
+        src: &str,
+        func_id: usize,
+        params: &[LocalValue],
+    ) -> Result<Vec<LocalValue>, String> {
+        let res = read_and_validate_wat(src).unwrap();
+        let mut slow: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        slow.set_func(func_id, params.iter().copied()).unwrap();
+        let expected = slow
+            .run_func(&res.bytecode, &res.info, &mut DebugEnv {})
+            .map_err(|e| e.to_string());
+
+        let mut fast: FastVm<DebugEnv> = FastVm::init_from_validation_result(&res).unwrap();
+        let actual = fast
+            .call(func_id, params, &mut DebugEnv {})
+            .map_err(|e| e.to_string());
+        assert_eq!(actual, expected);
+        assert_eq!(fast.vm().globals(), slow.globals());
+        assert_eq!(
+            fast.vm().memory().map(|m| m.bytes()),
+            slow.memory().map(|m| m.bytes())
+        );
+        actual
+    }
+
+    #[test]
+    fn calls_and_recursion() {
+        let src = r#"
+            (module
+                (func $fib (param i64) (result i64)
+                    local.get 0
+                    i64.const 2
+                    i64.lt_u
+                    if (result i64)
+                        local.get 0
+                    else
+                        local.get 0
+                        i64.const 1
+                        i64.sub
+                        call $fib
+                        local.get 0
+                        i64.const 2
+                        i64.sub
+                        call $fib
+                        i64.add
+                    end
+                )
+                (func $swap (param i32 i32) (result i32 i32)
+                    local.get 1
+                    local.get 0
+                )
+                (func $early (param i32) (result i32)
+                    block
+                        local.get 0
+                        i32.eqz
+                        br_if 0
+                        i32.const 7
+                        return
+                    end
+                    i32.const 9
+                )
+            )
+        "#;
+        let res = run_both(src, 0, &[LocalValue::I64(20)]);
+        assert_eq!(res, Ok(vec![LocalValue::I64(6765)]));
+        let res = run_both(src, 1, &[LocalValue::I32(3), LocalValue::I32(8)]);
+        assert_eq!(res, Ok(vec![LocalValue::I32(8), LocalValue::I32(3)]));
+        assert_eq!(
+            run_both(src, 2, &[LocalValue::I32(0)]),
+            Ok(vec![LocalValue::I32(9)])
+        );
+        assert_eq!(
+            run_both(src, 2, &[LocalValue::I32(1)]),
+            Ok(vec![LocalValue::I32(7)])
+        );
+    }
+
+    #[test]
+    fn blocks_and_branches() {
+        let src = r#"
+            (module
+                (type $pair (func (param i32) (result i32 i32)))
+                (func $sum (param i32) (result i32)
+                    (local i32)
+                    loop $l
+                        local.get 1
+                        local.get 0
+                        i32.add
+                        local.set 1
+                        local.get 0
+                        i32.const -1
+                        i32.add
+                        local.tee 0
+                        br_if $l
+                    end
+                    local.get 1
+                )
+                (func $switch (param i32) (result i32)
+                    block $c
+                        block $b
+                            block $a
+                                local.get 0
+                                br_table $a $b $c
+                            end
+                            i32.const 10
+                            return
+                        end
+                        i32.const 20
+                        return
+                    end
+                    i32.const 30
+                )
+                (func $carry (param i32) (result i32)
+                    i32.const 1
+                    local.get 0
+                    block $b (type $pair)
+                        i32.const 100
+                        local.get 0
+                        br_if $b
+                        drop
+                        i32.const 5
+                    end
+                    i32.add
+                    i32.add
+                )
+                (func $dead (result i32)
+                    block (result i32)
+                        i32.const 3
+                        br 0
+                        block
+                            unreachable
+                        end
+                        i32.const 4
+                    end
+                )
+            )
+        "#;
+        assert_eq!(
+            run_both(src, 0, &[LocalValue::I32(100)]),
+            Ok(vec![LocalValue::I32(5050)])
+        );
+        for (i, expected) in [10, 20, 30, 30].into_iter().enumerate() {
+            let res = run_both(src, 1, &[LocalValue::I32(i as u32)]);
+            assert_eq!(res, Ok(vec![LocalValue::I32(expected)]));
+        }
+        assert_eq!(
+            run_both(src, 2, &[LocalValue::I32(0)]),
+            Ok(vec![LocalValue::I32(6)])
+        );
+        assert_eq!(
+            run_both(src, 2, &[LocalValue::I32(4)]),
+            Ok(vec![LocalValue::I32(105)])
+        );
+        assert_eq!(run_both(src, 3, &[]), Ok(vec![LocalValue::I32(3)]));
+    }
+
+    #[test]
+    fn memory_globals_and_host_calls() {
+        let src = r#"
+            (module
+                (import "env" "dbg_print_string" (func $print (param i32 i32)))
+                (memory 1 4)
+                (global $count (mut i32) (i32.const 0))
+                (data "hello\n")
+                (data $tail "tail")
+                (func $main (result i32)
+                    (local i32)
+                    i32.const 0
+                    i32.const 6
+                    call $print
+                    loop $l
+                        local.get 0
+                        i32.const 64
+                        i32.add
+                        local.get 0
+                        i32.const 3
+                        i32.mul
+                        i32.store8 offset=16
+                        local.get 0
+                        i32.const 1
+                        i32.add
+                        local.set 0
+                        local.get 0
+                        i32.const 32
+                        i32.lt_u
+                        br_if $l
+                    end
+                    i32.const 200
+                    i32.const 0
+                    i32.const 4
+                    memory.init $tail
+                    i32.const 300
+                    i32.const 80
+                    i32.const 40
+                    memory.copy
+                    i32.const 1
+                    memory.grow
+                    global.set $count
+                    i32.const 80
+                    i64.load32_s offset=4
+                    i32.wrap_i64
+                    memory.size
+                    i32.add
+                )
+            )
+        "#;
+        let res = run_both(src, 1, &[]);
+        assert_eq!(res, Ok(vec![LocalValue::I32(0x1512_0f0c + 2)]));
+    }
+
+    #[test]
+    fn traps_match_the_slow_vm() {
+        let src = r#"
+            (module
+                (import "env" "dbg_fail" (func $fail (param i32)))
+                (memory 1)
+                (table 2 funcref)
+                (elem (i32.const 0) $div)
+                (type $unary (func (param i32) (result i32)))
+                (func $div (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.div_s
+                )
+                (func $load (param i32) (result i64)
+                    local.get 0
+                    i64.load offset=8
+                )
+                (func $indirect (param i32) (result i32)
+                    i32.const 1
+                    local.get 0
+                    call_indirect (type $unary)
+                )
+                (func $host
+                    i32.const 42
+                    call $fail
+                )
+                (func $recurse
+                    call $recurse
+                )
+            )
+        "#;
+        let int = |v: i32| LocalValue::I32(v as u32);
+        assert_eq!(run_both(src, 1, &[int(7), int(-2)]), Ok(vec![int(-3)]));
+        let err = run_both(src, 1, &[int(1), int(0)]).unwrap_err();
+        assert!(err.contains("divide by zero"));
+        assert!(run_both(src, 1, &[int(i32::MIN), int(-1)]).is_err());
+        assert!(run_both(src, 2, &[int(65530)]).is_err());
+        let err = run_both(src, 3, &[int(0)]).unwrap_err();
+        assert!(err.contains("type mismatch"));
+        assert!(run_both(src, 3, &[int(1)]).is_err());
+        assert!(run_both(src, 3, &[int(2)]).is_err());
+        assert!(run_both(src, 4, &[]).unwrap_err().contains("42"));
+        assert!(
+            run_both(src, 5, &[])
+                .unwrap_err()
+                .contains("Stack overflow")
+        );
+    }
+
+    #[test]
+    fn fuel_and_unknown_functions() {
+        let src = r#"
+            (module
+                (func $spin
+                    loop $l
+                        br $l
+                    end
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut fast: FastVm<DebugEnv> = FastVm::init_from_validation_result(&res).unwrap();
+        fast.vm_mut().set_fuel(Some(100));
+        let err = fast.call(0, &[], &mut DebugEnv {}).unwrap_err();
+        assert!(matches!(err, RuntimeError::OutOfFuel));
+        assert_eq!(fast.vm().remaining_fuel(), Some(0));
+
+        let err = fast.call(1, &[], &mut DebugEnv {}).unwrap_err();
+        assert!(matches!(err, RuntimeError::UnknownFunction(1)));
+    }
+}
@@ -0,0 +1,93 @@
+use crate::slow_vm::RuntimeError;
+
+//NOTE: Rusts min/max ignore NaN and do not order -0 below +0.
+//Wasm requires both: https://webassembly.github.io/spec/core/exec/numerics.html#op-fmin
+macro_rules! impl_wasm_min_max {
+    ($min_name: ident, $max_name: ident, $t: ty) => {
+        pub fn $min_name(a: $t, b: $t) -> $t {
+            if a.is_nan() || b.is_nan() {
+                <$t>::NAN
+            } else if a == b {
+                <$t>::from_bits(a.to_bits() | b.to_bits())
+            } else {
+                a.min(b)
+            }
+        }
+
+        pub fn $max_name(a: $t, b: $t) -> $t {
+            if a.is_nan() || b.is_nan() {
+                <$t>::NAN
+            } else if a == b {
+                <$t>::from_bits(a.to_bits() & b.to_bits())
+            } else {
+                a.max(b)
+            }
+        }
+    };
+}
+
+impl_wasm_min_max!(f32_min, f32_max, f32);
+impl_wasm_min_max!(f64_min, f64_max, f64);
+
+//NOTE: Every f32 is exactly representable as f64, so checking the bounds in f64 is enough.
+macro_rules! impl_trunc {
+    ($fn_name: ident, $t: ty, $lower_exclusive: expr, $upper_exclusive: expr) => {
+        pub fn $fn_name(val: f64) -> Result<$t, RuntimeError> {
+            if val.is_nan() {
+                return Err(RuntimeError::InvalidConversionToInteger);
+            }
+            let truncated = val.trunc();
+            if truncated <= $lower_exclusive || truncated >= $upper_exclusive {
+                Err(RuntimeError::IntegerOverflow)
+            } else {
+                Ok(truncated as $t)
+            }
+        }
+    };
+}
+
+impl_trunc!(trunc_i32, i32, -2147483649.0, 2147483648.0);
+impl_trunc!(trunc_u32, u32, -1.0, 4294967296.0);
+impl_trunc!(trunc_i64, i64, -9223372036854777856.0, 9223372036854775808.0);
+impl_trunc!(trunc_u64, u64, -1.0, 18446744073709551616.0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn min_max_signed_zero() {
+        assert!(f32_min(0.0, -0.0).is_sign_negative());
+        assert!(f32_min(-0.0, 0.0).is_sign_negative());
+        assert!(f64_max(0.0, -0.0).is_sign_positive());
+        assert!(f64_max(-0.0, 0.0).is_sign_positive());
+    }
+
+    #[test]
+    fn min_max_nan() {
+        assert!(f32_min(f32::NAN, 1.0).is_nan());
+        assert!(f32_max(1.0, f32::NAN).is_nan());
+        assert!(f64_min(f64::NAN, f64::NEG_INFINITY).is_nan());
+    }
+
+    #[test]
+    fn trunc_bounds() {
+        assert_eq!(trunc_i32(-2147483648.9).unwrap(), i32::MIN);
+        assert_eq!(trunc_i32(2147483647.9).unwrap(), i32::MAX);
+        assert!(matches!(
+            trunc_i32(2147483648.0),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+        assert_eq!(trunc_u32(-0.9).unwrap(), 0);
+        assert!(matches!(trunc_u32(-1.0), Err(RuntimeError::IntegerOverflow)));
+        assert_eq!(trunc_i64(-9223372036854775808.0).unwrap(), i64::MIN);
+        assert!(matches!(
+            trunc_i64(9223372036854775808.0),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+        assert!(matches!(
+            trunc_u64(f64::NAN),
+            Err(RuntimeError::InvalidConversionToInteger)
+        ));
+    }
+}
@@ -0,0 +1,357 @@
+use std::marker::PhantomData;
+
+use parser::reader::ValueType;
+use smallvec::SmallVec;
+use validator::validator::ValidateResult;
+
+use crate::{
+    env::{Env, Imports},
+    fast_vm::FastVm,
+    slow_vm::{ExecutionError, FunctionType, InstanceError, LocalValue, RuntimeError, Type, Vm},
+    stack::StackValue,
+};
+
+/// A Rust type that maps to a single wasm value type
+pub trait WasmType: Sized {
+    const TYPE: ValueType;
+    fn into_value(self) -> LocalValue;
+    fn from_value(value: LocalValue) -> Self;
+}
+
+macro_rules! impl_wasm_type {
+    ($($t: ty => $value_type: ident as $bits: ty),+ $(,)?) => {
+        $(impl WasmType for $t {
+            const TYPE: ValueType = ValueType::$value_type;
+            fn into_value(self) -> LocalValue {
+                self.into()
+            }
+            fn from_value(value: LocalValue) -> Self {
+                //NOTE: Going through the bits accepts both signed and unsigned integer values
+                bytemuck::cast(StackValue::from(value).bits() as $bits)
+            }
+        })+
+    };
+}
+
+impl_wasm_type! {
+    u32 => I32 as u32,
+    i32 => I32 as u32,
+    u64 => I64 as u64,
+    i64 => I64 as u64,
+    f32 => F32 as u32,
+    f64 => F64 as u64,
+}
+
+/// Params or results of a function: `()`, a single [`WasmType`] or a tuple of them
+pub trait WasmTypeList: Sized {
+    fn types() -> Vec<ValueType>;
+    fn into_values(self) -> SmallVec<[LocalValue; 8]>;
+    /// `values` has to match [`Self::types`]
+    fn from_values(values: &[LocalValue]) -> Self;
+}
+
+impl<T: WasmType> WasmTypeList for T {
+    fn types() -> Vec<ValueType> {
+        vec![T::TYPE]
+    }
+    fn into_values(self) -> SmallVec<[LocalValue; 8]> {
+        [self.into_value()].into_iter().collect()
+    }
+    fn from_values(values: &[LocalValue]) -> Self {
+        T::from_value(values[0])
+    }
+}
+
+macro_rules! impl_wasm_type_list {
+    ($($t: ident),*) => {
+        impl<$($t: WasmType),*> WasmTypeList for ($($t,)*) {
+            fn types() -> Vec<ValueType> {
+                vec![$($t::TYPE),*]
+            }
+            #[allow(non_snake_case)]
+            fn into_values(self) -> SmallVec<[LocalValue; 8]> {
+                let ($($t,)*) = self;
+                [$($t.into_value()),*].into_iter().collect()
+            }
+            #[allow(unused_variables, unused_mut, clippy::unused_unit)]
+            fn from_values(values: &[LocalValue]) -> Self {
+                let mut values = values.iter();
+                ($($t::from_value(*values.next().unwrap()),)*)
+            }
+        }
+    };
+}
+
+impl_wasm_type_list!();
+impl_wasm_type_list!(A);
+impl_wasm_type_list!(A, B);
+impl_wasm_type_list!(A, B, C);
+impl_wasm_type_list!(A, B, C, D);
+impl_wasm_type_list!(A, B, C, D, E);
+impl_wasm_type_list!(A, B, C, D, E, F);
+impl_wasm_type_list!(A, B, C, D, E, F, G);
+impl_wasm_type_list!(A, B, C, D, E, F, G, H);
+
+/// An instantiated module, its exported functions can be called by name
+#[derive(Debug)]
+pub struct Instance<E: Env> {
+    module: ValidateResult,
+    vm: Vm<E>,
+}
+
+impl<E: Env> Instance<E> {
+    /// Instantiates the module and runs its start function
+    pub fn new(module: ValidateResult, env: &mut E) -> Result<Self, ExecutionError> {
+        let vm = Vm::init_from_validation_result(&module)?;
+        Self::from_vm(module, vm, env)
+    }
+
+    /// Runs the start function on a vm initialized from `module`
+    pub(crate) fn from_vm(
+        module: ValidateResult,
+        vm: Vm<E>,
+        env: &mut E,
+    ) -> Result<Self, ExecutionError> {
+        let mut instance = Self { module, vm };
+        instance.run_start(env)?;
+        Ok(instance)
+    }
+
+    /// An instance whose start function still has to be run with [`Self::run_start`]
+    pub(crate) fn unstarted(module: ValidateResult, vm: Vm<E>) -> Self {
+        Self { module, vm }
+    }
+
+    pub(crate) fn run_start(&mut self, env: &mut E) -> Result<(), ExecutionError> {
+        if self.module.bytecode.start.is_some() {
+            self.vm.enter_start_function(env)?;
+            let result = self.vm.run(&self.module.bytecode, env);
+            self.vm.reset_state();
+            result?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the code of the instance with the one of `module`, see [`Vm::reload_code`]
+    pub(crate) fn reload_code(
+        &mut self,
+        module: ValidateResult,
+        imports: Imports,
+    ) -> Result<(), InstanceError> {
+        self.vm
+            .reload_code_with_imports(&module.bytecode, &module.info, imports)?;
+        self.module = module;
+        Ok(())
+    }
+
+    pub fn module(&self) -> &ValidateResult {
+        &self.module
+    }
+
+    pub fn vm(&self) -> &Vm<E> {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm<E> {
+        &mut self.vm
+    }
+
+    pub fn func_id(&self, name: &str) -> Option<usize> {
+        self.module
+            .bytecode
+            .get_exports_as_map()?
+            .get_function_id(name)
+    }
+
+    pub fn func_type(&self, func_id: usize) -> Option<&Type> {
+        self.vm.functions().get(func_id).map(|f| &f.t)
+    }
+
+    /// Looks up an exported function and checks its signature against `P` and `R`
+    pub fn get_typed_func<P: WasmTypeList, R: WasmTypeList>(
+        &mut self,
+        name: &str,
+    ) -> Result<TypedFunc<'_, E, P, R>, RuntimeError> {
+        let func_id = self
+            .func_id(name)
+            .ok_or_else(|| RuntimeError::UnknownExportedFunc(name.to_string()))?;
+        let expected = Type {
+            params: P::types(),
+            results: R::types(),
+        };
+        let got = self.func_type(func_id).unwrap();
+        if *got != expected {
+            return Err(RuntimeError::ExportedFuncTypeMismatch {
+                name: name.to_string(),
+                expected,
+                got: got.clone(),
+            });
+        }
+        Ok(TypedFunc {
+            instance: self,
+            func_id,
+            signature: PhantomData,
+        })
+    }
+
+    /// Calls a function like [`Self::call`] on the fast tier, the code is lowered for every call
+    pub(crate) fn call_fast(
+        self,
+        func_id: usize,
+        params: &[LocalValue],
+        env: &mut E,
+    ) -> (Self, Result<Vec<LocalValue>, RuntimeError>) {
+        let Self { module, vm } = self;
+        let mut fast = FastVm::new(vm, &module.bytecode);
+        let result = fast.call(func_id, params, env);
+        let vm = fast.into_vm();
+        (Self { module, vm }, result)
+    }
+
+    /// Calls a function with untyped values, the params have to match its type
+    pub fn call(
+        &mut self,
+        func_id: usize,
+        params: &[LocalValue],
+        env: &mut E,
+    ) -> Result<Vec<LocalValue>, RuntimeError> {
+        let function = &self.vm.functions()[func_id];
+        if let FunctionType::Native(native) = &function.kind {
+            //NOTE: A re-exported import is called on the env directly
+            let (id, results) = (native.id, function.t.results.iter());
+            let mut results = results
+                .map(|t| LocalValue::init_from_type(*t))
+                .collect::<Vec<_>>();
+            env.call(&mut self.vm, params, &mut results, id)
+                .map_err(RuntimeError::NativeFuncCallError)?;
+            return Ok(results);
+        }
+        //NOTE: A previous call may have trapped and left its state behind
+        self.vm.reset_state();
+        self.vm.set_func(func_id, params.iter().copied())?;
+        self.vm
+            .run_func(&self.module.bytecode, &self.module.info, env)
+    }
+}
+
+/// An exported function whose signature was checked against `P` and `R`,
+/// see [`Instance::get_typed_func`]
+#[derive(Debug)]
+pub struct TypedFunc<'a, E: Env, P, R> {
+    instance: &'a mut Instance<E>,
+    func_id: usize,
+    signature: PhantomData<fn(P) -> R>,
+}
+
+impl<E: Env, P: WasmTypeList, R: WasmTypeList> TypedFunc<'_, E, P, R> {
+    pub fn func_id(&self) -> usize {
+        self.func_id
+    }
+
+    pub fn call(&mut self, env: &mut E, params: P) -> Result<R, RuntimeError> {
+        let params = params.into_values();
+        let results = self.instance.call(self.func_id, &params, env)?;
+        Ok(R::from_values(&results))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::DebugEnv;
+
+    const SRC: &str = r#"
+        (module
+            (import "env" "dbg_fail" (func $fail (param i32)))
+            (global $calls (mut i32) (i32.const 0))
+            (func $count
+                global.get $calls
+                i32.const 1
+                i32.add
+                global.set $calls
+            )
+            (func (export "add") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                i32.add
+            )
+            (func (export "divmod") (param i32 i32) (result i32 i32)
+                local.get 0
+                local.get 1
+                i32.div_u
+                local.get 0
+                local.get 1
+                i32.rem_u
+            )
+            (func (export "scale") (param f64 i64) (result f64)
+                local.get 0
+                local.get 1
+                f64.convert_i64_s
+                f64.mul
+            )
+            (export "count" (func $count))
+            (export "fail" (func $fail))
+            (start $count)
+        )
+    "#;
+
+    fn instance() -> Instance<DebugEnv> {
+        let module = read_and_validate_wat(SRC).unwrap();
+        Instance::new(module, &mut DebugEnv {}).unwrap()
+    }
+
+    #[test]
+    fn typed_calls() {
+        let mut instance = instance();
+        let env = &mut DebugEnv {};
+        let mut sum = instance.get_typed_func::<(i32, i32), i32>("add").unwrap();
+        assert_eq!(sum.call(env, (-5, 2)).unwrap(), -3);
+        let mut divmod = instance
+            .get_typed_func::<(u32, u32), (u32, u32)>("divmod")
+            .unwrap();
+        assert_eq!(divmod.call(env, (17, 5)).unwrap(), (3, 2));
+        let mut scale = instance.get_typed_func::<(f64, i64), f64>("scale").unwrap();
+        assert_eq!(scale.call(env, (1.5, -4)).unwrap(), -6.0);
+
+        //NOTE: The start function already counted once
+        let mut count = instance.get_typed_func::<(), ()>("count").unwrap();
+        count.call(env, ()).unwrap();
+        assert_eq!(instance.vm().globals(), &[LocalValue::I32(2)]);
+    }
+
+    #[test]
+    fn signature_mismatch_and_traps() {
+        let mut instance = instance();
+        let env = &mut DebugEnv {};
+        let err = instance
+            .get_typed_func::<(i32, i64), i32>("add")
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Exported function add has type (i32, i32) -> (i32), expected (i32, i64) -> (i32)"
+        );
+        assert!(matches!(
+            instance.get_typed_func::<(), ()>("missing"),
+            Err(RuntimeError::UnknownExportedFunc(_))
+        ));
+
+        let mut divmod = instance
+            .get_typed_func::<(u32, u32), (u32, u32)>("divmod")
+            .unwrap();
+        let err = divmod.call(env, (1, 0)).unwrap_err();
+        assert!(matches!(
+            err.trap_cause(),
+            RuntimeError::IntegerDivideByZero
+        ));
+        assert_eq!(divmod.call(env, (9, 2)).unwrap(), (4, 1));
+
+        let mut fail = instance.get_typed_func::<i32, ()>("fail").unwrap();
+        assert!(matches!(
+            fail.call(env, 7),
+            Err(RuntimeError::NativeFuncCallError(7))
+        ));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::slow_vm::RuntimeError;
+
+//NOTE: Wasm traps on division by zero and on the one signed division that overflows (MIN / -1).
+//The signed remainder of MIN % -1 is defined as 0.
+//https://webassembly.github.io/spec/core/exec/numerics.html#op-idiv-s
+macro_rules! impl_int_div {
+    ($div_s: ident, $div_u: ident, $rem_s: ident, $rem_u: ident, $s: ty, $u: ty) => {
+        pub fn $div_s(a: $s, b: $s) -> Result<$s, RuntimeError> {
+            if b == 0 {
+                Err(RuntimeError::IntegerDivideByZero)
+            } else {
+                a.checked_div(b).ok_or(RuntimeError::IntegerOverflow)
+            }
+        }
+
+        pub fn $div_u(a: $u, b: $u) -> Result<$u, RuntimeError> {
+            a.checked_div(b).ok_or(RuntimeError::IntegerDivideByZero)
+        }
+
+        pub fn $rem_s(a: $s, b: $s) -> Result<$s, RuntimeError> {
+            if b == 0 {
+                Err(RuntimeError::IntegerDivideByZero)
+            } else {
+                Ok(a.wrapping_rem(b))
+            }
+        }
+
+        pub fn $rem_u(a: $u, b: $u) -> Result<$u, RuntimeError> {
+            a.checked_rem(b).ok_or(RuntimeError::IntegerDivideByZero)
+        }
+    };
+}
+
+impl_int_div!(i32_div_s, i32_div_u, i32_rem_s, i32_rem_u, i32, u32);
+impl_int_div!(i64_div_s, i64_div_u, i64_rem_s, i64_rem_u, i64, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn div_by_zero() {
+        assert!(matches!(
+            i32_div_s(1, 0),
+            Err(RuntimeError::IntegerDivideByZero)
+        ));
+        assert!(matches!(
+            i64_div_u(1, 0),
+            Err(RuntimeError::IntegerDivideByZero)
+        ));
+        assert!(matches!(
+            i32_rem_s(i32::MIN, 0),
+            Err(RuntimeError::IntegerDivideByZero)
+        ));
+        assert!(matches!(
+            i64_rem_u(0, 0),
+            Err(RuntimeError::IntegerDivideByZero)
+        ));
+    }
+
+    #[test]
+    fn signed_overflow() {
+        assert!(matches!(
+            i32_div_s(i32::MIN, -1),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+        assert!(matches!(
+            i64_div_s(i64::MIN, -1),
+            Err(RuntimeError::IntegerOverflow)
+        ));
+        assert_eq!(i32_rem_s(i32::MIN, -1).unwrap(), 0);
+        assert_eq!(i64_rem_s(i64::MIN, -1).unwrap(), 0);
+    }
+
+    #[test]
+    fn rounds_towards_zero() {
+        assert_eq!(i32_div_s(-7, 2).unwrap(), -3);
+        assert_eq!(i32_rem_s(-7, 2).unwrap(), -1);
+        assert_eq!(i64_div_u(u64::MAX, 2).unwrap(), u64::MAX / 2);
+    }
+}
@@ -0,0 +1,27 @@
+//! Minimal JSON writing for the trace and coverage output
+
+use std::fmt::Write;
+
+pub fn json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => _ = write!(out, "\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+pub fn json_array<T: std::fmt::Display>(out: &mut String, values: &[T]) {
+    out.push('[');
+    for (i, v) in values.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        _ = write!(out, "{v}");
+    }
+    out.push(']');
+}
@@ -1,3 +1,23 @@
+#[cfg(feature = "coverage")]
+pub mod coverage;
+pub mod debugger;
+pub mod fast_vm;
 pub mod env;
+pub mod float;
+pub mod instance;
+pub mod int;
+#[cfg(any(feature = "trace", feature = "coverage"))]
+mod json;
+pub mod linker;
+pub mod memory;
+#[cfg(feature = "profile")]
+pub mod profile;
 pub mod slow_vm;
+pub mod snapshot;
 pub mod stack;
+pub mod store;
+pub mod table;
+#[cfg(feature = "time-travel")]
+pub mod time_travel;
+#[cfg(feature = "trace")]
+pub mod trace;
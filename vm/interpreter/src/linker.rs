@@ -0,0 +1,888 @@
+use std::{collections::HashMap, fmt::Display, rc::Rc};
+
+use parser::reader::{Bytecode, ImportDesc, Limits, TableType, ValueType, WithPosition};
+use thiserror::Error;
+use validator::validator::ValidateResult;
+
+use crate::{
+    env::{Env, ExternalFunction, ExternalGlobal, Imports},
+    instance::{Instance, WasmType, WasmTypeList},
+    memory::MemoryInstance,
+    slow_vm::{ExecutionError, LocalValue, Type, Vm},
+    store::{self, Modules},
+    table::TableInstance,
+};
+
+type HostCall<T> = dyn Fn(Caller<'_, T>, &[LocalValue], &mut [LocalValue]) -> Result<(), usize>;
+
+pub(crate) enum Callee<T> {
+    Host(Rc<HostCall<T>>),
+    /// A function exported by an instance of a [`crate::store::Store`]
+    Export {
+        instance: usize,
+        func_id: usize,
+    },
+}
+
+/// A host function registered with a [`Linker`]
+pub struct HostFunc<T> {
+    pub(crate) t: Type,
+    pub(crate) call: Callee<T>,
+}
+
+impl<T> Clone for HostFunc<T> {
+    fn clone(&self) -> Self {
+        let call = match &self.call {
+            Callee::Host(call) => Callee::Host(call.clone()),
+            Callee::Export { instance, func_id } => Callee::Export {
+                instance: *instance,
+                func_id: *func_id,
+            },
+        };
+        Self {
+            t: self.t.clone(),
+            call,
+        }
+    }
+}
+
+impl<T> HostFunc<T> {
+    pub fn func_type(&self) -> &Type {
+        &self.t
+    }
+}
+
+/// The state of a host function call: the host data and the vm that called it
+pub struct Caller<'a, T> {
+    data: &'a mut T,
+    vm: &'a mut Vm<Host<T>>,
+}
+
+impl<T> Caller<'_, T> {
+    pub fn data(&self) -> &T {
+        self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        self.data
+    }
+
+    pub fn vm(&self) -> &Vm<Host<T>> {
+        self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm<Host<T>> {
+        self.vm
+    }
+
+    pub fn memory(&self) -> Option<&MemoryInstance> {
+        self.vm.memory()
+    }
+
+    /// The memory of the calling instance, with time travel its contents are saved first so
+    /// the writes of the host function can be undone
+    pub fn memory_mut(&mut self) -> Option<&mut MemoryInstance> {
+        self.vm.save_whole_memory();
+        self.vm.memory_slot().as_mut()
+    }
+}
+
+/// The [`Env`] of modules instantiated by a [`Linker`], owns the host data `T`
+pub struct Host<T> {
+    data: T,
+    pub(crate) funcs: Vec<HostFunc<T>>,
+    pub(crate) modules: Modules<T>,
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Host<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Host")
+            .field("data", &self.data)
+            .field("funcs", &self.funcs.len())
+            .finish()
+    }
+}
+
+impl<T> Host<T> {
+    pub fn new(data: T) -> Self {
+        Self {
+            data,
+            funcs: vec![],
+            modules: Modules::default(),
+        }
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+}
+
+impl<T> Env for Host<T> {
+    //NOTE: Imports are resolved by the linker, not by name
+    fn get_func(_env: &str, _name: &str) -> Option<ExternalFunction> {
+        None
+    }
+
+    fn get_global(_env: &str, _name: &str) -> Option<ExternalGlobal> {
+        None
+    }
+
+    fn call(
+        &mut self,
+        vm: &mut Vm<Self>,
+        params: &[LocalValue],
+        results: &mut [LocalValue],
+        func_id: usize,
+    ) -> Result<(), usize> {
+        let call = match &self.funcs[func_id].call {
+            Callee::Host(call) => call.clone(),
+            Callee::Export { instance, func_id } => {
+                return store::call_export(self, vm, *instance, *func_id, params, results);
+            }
+        };
+        let caller = Caller {
+            data: &mut self.data,
+            vm,
+        };
+        call(caller, params, results)
+    }
+}
+
+/// The result of a typed host function, its values or an error code that traps
+pub trait HostResult {
+    type Results: WasmTypeList;
+    fn into_result(self) -> Result<Self::Results, usize>;
+}
+
+impl<R: WasmTypeList> HostResult for R {
+    type Results = R;
+    fn into_result(self) -> Result<R, usize> {
+        Ok(self)
+    }
+}
+
+impl<R: WasmTypeList> HostResult for Result<R, usize> {
+    type Results = R;
+    fn into_result(self) -> Result<R, usize> {
+        self
+    }
+}
+
+/// A closure taking a [`Caller`] and [`WasmType`] params, see [`Linker::func_wrap`]
+pub trait IntoHostFunc<T, P, R> {
+    fn into_host_func(self) -> HostFunc<T>;
+}
+
+macro_rules! impl_into_host_func {
+    ($($t: ident),*) => {
+        impl<T, Func, R, $($t: WasmType),*> IntoHostFunc<T, ($($t,)*), R> for Func
+        where
+            Func: Fn(Caller<'_, T>, $($t),*) -> R + 'static,
+            R: HostResult,
+        {
+            #[allow(non_snake_case, unused_variables, unused_mut)]
+            fn into_host_func(self) -> HostFunc<T> {
+                let t = Type {
+                    params: vec![$($t::TYPE),*],
+                    results: R::Results::types(),
+                };
+                let call = move |caller: Caller<'_, T>,
+                                 params: &[LocalValue],
+                                 results: &mut [LocalValue]| {
+                    let mut params = params.iter();
+                    $(let $t = $t::from_value(*params.next().unwrap());)*
+                    let values = self(caller, $($t),*).into_result()?.into_values();
+                    results.copy_from_slice(&values);
+                    Ok(())
+                };
+                HostFunc {
+                    t,
+                    call: Callee::Host(Rc::new(call)),
+                }
+            }
+        }
+    };
+}
+
+impl_into_host_func!();
+impl_into_host_func!(A);
+impl_into_host_func!(A, B);
+impl_into_host_func!(A, B, C);
+impl_into_host_func!(A, B, C, D);
+impl_into_host_func!(A, B, C, D, E);
+impl_into_host_func!(A, B, C, D, E, F);
+impl_into_host_func!(A, B, C, D, E, F, G);
+impl_into_host_func!(A, B, C, D, E, F, G, H);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportKind {
+    Function,
+    Global,
+    Memory,
+    Table,
+}
+
+impl ImportKind {
+    pub(crate) fn of(desc: &ImportDesc) -> Self {
+        match desc {
+            ImportDesc::TypeIdx(_) => Self::Function,
+            ImportDesc::GlobalType(_) => Self::Global,
+            ImportDesc::MemType(_) => Self::Memory,
+            ImportDesc::TableType(_) => Self::Table,
+        }
+    }
+}
+
+impl Display for ImportKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Function => "function",
+            Self::Global => "global",
+            Self::Memory => "memory",
+            Self::Table => "table",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum LinkError {
+    #[error("{module}.{name} is already defined")]
+    DuplicateDefinition { module: String, name: String },
+    #[error("Unknown import: {kind} {module}.{name} is not defined")]
+    UnknownImport {
+        module: String,
+        name: String,
+        kind: ImportKind,
+    },
+    #[error("Import {module}.{name} expects a {expected}, but a {got} is defined")]
+    IncompatibleImportKind {
+        module: String,
+        name: String,
+        expected: ImportKind,
+        got: ImportKind,
+    },
+    #[error("Imported function {module}.{name} has type {got}, expected {expected}")]
+    FuncTypeMismatch {
+        module: String,
+        name: String,
+        expected: Box<Type>,
+        got: Box<Type>,
+    },
+    #[error("Imported global {module}.{name} has type {got}, expected {expected}")]
+    GlobalTypeMismatch {
+        module: String,
+        name: String,
+        expected: ValueType,
+        got: ValueType,
+    },
+    #[error(
+        "Imported global {module}.{name} has the wrong mutability, expected mutable: {expected}"
+    )]
+    GlobalMutabilityMismatch {
+        module: String,
+        name: String,
+        expected: bool,
+    },
+    #[error("Imported memory {module}.{name} has limits {got}, expected {expected}")]
+    MemoryTypeMismatch {
+        module: String,
+        name: String,
+        expected: Box<Limits>,
+        got: Box<Limits>,
+    },
+    #[error("Imported table {module}.{name} has type {got}, expected {expected}")]
+    TableTypeMismatch {
+        module: String,
+        name: String,
+        expected: Box<TableType>,
+        got: Box<TableType>,
+    },
+    #[error("Multiple memories are not supported, {module}.{name} is a second memory import")]
+    MultipleMemories { module: String, name: String },
+    #[error("Import {0} is not part of the module")]
+    InvalidImportId(usize),
+    #[error("A module named {0} is already instantiated")]
+    DuplicateModule(String),
+    #[error("No module named {0} is instantiated")]
+    UnknownModule(String),
+    #[error(transparent)]
+    Instantiation(#[from] ExecutionError),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Definition {
+    Func(usize),
+    Global(ExternalGlobal),
+    Memory(MemoryInstance),
+    Table(TableInstance),
+}
+
+impl Definition {
+    pub(crate) fn kind(&self) -> ImportKind {
+        match self {
+            Self::Func(_) => ImportKind::Function,
+            Self::Global(_) => ImportKind::Global,
+            Self::Memory(_) => ImportKind::Memory,
+            Self::Table(_) => ImportKind::Table,
+        }
+    }
+}
+
+fn limits(min: u32, max: Option<u32>) -> Limits {
+    Limits {
+        min: WithPosition::new(min, 0..0),
+        max: max.map(|m| WithPosition::new(m, 0..0)),
+    }
+}
+
+/// An import with size `min` and maximum `max` can be used for one declaring `expected`
+fn limits_match(min: u32, max: Option<u32>, expected: &Limits) -> bool {
+    let max_matches = match (&expected.max, max) {
+        (None, _) => true,
+        (Some(expected), Some(max)) => max <= expected.data,
+        (Some(_), None) => false,
+    };
+    min >= expected.min.data && max_matches
+}
+
+/// Checks `definition` against the type of import `import_id` and adds it to `imports`,
+/// the types of functions are taken from `funcs`
+pub(crate) fn link<T>(
+    funcs: &[HostFunc<T>],
+    bytecode: &Bytecode,
+    import_id: usize,
+    definition: &Definition,
+    imports: &mut Imports,
+) -> Result<(), LinkError> {
+    let import = bytecode
+        .get_import(import_id)
+        .ok_or(LinkError::InvalidImportId(import_id))?;
+    let desc = &import.desc.data;
+    let (module, name) = (
+        import.get_mod_name().to_string(),
+        import.get_name().to_string(),
+    );
+    match (desc, definition) {
+        (ImportDesc::TypeIdx(type_id), Definition::Func(id)) => {
+            let expected = Type::from(bytecode.get_type(*type_id).unwrap());
+            let got = &funcs[*id].t;
+            if *got != expected {
+                return Err(LinkError::FuncTypeMismatch {
+                    module,
+                    name,
+                    expected: Box::new(expected),
+                    got: Box::new(got.clone()),
+                });
+            }
+            let func = ExternalFunction {
+                params: got.params.clone(),
+                result: got.results.clone(),
+                id: *id,
+            };
+            imports.functions.insert(import_id, func);
+        }
+        (ImportDesc::GlobalType(t), Definition::Global(global)) => {
+            let (expected, got) = (t.value_type(), global.value.get_value_type());
+            if expected != got {
+                return Err(LinkError::GlobalTypeMismatch {
+                    module,
+                    name,
+                    expected,
+                    got,
+                });
+            }
+            if t.is_mut() != global.mutable {
+                let expected = t.is_mut();
+                return Err(LinkError::GlobalMutabilityMismatch {
+                    module,
+                    name,
+                    expected,
+                });
+            }
+            imports.globals.insert(import_id, global.clone());
+        }
+        (ImportDesc::MemType(expected), Definition::Memory(memory)) => {
+            if !limits_match(memory.size(), memory.max(), expected) {
+                return Err(LinkError::MemoryTypeMismatch {
+                    module,
+                    name,
+                    expected: Box::new(expected.clone()),
+                    got: Box::new(limits(memory.size(), memory.max())),
+                });
+            }
+            if imports.memory.is_some() {
+                return Err(LinkError::MultipleMemories { module, name });
+            }
+            imports.memory = Some(memory.clone());
+        }
+        (ImportDesc::TableType(expected), Definition::Table(table)) => {
+            let (min, max) = (table.size(), table.max());
+            if table.value_type() != expected.value_type()
+                || !limits_match(min, max, &expected.limits.data)
+            {
+                let got = TableType {
+                    t: WithPosition::new(table.value_type(), 0..0),
+                    limits: WithPosition::new(limits(min, max), 0..0),
+                };
+                return Err(LinkError::TableTypeMismatch {
+                    module,
+                    name,
+                    expected: Box::new(expected.clone()),
+                    got: Box::new(got),
+                });
+            }
+            imports.tables.insert(import_id, table.clone());
+        }
+        (desc, definition) => {
+            return Err(LinkError::IncompatibleImportKind {
+                module,
+                name,
+                expected: ImportKind::of(desc),
+                got: definition.kind(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Defines host functions, globals, memories and tables by module and name and resolves the
+/// imports of modules against them when instantiating.
+///
+/// Registered globals, memories and tables are copied into each instance, changes made by one
+/// instance are not visible to the linker or to other instances.
+pub struct Linker<T> {
+    definitions: HashMap<(String, String), Definition>,
+    pub(crate) funcs: Vec<HostFunc<T>>,
+}
+
+impl<T> Default for Linker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for Linker<T> {
+    fn clone(&self) -> Self {
+        Self {
+            definitions: self.definitions.clone(),
+            funcs: self.funcs.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for Linker<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Linker")
+            .field("definitions", &self.definitions)
+            .field("funcs", &self.funcs.len())
+            .finish()
+    }
+}
+
+impl<T> Linker<T> {
+    pub fn new() -> Self {
+        Self {
+            definitions: HashMap::new(),
+            funcs: vec![],
+        }
+    }
+
+    fn define(
+        &mut self,
+        module: &str,
+        name: &str,
+        definition: Definition,
+    ) -> Result<&mut Self, LinkError> {
+        let key = (module.to_string(), name.to_string());
+        if self.definitions.contains_key(&key) {
+            return Err(LinkError::DuplicateDefinition {
+                module: module.to_string(),
+                name: name.to_string(),
+            });
+        }
+        self.definitions.insert(key, definition);
+        Ok(self)
+    }
+
+    /// Defines a host function taking and returning untyped values, `results` is initialized
+    /// with zeros of the result types
+    pub fn func_new(
+        &mut self,
+        module: &str,
+        name: &str,
+        t: Type,
+        f: impl Fn(Caller<'_, T>, &[LocalValue], &mut [LocalValue]) -> Result<(), usize> + 'static,
+    ) -> Result<&mut Self, LinkError> {
+        self.define(module, name, Definition::Func(self.funcs.len()))?;
+        self.funcs.push(HostFunc {
+            t,
+            call: Callee::Host(Rc::new(f)),
+        });
+        Ok(self)
+    }
+
+    /// Defines a host function from a closure, its type is taken from the closure's signature.
+    /// Returning `Err(code)` traps with [`crate::slow_vm::RuntimeError::NativeFuncCallError`].
+    pub fn func_wrap<P, R>(
+        &mut self,
+        module: &str,
+        name: &str,
+        f: impl IntoHostFunc<T, P, R>,
+    ) -> Result<&mut Self, LinkError> {
+        self.define(module, name, Definition::Func(self.funcs.len()))?;
+        self.funcs.push(f.into_host_func());
+        Ok(self)
+    }
+
+    pub fn global(
+        &mut self,
+        module: &str,
+        name: &str,
+        value: LocalValue,
+        mutable: bool,
+    ) -> Result<&mut Self, LinkError> {
+        let global = ExternalGlobal { value, mutable };
+        self.define(module, name, Definition::Global(global))
+    }
+
+    pub fn memory(
+        &mut self,
+        module: &str,
+        name: &str,
+        memory: MemoryInstance,
+    ) -> Result<&mut Self, LinkError> {
+        self.define(module, name, Definition::Memory(memory))
+    }
+
+    pub fn table(
+        &mut self,
+        module: &str,
+        name: &str,
+        table: TableInstance,
+    ) -> Result<&mut Self, LinkError> {
+        self.define(module, name, Definition::Table(table))
+    }
+
+    /// Resolves the imports of `module` and checks their types
+    fn resolve(&self, module: &ValidateResult) -> Result<Imports, LinkError> {
+        let bytecode = &module.bytecode;
+        let mut imports = Imports::default();
+        let Some(iter) = bytecode.iter_imports() else {
+            return Ok(imports);
+        };
+        for (import_id, import) in iter.enumerate() {
+            let (module, name) = (import.get_mod_name(), import.get_name());
+            let key = (module.to_string(), name.to_string());
+            let Some(definition) = self.definitions.get(&key) else {
+                return Err(LinkError::UnknownImport {
+                    module: module.to_string(),
+                    name: name.to_string(),
+                    kind: ImportKind::of(&import.desc.data),
+                });
+            };
+            link(&self.funcs, bytecode, import_id, definition, &mut imports)?;
+        }
+        Ok(imports)
+    }
+
+    pub(crate) fn definition(&self, module: &str, name: &str) -> Option<&Definition> {
+        self.definitions
+            .get(&(module.to_string(), name.to_string()))
+    }
+
+    /// Instantiates `module` with its imports taken from the linker and runs its start function.
+    /// The host functions of the linker are registered with `host`, which is then the env to
+    /// call the instance with.
+    pub fn instantiate(
+        &self,
+        module: ValidateResult,
+        host: &mut Host<T>,
+    ) -> Result<Instance<Host<T>>, LinkError> {
+        let imports = self.resolve(&module)?;
+        host.funcs = self.funcs.clone();
+        let vm = Vm::init_with_imports(&module.bytecode, &module.info, imports)
+            .map_err(ExecutionError::from)?;
+        Ok(Instance::from_vm(module, vm, host)?)
+    }
+
+    /// Replaces the code of an instance created by this linker with the one of `module`, see
+    /// [`Vm::reload_code`]. The memory is kept, the start function is not run.
+    pub fn reload_code(
+        &self,
+        instance: &mut Instance<Host<T>>,
+        module: ValidateResult,
+    ) -> Result<(), LinkError> {
+        let imports = self.resolve(&module)?;
+        instance
+            .reload_code(module, imports)
+            .map_err(ExecutionError::from)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::{DebugEnv, InstanceError, RuntimeError};
+
+    #[derive(Debug, Default)]
+    struct Console {
+        printed: Vec<i32>,
+        output: String,
+    }
+
+    const SRC: &str = r#"
+        (module
+            (import "env" "io_print_sint" (func $print (param i32)))
+            (import "env" "io_print_string" (func $print_string (param i32 i32)))
+            (import "env" "checked_div" (func $div (param i32 i32) (result i32)))
+            (import "env" "offset" (global $offset i32))
+            (import "env" "memory" (memory 1))
+            (data (i32.const 16) "hello")
+            (func (export "run") (param i32)
+                local.get 0
+                global.get $offset
+                i32.add
+                call $print
+                i32.const 16
+                i32.const 5
+                call $print_string
+            )
+            (func (export "div") (param i32 i32) (result i32)
+                local.get 0
+                local.get 1
+                call $div
+            )
+        )
+    "#;
+
+    fn linker() -> Linker<Console> {
+        let mut linker = Linker::new();
+        linker
+            .func_wrap(
+                "env",
+                "io_print_sint",
+                |mut caller: Caller<'_, Console>, x: i32| {
+                    caller.data_mut().printed.push(x);
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "env",
+                "io_print_string",
+                |mut caller: Caller<'_, Console>, ptr: u32, len: u32| -> Result<(), usize> {
+                    let bytes = caller
+                        .memory()
+                        .ok_or(1usize)?
+                        .range(ptr as usize, len as usize);
+                    let s = String::from_utf8_lossy(bytes.map_err(|_| 2usize)?).to_string();
+                    caller.data_mut().output.push_str(&s);
+                    Ok(())
+                },
+            )
+            .unwrap()
+            .func_wrap(
+                "env",
+                "checked_div",
+                |_: Caller<'_, Console>, a: i32, b: i32| a.checked_div(b).ok_or(7usize),
+            )
+            .unwrap()
+            .global("env", "offset", LocalValue::from(100i32), false)
+            .unwrap()
+            .memory("env", "memory", MemoryInstance::with_pages(1, None))
+            .unwrap();
+        linker
+    }
+
+    #[test]
+    fn host_closures() {
+        let linker = linker();
+        let mut host = Host::new(Console::default());
+        let module = read_and_validate_wat(SRC).unwrap();
+        let mut instance = linker.instantiate(module, &mut host).unwrap();
+
+        let mut run = instance.get_typed_func::<i32, ()>("run").unwrap();
+        run.call(&mut host, 5).unwrap();
+        run.call(&mut host, -1).unwrap();
+        let mut div = instance.get_typed_func::<(i32, i32), i32>("div").unwrap();
+        assert_eq!(div.call(&mut host, (9, 2)).unwrap(), 4);
+        let err = div.call(&mut host, (9, 0)).unwrap_err();
+        assert!(matches!(
+            err.trap_cause(),
+            RuntimeError::NativeFuncCallError(7)
+        ));
+        let console = host.into_data();
+        assert_eq!(console.printed, [105, 99]);
+        assert_eq!(console.output, "hellohello");
+    }
+
+    #[test]
+    fn reload_code() {
+        let linker = linker();
+        let mut host = Host::new(Console::default());
+        let module = read_and_validate_wat(SRC).unwrap();
+        let mut instance = linker.instantiate(module, &mut host).unwrap();
+        let run = instance.func_id("run").unwrap();
+        instance
+            .call(run, &[LocalValue::from(1i32)], &mut host)
+            .unwrap();
+
+        let src = r#"
+            (module
+                (import "env" "io_print_sint" (func $print (param i32)))
+                (func (export "run") (param i32)
+                    local.get 0
+                    i32.const 2
+                    i32.mul
+                    call $print
+                )
+            )
+        "#;
+        let module = read_and_validate_wat(src).unwrap();
+        linker.reload_code(&mut instance, module).unwrap();
+        let run = instance.func_id("run").unwrap();
+        instance
+            .call(run, &[LocalValue::from(21i32)], &mut host)
+            .unwrap();
+        assert_eq!(host.data().printed, [101, 42]);
+
+        let missing = read_and_validate_wat(r#"(module (import "env" "nope" (func)))"#).unwrap();
+        assert!(matches!(
+            linker.reload_code(&mut instance, missing),
+            Err(LinkError::UnknownImport { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_and_mistyped_imports() {
+        let module = || read_and_validate_wat(SRC).unwrap();
+        let instantiate = |linker: &Linker<Console>| {
+            let mut host = Host::new(Console::default());
+            linker
+                .instantiate(module(), &mut host)
+                .map(|_| ())
+                .unwrap_err()
+        };
+
+        let mut missing = Linker::new();
+        missing
+            .func_wrap("env", "io_print_sint", |_: Caller<'_, Console>, _: i32| {})
+            .unwrap();
+        assert_eq!(
+            instantiate(&missing).to_string(),
+            "Unknown import: function env.io_print_string is not defined"
+        );
+
+        let mut linker = linker();
+        assert!(matches!(
+            linker.func_wrap("env", "offset", |_: Caller<'_, Console>| {}),
+            Err(LinkError::DuplicateDefinition { .. })
+        ));
+
+        let mut wrong_func = Linker::new();
+        wrong_func
+            .func_wrap("env", "io_print_sint", |_: Caller<'_, Console>, _: i64| {})
+            .unwrap();
+        assert_eq!(
+            instantiate(&wrong_func).to_string(),
+            "Imported function env.io_print_sint has type (i64) -> (), expected (i32) -> ()"
+        );
+
+        let mut kinds = linker.clone();
+        kinds.definitions.remove(&("env".into(), "offset".into()));
+        kinds
+            .func_wrap("env", "offset", |_: Caller<'_, Console>| 1i32)
+            .unwrap();
+        assert_eq!(
+            instantiate(&kinds).to_string(),
+            "Import env.offset expects a global, but a function is defined"
+        );
+
+        let mut global = linker.clone();
+        global.definitions.remove(&("env".into(), "offset".into()));
+        global
+            .global("env", "offset", LocalValue::from(1i32), true)
+            .unwrap();
+        assert!(matches!(
+            instantiate(&global),
+            LinkError::GlobalMutabilityMismatch {
+                expected: false,
+                ..
+            }
+        ));
+
+        let mut memory = linker.clone();
+        memory.definitions.remove(&("env".into(), "memory".into()));
+        memory
+            .memory("env", "memory", MemoryInstance::with_pages(0, Some(4)))
+            .unwrap();
+        assert_eq!(
+            instantiate(&memory).to_string(),
+            "Imported memory env.memory has limits (0..4), expected (1..)"
+        );
+
+        //NOTE: A static env reports the imports it does not know the same way
+        let err = Vm::<DebugEnv>::init_from_validation_result(&module())
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(
+            &err,
+            InstanceError::Link(e) if matches!(**e, LinkError::UnknownImport { kind: ImportKind::Function, .. })
+        ));
+        assert!(matches!(
+            link(
+                &linker.funcs,
+                &module().bytecode,
+                usize::MAX,
+                &Definition::Func(0),
+                &mut Imports::default()
+            ),
+            Err(LinkError::InvalidImportId(usize::MAX))
+        ));
+    }
+
+    #[test]
+    fn imported_table() {
+        let src = r#"
+            (module
+                (import "env" "table" (table 2 funcref))
+                (func $seven (result i32) i32.const 7)
+                (elem (i32.const 1) $seven)
+                (func (export "size") (result i32) table.size 0)
+            )
+        "#;
+        let mut linker: Linker<()> = Linker::new();
+        let table = TableInstance::with_size(ValueType::Funcref, 3, Some(10));
+        linker.table("env", "table", table).unwrap();
+        let mut host = Host::new(());
+        let module = read_and_validate_wat(src).unwrap();
+        let mut instance = linker.instantiate(module, &mut host).unwrap();
+        let mut size = instance.get_typed_func::<(), u32>("size").unwrap();
+        assert_eq!(size.call(&mut host, ()).unwrap(), 3);
+        assert!(instance.vm().tables()[0].get(1).unwrap().is_some());
+
+        let mut small: Linker<()> = Linker::new();
+        let table = TableInstance::with_size(ValueType::Funcref, 1, None);
+        small.table("env", "table", table).unwrap();
+        let module = read_and_validate_wat(src).unwrap();
+        let err = small
+            .instantiate(module, &mut host)
+            .map(|_| ())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Imported table env.table has type funcref (1..), expected funcref (2..)"
+        );
+    }
+}
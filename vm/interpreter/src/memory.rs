@@ -0,0 +1,114 @@
+use parser::reader::Limits;
+
+use crate::slow_vm::RuntimeError;
+
+pub const WASM_PAGE_SIZE: usize = 65536;
+
+//NOTE: The spec allows 65536 pages (4GiB), memory.grow is capped lower by default so a
+//program growing in a loop fails with -1 instead of taking the host down.
+pub const DEFAULT_MAX_MEMORY_PAGES: u32 = 16384;
+
+#[derive(Debug, Clone)]
+pub struct MemoryInstance {
+    data: Vec<u8>,
+    /// Maximum declared by the module
+    max: Option<u32>,
+    /// Maximum set by the embedder, applies on top of `max`
+    cap: u32,
+}
+
+impl MemoryInstance {
+    pub fn new(limits: &Limits) -> Self {
+        Self::with_pages(limits.min.data, limits.max.as_ref().map(|m| m.data))
+    }
+
+    /// A memory of `min` pages that can grow up to `max` pages
+    pub fn with_pages(min: u32, max: Option<u32>) -> Self {
+        Self {
+            data: vec![0; min as usize * WASM_PAGE_SIZE],
+            max,
+            cap: DEFAULT_MAX_MEMORY_PAGES,
+        }
+    }
+
+    pub fn set_cap(&mut self, pages: u32) {
+        self.cap = pages;
+    }
+
+    /// The size in pages
+    pub fn size(&self) -> u32 {
+        (self.data.len() / WASM_PAGE_SIZE) as u32
+    }
+
+    /// The maximum in pages as declared, without the cap
+    pub fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    pub fn max_pages(&self) -> u32 {
+        self.max.map_or(self.cap, |max| max.min(self.cap))
+    }
+
+    /// Returns the previous size in pages or `None` if the memory can not grow by `n` pages.
+    pub fn grow(&mut self, n: u32) -> Option<u32> {
+        let old_size = self.size();
+        let new_size = old_size.checked_add(n)?;
+        if new_size > self.max_pages() {
+            return None;
+        }
+        self.data.resize(new_size as usize * WASM_PAGE_SIZE, 0);
+        Some(old_size)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Used to restore snapshots, the caller checks the size against the limits
+    pub(crate) fn replace_bytes(&mut self, data: Vec<u8>) {
+        self.data = data;
+    }
+
+    /// Used to undo memory.grow
+    #[cfg(feature = "time-travel")]
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.data.truncate(len);
+    }
+
+    pub fn range(&self, start: usize, n: usize) -> Result<&[u8], RuntimeError> {
+        let end = start
+            .checked_add(n)
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
+        self.data
+            .get(start..end)
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)
+    }
+
+    pub fn range_mut(&mut self, start: usize, n: usize) -> Result<&mut [u8], RuntimeError> {
+        let end = start
+            .checked_add(n)
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
+        self.data
+            .get_mut(start..end)
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)
+    }
+
+    pub fn copy_within(&mut self, dst: usize, src: usize, n: usize) -> Result<(), RuntimeError> {
+        _ = self.range(src, n)?;
+        _ = self.range(dst, n)?;
+        self.data.copy_within(src..src + n, dst);
+        Ok(())
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use itertools::Itertools;
+use parser::names::NameMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+impl Sample {
+    fn add(&mut self, other: &Sample) {
+        self.instructions += other.instructions;
+        self.time += other.time;
+    }
+}
+
+/// Which value folded stacks are weighted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileMetric {
+    Instructions,
+    Microseconds,
+}
+
+/// Counts executed instructions and wall time per call path, attached with `Vm::set_profiler`.
+/// Only available with the `profile` feature.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    /// Function ids of the active calls, outermost first
+    paths: Vec<Vec<usize>>,
+    samples: Vec<Sample>,
+    index: HashMap<Vec<usize>, usize>,
+    current: Option<usize>,
+    since: Option<Instant>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn current_path(&self) -> &[usize] {
+        self.current.map_or(&[], |i| &self.paths[i])
+    }
+
+    /// Called before every instruction with the active calls
+    pub fn enter(&mut self, depth: usize, func_id: usize, path: impl FnOnce() -> Vec<usize>) {
+        let current = self.current_path();
+        if current.len() != depth || current.last() != Some(&func_id) {
+            self.switch(Some(path()));
+        }
+        if let Some(i) = self.current {
+            self.samples[i].instructions += 1;
+        }
+    }
+
+    /// Stops the clock, called when the profiled function finished or trapped
+    pub fn pause(&mut self) {
+        self.switch(None);
+    }
+
+    //NOTE: The clock is only read when the call path changes, not for every instruction
+    fn switch(&mut self, path: Option<Vec<usize>>) {
+        let now = Instant::now();
+        if let (Some(i), Some(since)) = (self.current, self.since) {
+            self.samples[i].time += now - since;
+        }
+        self.current = path.map(|path| match self.index.get(&path) {
+            Some(i) => *i,
+            None => {
+                self.index.insert(path.clone(), self.paths.len());
+                self.paths.push(path);
+                self.samples.push(Sample::default());
+                self.paths.len() - 1
+            }
+        });
+        self.since = self.current.map(|_| now);
+    }
+
+    /// Every recorded call path, outermost call first, with the samples spent directly in it
+    pub fn paths(&self) -> impl Iterator<Item = (&[usize], &Sample)> {
+        self.paths.iter().map(Vec::as_slice).zip(&self.samples)
+    }
+
+    pub fn report(&self, names: &NameMap) -> ProfileReport {
+        let mut functions: HashMap<usize, FunctionProfile> = HashMap::new();
+        for (path, sample) in self.paths() {
+            let Some(func_id) = path.last() else {
+                continue;
+            };
+            let profile = |id| FunctionProfile {
+                func_id: id,
+                name: names.describe(id),
+                inclusive: Sample::default(),
+                exclusive: Sample::default(),
+            };
+            functions
+                .entry(*func_id)
+                .or_insert_with(|| profile(*func_id))
+                .exclusive
+                .add(sample);
+            //NOTE: Recursive calls appear several times in a path but count only once
+            for id in path.iter().collect::<HashSet<_>>() {
+                functions
+                    .entry(*id)
+                    .or_insert_with(|| profile(*id))
+                    .inclusive
+                    .add(sample);
+            }
+        }
+        let mut functions = functions.into_values().collect::<Vec<_>>();
+        functions.sort_by(|a, b| {
+            b.exclusive
+                .time
+                .cmp(&a.exclusive.time)
+                .then(b.exclusive.instructions.cmp(&a.exclusive.instructions))
+                .then(a.func_id.cmp(&b.func_id))
+        });
+        ProfileReport { functions }
+    }
+
+    /// Writes one line per call path in the folded format of flamegraph tools,
+    /// e.g. `$main;$update;$draw 1234`
+    pub fn write_folded(
+        &self,
+        w: &mut impl Write,
+        names: &NameMap,
+        metric: ProfileMetric,
+    ) -> io::Result<()> {
+        for (path, sample) in self.paths() {
+            let value = match metric {
+                ProfileMetric::Instructions => sample.instructions,
+                ProfileMetric::Microseconds => sample.time.as_micros() as u64,
+            };
+            if value == 0 {
+                continue;
+            }
+            let frames = path
+                .iter()
+                .map(|id| names.describe(*id).replace([';', ' '], "_"))
+                .join(";");
+            writeln!(w, "{frames} {value}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionProfile {
+    pub func_id: usize,
+    pub name: String,
+    /// Including all functions called from this one
+    pub inclusive: Sample,
+    /// Only instructions of this function
+    pub exclusive: Sample,
+}
+
+/// Functions sorted by exclusive time, most expensive first
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileReport {
+    pub functions: Vec<FunctionProfile>,
+}
+
+impl Display for ProfileReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>12} {:>12} {:>14} {:>14}  function",
+            "self time", "total time", "self instr", "total instr"
+        )?;
+        for func in &self.functions {
+            writeln!(
+                f,
+                "{:>12} {:>12} {:>14} {:>14}  {}",
+                format!("{:.3?}", func.exclusive.time),
+                format!("{:.3?}", func.inclusive.time),
+                func.exclusive.instructions,
+                func.inclusive.instructions,
+                func.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::{DebugEnv, LocalValue, Vm};
+
+    #[test]
+    fn inclusive_and_exclusive_counts() {
+        let src = r#"
+            (module
+                (func $leaf (param i32) (result i32)
+                    local.get 0
+                    i32.const 1
+                    i32.add
+                )
+                (func $main (result i32)
+                    i32.const 1
+                    call $leaf
+                    call $leaf
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_profiler(Some(Profiler::new()));
+        vm.set_func(1, []).unwrap();
+        let results = vm
+            .run_func(&res.bytecode, &res.info, &mut DebugEnv {})
+            .unwrap();
+        assert_eq!(results, vec![LocalValue::I32(3)]);
+
+        let profiler = vm.take_profiler().unwrap();
+        let report = profiler.report(vm.function_names());
+        let count = |name: &str| {
+            let f = report.functions.iter().find(|f| f.name == name).unwrap();
+            (f.exclusive.instructions, f.inclusive.instructions)
+        };
+        assert_eq!(count("$leaf"), (8, 8));
+        assert_eq!(count("$main"), (4, 12));
+
+        let mut folded = vec![];
+        profiler
+            .write_folded(
+                &mut folded,
+                vm.function_names(),
+                ProfileMetric::Instructions,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(folded).unwrap(),
+            "$main 4\n$main;$leaf 8\n"
+        );
+    }
+}
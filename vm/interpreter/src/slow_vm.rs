@@ -1,26 +1,49 @@
 use std::marker::PhantomData;
 use std::ops::DerefMut;
 
-use parser::reader::{Data, iter_without_position};
+use parser::reader::{Data, ElementInit, ElementMode, iter_without_position};
 use std::slice;
 use std::{
     collections::HashMap,
-    fmt::{Debug, Display},
+    fmt::{Debug, Display, Write as _},
 };
 use thiserror::Error;
 
 use itertools::Itertools;
 use parser::{
-    info::BytecodeInfo,
+    info::{BytecodeInfo, TableInfo},
+    names::{NameMap, describe},
     op::{Blocktype, Memarg, Op},
     reader::{Bytecode, BytecodeReader, ValueType},
 };
 use smallvec::SmallVec;
 use validator::validator::{ReadAndValidateError, ValidateResult};
 
+#[cfg(feature = "coverage")]
+use crate::coverage::Coverage;
 use crate::env::Env;
-use crate::{env::ExternalFunction, stack::StackValue};
-const WASM_PAGE_SIZE: usize = 65536;
+use crate::float;
+use crate::int;
+use crate::linker::LinkError;
+use crate::memory::{MemoryInstance, WASM_PAGE_SIZE};
+#[cfg(feature = "profile")]
+use crate::profile::Profiler;
+use crate::snapshot::{ExecutionState, Fingerprint, Snapshot, SnapshotError};
+use crate::stack::NULL_REF;
+use crate::table::{Ref, TableInstance};
+#[cfg(feature = "time-travel")]
+use crate::time_travel::{History, UndoEntry};
+#[cfg(feature = "trace")]
+use crate::trace::TraceRecorder;
+use crate::{
+    env::{ExternalFunction, Imports},
+    stack::StackValue,
+};
+#[cfg(feature = "trace")]
+use std::sync::{Arc, Mutex};
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+/// Values on the value stack and locals of all active functions combined
+pub const DEFAULT_MAX_STACK_SIZE: usize = 1 << 20;
 
 #[derive(Error, Debug)]
 pub enum InstanceError {
@@ -38,6 +61,22 @@ pub enum InstanceError {
     InvalidReturnCountInConstExpr(usize),
     #[error("Invalid return type in const expr: {0}")]
     InvalidReturnTypeInConstExpr(ValueType),
+    #[error("Imported tables are not supported, import id: {0}")]
+    UnsupportedTableImport(usize),
+    #[error("Active element segment {0} does not fit into its table")]
+    ElementSegmentOutOfBounds(usize),
+    #[error("Active data segment {0} does not fit into the memory")]
+    DataSegmentOutOfBounds(usize),
+    #[error("Global {0} is not defined before the const expr using it")]
+    UnknownGlobalInConstExpr(usize),
+    #[error(transparent)]
+    Link(Box<LinkError>),
+}
+
+impl From<LinkError> for InstanceError {
+    fn from(e: LinkError) -> Self {
+        Self::Link(Box::new(e))
+    }
 }
 #[derive(Error, Debug)]
 pub enum RuntimeError {
@@ -53,37 +92,149 @@ pub enum RuntimeError {
     UnexpectedNoStartFunction,
     #[error("Cannot find exported function by name: {0}")]
     UnknownExportedFunc(String), // #[error("Wrong parameter count provided: Got {0}, expected: {1}")]
+    #[error("Exported function {name} has type {got}, expected {expected}")]
+    ExportedFuncTypeMismatch {
+        name: String,
+        expected: Type,
+        got: Type,
+    },
     #[error("No function set")]
     NoFunctionSet,
+    #[error("Unknown function: {0}")]
+    UnknownFunction(usize),
+    #[error("Invalid conversion to integer")]
+    InvalidConversionToInteger,
+    #[error("Integer overflow")]
+    IntegerOverflow,
+    #[error("Integer divide by zero")]
+    IntegerDivideByZero,
+    #[error("Table address out of scope")]
+    TableAddressOutOfScope,
+    #[error("Undefined element in indirect call")]
+    UndefinedElement,
+    #[error("Uninitialized element in indirect call")]
+    UninitializedElement,
+    #[error("Indirect call type mismatch: function {func_id} does not have type {type_id}")]
+    IndirectCallTypeMismatch { type_id: usize, func_id: usize },
+    #[error("Out of fuel, execution can be resumed after adding more")]
+    OutOfFuel,
+    #[error("Instance {0} is already running and cannot be called from another instance")]
+    InstanceRunning(usize),
+    #[error("Replaying from a checkpoint did not reach the recorded execution again")]
+    ReplayDiverged,
+    #[error("Stack overflow at call depth {depth}, call stack:\n{call_stack}")]
+    StackOverflow { depth: usize, call_stack: CallStack },
+    #[error(
+        "Trap in function {} at instruction {instruction} ({op}): {error}",
+        describe(func_name.as_deref(), *func_id)
+    )]
+    Trap {
+        func_id: usize,
+        func_name: Option<String>,
+        instruction: usize,
+        op: Op,
+        error: Box<RuntimeError>,
+    },
+}
+
+impl RuntimeError {
+    /// Returns the error that caused a trap, or the error itself if it carries no location.
+    pub fn trap_cause(&self) -> &RuntimeError {
+        match self {
+            RuntimeError::Trap { error, .. } => error,
+            _ => self,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallFrame {
+    pub func_id: usize,
+    pub func_name: Option<String>,
+    pub instruction: usize,
+}
+
+/// The active function calls, innermost call first
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallStack(pub Vec<CallFrame>);
+
+impl CallStack {
+    /// Frames at most printed by Display, runs of identical frames count as one
+    const MAX_DISPLAYED: usize = 16;
+}
+
+impl Display for CallStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //NOTE: Recursion produces long runs of the same call site, those are printed once
+        let runs = self.0.iter().dedup_with_count().collect::<Vec<_>>();
+        for (count, frame) in runs.iter().take(Self::MAX_DISPLAYED) {
+            write!(
+                f,
+                "  {} at instruction {}",
+                describe(frame.func_name.as_deref(), frame.func_id),
+                frame.instruction
+            )?;
+            if *count > 1 {
+                write!(f, " ({count} times)")?;
+            }
+            writeln!(f)?;
+        }
+        if runs.len() > Self::MAX_DISPLAYED {
+            let hidden: usize = runs[Self::MAX_DISPLAYED..].iter().map(|(n, _)| n).sum();
+            writeln!(f, "  ... {hidden} more")?;
+        }
+        Ok(())
+    }
 }
 
+/// Read-only view of an active function call
 #[derive(Debug, Clone)]
+pub struct FrameView<'a> {
+    pub func_id: usize,
+    pub func_name: Option<&'a str>,
+    pub instruction: usize,
+    /// Params followed by the declared locals
+    pub locals: &'a [LocalValue],
+    pub labels: &'a [Label],
+    /// The operand stack of this call, values are untyped
+    pub stack: &'a [StackValue],
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ActivationFrame {
-    locals_offset: usize,
-    func_id: usize,
-    arity: usize,
-    ip: usize,
-    stack_height: usize,
-    label_stack_offset: usize,
+    pub(crate) locals_offset: usize,
+    pub(crate) func_id: usize,
+    pub(crate) arity: usize,
+    pub(crate) ip: usize,
+    pub(crate) stack_height: usize,
+    pub(crate) label_stack_offset: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Label {
-    stack_height: usize,
-    out_count: usize,
+    /// Height the value stack is reset to when branching to this label
+    pub stack_height: usize,
+    /// Number of values kept when branching to this label
+    pub out_count: usize,
 }
 
 #[derive(Debug, Clone)]
 pub struct InternalFunctionInstance {
-    locals: Vec<ValueType>,
-    code_offset: usize,
+    pub(crate) locals: Vec<ValueType>,
+    pub(crate) code_offset: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Type {
     pub params: Vec<ValueType>,
     pub results: Vec<ValueType>,
 }
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (p, r) = (self.params.iter(), self.results.iter());
+        write!(f, "({}) -> ({})", p.format(", "), r.format(", "))
+    }
+}
 impl From<parser::reader::Type> for Type {
     fn from(value: parser::reader::Type) -> Self {
         Self {
@@ -105,7 +256,7 @@ impl From<&parser::reader::Type> for Type {
 pub struct NativeFunctionInstance {
     module: String,
     name: String,
-    id: usize,
+    pub(crate) id: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -116,14 +267,29 @@ pub enum FunctionType {
 
 #[derive(Debug, Clone)]
 pub struct Function {
-    t: Type,
-    kind: FunctionType,
+    pub(crate) t: Type,
+    pub(crate) kind: FunctionType,
+}
+
+impl Function {
+    /// A function calling native function `id` of the env, it is not imported by the module
+    pub(crate) fn native(t: Type, id: usize) -> Self {
+        Self {
+            t,
+            kind: FunctionType::Native(NativeFunctionInstance {
+                module: String::new(),
+                name: String::new(),
+                id,
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Code {
-    instructions: Vec<Op>,
-    functions: Vec<Function>,
+    pub(crate) instructions: Vec<Op>,
+    pub(crate) functions: Vec<Function>,
+    function_names: NameMap,
 }
 
 pub trait PopFromValueStack {
@@ -137,6 +303,13 @@ impl PopFromValueStack for bool {
     }
 }
 
+impl PopFromValueStack for Ref {
+    unsafe fn pop<E: Env>(vm: &mut Vm<E>) -> Self {
+        let val = unsafe { vm.pop_u32() };
+        (val != NULL_REF).then_some(val)
+    }
+}
+
 macro_rules! impl_pop_from_value_stack {
     ($t: tt, $func_name: ident) => {
         impl PopFromValueStack for $t {
@@ -169,6 +342,26 @@ impl_vm_pop!(pop_f32, f32, f32);
 impl_vm_pop!(pop_f64, f64, f64);
 
 impl Code {
+    /// Identifies the code of a module, used to match snapshots to their module
+    fn fingerprint(&self) -> u64 {
+        let mut fingerprint = Fingerprint::new();
+        for function in &self.functions {
+            _ = write!(
+                fingerprint,
+                "{:?}{:?}",
+                function.t.params, function.t.results
+            );
+            _ = match &function.kind {
+                FunctionType::Wasm(f) => write!(fingerprint, "{:?}{}", f.locals, f.code_offset),
+                FunctionType::Native(f) => write!(fingerprint, "{}.{}", f.module, f.name),
+            };
+        }
+        for op in &self.instructions {
+            _ = write!(fingerprint, "{op:?}");
+        }
+        fingerprint.finish()
+    }
+
     fn append_internal_code(
         module: &Bytecode,
         linear_code: &mut Vec<Op>,
@@ -193,9 +386,10 @@ impl Code {
         )
     }
 
-    fn get_function_instances<E: Env>(
+    fn get_function_instances(
         module: &Bytecode,
         info: &BytecodeInfo,
+        imports: &Imports,
     ) -> Result<(Vec<Function>, Vec<Op>), InstanceError> {
         let mut linear_code: Vec<Op> = Vec::new();
         let mut code_offset: usize = 0;
@@ -224,7 +418,9 @@ impl Code {
                             let module_name = import.get_mod_name();
                             let name = import.get_name();
 
-                            let func = E::get_func(module_name, name)
+                            let func = imports
+                                .functions
+                                .get(&import_id)
                                 .ok_or(InstanceError::ImportFunctionNameDoesNotMatch)?;
 
                             Ok(Function {
@@ -246,15 +442,17 @@ impl Code {
         ))
     }
 
-    pub fn from_module<E: Env>(
+    pub(crate) fn from_module(
         module: &Bytecode,
         info: &BytecodeInfo,
+        imports: &Imports,
     ) -> Result<Self, InstanceError> {
-        let (functions, instructions) = Self::get_function_instances::<E>(module, info)?;
+        let (functions, instructions) = Self::get_function_instances(module, info, imports)?;
 
         Ok(Self {
             instructions,
             functions,
+            function_names: info.names.functions.clone(),
         })
     }
 }
@@ -267,6 +465,8 @@ pub enum LocalValue {
     S64(i64),
     F32(f32),
     F64(f64),
+    FuncRef(Ref),
+    ExternRef(Ref),
 }
 
 impl Display for LocalValue {
@@ -279,6 +479,8 @@ impl Display for LocalValue {
             LocalValue::S64(i) => write!(f, "{}", i),
             LocalValue::F32(i) => write!(f, "{}", i),
             LocalValue::F64(i) => write!(f, "{}", i),
+            LocalValue::FuncRef(Some(r)) | LocalValue::ExternRef(Some(r)) => write!(f, "ref {}", r),
+            LocalValue::FuncRef(None) | LocalValue::ExternRef(None) => write!(f, "ref.null"),
         }
     }
 }
@@ -286,12 +488,13 @@ impl Display for LocalValue {
 impl From<LocalValue> for StackValue {
     fn from(value: LocalValue) -> Self {
         match value {
-            LocalValue::I32(val) => Self { i32: val },
-            LocalValue::S32(val) => Self { i32: val as u32 },
-            LocalValue::S64(val) => Self { i64: val as u64 },
-            LocalValue::I64(val) => Self { i64: val },
-            LocalValue::F32(val) => Self { f32: val },
-            LocalValue::F64(val) => Self { f64: val },
+            LocalValue::I32(val) => val.into(),
+            LocalValue::S32(val) => val.into(),
+            LocalValue::S64(val) => val.into(),
+            LocalValue::I64(val) => val.into(),
+            LocalValue::F32(val) => val.into(),
+            LocalValue::F64(val) => val.into(),
+            LocalValue::FuncRef(val) | LocalValue::ExternRef(val) => val.into(),
         }
     }
 }
@@ -304,6 +507,15 @@ impl LocalValue {
             LocalValue::S64(_) => ValueType::I64,
             LocalValue::F32(_) => ValueType::F32,
             LocalValue::F64(_) => ValueType::F64,
+            LocalValue::FuncRef(_) => ValueType::Funcref,
+            LocalValue::ExternRef(_) => ValueType::Externref,
+        }
+    }
+
+    pub fn reference(&self) -> Ref {
+        match self {
+            Self::FuncRef(r) | Self::ExternRef(r) => *r,
+            _ => unreachable!(),
         }
     }
 
@@ -315,6 +527,9 @@ impl LocalValue {
             LocalValue::S64(v) => *v = unsafe { val.i64 as i64 },
             LocalValue::F32(v) => *v = unsafe { val.f32 },
             LocalValue::F64(v) => *v = unsafe { val.f64 },
+            LocalValue::FuncRef(v) | LocalValue::ExternRef(v) => {
+                *v = (unsafe { val.i32 } != NULL_REF).then_some(unsafe { val.i32 })
+            }
         };
     }
     pub fn init_from_type(t: ValueType) -> Self {
@@ -323,8 +538,8 @@ impl LocalValue {
             ValueType::I64 => Self::I64(0),
             ValueType::F32 => Self::F32(0.0),
             ValueType::F64 => Self::F64(0.0),
-            ValueType::Funcref => todo!(),
-            ValueType::Externref => todo!(),
+            ValueType::Funcref => Self::FuncRef(None),
+            ValueType::Externref => Self::ExternRef(None),
             ValueType::Vectype => todo!(),
         }
     }
@@ -334,8 +549,11 @@ impl LocalValue {
             ValueType::I64 => Self::I64(unsafe { val.i64 }),
             ValueType::F32 => Self::F32(unsafe { val.f32 }),
             ValueType::F64 => Self::F64(unsafe { val.f64 }),
-            ValueType::Funcref => todo!(),
-            ValueType::Externref => todo!(),
+            ValueType::Funcref | ValueType::Externref => {
+                let mut res = Self::init_from_type(t);
+                unsafe { res.set_inner_from_stack_val(val) };
+                res
+            }
             ValueType::Vectype => todo!(),
         }
     }
@@ -411,10 +629,24 @@ pub struct Vm<E: Env> {
     code: Code,
     locals: Vec<LocalValue>,
     globals: Vec<LocalValue>,
-    mem: Option<Vec<u8>>,
+    mem: Option<MemoryInstance>,
+    tables: Vec<TableInstance>,
+    elements: Vec<Vec<Ref>>,
     start_func_id: Option<usize>,
     local_offset: usize,
     func_id: Option<usize>,
+    /// Remaining instruction budget, unlimited if not set
+    fuel: Option<u64>,
+    max_call_depth: usize,
+    max_stack_size: usize,
+    #[cfg(feature = "trace")]
+    tracer: Option<Arc<Mutex<TraceRecorder>>>,
+    #[cfg(feature = "profile")]
+    profiler: Option<Profiler>,
+    #[cfg(feature = "coverage")]
+    coverage: Option<Coverage>,
+    #[cfg(feature = "time-travel")]
+    history: Option<History>,
     _marker: PhantomData<E>,
 }
 
@@ -422,9 +654,10 @@ impl<E: Env> Vm<E> {
     fn get_global_init_value(
         module: &Bytecode,
         global_id: usize,
+        globals: &[LocalValue],
     ) -> Result<LocalValue, InstanceError> {
         let global = module.get_global(global_id).unwrap();
-        let result_stack = Self::run_const_expr(global.init_expr.data.iter_ops())?;
+        let result_stack = Self::run_const_expr(global.init_expr.data.iter_ops(), globals)?;
         if result_stack.len() > 1 {
             Err(InstanceError::InvalidReturnCountInConstExpr(
                 result_stack.len(),
@@ -443,31 +676,36 @@ impl<E: Env> Vm<E> {
         }
     }
 
-    pub fn get_global_instances(
+    pub(crate) fn get_global_instances(
         module: &Bytecode,
         info: &BytecodeInfo,
+        imports: &Imports,
     ) -> Result<Vec<LocalValue>, InstanceError> {
-        info.globals
-            .iter()
-            .map(|g| match g.info {
+        //NOTE: Init exprs can read the globals defined before them
+        let mut globals = Vec::with_capacity(info.globals.len());
+        for g in &info.globals {
+            let value = match g.info {
                 parser::info::GlobalInfo::Internal { global_id, .. } => {
-                    Self::get_global_init_value(module, global_id)
+                    Self::get_global_init_value(module, global_id, &globals)?
                 }
 
                 parser::info::GlobalInfo::Imported { import_id } => {
-                    let import = module.get_import(import_id).unwrap();
-                    let module_name = import.get_mod_name();
-                    let name = import.get_name();
-
-                    let global = E::get_global(module_name, name)
+                    let global = imports
+                        .globals
+                        .get(&import_id)
                         .ok_or(InstanceError::ImportGlobalNameDoesNotMatch)?;
-                    Ok(global.value)
+                    global.value
                 }
-            })
-            .collect()
+            };
+            globals.push(value);
+        }
+        Ok(globals)
     }
+
+    /// Evaluates a const expr, `global.get` reads from `globals`
     pub fn run_const_expr(
         expr: impl Iterator<Item = Op>,
+        globals: &[LocalValue],
     ) -> Result<Vec<LocalValue>, InstanceError> {
         let mut stack = Vec::new();
         for op in expr {
@@ -476,7 +714,13 @@ impl<E: Env> Vm<E> {
                 Op::I64Const(val) => stack.push(val.into()),
                 Op::F32Const(val) => stack.push(val.into()),
                 Op::F64Const(val) => stack.push(val.into()),
-                Op::GlobalGet(_) => todo!(),
+                Op::RefNull(t) => stack.push(LocalValue::init_from_type(t)),
+                Op::RefFunc(id) => stack.push(LocalValue::FuncRef(Some(id as u32))),
+                Op::GlobalGet(id) => stack.push(
+                    *globals
+                        .get(id)
+                        .ok_or(InstanceError::UnknownGlobalInConstExpr(id))?,
+                ),
                 Op::End(_) => break,
                 _ => return Err(InstanceError::InvalidConstOp(op)),
             }
@@ -484,53 +728,145 @@ impl<E: Env> Vm<E> {
         Ok(stack)
     }
 
-    fn make_memory(bytecode: &Bytecode, info: &BytecodeInfo) -> Option<Vec<u8>> {
-        info.inital_mem_size_pages().map(|s| vec![0; s])
+    fn make_memory(info: &BytecodeInfo, imports: &mut Imports) -> Option<MemoryInstance> {
+        let limits = info.memories.first()?.limits();
+        Some(
+            imports
+                .memory
+                .take()
+                .unwrap_or_else(|| MemoryInstance::new(limits)),
+        )
     }
 
-    fn copy_active_mem_section(
+    /// Copies the active data segments of `bytecode` into `mem`
+    fn copy_active_mem_sections(
+        bytecode: &Bytecode,
         mem: &mut [u8],
-        init_expr: impl Iterator<Item = Op>,
-        data: &[u8],
+        globals: &[LocalValue],
     ) -> Result<(), InstanceError> {
-        let offset = Self::run_const_expr(init_expr)?;
-        assert!(offset.len() == 1);
-        let offset = offset[0].u32() as usize;
-        assert!(data.len() <= mem.len());
-
-        mem[offset..offset + data.len()].copy_from_slice(data);
+        let Some(data) = bytecode.iter_data() else {
+            return Ok(());
+        };
+        for (data_id, d) in data.enumerate() {
+            let Data::Active { expr, data, .. } = d else {
+                continue;
+            };
+            let init_expr = expr.data.iter().map(|p| p.data.clone());
+            let offset = match Self::run_const_expr(init_expr, globals)?.as_slice() {
+                [offset] => offset.u32() as usize,
+                res => return Err(InstanceError::InvalidReturnCountInConstExpr(res.len())),
+            };
+            mem.get_mut(offset..)
+                .and_then(|mem| mem.get_mut(..data.data.len()))
+                .ok_or(InstanceError::DataSegmentOutOfBounds(data_id))?
+                .copy_from_slice(&data.data);
+        }
         Ok(())
     }
 
+    fn make_tables(
+        info: &BytecodeInfo,
+        imports: &mut Imports,
+    ) -> Result<Vec<TableInstance>, InstanceError> {
+        info.tables
+            .iter()
+            .map(|t| match t.info {
+                TableInfo::Internal { .. } => Ok(TableInstance::new(&t.t)),
+                TableInfo::Imported { import_id } => imports
+                    .tables
+                    .remove(&import_id)
+                    .ok_or(InstanceError::UnsupportedTableImport(import_id)),
+            })
+            .collect()
+    }
+
+    fn get_element_refs(
+        init: &ElementInit,
+        globals: &[LocalValue],
+    ) -> Result<Vec<Ref>, InstanceError> {
+        match init {
+            ElementInit::Functions(ids) => Ok(ids.iter().map(|id| Some(id.data as u32)).collect()),
+            ElementInit::Expressions(exprs) => exprs
+                .iter()
+                .map(|expr| {
+                    let res = Self::run_const_expr(expr.data.iter_ops(), globals)?;
+                    match res.as_slice() {
+                        [r @ (LocalValue::FuncRef(_) | LocalValue::ExternRef(_))] => {
+                            Ok(r.reference())
+                        }
+                        [r] => Err(InstanceError::InvalidReturnTypeInConstExpr(
+                            r.get_value_type(),
+                        )),
+                        _ => Err(InstanceError::InvalidReturnCountInConstExpr(res.len())),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Evaluates all element segments and copies the active ones into their tables.
+    /// Active and declarative segments are dropped afterwards.
+    fn make_elements(
+        bytecode: &Bytecode,
+        tables: &mut [TableInstance],
+        globals: &[LocalValue],
+    ) -> Result<Vec<Vec<Ref>>, InstanceError> {
+        let Some(elements) = bytecode.iter_elements() else {
+            return Ok(Vec::new());
+        };
+        elements
+            .enumerate()
+            .map(|(elem_id, elem)| {
+                let refs = Self::get_element_refs(&elem.init, globals)?;
+                match &elem.mode {
+                    ElementMode::Passive => Ok(refs),
+                    ElementMode::Declarative => Ok(Vec::new()),
+                    ElementMode::Active { table_id, expr } => {
+                        let init_expr = expr.data.iter().map(|p| p.data.clone());
+                        let offset = match Self::run_const_expr(init_expr, globals)?.as_slice() {
+                            [offset] => offset.u32(),
+                            res => {
+                                return Err(InstanceError::InvalidReturnCountInConstExpr(
+                                    res.len(),
+                                ));
+                            }
+                        };
+                        tables
+                            .get_mut(*table_id)
+                            .ok_or(InstanceError::ElementSegmentOutOfBounds(elem_id))?
+                            .write(offset, &refs)
+                            .map_err(|_| InstanceError::ElementSegmentOutOfBounds(elem_id))?;
+                        Ok(Vec::new())
+                    }
+                }
+            })
+            .collect()
+    }
+
     fn init(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<Vm<E>, InstanceError> {
-        let code = Code::from_module::<E>(bytecode, info)?;
-        let mut mem = Self::make_memory(bytecode, info);
+        Self::init_with_imports(bytecode, info, Imports::from_env::<E>(bytecode, info)?)
+    }
+
+    pub(crate) fn init_with_imports(
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        mut imports: Imports,
+    ) -> Result<Vm<E>, InstanceError> {
+        let code = Code::from_module(bytecode, info, &imports)?;
+        let mut mem = Self::make_memory(info, &mut imports);
         let locals = Vec::with_capacity(20);
         let start_func_id = bytecode.start.as_ref().map(|i| i.data as usize);
         let value_stack = Vec::with_capacity(20);
-        let globals = Self::get_global_instances(bytecode, info)?;
+        let globals = Self::get_global_instances(bytecode, info, &imports)?;
+        let mut tables = Self::make_tables(info, &mut imports)?;
+        let elements = Self::make_elements(bytecode, &mut tables, &globals)?;
         let types = bytecode
             .iter_types()
             .map(|i| i.map_into::<Type>().collect());
 
-        if let Some(data) = bytecode.iter_data()
-            && let Some(ref mut mem) = mem
-        {
-            data.filter_map(|d| {
-                if let Data::Active { expr, data, .. } = d {
-                    Some((expr, data))
-                } else {
-                    None
-                }
-            })
-            .try_for_each(|(expr, data)| {
-                Self::copy_active_mem_section(
-                    mem.as_mut_slice(),
-                    expr.data.iter().map(|p| p.data),
-                    &data.data,
-                )
-            })?;
-        };
+        if let Some(ref mut mem) = mem {
+            Self::copy_active_mem_sections(bytecode, mem.bytes_mut(), &globals)?;
+        }
         Ok(Vm {
             types,
             ip: 0,
@@ -539,11 +875,24 @@ impl<E: Env> Vm<E> {
             code,
             locals,
             mem,
+            tables,
+            elements,
             start_func_id,
             activation_stack: Vec::with_capacity(20),
             labels: Vec::with_capacity(20),
             local_offset: 0,
             func_id: None,
+            fuel: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_stack_size: DEFAULT_MAX_STACK_SIZE,
+            #[cfg(feature = "trace")]
+            tracer: None,
+            #[cfg(feature = "profile")]
+            profiler: None,
+            #[cfg(feature = "coverage")]
+            coverage: None,
+            #[cfg(feature = "time-travel")]
+            history: None,
             _marker: PhantomData {},
         })
     }
@@ -590,6 +939,9 @@ impl<E: Env> Vm<E> {
     pub fn get_local(&self, id: usize) -> LocalValue {
         self.locals[id + self.local_offset]
     }
+    pub fn get_global(&self, id: usize) -> Option<LocalValue> {
+        self.globals.get(id).copied()
+    }
 
     pub fn push_any(&mut self, val: StackValue) {
         self.value_stack.push(val);
@@ -626,6 +978,12 @@ impl<E: Env> Vm<E> {
     pub fn push_label(&mut self, label: Label) {
         self.labels.push(label);
     }
+    pub fn exec_select(&mut self) {
+        let (cond, b, a) = unsafe { (self.pop_value::<bool>(), self.pop_any(), self.pop_any()) };
+        self.push_any(if cond { a } else { b });
+        self.ip += 1;
+    }
+
     pub fn exec_local_get(&mut self, id: usize) {
         debug_assert!(self.locals.get(self.local_offset + id).is_some());
         let local_val = self.locals.get(self.local_offset + id).unwrap();
@@ -669,12 +1027,42 @@ impl<E: Env> Vm<E> {
         R: Into<StackValue> + Debug,
         F: FnOnce(T) -> R,
     {
-        debug_assert!(self.value_stack.len() >= 1);
+        debug_assert!(!self.value_stack.is_empty());
         let res = op(unsafe { self.pop_value::<T>() });
         self.push_value(res);
         self.ip += 1
     }
 
+    pub fn exec_unop_try_push<T, F, R>(&mut self, op: F) -> Result<(), RuntimeError>
+    where
+        T: PopFromValueStack + Debug,
+        R: Into<StackValue> + Debug,
+        F: FnOnce(T) -> Result<R, RuntimeError>,
+    {
+        debug_assert!(!self.value_stack.is_empty());
+        let res = op(unsafe { self.pop_value::<T>() })?;
+        self.push_value(res);
+        self.ip += 1;
+        Ok(())
+    }
+
+    pub fn exec_binop_try_push<T, F, R>(&mut self, op: F) -> Result<(), RuntimeError>
+    where
+        T: PopFromValueStack + Debug,
+        R: Into<StackValue> + Debug,
+        F: FnOnce(T, T) -> Result<R, RuntimeError>,
+    {
+        unsafe {
+            debug_assert!(self.value_stack.len() >= 2);
+            let c2 = self.pop_value::<T>();
+            let c1 = self.pop_value::<T>();
+            let res = op(c1, c2)?;
+            self.push_value(res);
+            self.ip += 1;
+        }
+        Ok(())
+    }
+
     pub fn exec_binop_push<T, F, R>(&mut self, op: F)
     where
         T: PopFromValueStack + Debug,
@@ -682,7 +1070,7 @@ impl<E: Env> Vm<E> {
         F: FnOnce(T, T) -> R,
     {
         unsafe {
-            debug_assert!(self.value_stack.len() >= 1);
+            debug_assert!(!self.value_stack.is_empty());
             let c2 = self.pop_value::<T>();
             let c1 = self.pop_value::<T>();
             let res = op(c1, c2);
@@ -727,6 +1115,17 @@ impl<E: Env> Vm<E> {
         let next_frame = self.get_return_frame();
         match &self.code.functions[func_id].kind {
             FunctionType::Wasm(internal_function_instance) => {
+                let new_locals = self.code.functions[func_id].t.params.len()
+                    + internal_function_instance.locals.len();
+                let stack_size = self.value_stack.len() + self.locals.len() + new_locals;
+                if self.activation_stack.len() >= self.max_call_depth
+                    || stack_size > self.max_stack_size
+                {
+                    return Err(RuntimeError::StackOverflow {
+                        depth: self.activation_stack.len(),
+                        call_stack: self.call_stack(),
+                    });
+                }
                 if let Some(f) = next_frame {
                     let frame = self.activation_stack.last_mut().unwrap();
                     *frame = f
@@ -826,6 +1225,24 @@ impl<E: Env> Vm<E> {
         self.enter_function(id, params.iter().cloned(), results.collect(), env)
     }
 
+    pub fn exec_call_indirect(
+        &mut self,
+        type_id: usize,
+        table: usize,
+        env: &mut E,
+    ) -> Result<(), RuntimeError> {
+        let i = unsafe { self.pop_u32() };
+        let func_id = self.tables[table]
+            .get(i)
+            .map_err(|_| RuntimeError::UndefinedElement)?
+            .ok_or(RuntimeError::UninitializedElement)? as usize;
+        let expected = &self.types.as_ref().unwrap()[type_id];
+        if self.code.functions[func_id].t != *expected {
+            return Err(RuntimeError::IndirectCallTypeMismatch { type_id, func_id });
+        }
+        self.exec_call(func_id, env)
+    }
+
     pub fn exec_return(&mut self) -> bool {
         let current_frame = self.activation_stack.last().cloned().unwrap();
         let return_values = (0..current_frame.arity)
@@ -836,32 +1253,29 @@ impl<E: Env> Vm<E> {
         self.leave_wasm_function()
     }
 
-    pub fn label_from_blocktype(&self, blocktype: &Blocktype) -> Label {
-        match blocktype {
+    pub fn label_from_blocktype(&self, blocktype: &Blocktype, is_loop: bool) -> Label {
+        let (in_count, out_count) = match blocktype {
             Blocktype::TypeIndex(t_id) => {
                 let t = &self.types.as_ref().unwrap()[*t_id as usize];
-                let in_count = t.params.len();
-                let out_count = t.results.len();
-                let stack_height = self.value_stack.len() - in_count;
-                Label {
-                    stack_height,
-                    out_count,
-                }
+                (t.params.len(), t.results.len())
             }
-            _ => Label {
-                stack_height: self.value_stack.len(),
-                out_count: 0,
-            },
+            Blocktype::Value(_) => (0, 1),
+            Blocktype::Empty => (0, 0),
+        };
+        //NOTE: Branching to a loop jumps back to its start, so the label takes the loop params
+        Label {
+            stack_height: self.value_stack.len() - in_count,
+            out_count: if is_loop { in_count } else { out_count },
         }
     }
 
     pub fn exec_block(&mut self, blocktype: Blocktype) {
-        self.push_label(self.label_from_blocktype(&blocktype));
+        self.push_label(self.label_from_blocktype(&blocktype, false));
         self.ip += 1;
     }
 
     pub fn exec_loop(&mut self, blocktype: Blocktype) {
-        self.push_label(self.label_from_blocktype(&blocktype));
+        self.push_label(self.label_from_blocktype(&blocktype, true));
         self.ip += 1;
     }
 
@@ -870,7 +1284,7 @@ impl<E: Env> Vm<E> {
     }
     pub fn exec_if(&mut self, jump: isize, blocktype: Blocktype) {
         let cond = unsafe { self.pop_value::<bool>() };
-        let label = self.label_from_blocktype(&blocktype);
+        let label = self.label_from_blocktype(&blocktype, false);
         if cond {
             self.ip += 1
         } else {
@@ -882,34 +1296,56 @@ impl<E: Env> Vm<E> {
         self.ip = (jmp + self.ip as isize) as usize;
     }
 
-    pub fn exec_br(&mut self, target: usize, jmp: isize) {
+    /// Returns false if the branch left the outermost function, just like `exec_return`.
+    pub fn exec_br(&mut self, target: usize, jmp: isize) -> bool {
+        let frame_label_offset = self.activation_stack.last().unwrap().label_stack_offset;
+        if target >= self.labels.len() - frame_label_offset {
+            //NOTE: The outermost label of a function is its body, branching there returns.
+            return self.exec_return();
+        }
         if target != 0 {
             self.labels.truncate(self.labels.len() - target);
         }
 
         let target_label = self.labels.pop().unwrap();
         self.jump(jmp);
-        if target_label.out_count > 0 {
-            if target_label.out_count > 1 {
-                todo!()
-            } else {
+        match target_label.out_count {
+            0 => self.value_stack.truncate(target_label.stack_height),
+            1 => {
                 let result = self.pop_any();
                 self.value_stack.truncate(target_label.stack_height);
                 self.push_any(result);
             }
-        } else {
-            self.value_stack.truncate(target_label.stack_height);
+            n => {
+                let results = self
+                    .value_stack
+                    .drain(self.value_stack.len() - n..)
+                    .collect::<SmallVec<[StackValue; 4]>>();
+                self.value_stack.truncate(target_label.stack_height);
+                self.value_stack.extend(results);
+            }
         }
+        true
     }
 
-    pub fn exec_br_if(&mut self, target: usize, jump: isize) {
+    pub fn exec_br_if(&mut self, target: usize, jump: isize) -> bool {
         if unsafe { self.pop_value() } {
-            self.exec_br(target, jump);
+            self.exec_br(target, jump)
         } else {
             self.ip += 1;
+            true
         }
     }
 
+    pub fn exec_br_table(&mut self) -> bool {
+        let index = unsafe { self.pop_u32() } as usize;
+        let Op::BrTable { labels, default } = self.fetch_instruction() else {
+            unreachable!()
+        };
+        let target = *labels.get(index).unwrap_or(default);
+        self.exec_br(target.label, target.jmp)
+    }
+
     pub fn exec_memory_init(
         &mut self,
         bytecode: &Bytecode,
@@ -920,33 +1356,55 @@ impl<E: Env> Vm<E> {
         let size = unsafe { self.pop_i32() } as usize;
         let source = unsafe { self.pop_i32() } as usize;
         let dest = unsafe { self.pop_i32() } as usize;
+        self.save_memory(dest, size);
         let mem = self.mem.as_mut().unwrap();
-        let src = data_info.get_data();
-        let src_region_size = (source + size);
-        let dst_region_size = (dest + size) as usize;
-        //println!("data: {:?}", data_info.get_data());
-        //println!("src region: {}, src len: {}", src_region_size, src.len());
-        //println!("dst region: {}, dst len: {}", dst_region_size, mem.len());
-        if src_region_size > src.len() || dst_region_size >= mem.len() {
-            return Err(RuntimeError::MemoryAddressOutOfScope);
-        };
-        let dst_region = &mut mem[dest..dst_region_size];
-        let src_region = &data_info.get_data()[source..src_region_size];
-        dst_region.clone_from_slice(src_region);
+        let src_region = data_info
+            .get_data()
+            .get(source..source + size)
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
+        mem.range_mut(dest, size)?.copy_from_slice(src_region);
+        self.record_memory_write(dest, size);
         self.ip += 1;
         //println!("memory now: {:?}", dst_region);
         Ok(())
     }
 
-    pub fn exec_memory_grow(&mut self) {
-        let grow_by = unsafe { self.pop_u32() as usize } * WASM_PAGE_SIZE;
+    pub fn exec_memory_size(&mut self) {
+        let size = self.mem.as_ref().unwrap().size();
+        self.push_value(size);
+        self.ip += 1;
+    }
+
+    pub fn exec_memory_grow(&mut self, env: &mut E) {
+        let n = unsafe { self.pop_u32() };
         let mem = self.mem.as_mut().unwrap();
-        let old_size = mem.len();
-        mem.resize(old_size + grow_by, 0);
-        self.push_value(old_size as u32);
+        match mem.grow(n) {
+            Some(old_size) => {
+                let new_size = mem.size();
+                self.push_value(old_size);
+                env.memory_grown(old_size, new_size);
+            }
+            None => self.push_value(u32::MAX),
+        }
         self.ip += 1;
     }
 
+    /// Limits how many pages memory.grow may allocate in total, on top of the declared maximum
+    pub fn set_max_memory_pages(&mut self, pages: u32) {
+        if let Some(mem) = &mut self.mem {
+            mem.set_cap(pages);
+        }
+    }
+
+    pub fn memory(&self) -> Option<&MemoryInstance> {
+        self.mem.as_ref()
+    }
+
+    /// The memory slot of the instance, a store moves shared memories in and out of it
+    pub(crate) fn memory_slot(&mut self) -> &mut Option<MemoryInstance> {
+        &mut self.mem
+    }
+
     pub fn exec_memory_fill(&mut self) -> Result<(), RuntimeError> {
         let (n, val, dest) = unsafe {
             (
@@ -955,11 +1413,10 @@ impl<E: Env> Vm<E> {
                 self.pop_u32() as usize,
             )
         };
+        self.save_memory(dest, n);
         let mem = self.mem.as_mut().unwrap();
-        let region = mem
-            .get_mut(dest..dest + n)
-            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
-        region.fill(val as u8);
+        mem.range_mut(dest, n)?.fill(val as u8);
+        self.record_memory_write(dest, n);
         self.ip += 1;
         Ok(())
     }
@@ -972,17 +1429,96 @@ impl<E: Env> Vm<E> {
                 self.pop_value::<u32>() as usize,
             )
         };
+        self.save_memory(d, n);
         let mem = self.mem.as_mut().unwrap();
-        _ = mem
-            .get(s..s + n)
-            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
-        _ = mem
-            .get(d..d + n)
-            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
-        mem.copy_within(s..s + n, d);
+        mem.copy_within(d, s, n)?;
+        self.record_memory_write(d, n);
+        self.ip += 1;
+        Ok(())
+    }
+    pub fn exec_table_get(&mut self, table: usize) -> Result<(), RuntimeError> {
+        let i = unsafe { self.pop_u32() };
+        let val = self.tables[table].get(i)?;
+        self.exec_push(val);
+        Ok(())
+    }
+
+    pub fn exec_table_set(&mut self, table: usize) -> Result<(), RuntimeError> {
+        let (val, i) = unsafe { (self.pop_value::<Ref>(), self.pop_u32()) };
+        self.tables[table].set(i, val)?;
+        self.ip += 1;
+        Ok(())
+    }
+
+    pub fn exec_table_grow(&mut self, table: usize) {
+        let (n, init) = unsafe { (self.pop_u32(), self.pop_value::<Ref>()) };
+        //NOTE: A failed grow pushes -1
+        let res = self.tables[table].grow(n, init).unwrap_or(u32::MAX);
+        self.exec_push(res);
+    }
+
+    pub fn exec_table_fill(&mut self, table: usize) -> Result<(), RuntimeError> {
+        let (n, val, i) = unsafe { (self.pop_u32(), self.pop_value::<Ref>(), self.pop_u32()) };
+        self.tables[table].fill(i, val, n)?;
+        self.ip += 1;
+        Ok(())
+    }
+
+    pub fn exec_table_copy(&mut self, dst: usize, src: usize) -> Result<(), RuntimeError> {
+        let (n, s, d) = unsafe { (self.pop_u32(), self.pop_u32(), self.pop_u32()) };
+        self.copy_table(dst, src, d, s, n)?;
+        self.ip += 1;
+        Ok(())
+    }
+
+    pub(crate) fn copy_table(
+        &mut self,
+        dst: usize,
+        src: usize,
+        d: u32,
+        s: u32,
+        n: u32,
+    ) -> Result<(), RuntimeError> {
+        if dst == src {
+            self.tables[dst].copy_within(d, s, n)
+        } else {
+            let src_region: SmallVec<[Ref; 16]> = self.tables[src].range(s, n)?.into();
+            self.tables[dst].write(d, &src_region)
+        }
+    }
+
+    pub fn exec_table_init(&mut self, elem_id: usize, table: usize) -> Result<(), RuntimeError> {
+        let (n, s, d) = unsafe {
+            (
+                self.pop_u32() as usize,
+                self.pop_u32() as usize,
+                self.pop_u32(),
+            )
+        };
+        self.init_table(elem_id, table, d, s, n)?;
         self.ip += 1;
         Ok(())
     }
+
+    pub(crate) fn init_table(
+        &mut self,
+        elem_id: usize,
+        table: usize,
+        d: u32,
+        s: usize,
+        n: usize,
+    ) -> Result<(), RuntimeError> {
+        let src_region = self.elements[elem_id]
+            .get(s..s + n)
+            .ok_or(RuntimeError::TableAddressOutOfScope)?;
+        self.tables[table].write(d, src_region)
+    }
+
+    pub fn exec_elem_drop(&mut self, elem_id: usize) {
+        self.elements[elem_id] = Vec::new();
+        self.ip += 1;
+    }
+
     pub fn exec_op(&mut self, bytecode: &Bytecode, env: &mut E) -> Result<bool, RuntimeError> {
         match self.fetch_instruction() {
             Op::Unreachable => {
@@ -999,8 +1535,21 @@ impl<E: Env> Vm<E> {
                     return Ok(true);
                 };
             }
-            Op::Br { label, jmp } => self.exec_br(*label, *jmp),
-            Op::BrIf { label, jmp } => self.exec_br_if(*label, *jmp),
+            Op::Br { label, jmp } => {
+                if !self.exec_br(*label, *jmp) {
+                    return Ok(true);
+                }
+            }
+            Op::BrIf { label, jmp } => {
+                if !self.exec_br_if(*label, *jmp) {
+                    return Ok(true);
+                }
+            }
+            Op::BrTable { .. } => {
+                if !self.exec_br_table() {
+                    return Ok(true);
+                }
+            }
             Op::Return => {
                 if !self.exec_return() {
                     //println!("Done!");
@@ -1008,12 +1557,14 @@ impl<E: Env> Vm<E> {
                 }
             }
             Op::Call(c) => self.exec_call(*c, env)?,
-            Op::CallIndirect { table, type_id } => todo!(),
+            Op::CallIndirect { type_id, table } => {
+                self.exec_call_indirect(*type_id, *table, env)?
+            }
             Op::Drop => {
                 _ = self.pop_any();
                 self.ip += 1
             }
-            Op::Select(value_type) => todo!(),
+            Op::Select(_) => self.exec_select(),
             Op::LocalGet(id) => self.exec_local_get(*id as usize),
             Op::LocalSet(id) => self.exec_local_set(*id as usize),
             Op::LocalTee(id) => self.exec_local_tee(*id as usize),
@@ -1057,54 +1608,142 @@ impl<E: Env> Vm<E> {
             Op::I32Les => self.exec_binop_push(|a: i32, b: i32| a <= b),
             Op::I32Geu => self.exec_binop_push(|a: u32, b: u32| a >= b),
             Op::I32Ges => self.exec_binop_push(|a: i32, b: i32| a >= b),
-            Op::I64Eqz => self.exec_unop_push(|val: u32| val == 0),
+            Op::I64Eqz => self.exec_unop_push(|val: u64| val == 0),
             Op::I64Eq => self.exec_binop_push(|a: u64, b: u64| a == b),
             Op::I64Ne => self.exec_binop_push(|a: u64, b: u64| a != b),
             Op::I64Lts => self.exec_binop_push(|a: i64, b: i64| a < b),
             Op::I64Ltu => self.exec_binop_push(|a: u64, b: u64| a < b),
             Op::I64Gts => self.exec_binop_push(|a: i64, b: i64| a > b),
             Op::I64Gtu => self.exec_binop_push(|a: u64, b: u64| a > b),
-            Op::I64Les => self.exec_binop_push(|a: u64, b: u64| a <= b),
-            Op::I64Leu => self.exec_binop_push(|a: i64, b: i64| a <= b),
+            Op::I64Les => self.exec_binop_push(|a: i64, b: i64| a <= b),
+            Op::I64Leu => self.exec_binop_push(|a: u64, b: u64| a <= b),
             Op::I64Geu => self.exec_binop_push(|a: u64, b: u64| a >= b),
             Op::I64Ges => self.exec_binop_push(|a: i64, b: i64| a >= b),
-            Op::I32Add => self.exec_binop_push(|a: u32, b: u32| a + b),
+            Op::F32Eq => self.exec_binop_push(|a: f32, b: f32| a == b),
+            Op::F32Ne => self.exec_binop_push(|a: f32, b: f32| a != b),
+            Op::F32Lt => self.exec_binop_push(|a: f32, b: f32| a < b),
+            Op::F32Gt => self.exec_binop_push(|a: f32, b: f32| a > b),
+            Op::F32Le => self.exec_binop_push(|a: f32, b: f32| a <= b),
+            Op::F32Ge => self.exec_binop_push(|a: f32, b: f32| a >= b),
+            Op::F64Eq => self.exec_binop_push(|a: f64, b: f64| a == b),
+            Op::F64Ne => self.exec_binop_push(|a: f64, b: f64| a != b),
+            Op::F64Lt => self.exec_binop_push(|a: f64, b: f64| a < b),
+            Op::F64Gt => self.exec_binop_push(|a: f64, b: f64| a > b),
+            Op::F64Le => self.exec_binop_push(|a: f64, b: f64| a <= b),
+            Op::F64Ge => self.exec_binop_push(|a: f64, b: f64| a >= b),
+            Op::I32Clz => self.exec_unop_push(|a: u32| a.leading_zeros()),
+            Op::I32Ctz => self.exec_unop_push(|a: u32| a.trailing_zeros()),
+            Op::I32Popcnt => self.exec_unop_push(|a: u32| a.count_ones()),
+            Op::I64Clz => self.exec_unop_push(|a: u64| a.leading_zeros() as u64),
+            Op::I64Ctz => self.exec_unop_push(|a: u64| a.trailing_zeros() as u64),
+            Op::I64Popcnt => self.exec_unop_push(|a: u64| a.count_ones() as u64),
+            Op::I32Add => self.exec_binop_push(|a: u32, b: u32| a.wrapping_add(b)),
             Op::I32Sub => self.exec_binop_push(|a: u32, b: u32| a.wrapping_sub(b)),
-            Op::I32Mul => self.exec_binop_push(|a: u32, b: u32| a * b),
-            Op::I32Divs => self.exec_binop_push(|a: i32, b: i32| a / b),
-            Op::I32Divu => self.exec_binop_push(|a: u32, b: u32| a / b),
-            Op::I32Rems => self.exec_binop_push(|a: i32, b: i32| a % b),
-            Op::I32Remu => self.exec_binop_push(|a: u32, b: u32| a % b),
+            Op::I32Mul => self.exec_binop_push(|a: u32, b: u32| a.wrapping_mul(b)),
+            Op::I32Divs => self.exec_binop_try_push(int::i32_div_s)?,
+            Op::I32Divu => self.exec_binop_try_push(int::i32_div_u)?,
+            Op::I32Rems => self.exec_binop_try_push(int::i32_rem_s)?,
+            Op::I32Remu => self.exec_binop_try_push(int::i32_rem_u)?,
             Op::I32And => self.exec_binop_push(|a: u32, b: u32| a & b),
             Op::I32Or => self.exec_binop_push(|a: u32, b: u32| a | b),
             Op::I32Xor => self.exec_binop_push(|a: u32, b: u32| a ^ b),
             Op::I32Shl => self.exec_binop_push(|a: u32, b: u32| a.wrapping_shl(b)),
-            Op::I32Shrs => self.exec_binop_push(|a: i32, b: i32| a >> b),
-            Op::I32Shru => self.exec_binop_push(|a: u32, b: u32| a >> b),
-            Op::I32Rotl => todo!(),
-            Op::I32Rotr => todo!(),
-            Op::I64Add => self.exec_binop_push(|a: u64, b: u64| a + b),
-            Op::I64Sub => self.exec_binop_push(|a: u64, b: u64| a - b),
-            Op::I64Mul => self.exec_binop_push(|a: u64, b: u64| a * b),
-            Op::I64Divs => self.exec_binop_push(|a: i64, b: i64| a / b),
-            Op::I64Divu => self.exec_binop_push(|a: u64, b: u64| a / b),
-            Op::I64Rems => self.exec_binop_push(|a: i64, b: i64| a % b),
-            Op::I64Remu => self.exec_binop_push(|a: u64, b: u64| a % b),
-            Op::I64And => self.exec_binop_push(|a: u32, b: u32| a & b),
-            Op::I64Or => self.exec_binop_push(|a: u32, b: u32| a | b),
+            Op::I32Shrs => self.exec_binop_push(|a: i32, b: i32| a.wrapping_shr(b as u32)),
+            Op::I32Shru => self.exec_binop_push(|a: u32, b: u32| a.wrapping_shr(b)),
+            Op::I32Rotl => self.exec_binop_push(|a: u32, b: u32| a.rotate_left(b % 32)),
+            Op::I32Rotr => self.exec_binop_push(|a: u32, b: u32| a.rotate_right(b % 32)),
+            Op::I64Add => self.exec_binop_push(|a: u64, b: u64| a.wrapping_add(b)),
+            Op::I64Sub => self.exec_binop_push(|a: u64, b: u64| a.wrapping_sub(b)),
+            Op::I64Mul => self.exec_binop_push(|a: u64, b: u64| a.wrapping_mul(b)),
+            Op::I64Divs => self.exec_binop_try_push(int::i64_div_s)?,
+            Op::I64Divu => self.exec_binop_try_push(int::i64_div_u)?,
+            Op::I64Rems => self.exec_binop_try_push(int::i64_rem_s)?,
+            Op::I64Remu => self.exec_binop_try_push(int::i64_rem_u)?,
+            Op::I64And => self.exec_binop_push(|a: u64, b: u64| a & b),
+            Op::I64Or => self.exec_binop_push(|a: u64, b: u64| a | b),
             Op::I64Xor => self.exec_binop_push(|a: u64, b: u64| a ^ b),
-            Op::I64Shl => self.exec_binop_push(|a: u64, b: u64| a << b),
-            Op::I64Shrs => self.exec_binop_push(|a: i64, b: i64| a >> b),
-            Op::I64Shru => self.exec_binop_push(|a: u64, b: u64| a >> b),
+            Op::I64Shl => self.exec_binop_push(|a: u64, b: u64| a.wrapping_shl(b as u32)),
+            Op::I64Shrs => self.exec_binop_push(|a: i64, b: i64| a.wrapping_shr(b as u32)),
+            Op::I64Shru => self.exec_binop_push(|a: u64, b: u64| a.wrapping_shr(b as u32)),
             Op::MemoryInit { data_id, .. } => self.exec_memory_init(bytecode, *data_id)?,
-            Op::I64Rotl => todo!(),
-            Op::I64Rotr => todo!(),
+            Op::I64Rotl => self.exec_binop_push(|a: u64, b: u64| a.rotate_left((b % 64) as u32)),
+            Op::I64Rotr => self.exec_binop_push(|a: u64, b: u64| a.rotate_right((b % 64) as u32)),
             Op::MemoryCopy { .. } => self.exec_memory_copy()?,
             Op::MemoryFill { .. } => self.exec_memory_fill()?,
-            Op::MemoryGrow { .. } => self.exec_memory_grow(),
+            Op::MemorySize { .. } => self.exec_memory_size(),
+            Op::MemoryGrow { .. } => self.exec_memory_grow(env),
+            Op::TableGet(table) => self.exec_table_get(*table)?,
+            Op::TableSet(table) => self.exec_table_set(*table)?,
+            Op::TableSize(table) => self.exec_push(self.tables[*table].size()),
+            Op::TableGrow(table) => self.exec_table_grow(*table),
+            Op::TableFill(table) => self.exec_table_fill(*table)?,
+            Op::TableCopy { dst, src } => self.exec_table_copy(*dst, *src)?,
+            Op::TableInit { elem_id, table } => self.exec_table_init(*elem_id, *table)?,
+            Op::ElemDrop(elem_id) => self.exec_elem_drop(*elem_id),
+            Op::RefNull(_) => self.exec_push(NULL_REF),
+            Op::RefIsNull => self.exec_unop_push(|r: Ref| r.is_none()),
+            Op::RefFunc(id) => self.exec_push(Some(*id as u32)),
             Op::I32WrapI64 => impl_convert!(self, a, u64, a as u32),
             Op::I64ExtendI32s => impl_convert!(self, a, i32, a as i64),
             Op::I64ExtendI32u => impl_convert!(self, a, u32, a as u64),
+            Op::F32Abs => self.exec_unop_push(|a: f32| a.abs()),
+            Op::F32Neg => self.exec_unop_push(|a: f32| -a),
+            Op::F32Ceil => self.exec_unop_push(|a: f32| a.ceil()),
+            Op::F32Floor => self.exec_unop_push(|a: f32| a.floor()),
+            Op::F32Trunc => self.exec_unop_push(|a: f32| a.trunc()),
+            Op::F32Nearest => self.exec_unop_push(|a: f32| a.round_ties_even()),
+            Op::F32Sqrt => self.exec_unop_push(|a: f32| a.sqrt()),
+            Op::F32Add => self.exec_binop_push(|a: f32, b: f32| a + b),
+            Op::F32Sub => self.exec_binop_push(|a: f32, b: f32| a - b),
+            Op::F32Mul => self.exec_binop_push(|a: f32, b: f32| a * b),
+            Op::F32Div => self.exec_binop_push(|a: f32, b: f32| a / b),
+            Op::F32Min => self.exec_binop_push(float::f32_min),
+            Op::F32Max => self.exec_binop_push(float::f32_max),
+            Op::F32Copysign => self.exec_binop_push(|a: f32, b: f32| a.copysign(b)),
+            Op::F64Abs => self.exec_unop_push(|a: f64| a.abs()),
+            Op::F64Neg => self.exec_unop_push(|a: f64| -a),
+            Op::F64Ceil => self.exec_unop_push(|a: f64| a.ceil()),
+            Op::F64Floor => self.exec_unop_push(|a: f64| a.floor()),
+            Op::F64Trunc => self.exec_unop_push(|a: f64| a.trunc()),
+            Op::F64Nearest => self.exec_unop_push(|a: f64| a.round_ties_even()),
+            Op::F64Sqrt => self.exec_unop_push(|a: f64| a.sqrt()),
+            Op::F64Add => self.exec_binop_push(|a: f64, b: f64| a + b),
+            Op::F64Sub => self.exec_binop_push(|a: f64, b: f64| a - b),
+            Op::F64Mul => self.exec_binop_push(|a: f64, b: f64| a * b),
+            Op::F64Div => self.exec_binop_push(|a: f64, b: f64| a / b),
+            Op::F64Min => self.exec_binop_push(float::f64_min),
+            Op::F64Max => self.exec_binop_push(float::f64_max),
+            Op::F64Copysign => self.exec_binop_push(|a: f64, b: f64| a.copysign(b)),
+            Op::I32TruncF32s => self.exec_unop_try_push(|a: f32| float::trunc_i32(a as f64))?,
+            Op::I32TruncF32u => self.exec_unop_try_push(|a: f32| float::trunc_u32(a as f64))?,
+            Op::I32TruncF64s => self.exec_unop_try_push(float::trunc_i32)?,
+            Op::I32TruncF64u => self.exec_unop_try_push(float::trunc_u32)?,
+            Op::I64TruncF32s => self.exec_unop_try_push(|a: f32| float::trunc_i64(a as f64))?,
+            Op::I64TruncF32u => self.exec_unop_try_push(|a: f32| float::trunc_u64(a as f64))?,
+            Op::I64TruncF64s => self.exec_unop_try_push(float::trunc_i64)?,
+            Op::I64TruncF64u => self.exec_unop_try_push(float::trunc_u64)?,
+            Op::I32TruncSatF32s => impl_convert!(self, a, f32, a as i32),
+            Op::I32TruncSatF32u => impl_convert!(self, a, f32, a as u32),
+            Op::I32TruncSatF64s => impl_convert!(self, a, f64, a as i32),
+            Op::I32TruncSatF64u => impl_convert!(self, a, f64, a as u32),
+            Op::I64TruncSatF32s => impl_convert!(self, a, f32, a as i64),
+            Op::I64TruncSatF32u => impl_convert!(self, a, f32, a as u64),
+            Op::I64TruncSatF64s => impl_convert!(self, a, f64, a as i64),
+            Op::I64TruncSatF64u => impl_convert!(self, a, f64, a as u64),
+            Op::F32ConvertI32s => impl_convert!(self, a, i32, a as f32),
+            Op::F32ConvertI32u => impl_convert!(self, a, u32, a as f32),
+            Op::F32ConvertI64s => impl_convert!(self, a, i64, a as f32),
+            Op::F32ConvertI64u => impl_convert!(self, a, u64, a as f32),
+            Op::F32DemoteF64 => impl_convert!(self, a, f64, a as f32),
+            Op::F64ConvertI32s => impl_convert!(self, a, i32, a as f64),
+            Op::F64ConvertI32u => impl_convert!(self, a, u32, a as f64),
+            Op::F64ConvertI64s => impl_convert!(self, a, i64, a as f64),
+            Op::F64ConvertI64u => impl_convert!(self, a, u64, a as f64),
+            Op::F64PromoteF32 => impl_convert!(self, a, f32, a as f64),
+            Op::I32ReinterpretF32 => impl_convert!(self, a, f32, a.to_bits()),
+            Op::I64ReinterpretF64 => impl_convert!(self, a, f64, a.to_bits()),
+            Op::F32ReinterpretI32 => impl_convert!(self, a, u32, f32::from_bits(a)),
+            Op::F64ReinterpretI64 => impl_convert!(self, a, u64, f64::from_bits(a)),
         };
         Ok(false)
     }
@@ -1118,56 +1757,653 @@ impl<E: Env> Vm<E> {
     }
 
     pub fn run(&mut self, bytecode: &Bytecode, env: &mut E) -> Result<(), RuntimeError> {
-        if self.func_id.is_none() {
-            Err(RuntimeError::NoFunctionToExecute)
-        } else {
-            loop {
-                let end = self.exec_op(bytecode, env)?;
-                //println!("stack now: {:?}", self.value_stack);
-                if end {
-                    break;
-                }
-            }
-            Ok(())
-        }
-    }
-    pub fn stack_to_local_vals(
-        &self,
-        result_types: impl Iterator<Item = ValueType>,
-    ) -> Vec<LocalValue> {
-        self.value_stack
-            .iter()
-            .zip(result_types)
-            .map(|(stack_val, t)| LocalValue::init_from_type_and_val(t, *stack_val))
-            .collect()
+        while !self.step(bytecode, env)? {}
+        Ok(())
     }
 
-    pub fn set_func(
-        &mut self,
-        func_id: usize,
-        params: impl IntoIterator<Item = LocalValue>,
-    ) -> Result<(), RuntimeError> {
-        self.enter_native_function(func_id, params.into_iter())
+    /// Executes a single instruction, returns true once the entered function has finished.
+    /// A trap leaves the vm at the failing instruction so its state can still be inspected.
+    pub fn step(&mut self, bytecode: &Bytecode, env: &mut E) -> Result<bool, RuntimeError> {
+        if self.activation_stack.is_empty() {
+            return Err(RuntimeError::NoFunctionToExecute);
+        }
+        self.consume_fuel()?;
+        #[cfg(feature = "profile")]
+        if let Some(profiler) = &mut self.profiler {
+            let depth = self.activation_stack.len();
+            let path = || self.activation_stack.iter().map(|f| f.func_id).collect();
+            profiler.enter(depth, self.func_id.unwrap(), path);
+        }
+        #[cfg(feature = "time-travel")]
+        self.history_begin();
+        #[cfg(feature = "coverage")]
+        self.record_coverage();
+        #[cfg(feature = "trace")]
+        self.trace_begin();
+        let res = self.exec_op(bytecode, env).map_err(|e| self.trap_at_ip(e));
+        #[cfg(feature = "trace")]
+        self.trace_end();
+        #[cfg(feature = "time-travel")]
+        if let Some(history) = &mut self.history {
+            history.commit();
+        }
+        #[cfg(feature = "profile")]
+        if !matches!(res, Ok(false))
+            && let Some(profiler) = &mut self.profiler
+        {
+            profiler.pause();
+        }
+        res
     }
 
-    pub fn run_func(
-        &mut self,
-        bytecode: &Bytecode,
-        info: &BytecodeInfo,
-        env: &mut E,
-    ) -> Result<Vec<LocalValue>, RuntimeError> {
-        self.run(bytecode, env)?;
-        let res = if let Some(func_id) = self.func_id {
-            let func_t = &self.types.as_ref().unwrap()[info.functions[func_id].type_id];
-            let res = self.stack_to_local_vals(func_t.results.iter().cloned());
-            // println!("res: {:?}", res);
-            assert!(res.len() == func_t.results.len());
-            Ok(res)
-        } else {
-            Err(RuntimeError::NoFunctionSet)
-        };
-        self.reset_state();
-        res
+    /// The operand stack of the innermost call
+    pub fn operand_stack(&self) -> &[StackValue] {
+        &self.value_stack[self.operand_stack_start()..]
+    }
+
+    fn operand_stack_start(&self) -> usize {
+        //NOTE: Caller frames store the stack height of their call instruction
+        let depth = self.activation_stack.len();
+        match depth {
+            0 | 1 => 0,
+            _ => self.activation_stack[depth - 2].stack_height,
+        }
+    }
+
+    /// Records every executed instruction until it is replaced or removed with `None`
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Option<Arc<Mutex<TraceRecorder>>>) {
+        self.tracer = tracer;
+    }
+
+    /// Counts instructions and time per call path until it is removed
+    #[cfg(feature = "profile")]
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+    }
+
+    #[cfg(feature = "profile")]
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    /// Counts executed instructions and branch directions until it is removed
+    #[cfg(feature = "coverage")]
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) {
+        self.coverage = coverage;
+    }
+
+    #[cfg(feature = "coverage")]
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    #[cfg(feature = "coverage")]
+    fn record_coverage(&mut self) {
+        if self.coverage.is_none() {
+            return;
+        }
+        let Some((func_id, instruction)) = self.position() else {
+            return;
+        };
+        //NOTE: The condition is still on the stack before the branch executes
+        let condition = match self.code.instructions[self.ip] {
+            Op::If { .. } | Op::BrIf { .. } => self.value_stack.last().map(|v| v.bits() != 0),
+            _ => None,
+        };
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(func_id, instruction, condition);
+        }
+    }
+
+    /// Records executed instructions for [`Self::step_back`] until it is removed
+    #[cfg(feature = "time-travel")]
+    pub fn set_history(&mut self, history: Option<History>) {
+        self.history = history;
+    }
+
+    #[cfg(feature = "time-travel")]
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    #[cfg(feature = "time-travel")]
+    pub fn take_history(&mut self) -> Option<History> {
+        self.history.take()
+    }
+
+    /// Undoes the last executed instruction and returns what it changed, the vm is left before
+    /// the instruction as if it never ran. Returns `None` if the history does not reach back
+    /// further. Going back past the undo log replays from a checkpoint, which calls host functions
+    /// again.
+    #[cfg(feature = "time-travel")]
+    pub fn step_back(
+        &mut self,
+        bytecode: &Bytecode,
+        env: &mut E,
+    ) -> Result<Option<UndoEntry>, RuntimeError> {
+        let Some(history) = &mut self.history else {
+            return Ok(None);
+        };
+        if history.entries.is_empty() {
+            let Some((step, snapshot)) = history.checkpoint_before(history.step).cloned() else {
+                return Ok(None);
+            };
+            //NOTE: Replaying up to the current instruction fills the undo log again
+            let target = history.step;
+            history.step = step;
+            //NOTE: Restoring a snapshot from outside clears the history, a checkpoint must not
+            let history = self.history.take();
+            let res = self.restore(&snapshot);
+            self.history = history;
+            res.map_err(|_| RuntimeError::ReplayDiverged)?;
+            let replaying = |vm: &Self| vm.history.as_ref().is_some_and(|h| h.step < target);
+            while replaying(self) {
+                //NOTE: A host function that returns something else than before can end the
+                //replay early
+                if self.step(bytecode, env)? && replaying(self) {
+                    return Err(RuntimeError::ReplayDiverged);
+                }
+            }
+        }
+        let Some(history) = &mut self.history else {
+            return Ok(None);
+        };
+        let Some(entry) = history.entries.pop_back() else {
+            return Ok(None);
+        };
+        history.step -= 1;
+        self.undo(&entry);
+        Ok(Some(entry))
+    }
+
+    #[cfg(feature = "time-travel")]
+    fn history_begin(&mut self) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        if history.wants_checkpoint() {
+            let snapshot = self.snapshot(true);
+            if let Some(history) = &mut self.history {
+                history.add_checkpoint(snapshot);
+            }
+        }
+        let top_frame = self.activation_stack.last().unwrap().clone();
+        let stack_start = self.operand_stack_start();
+        let op = &self.code.instructions[self.ip];
+        let global = match op {
+            Op::GlobalSet(id) => Some((*id, self.globals[*id])),
+            _ => None,
+        };
+        let memory_len = match op {
+            Op::MemoryGrow { .. } => self.mem.as_ref().map(MemoryInstance::len),
+            _ => None,
+        };
+        let tables = matches!(
+            op,
+            Op::TableSet(_)
+                | Op::TableGrow(_)
+                | Op::TableFill(_)
+                | Op::TableCopy { .. }
+                | Op::TableInit { .. }
+                | Op::ElemDrop(_)
+        )
+        .then(|| (self.tables.clone(), self.elements.clone()));
+        let entry = UndoEntry {
+            ip: self.ip,
+            func_id: self.func_id,
+            local_offset: self.local_offset,
+            frames: self.activation_stack.len(),
+            stack_start,
+            stack: self.value_stack[stack_start..].to_vec(),
+            labels_start: top_frame.label_stack_offset,
+            labels: self.labels[top_frame.label_stack_offset..].to_vec(),
+            locals_start: top_frame.locals_offset,
+            locals: self.locals[top_frame.locals_offset..].to_vec(),
+            top_frame,
+            global,
+            memory: vec![],
+            memory_len,
+            tables,
+        };
+        if let Some(history) = &mut self.history {
+            history.current = Some(entry);
+        }
+    }
+
+    #[cfg(feature = "time-travel")]
+    fn undo(&mut self, entry: &UndoEntry) {
+        self.ip = entry.ip;
+        self.func_id = entry.func_id;
+        self.local_offset = entry.local_offset;
+        self.activation_stack.truncate(entry.frames - 1);
+        self.activation_stack.push(entry.top_frame.clone());
+        self.value_stack.truncate(entry.stack_start);
+        self.value_stack.extend_from_slice(&entry.stack);
+        self.labels.truncate(entry.labels_start);
+        self.labels.extend_from_slice(&entry.labels);
+        self.locals.truncate(entry.locals_start);
+        self.locals.extend_from_slice(&entry.locals);
+        if let Some((id, value)) = entry.global {
+            self.globals[id] = value;
+        }
+        if let Some(mem) = &mut self.mem {
+            if let Some(len) = entry.memory_len {
+                mem.truncate(len);
+            }
+            for (addr, bytes) in entry.memory.iter().rev() {
+                mem.bytes_mut()[*addr..*addr + bytes.len()].copy_from_slice(bytes);
+            }
+        }
+        if let Some((tables, elements)) = &entry.tables {
+            self.tables.clone_from(tables);
+            self.elements.clone_from(elements);
+        }
+    }
+
+    pub fn function_names(&self) -> &NameMap {
+        &self.code.function_names
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace_begin(&mut self) {
+        let Some(tracer) = &self.tracer else {
+            return;
+        };
+        let Some((func_id, instruction)) = self.position() else {
+            return;
+        };
+        tracer.lock().unwrap().begin(
+            func_id,
+            self.code.function_names.get(func_id),
+            instruction,
+            &self.code.instructions[self.ip],
+            self.operand_stack().iter().map(StackValue::bits),
+        );
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace_end(&mut self) {
+        if let Some(tracer) = &self.tracer {
+            let stack = self.operand_stack().iter().map(StackValue::bits);
+            tracer.lock().unwrap().end(stack);
+        }
+    }
+
+    /// Called before memory is written, keeps the old contents to undo the write
+    #[cfg_attr(not(feature = "time-travel"), allow(unused_variables))]
+    #[inline(always)]
+    fn save_memory(&mut self, addr: usize, len: usize) {
+        #[cfg(feature = "time-travel")]
+        if let Some(entry) = self.history.as_mut().and_then(|h| h.current.as_mut())
+            && let Some(mem) = &self.mem
+            && let Ok(bytes) = mem.range(addr, len)
+        {
+            entry.memory.push((addr, bytes.to_vec()));
+        }
+    }
+
+    /// Called before the whole memory is handed to a host function, which may write or grow it
+    pub(crate) fn save_whole_memory(&mut self) {
+        #[cfg(feature = "time-travel")]
+        if let Some(entry) = self.history.as_mut().and_then(|h| h.current.as_mut())
+            && let Some(mem) = &self.mem
+        {
+            entry.memory_len.get_or_insert(mem.len());
+            entry.memory.push((0, mem.bytes().to_vec()));
+        }
+    }
+
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    #[inline(always)]
+    fn record_memory_write(&mut self, addr: usize, len: usize) {
+        #[cfg(feature = "trace")]
+        if let Some(tracer) = &self.tracer {
+            let mut tracer = tracer.lock().unwrap();
+            if tracer.is_recording()
+                && let Some(mem) = &self.mem
+                && let Ok(bytes) = mem.range(addr, len)
+            {
+                tracer.memory_write(addr, bytes);
+            }
+        }
+    }
+    /// Limits the number of instructions [`Self::run`] may execute, `None` removes the limit.
+    /// Running out of fuel is reported as [`RuntimeError::OutOfFuel`] before the next instruction
+    /// executes, so the current function can be continued with `run` after adding fuel.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    pub fn add_fuel(&mut self, fuel: u64) {
+        if let Some(remaining) = &mut self.fuel {
+            *remaining = remaining.saturating_add(fuel);
+        }
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    #[inline(always)]
+    pub(crate) fn consume_fuel(&mut self) -> Result<(), RuntimeError> {
+        match &mut self.fuel {
+            Some(0) => Err(RuntimeError::OutOfFuel),
+            Some(fuel) => {
+                *fuel -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    /// Limits the number of values on the value stack and in locals of all active calls
+    pub fn set_max_stack_size(&mut self, size: usize) {
+        self.max_stack_size = size;
+    }
+
+    pub fn max_stack_size(&self) -> usize {
+        self.max_stack_size
+    }
+
+    fn instruction_index(&self, func_id: usize, ip: usize) -> usize {
+        match &self.code.functions[func_id].kind {
+            FunctionType::Wasm(f) => ip - f.code_offset,
+            FunctionType::Native(_) => ip,
+        }
+    }
+
+    pub(crate) fn call_frame(&self, func_id: usize, ip: usize) -> CallFrame {
+        CallFrame {
+            func_id,
+            func_name: self.code.function_names.get(func_id).map(str::to_string),
+            instruction: self.instruction_index(func_id, ip),
+        }
+    }
+
+    /// The current call stack, starting with the instruction that is executed next
+    pub fn call_stack(&self) -> CallStack {
+        let frame = |func_id: usize, ip: usize| self.call_frame(func_id, ip);
+        //NOTE: The ip of a caller frame points behind its call instruction
+        let callers = self.activation_stack.iter().rev().skip(1);
+        let frames = self
+            .func_id
+            .map(|func_id| frame(func_id, self.ip))
+            .into_iter()
+            .chain(callers.map(|f| frame(f.func_id, f.ip - 1)));
+        CallStack(frames.collect())
+    }
+
+    /// Number of active wasm function calls
+    pub fn call_depth(&self) -> usize {
+        self.activation_stack.len()
+    }
+
+    /// Function and instruction index of the instruction that is executed next
+    pub fn position(&self) -> Option<(usize, usize)> {
+        if self.activation_stack.is_empty() {
+            return None;
+        }
+        let func_id = self.func_id?;
+        Some((func_id, self.instruction_index(func_id, self.ip)))
+    }
+
+    /// The active calls, innermost call first
+    pub fn frames(&self) -> Vec<FrameView<'_>> {
+        let depth = self.activation_stack.len();
+        let mut stack_start = 0;
+        let mut frames = Vec::with_capacity(depth);
+        for (i, frame) in self.activation_stack.iter().enumerate() {
+            let next = self.activation_stack.get(i + 1);
+            //NOTE: Caller frames store the position and stack height of their call instruction
+            let (ip, stack_end) = match next {
+                Some(_) => (frame.ip - 1, frame.stack_height),
+                None => (self.ip, self.value_stack.len()),
+            };
+            frames.push(FrameView {
+                func_id: frame.func_id,
+                func_name: self.code.function_names.get(frame.func_id),
+                instruction: self.instruction_index(frame.func_id, ip),
+                locals: &self.locals
+                    [frame.locals_offset..next.map_or(self.locals.len(), |n| n.locals_offset)],
+                labels: &self.labels[frame.label_stack_offset
+                    ..next.map_or(self.labels.len(), |n| n.label_stack_offset)],
+                stack: &self.value_stack[stack_start..stack_end],
+            });
+            stack_start = stack_end;
+        }
+        frames.reverse();
+        frames
+    }
+
+    pub fn value_stack(&self) -> &[StackValue] {
+        &self.value_stack
+    }
+
+    pub fn globals(&self) -> &[LocalValue] {
+        &self.globals
+    }
+
+    pub(crate) fn globals_mut(&mut self) -> &mut [LocalValue] {
+        &mut self.globals
+    }
+
+    pub fn tables(&self) -> &[TableInstance] {
+        &self.tables
+    }
+
+    pub(crate) fn tables_mut(&mut self) -> &mut [TableInstance] {
+        &mut self.tables
+    }
+
+    pub(crate) fn elements_mut(&mut self) -> &mut [Vec<Ref>] {
+        &mut self.elements
+    }
+
+    /// The function types of the module by type id
+    pub(crate) fn types(&self) -> &[Type] {
+        self.types.as_deref().unwrap_or_default()
+    }
+
+    pub(crate) fn instructions(&self) -> &[Op] {
+        &self.code.instructions
+    }
+
+    pub(crate) fn functions(&self) -> &[Function] {
+        &self.code.functions
+    }
+
+    pub(crate) fn functions_mut(&mut self) -> &mut Vec<Function> {
+        &mut self.code.functions
+    }
+
+    fn trap_at_ip(&self, error: RuntimeError) -> RuntimeError {
+        self.trap_at(self.func_id.unwrap(), self.ip, error)
+    }
+
+    pub(crate) fn trap_at(&self, func_id: usize, ip: usize, error: RuntimeError) -> RuntimeError {
+        RuntimeError::Trap {
+            func_id,
+            func_name: self.code.function_names.get(func_id).map(str::to_string),
+            instruction: self.instruction_index(func_id, ip),
+            op: self.code.instructions[ip].clone(),
+            error: Box::new(error),
+        }
+    }
+
+    pub fn stack_to_local_vals(
+        &self,
+        result_types: impl Iterator<Item = ValueType>,
+    ) -> Vec<LocalValue> {
+        self.value_stack
+            .iter()
+            .zip(result_types)
+            .map(|(stack_val, t)| LocalValue::init_from_type_and_val(t, *stack_val))
+            .collect()
+    }
+
+    pub fn set_func(
+        &mut self,
+        func_id: usize,
+        params: impl IntoIterator<Item = LocalValue>,
+    ) -> Result<(), RuntimeError> {
+        //NOTE: Replaying from a checkpoint can not reach back into a previous call
+        #[cfg(feature = "time-travel")]
+        if let Some(history) = &mut self.history {
+            history.checkpoints.clear();
+        }
+        self.enter_native_function(func_id, params.into_iter())
+    }
+
+    /// Runs the function entered with [`Self::set_func`] and returns its results.
+    /// After [`RuntimeError::OutOfFuel`] the function is still entered and calling this again resumes it.
+    pub fn run_func(
+        &mut self,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        env: &mut E,
+    ) -> Result<Vec<LocalValue>, RuntimeError> {
+        self.run(bytecode, env)?;
+        self.finish(info)
+    }
+
+    /// Collects the results of the function that just finished and resets the vm
+    pub fn finish(&mut self, info: &BytecodeInfo) -> Result<Vec<LocalValue>, RuntimeError> {
+        let res = if let Some(func_id) = self.func_id {
+            let func_t = &self.types.as_ref().unwrap()[info.functions[func_id].type_id];
+            let res = self.stack_to_local_vals(func_t.results.iter().cloned());
+            // println!("res: {:?}", res);
+            assert!(res.len() == func_t.results.len());
+            Ok(res)
+        } else {
+            Err(RuntimeError::NoFunctionSet)
+        };
+        self.reset_state();
+        res
+    }
+
+    /// Captures memory, globals, tables and element segments. With `call_stack` a suspended call,
+    /// e.g. after running out of fuel or stopping in the debugger, is included as well.
+    pub fn snapshot(&self, call_stack: bool) -> Snapshot {
+        let execution = (call_stack && !self.activation_stack.is_empty()).then(|| ExecutionState {
+            ip: self.ip,
+            func_id: self.func_id.unwrap(),
+            local_offset: self.local_offset,
+            value_stack: self.value_stack.iter().map(StackValue::bits).collect(),
+            activation_stack: self.activation_stack.clone(),
+            labels: self.labels.clone(),
+            locals: self.locals.clone(),
+        });
+        Snapshot {
+            module: self.code.fingerprint(),
+            memory: self.mem.as_ref().map(|m| m.bytes().to_vec()),
+            globals: self.globals.clone(),
+            tables: self.tables.iter().map(|t| t.elements().to_vec()).collect(),
+            elements: self.elements.clone(),
+            execution,
+        }
+    }
+
+    /// Restores a snapshot taken from an instance of the same module. A suspended call in the
+    /// snapshot can be continued with [`Self::run`], otherwise the vm is reset.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.module != self.code.fingerprint() {
+            return Err(SnapshotError::ModuleMismatch);
+        }
+        //NOTE: Everything is checked before anything is changed, so a failed restore leaves the
+        //vm untouched
+        let memory_fits = match (&self.mem, &snapshot.memory) {
+            (Some(mem), Some(data)) => {
+                data.len() % WASM_PAGE_SIZE == 0
+                    && data.len() / WASM_PAGE_SIZE <= mem.max_pages() as usize
+            }
+            (None, None) => true,
+            _ => false,
+        };
+        if !memory_fits {
+            return Err(SnapshotError::Malformed("memory does not fit the module"));
+        }
+        let globals_fit = self.globals.len() == snapshot.globals.len()
+            && self
+                .globals
+                .iter()
+                .zip(&snapshot.globals)
+                .all(|(a, b)| a.get_value_type() == b.get_value_type());
+        if !globals_fit {
+            return Err(SnapshotError::Malformed("globals do not fit the module"));
+        }
+        let tables_fit = self.tables.len() == snapshot.tables.len()
+            && self
+                .tables
+                .iter()
+                .zip(&snapshot.tables)
+                .all(|(t, elements)| elements.len() <= t.max_size() as usize);
+        if !tables_fit || self.elements.len() != snapshot.elements.len() {
+            return Err(SnapshotError::Malformed("tables do not fit the module"));
+        }
+        if let Some(state) = &snapshot.execution {
+            self.check_execution_state(state)?;
+        }
+
+        if let (Some(mem), Some(data)) = (&mut self.mem, &snapshot.memory) {
+            mem.replace_bytes(data.clone());
+        }
+        self.globals.clone_from(&snapshot.globals);
+        for (table, elements) in self.tables.iter_mut().zip(&snapshot.tables) {
+            table.replace_elements(elements.clone());
+        }
+        self.elements.clone_from(&snapshot.elements);
+        #[cfg(feature = "time-travel")]
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        self.reset_state();
+        if let Some(state) = &snapshot.execution {
+            self.ip = state.ip;
+            self.func_id = Some(state.func_id);
+            self.local_offset = state.local_offset;
+            self.value_stack = state.value_stack.iter().map(|v| (*v).into()).collect();
+            self.activation_stack.clone_from(&state.activation_stack);
+            self.labels.clone_from(&state.labels);
+            self.locals.clone_from(&state.locals);
+        }
+        Ok(())
+    }
+
+    //NOTE: Like the module itself, snapshots are trusted to come from a valid execution. These
+    //checks catch corrupted files, they can not prove that the stack matches the code.
+    fn check_execution_state(&self, state: &ExecutionState) -> Result<(), SnapshotError> {
+        let code_len = self.code.instructions.len();
+        let functions = self.code.functions.len();
+        let stack_len = state.value_stack.len();
+        let frames_fit = !state.activation_stack.is_empty()
+            && state.activation_stack.iter().all(|f| {
+                f.func_id < functions
+                    && f.ip <= code_len
+                    && f.stack_height <= stack_len
+                    && f.locals_offset <= state.locals.len()
+                    && f.label_stack_offset <= state.labels.len()
+            });
+        let labels_fit = state.labels.iter().all(|l| l.stack_height <= stack_len);
+        if !frames_fit
+            || !labels_fit
+            || state.ip >= code_len
+            || state.func_id >= functions
+            || state.local_offset > state.locals.len()
+        {
+            return Err(SnapshotError::Malformed(
+                "call stack does not fit the module",
+            ));
+        }
+        Ok(())
     }
 
     pub fn reset_state(&mut self) {
@@ -1182,33 +2418,31 @@ impl<E: Env> Vm<E> {
         &mut self,
         bytecode: &Bytecode,
         info: &BytecodeInfo,
+    ) -> Result<(), InstanceError> {
+        let imports = Imports::from_env::<E>(bytecode, info)?;
+        self.reload_code_with_imports(bytecode, info, imports)
+    }
+
+    /// Like [`Self::reload_code`], with the imports already resolved by a linker
+    pub(crate) fn reload_code_with_imports(
+        &mut self,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        mut imports: Imports,
     ) -> Result<(), InstanceError> {
         self.reset_state();
-        self.code = Code::from_module::<E>(bytecode, info)?;
+        self.code = Code::from_module(bytecode, info, &imports)?;
         self.start_func_id = bytecode.start.as_ref().map(|i| i.data as usize);
-        self.globals = Self::get_global_instances(bytecode, info)?;
+        self.globals = Self::get_global_instances(bytecode, info, &imports)?;
+        self.tables = Self::make_tables(info, &mut imports)?;
+        self.elements = Self::make_elements(bytecode, &mut self.tables, &self.globals)?;
         self.types = bytecode
             .iter_types()
             .map(|i| i.map_into::<Type>().collect());
 
-        if let Some(data) = bytecode.iter_data()
-            && let Some(ref mut mem) = self.mem
-        {
-            data.filter_map(|d| {
-                if let Data::Active { expr, data, .. } = d {
-                    Some((expr, data))
-                } else {
-                    None
-                }
-            })
-            .try_for_each(|(expr, data)| {
-                Self::copy_active_mem_section(
-                    mem.as_mut_slice(),
-                    expr.data.iter().map(|p| p.data),
-                    &data.data,
-                )
-            })?;
-        };
+        if let Some(ref mut mem) = self.mem {
+            Self::copy_active_mem_sections(bytecode, mem.bytes_mut(), &self.globals)?;
+        }
         Ok(())
     }
     pub fn get_bytes_from_mem<'a>(
@@ -1216,30 +2450,23 @@ impl<E: Env> Vm<E> {
         addr: usize,
         count: usize,
     ) -> Result<&'a [u8], RuntimeError> {
-        let mem = self
-            .mem
+        self.mem
             .as_ref()
-            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
-        if addr + count >= mem.len() {
-            Err(RuntimeError::MemoryAddressOutOfScope)
-        } else {
-            Ok(&mem[addr..addr + count])
-        }
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)?
+            .range(addr, count)
     }
     pub fn get_bytes_from_mem_mut<'a>(
         &'a mut self,
         addr: usize,
         count: usize,
     ) -> Result<&'a mut [u8], RuntimeError> {
-        let mem = self
-            .mem
+        //NOTE: Host functions write through the returned slice, the write can only be undone
+        //if the old bytes are kept beforehand
+        self.save_memory(addr, count);
+        self.mem
             .as_mut()
-            .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
-        if addr + count >= mem.len() {
-            Err(RuntimeError::MemoryAddressOutOfScope)
-        } else {
-            Ok(&mut mem[addr..addr + count])
-        }
+            .ok_or(RuntimeError::MemoryAddressOutOfScope)?
+            .range_mut(addr, count)
     }
 }
 
@@ -1283,6 +2510,7 @@ macro_rules! impl_mem_load {
                     .mem
                     .as_ref()
                     .unwrap()
+                    .bytes()
                     .get(range)
                     .ok_or(RuntimeError::MemoryAddressOutOfScope)?;
 
@@ -1329,16 +2557,19 @@ macro_rules! impl_mem_store {
                 let addr = unsafe { self.pop_value::<u32>() as usize };
                 let addr_start = addr + arg.offset as usize;
                 let range = addr_start..addr_start + std::mem::size_of::<$real_type>();
+                self.save_memory(addr_start, range.len());
 
                 let dest = unsafe {
                     self.mem
                         .as_mut()
                         .unwrap_unchecked()
+                        .bytes_mut()
                         .get_mut(range)
                         .ok_or(RuntimeError::MemoryAddressOutOfScope)?
                 };
 
                 dest.copy_from_slice(&data_buffer);
+                self.record_memory_write(addr_start, data_buffer.len());
 
                 // println!(
                 //     "store op: addr: {}, raw: {raw}, data: {:?}, buffer: {:?}",
@@ -1421,10 +2652,11 @@ impl Env for DebugEnv {
     }
 }
 
+#[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
-    use parser::reader::ValueType;
+    use parser::op::Op;
     use validator::validator::read_and_validate_wat;
 
     use crate::{env::ExternalFunction, slow_vm::RuntimeError};
@@ -1459,7 +2691,7 @@ mod tests {
                 let mut vm = Vm::init_from_validation_result(&res).unwrap();
                 vm.set_func($func_id, $params).unwrap();
                 let result = vm.run_func(&res.bytecode, &res.info, &mut env).unwrap_err();
-                assert!(matches!(result, $expecting));
+                assert!(matches!(result.trap_cause(), $expecting));
                 Ok(())
             }
         };
@@ -1475,13 +2707,26 @@ mod tests {
                     i32.const 1
                     i32.add
                 )
-                (start 0)
             )
         "#,
         vec![],
         vec![LocalValue::I32(6)]
     );
 
+    run_code_expect_result!(
+        run_select,
+        0,
+        r#"
+            (module
+                (func (param i32) (result i64)
+                    (select (i64.const 1) (i64.const 2) (local.get 0))
+                )
+            )
+        "#,
+        vec![LocalValue::I32(0)],
+        vec![LocalValue::I64(2)]
+    );
+
     run_code_expect_result! {
     run_add_locals,
     0,
@@ -1498,7 +2743,6 @@ mod tests {
                     i32.add
 
                 )
-                (start 0)
             )
         "#,
         vec![],
@@ -1648,7 +2892,6 @@ mod tests {
                     i32.const 10
                     i32.load 
                 )
-                (start $main)
             )
         "#,
         vec![],
@@ -1660,8 +2903,8 @@ mod tests {
         0,
         r#"
             (module
-                (global $global_test (mut i32))
-                (global $global_test2 (mut i32))
+                (global $global_test (mut i32) (i32.const 0))
+                (global $global_test2 (mut i32) (i32.const 0))
                 (global $global_test_init (mut i32) (i32.const 900))
                 
                 (func $main (result i32) 
@@ -1671,7 +2914,6 @@ mod tests {
                     global.get $global_test_init 
                     i32.add
                 )
-                (start $main)
             )
         "#,
         vec![],
@@ -1699,7 +2941,6 @@ mod tests {
                     )
                     local.get $i
                 )
-                (start $main)
             )
         "#,
         vec![],
@@ -1724,7 +2965,6 @@ mod tests {
                     )
                     i32.const 10
                 )
-                (start $main)
             )
         "#,
         vec![],
@@ -1978,4 +3218,613 @@ mod tests {
         vec![],
         vec![]
     }
+    run_code_expect_result! {
+        float_arith,
+        0,
+        r#"
+            (module
+                (func (result f64)
+                    f32.const 1.5
+                    f32.const 2.25
+                    f32.mul
+                    f64.promote_f32
+                    f64.const -0.5
+                    f64.add
+                    f64.sqrt
+                    f64.nearest
+                )
+            )
+        "#,
+        vec![],
+        vec![LocalValue::F64(2.0)]
+    }
+
+    run_code_expect_result! {
+        float_compare_nan,
+        0,
+        r#"
+            (module
+                (func (result i32)
+                    f32.const nan
+                    f32.const nan
+                    f32.eq
+                    f64.const 0
+                    f64.const -0
+                    f64.eq
+                    i32.add
+                    f32.const nan
+                    f32.const 1
+                    f32.ne
+                    i32.add
+                )
+            )
+        "#,
+        vec![],
+        vec![LocalValue::I32(2)]
+    }
+
+    run_code_expect_result! {
+        float_int_conversions,
+        0,
+        r#"
+            (module
+                (func (result i32)
+                    f64.const -3.75
+                    i32.trunc_f64_s
+                    i32.const 1
+                    f32.convert_i32_u
+                    i32.reinterpret_f32
+                    i32.const 0x3f800000
+                    i32.eq
+                    i32.add
+                    f32.const 1e10
+                    i32.trunc_sat_f32_s
+                    i32.const 2147483647
+                    i32.eq
+                    i32.add
+                )
+            )
+        "#,
+        vec![],
+        vec![LocalValue::I32((-1_i32) as u32)]
+    }
+
+    run_code_expect_failure! {
+        float_trunc_nan_traps,
+        0,
+        r#"
+            (module
+                (func (result i32)
+                    f32.const nan
+                    i32.trunc_f32_s
+                )
+            )
+        "#,
+        vec![],
+        RuntimeError::InvalidConversionToInteger
+    }
+
+    run_code_expect_failure! {
+        float_trunc_overflow_traps,
+        0,
+        r#"
+            (module
+                (func (result i64)
+                    f64.const 1e20
+                    i64.trunc_f64_u
+                )
+            )
+        "#,
+        vec![],
+        RuntimeError::IntegerOverflow
+    }
+
+    run_code_expect_result! {
+        br_table_switch,
+        1,
+        r#"
+            (module
+                (func $switch (param i32) (result i32)
+                    (block $default
+                        (block $two
+                            (block $one
+                                (block $zero
+                                    local.get 0
+                                    br_table $zero $one $two $default
+                                )
+                                i32.const 100
+                                return
+                            )
+                            i32.const 101
+                            return
+                        )
+                        i32.const 102
+                        return
+                    )
+                    i32.const 999
+                )
+                (func (result i32)
+                    i32.const 0
+                    call $switch
+                    i32.const 2
+                    call $switch
+                    i32.add
+                    i32.const 7
+                    call $switch
+                    i32.add
+                )
+            )
+        "#,
+        vec![],
+        vec![LocalValue::I32(100 + 102 + 999)]
+    }
+
+    run_code_expect_result! {
+        br_table_with_value,
+        0,
+        r#"
+            (module
+                (func (param i32) (result i32)
+                    (block $outer (result i32)
+                        (block $inner (result i32)
+                            i32.const 42
+                            local.get 0
+                            br_table $inner $outer 0
+                        )
+                        i32.const 1
+                        i32.add
+                    )
+                )
+            )
+        "#,
+        vec![LocalValue::I32(1)],
+        vec![LocalValue::I32(42)]
+    }
+
+    run_code_expect_result! {
+        br_table_function_label,
+        0,
+        r#"
+            (module
+                (func (param i32) (result i32)
+                    i32.const 7
+                    local.get 0
+                    br_table 0
+                )
+            )
+        "#,
+        vec![LocalValue::I32(3)],
+        vec![LocalValue::I32(7)]
+    }
+
+    const INDIRECT_CALL_MODULE: &str = r#"
+        (module
+            (type $binop (func (param i32 i32) (result i32)))
+            (table $ops 4 funcref)
+            (func $add (type $binop) local.get 0 local.get 1 i32.add)
+            (func $sub (type $binop) local.get 0 local.get 1 i32.sub)
+            (func $neg (param i32) (result i32) i32.const 0 local.get 0 i32.sub)
+            (elem (table $ops) (i32.const 0) func $add $sub $neg)
+            (func (param i32) (result i32)
+                i32.const 10
+                i32.const 3
+                local.get 0
+                call_indirect $ops (type $binop)
+            )
+        )
+    "#;
+
+    run_code_expect_result! {
+        call_indirect_dispatch,
+        3,
+        INDIRECT_CALL_MODULE,
+        vec![LocalValue::I32(1)],
+        vec![LocalValue::I32(7)]
+    }
+
+    run_code_expect_failure! {
+        call_indirect_type_mismatch,
+        3,
+        INDIRECT_CALL_MODULE,
+        vec![LocalValue::I32(2)],
+        RuntimeError::IndirectCallTypeMismatch { type_id: 0, func_id: 2 }
+    }
+
+    run_code_expect_failure! {
+        call_indirect_null_entry,
+        3,
+        INDIRECT_CALL_MODULE,
+        vec![LocalValue::I32(3)],
+        RuntimeError::UninitializedElement
+    }
+
+    run_code_expect_failure! {
+        call_indirect_out_of_bounds,
+        3,
+        INDIRECT_CALL_MODULE,
+        vec![LocalValue::I32(4)],
+        RuntimeError::UndefinedElement
+    }
+
+    run_code_expect_result! {
+        table_ops,
+        2,
+        r#"
+            (module
+                (table $t 1 3 funcref)
+                (func $a (result i32) i32.const 1)
+                (func $b (result i32) i32.const 2)
+                (elem $passive func $a $b)
+                (func (result i32 i32 i32 i32 i32)
+                    (table.grow $t (ref.null func) (i32.const 2))
+                    (table.grow $t (ref.null func) (i32.const 1))
+                    (table.init $t $passive (i32.const 1) (i32.const 0) (i32.const 2))
+                    elem.drop $passive
+                    (table.copy $t $t (i32.const 0) (i32.const 2) (i32.const 1))
+                    (table.set $t (i32.const 2) (table.get $t (i32.const 1)))
+                    (call_indirect $t (result i32) (i32.const 0))
+                    (call_indirect $t (result i32) (i32.const 2))
+                    i32.add
+                    (ref.is_null (table.get $t (i32.const 1)))
+                    table.size $t
+                )
+            )
+        "#,
+        vec![],
+        vec![
+            LocalValue::I32(1),
+            LocalValue::I32(u32::MAX),
+            LocalValue::I32(3),
+            LocalValue::I32(0),
+            LocalValue::I32(3)
+        ]
+    }
+
+    run_code_expect_failure! {
+        table_init_dropped_segment,
+        1,
+        r#"
+            (module
+                (table 2 funcref)
+                (func)
+                (elem $e func 0)
+                (func
+                    elem.drop $e
+                    (table.init $e (i32.const 0) (i32.const 0) (i32.const 1))
+                )
+            )
+        "#,
+        vec![],
+        RuntimeError::TableAddressOutOfScope
+    }
+
+    run_code_expect_failure! {
+        i32_div_by_zero_traps,
+        0,
+        r#"
+            (module
+                (func (param i32) (result i32)
+                    i32.const 1
+                    local.get 0
+                    i32.div_u
+                )
+            )
+        "#,
+        vec![LocalValue::I32(0)],
+        RuntimeError::IntegerDivideByZero
+    }
+
+    run_code_expect_failure! {
+        i64_rem_by_zero_traps,
+        0,
+        r#"
+            (module
+                (func (param i64) (result i64)
+                    i64.const -5
+                    local.get 0
+                    i64.rem_s
+                )
+            )
+        "#,
+        vec![LocalValue::I64(0)],
+        RuntimeError::IntegerDivideByZero
+    }
+
+    run_code_expect_failure! {
+        i32_div_s_overflow_traps,
+        0,
+        r#"
+            (module
+                (func (result i32)
+                    i32.const 0x80000000
+                    i32.const -1
+                    i32.div_s
+                )
+            )
+        "#,
+        vec![],
+        RuntimeError::IntegerOverflow
+    }
+
+    #[test]
+    fn trap_reports_location() {
+        let src = r#"
+            (module
+                (func $div (param i64 i64) (result i64)
+                    local.get 0
+                    local.get 1
+                    i64.div_s
+                )
+                (func (result i64)
+                    i64.const 0x8000000000000000
+                    i64.const -1
+                    call $div
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = DebugEnv {};
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_func(1, vec![]).unwrap();
+        let err = vm.run_func(&res.bytecode, &res.info, &mut env).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Trap in function $div at instruction 2")
+        );
+        let RuntimeError::Trap {
+            func_id,
+            func_name,
+            instruction,
+            op,
+            error,
+        } = err
+        else {
+            panic!("expected a trap, got {err}");
+        };
+        assert_eq!(func_id, 0);
+        assert_eq!(func_name.as_deref(), Some("div"));
+        assert_eq!(instruction, 2);
+        assert_eq!(op, Op::I64Divs);
+        assert!(matches!(*error, RuntimeError::IntegerOverflow));
+    }
+
+    #[test]
+    fn unbounded_recursion_overflows() {
+        let src = r#"
+            (module
+                (func $count (param $n i32) (result i32)
+                    (i32.add
+                        (call $count (i32.add (local.get $n) (i32.const 1)))
+                        (i32.const 1)
+                    )
+                )
+                (func $main (result i32)
+                    (call $count (i32.const 0))
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = DebugEnv {};
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_max_call_depth(100);
+        vm.set_func(1, vec![]).unwrap();
+        let err = vm.run_func(&res.bytecode, &res.info, &mut env).unwrap_err();
+        let RuntimeError::StackOverflow { depth, call_stack } = err.trap_cause() else {
+            panic!("expected a stack overflow, got {err}");
+        };
+        assert_eq!(*depth, 100);
+        assert_eq!(call_stack.0.len(), 100);
+        assert_eq!(call_stack.0[0].func_name.as_deref(), Some("count"));
+        assert_eq!(call_stack.0[99].func_name.as_deref(), Some("main"));
+        assert!(
+            call_stack
+                .to_string()
+                .starts_with("  $count at instruction 3 (99 times)\n  $main at instruction 1\n")
+        );
+    }
+
+    #[test]
+    fn value_stack_limit() {
+        let src = r#"
+            (module
+                (func $grow (param i64 i64 i64 i64)
+                    (call $grow (local.get 0) (local.get 1) (local.get 2) (local.get 3))
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = DebugEnv {};
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_max_stack_size(1000);
+        vm.set_func(0, [LocalValue::I64(0); 4]).unwrap();
+        let err = vm.run_func(&res.bytecode, &res.info, &mut env).unwrap_err();
+        assert!(matches!(
+            err.trap_cause(),
+            RuntimeError::StackOverflow { depth: 250, .. }
+        ));
+    }
+
+    #[test]
+    fn out_of_fuel_stops_infinite_loop() {
+        let src = r#"
+            (module
+                (func
+                    (loop $l
+                        br $l
+                    )
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = DebugEnv {};
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_fuel(Some(1000));
+        vm.set_func(0, vec![]).unwrap();
+        let err = vm.run_func(&res.bytecode, &res.info, &mut env).unwrap_err();
+        assert!(matches!(err, RuntimeError::OutOfFuel));
+        assert_eq!(vm.remaining_fuel(), Some(0));
+    }
+
+    #[test]
+    fn resume_after_adding_fuel() {
+        let src = r#"
+            (module
+                (func (param $n i32) (result i32) (local $sum i32)
+                    (loop $l
+                        (local.set $sum (i32.add (local.get $sum) (local.get $n)))
+                        (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                        (br_if $l (local.get $n))
+                    )
+                    local.get $sum
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = DebugEnv {};
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_fuel(Some(10));
+        vm.set_func(0, vec![LocalValue::I32(100)]).unwrap();
+        let mut resumed = 0;
+        let result = loop {
+            match vm.run_func(&res.bytecode, &res.info, &mut env) {
+                Err(RuntimeError::OutOfFuel) => {
+                    resumed += 1;
+                    vm.add_fuel(10);
+                }
+                result => break result.unwrap(),
+            }
+        };
+        assert!(resumed > 10);
+        assert_eq!(result, vec![LocalValue::I32(5050)]);
+    }
+
+    run_code_expect_result! {
+        i32_wrapping_arith,
+        0,
+        r#"
+            (module
+                (func (result i32 i32 i32 i32)
+                    (i32.add (i32.const 0x7fffffff) (i32.const 1))
+                    (i32.mul (i32.const 0x10000) (i32.const 0x10000))
+                    (i32.rem_s (i32.const 0x80000000) (i32.const -1))
+                    (i32.div_s (i32.const -7) (i32.const 2))
+                )
+            )
+        "#,
+        vec![],
+        vec![
+            LocalValue::I32(0x80000000),
+            LocalValue::I32(0),
+            LocalValue::I32(0),
+            LocalValue::I32(-3i32 as u32)
+        ]
+    }
+
+    run_code_expect_result! {
+        i32_shift_and_rotate,
+        0,
+        r#"
+            (module
+                (func (result i32 i32 i32 i32 i32)
+                    (i32.shl (i32.const 1) (i32.const 33))
+                    (i32.shr_s (i32.const -8) (i32.const 34))
+                    (i32.shr_u (i32.const -8) (i32.const 60))
+                    (i32.rotl (i32.const 0x80000001) (i32.const 1))
+                    (i32.rotr (i32.const 1) (i32.const 36))
+                )
+            )
+        "#,
+        vec![],
+        vec![
+            LocalValue::I32(2),
+            LocalValue::I32(-2i32 as u32),
+            LocalValue::I32(0xf),
+            LocalValue::I32(3),
+            LocalValue::I32(0x10000000)
+        ]
+    }
+
+    run_code_expect_result! {
+        i64_wrapping_and_shifts,
+        0,
+        r#"
+            (module
+                (func (result i64 i64 i64 i64 i64)
+                    (i64.sub (i64.const 0) (i64.const 1))
+                    (i64.mul (i64.const 0x100000000) (i64.const 0x100000000))
+                    (i64.shl (i64.const 1) (i64.const 65))
+                    (i64.rotl (i64.const 0x8000000000000000) (i64.const 1))
+                    (i64.or (i64.const 0x100000000) (i64.and (i64.const -1) (i64.const 0x200000000)))
+                )
+            )
+        "#,
+        vec![],
+        vec![
+            LocalValue::I64(u64::MAX),
+            LocalValue::I64(0),
+            LocalValue::I64(2),
+            LocalValue::I64(1),
+            LocalValue::I64(0x300000000)
+        ]
+    }
+
+    run_code_expect_result! {
+        i64_compare,
+        0,
+        r#"
+            (module
+                (func (result i32 i32 i32)
+                    (i64.le_s (i64.const -1) (i64.const 0))
+                    (i64.le_u (i64.const -1) (i64.const 0))
+                    (i64.eqz (i64.const 0x100000000))
+                )
+            )
+        "#,
+        vec![],
+        vec![LocalValue::I32(1), LocalValue::I32(0), LocalValue::I32(0)]
+    }
+
+    run_code_expect_result!(
+        memory_grow_returns_old_size,
+        0,
+        r#"
+            (module
+                (memory 1 4)
+                (func (result i32 i32 i32 i32)
+                    (memory.grow (i32.const 2))
+                    memory.size
+                    (memory.grow (i32.const 2))
+                    memory.size
+                )
+            )
+        "#,
+        vec![],
+        vec![
+            LocalValue::I32(1),
+            LocalValue::I32(3),
+            LocalValue::I32(u32::MAX),
+            LocalValue::I32(3)
+        ]
+    );
+
+    #[test]
+    fn memory_grow_respects_embedder_cap() {
+        let src = r#"
+            (module
+                (memory 1)
+                (func (result i32 i32)
+                    (memory.grow (i32.const 1))
+                    (memory.grow (i32.const 1))
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = DebugEnv {};
+        let mut vm = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_max_memory_pages(2);
+        vm.set_func(0, []).unwrap();
+        let results = vm.run_func(&res.bytecode, &res.info, &mut env).unwrap();
+        assert_eq!(results, vec![LocalValue::I32(1), LocalValue::I32(u32::MAX)]);
+        assert_eq!(vm.memory().unwrap().size(), 2);
+    }
 }
@@ -0,0 +1,390 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use thiserror::Error;
+
+use crate::{
+    slow_vm::{ActivationFrame, Label, LocalValue},
+    stack::NULL_REF,
+    table::Ref,
+};
+
+/// Identifies snapshot files, followed by a version byte
+pub const SNAPSHOT_MAGIC: &[u8; 4] = b"WSNP";
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("Unable to read or write snapshot: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a snapshot file")]
+    InvalidMagic,
+    #[error("Unsupported snapshot version {0}, expected {SNAPSHOT_VERSION}")]
+    UnsupportedVersion(u8),
+    #[error("The snapshot was taken from a different module")]
+    ModuleMismatch,
+    #[error("Malformed snapshot: {0}")]
+    Malformed(&'static str),
+}
+
+/// The suspended call of a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ExecutionState {
+    pub ip: usize,
+    pub func_id: usize,
+    pub local_offset: usize,
+    /// Raw bits of the untyped stack values
+    pub value_stack: Vec<u64>,
+    pub activation_stack: Vec<ActivationFrame>,
+    pub labels: Vec<Label>,
+    pub locals: Vec<LocalValue>,
+}
+
+/// The state of an instance, taken with `Vm::snapshot` and restored with `Vm::restore` into an
+/// instance of the same module. The state of the env is not part of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Fingerprint of the code the snapshot was taken from
+    pub(crate) module: u64,
+    pub(crate) memory: Option<Vec<u8>>,
+    pub(crate) globals: Vec<LocalValue>,
+    pub(crate) tables: Vec<Vec<Ref>>,
+    pub(crate) elements: Vec<Vec<Ref>>,
+    pub(crate) execution: Option<ExecutionState>,
+}
+
+impl Snapshot {
+    /// True if the snapshot contains a suspended call that can be continued after restoring
+    pub fn has_call_stack(&self) -> bool {
+        self.execution.is_some()
+    }
+
+    pub fn memory(&self) -> Option<&[u8]> {
+        self.memory.as_deref()
+    }
+
+    pub fn globals(&self) -> &[LocalValue] {
+        &self.globals
+    }
+
+    /// Writes [`SNAPSHOT_MAGIC`] and the version followed by the state, everything is little
+    /// endian and vectors are prefixed with their length
+    pub fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(SNAPSHOT_MAGIC)?;
+        w.write_u8(SNAPSHOT_VERSION)?;
+        w.write_u64::<LittleEndian>(self.module)?;
+        match &self.memory {
+            Some(memory) => {
+                w.write_u8(1)?;
+                w.write_u64::<LittleEndian>(memory.len() as u64)?;
+                w.write_all(memory)?;
+            }
+            None => w.write_u8(0)?,
+        }
+        write_values(w, &self.globals)?;
+        for refs in [&self.tables, &self.elements] {
+            w.write_u32::<LittleEndian>(refs.len() as u32)?;
+            refs.iter().try_for_each(|r| write_refs(w, r))?;
+        }
+        let Some(state) = &self.execution else {
+            return w.write_u8(0);
+        };
+        w.write_u8(1)?;
+        for v in [state.ip, state.func_id, state.local_offset] {
+            w.write_u64::<LittleEndian>(v as u64)?;
+        }
+        w.write_u32::<LittleEndian>(state.value_stack.len() as u32)?;
+        for v in &state.value_stack {
+            w.write_u64::<LittleEndian>(*v)?;
+        }
+        w.write_u32::<LittleEndian>(state.activation_stack.len() as u32)?;
+        for frame in &state.activation_stack {
+            for v in [
+                frame.locals_offset,
+                frame.func_id,
+                frame.arity,
+                frame.ip,
+                frame.stack_height,
+                frame.label_stack_offset,
+            ] {
+                w.write_u64::<LittleEndian>(v as u64)?;
+            }
+        }
+        w.write_u32::<LittleEndian>(state.labels.len() as u32)?;
+        for label in &state.labels {
+            w.write_u64::<LittleEndian>(label.stack_height as u64)?;
+            w.write_u64::<LittleEndian>(label.out_count as u64)?;
+        }
+        write_values(w, &state.locals)
+    }
+
+    pub fn read(r: &mut impl Read) -> Result<Self, SnapshotError> {
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        match r.read_u8()? {
+            SNAPSHOT_VERSION => {}
+            version => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+        let module = r.read_u64::<LittleEndian>()?;
+        let memory = match read_flag(r)? {
+            true => {
+                let len = r.read_u64::<LittleEndian>()?;
+                //NOTE: The length is not trusted for the allocation, a truncated file fails below
+                let mut memory = Vec::new();
+                r.take(len).read_to_end(&mut memory)?;
+                if memory.len() as u64 != len {
+                    return Err(SnapshotError::Malformed("memory is truncated"));
+                }
+                Some(memory)
+            }
+            false => None,
+        };
+        let globals = read_values(r)?;
+        let tables = read_vec(r, read_refs)?;
+        let elements = read_vec(r, read_refs)?;
+        let execution = match read_flag(r)? {
+            true => Some(ExecutionState {
+                ip: read_usize(r)?,
+                func_id: read_usize(r)?,
+                local_offset: read_usize(r)?,
+                value_stack: read_vec(r, |r| Ok(r.read_u64::<LittleEndian>()?))?,
+                activation_stack: read_vec(r, |r| {
+                    Ok(ActivationFrame {
+                        locals_offset: read_usize(r)?,
+                        func_id: read_usize(r)?,
+                        arity: read_usize(r)?,
+                        ip: read_usize(r)?,
+                        stack_height: read_usize(r)?,
+                        label_stack_offset: read_usize(r)?,
+                    })
+                })?,
+                labels: read_vec(r, |r| {
+                    Ok(Label {
+                        stack_height: read_usize(r)?,
+                        out_count: read_usize(r)?,
+                    })
+                })?,
+                locals: read_values(r)?,
+            }),
+            false => None,
+        };
+        Ok(Self {
+            module,
+            memory,
+            globals,
+            tables,
+            elements,
+            execution,
+        })
+    }
+}
+
+fn read_flag(r: &mut impl Read) -> Result<bool, SnapshotError> {
+    match r.read_u8()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(SnapshotError::Malformed("invalid flag")),
+    }
+}
+
+fn read_usize(r: &mut impl Read) -> Result<usize, SnapshotError> {
+    usize::try_from(r.read_u64::<LittleEndian>()?)
+        .map_err(|_| SnapshotError::Malformed("index does not fit into usize"))
+}
+
+fn read_vec<R: Read, T>(
+    r: &mut R,
+    mut read: impl FnMut(&mut R) -> Result<T, SnapshotError>,
+) -> Result<Vec<T>, SnapshotError> {
+    let len = r.read_u32::<LittleEndian>()?;
+    (0..len).map(|_| read(r)).collect()
+}
+
+fn write_refs(w: &mut impl Write, refs: &[Ref]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(refs.len() as u32)?;
+    refs.iter()
+        .try_for_each(|r| w.write_u32::<LittleEndian>(r.unwrap_or(NULL_REF)))
+}
+
+fn read_refs(r: &mut impl Read) -> Result<Vec<Ref>, SnapshotError> {
+    read_vec(r, |r| {
+        let v = r.read_u32::<LittleEndian>()?;
+        Ok((v != NULL_REF).then_some(v))
+    })
+}
+
+fn write_values(w: &mut impl Write, values: &[LocalValue]) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(values.len() as u32)?;
+    for value in values {
+        let (tag, bits) = match *value {
+            LocalValue::I32(v) => (0, v as u64),
+            LocalValue::S32(v) => (1, v as u32 as u64),
+            LocalValue::I64(v) => (2, v),
+            LocalValue::S64(v) => (3, v as u64),
+            LocalValue::F32(v) => (4, v.to_bits() as u64),
+            LocalValue::F64(v) => (5, v.to_bits()),
+            LocalValue::FuncRef(r) => (6, r.unwrap_or(NULL_REF) as u64),
+            LocalValue::ExternRef(r) => (7, r.unwrap_or(NULL_REF) as u64),
+        };
+        w.write_u8(tag)?;
+        w.write_u64::<LittleEndian>(bits)?;
+    }
+    Ok(())
+}
+
+fn read_values(r: &mut impl Read) -> Result<Vec<LocalValue>, SnapshotError> {
+    read_vec(r, |r| {
+        let tag = r.read_u8()?;
+        let bits = r.read_u64::<LittleEndian>()?;
+        let reference = (bits as u32 != NULL_REF).then_some(bits as u32);
+        Ok(match tag {
+            0 => LocalValue::I32(bits as u32),
+            1 => LocalValue::S32(bits as u32 as i32),
+            2 => LocalValue::I64(bits),
+            3 => LocalValue::S64(bits as i64),
+            4 => LocalValue::F32(f32::from_bits(bits as u32)),
+            5 => LocalValue::F64(f64::from_bits(bits)),
+            6 => LocalValue::FuncRef(reference),
+            7 => LocalValue::ExternRef(reference),
+            _ => return Err(SnapshotError::Malformed("invalid value type")),
+        })
+    })
+}
+
+/// 64 bit FNV-1a hash of everything formatted into it, used to recognize the module of a
+/// snapshot. Unlike `DefaultHasher` the result is stable across Rust versions.
+pub(crate) struct Fingerprint(u64);
+
+impl Fingerprint {
+    pub fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Write for Fingerprint {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::{DebugEnv, RuntimeError, Vm};
+
+    const SRC: &str = r#"
+        (module
+            (memory 1)
+            (global $counter (mut i32) (i32.const 0))
+            (table 2 funcref)
+            (func $init
+                i32.const 16
+                i32.const 42
+                i32.store
+                i32.const 5
+                global.set $counter
+            )
+            (func $count (result i32)
+                loop $l
+                    global.get $counter
+                    i32.const 1
+                    i32.sub
+                    global.set $counter
+                    global.get $counter
+                    br_if $l
+                end
+                i32.const 16
+                i32.load
+            )
+        )
+    "#;
+
+    #[test]
+    fn restore_into_fresh_instance() {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let (bytecode, info) = (&res.bytecode, &res.info);
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_func(0, []).unwrap();
+        vm.run_func(bytecode, info, &mut DebugEnv {}).unwrap();
+
+        let mut file = vec![];
+        vm.snapshot(false).write(&mut file).unwrap();
+        let snapshot = Snapshot::read(&mut file.as_slice()).unwrap();
+        assert!(!snapshot.has_call_stack());
+        assert_eq!(snapshot, vm.snapshot(false));
+
+        let mut fresh: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        fresh.restore(&snapshot).unwrap();
+        assert_eq!(fresh.globals(), &[LocalValue::I32(5)]);
+        assert_eq!(fresh.memory().unwrap().range(16, 1).unwrap(), &[42]);
+    }
+
+    #[test]
+    fn continue_suspended_call() {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let (bytecode, info) = (&res.bytecode, &res.info);
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_func(0, []).unwrap();
+        vm.run_func(bytecode, info, &mut DebugEnv {}).unwrap();
+        vm.set_func(1, []).unwrap();
+        vm.set_fuel(Some(10));
+        assert!(matches!(
+            vm.run(bytecode, &mut DebugEnv {}),
+            Err(RuntimeError::OutOfFuel)
+        ));
+
+        let mut file = vec![];
+        vm.snapshot(true).write(&mut file).unwrap();
+        let snapshot = Snapshot::read(&mut file.as_slice()).unwrap();
+        assert!(snapshot.has_call_stack());
+
+        let mut fresh: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        fresh.restore(&snapshot).unwrap();
+        assert_eq!(fresh.position(), vm.position());
+        fresh.run(bytecode, &mut DebugEnv {}).unwrap();
+        assert_eq!(fresh.finish(info).unwrap(), vec![LocalValue::I32(42)]);
+        assert_eq!(fresh.globals(), &[LocalValue::I32(0)]);
+    }
+
+    #[test]
+    fn reject_other_modules_and_versions() {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        let snapshot = vm.snapshot(false);
+
+        let other = read_and_validate_wat("(module (memory 1) (func))").unwrap();
+        let mut other: Vm<DebugEnv> = Vm::init_from_validation_result(&other).unwrap();
+        assert!(matches!(
+            other.restore(&snapshot),
+            Err(SnapshotError::ModuleMismatch)
+        ));
+
+        let mut file = vec![];
+        snapshot.write(&mut file).unwrap();
+        file[4] = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            Snapshot::read(&mut file.as_slice()),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            Snapshot::read(&mut &b"WASM"[..]),
+            Err(SnapshotError::InvalidMagic)
+        ));
+    }
+}
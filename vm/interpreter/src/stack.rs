@@ -9,38 +9,66 @@ pub union StackValue {
     pub f32: f32,
     pub f64: f64,
 }
+impl StackValue {
+    //NOTE: 32 bit values are written into a zeroed slot, so all 8 bytes are always initialized
+    fn from_u32(value: u32) -> Self {
+        let mut v = Self { i64: 0 };
+        v.i32 = value;
+        v
+    }
+
+    /// The raw bits of the slot, 32 bit values are zero extended
+    pub fn bits(&self) -> u64 {
+        unsafe { self.i64 }
+    }
+}
+
 impl fmt::Debug for StackValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "AnyStackValue")
     }
 }
 macro_rules! impl_from_num_stackval {
-    ($field_name: ident, $type: tt) => {
+    ($type: tt, u32) => {
+        impl From<$type> for StackValue {
+            fn from(value: $type) -> Self {
+                Self::from_u32(cast(value))
+            }
+        }
+    };
+    ($type: tt, u64) => {
         impl From<$type> for StackValue {
             fn from(value: $type) -> Self {
-                Self {
-                    $field_name: cast(value),
-                }
+                Self { i64: cast(value) }
             }
         }
     };
 }
 
+impl_from_num_stackval!(u32, u32);
 impl_from_num_stackval!(i32, u32);
-impl_from_num_stackval!(i32, i32);
+impl_from_num_stackval!(u64, u64);
 impl_from_num_stackval!(i64, u64);
-impl_from_num_stackval!(i64, i64);
-impl_from_num_stackval!(f32, f32);
-impl_from_num_stackval!(f64, f64);
+impl_from_num_stackval!(f32, u32);
+impl_from_num_stackval!(f64, u64);
 
 impl From<bool> for StackValue {
     fn from(value: bool) -> Self {
-        Self { i32: value.into() }
+        Self::from_u32(value.into())
     }
 }
 
 impl From<u16> for StackValue {
     fn from(value: u16) -> Self {
-        Self { i32: value.into() }
+        Self::from_u32(value.into())
+    }
+}
+
+//NOTE: References share the i32 slot, u32::MAX can never be a valid function index
+pub const NULL_REF: u32 = u32::MAX;
+
+impl From<Option<u32>> for StackValue {
+    fn from(value: Option<u32>) -> Self {
+        Self::from_u32(value.unwrap_or(NULL_REF))
     }
 }
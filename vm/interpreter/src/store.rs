@@ -0,0 +1,999 @@
+use std::collections::HashMap;
+
+use parser::{
+    info::{BytecodeInfo, FunctionType, GlobalInfo, TableInfo},
+    reader::{ExportDesc, ValueType},
+};
+use validator::validator::ValidateResult;
+
+use crate::{
+    env::{ExternalGlobal, Imports},
+    instance::{Instance, WasmTypeList},
+    linker::{Callee, Definition, Host, HostFunc, ImportKind, LinkError, Linker, link},
+    memory::MemoryInstance,
+    slow_vm::{ExecutionError, Function, LocalValue, RuntimeError, Type, Vm},
+    table::TableInstance,
+};
+
+//NOTE: Returned to the calling vm when a call into another instance trapped,
+//the trap itself is kept in `Modules::trap` until the caller's error reaches the store
+const CALLEE_TRAPPED: usize = usize::MAX;
+
+/// Maps the function indices of an instance to store function addresses, which index the
+/// functions of the [`Host`]
+#[derive(Debug, Clone, Default)]
+struct FuncMap {
+    to_store: HashMap<u32, u32>,
+    to_local: HashMap<u32, u32>,
+}
+
+impl FuncMap {
+    fn insert(&mut self, id: u32, addr: u32) {
+        self.to_store.insert(id, addr);
+        self.to_local.entry(addr).or_insert(id);
+    }
+
+    /// The index of the function at `addr` in the instance. Functions the instance does not
+    /// import are appended to `functions` as a native function calling `addr`, `first_id` is
+    /// the index of the first function in `functions`.
+    fn local<T>(
+        &mut self,
+        addr: u32,
+        funcs: &[HostFunc<T>],
+        functions: &mut Vec<Function>,
+        first_id: usize,
+    ) -> u32 {
+        if let Some(id) = self.to_local.get(&addr) {
+            return *id;
+        }
+        let id = (first_id + functions.len()) as u32;
+        let t = funcs[addr as usize].t.clone();
+        functions.push(Function::native(t, addr as usize));
+        self.insert(id, addr);
+        id
+    }
+
+    /// The address of function `id` of `instance`, its own functions get an address the first
+    /// time they are used outside of it
+    fn store<T>(
+        &mut self,
+        instance: usize,
+        id: u32,
+        funcs: &mut Vec<HostFunc<T>>,
+        functions: &[Function],
+    ) -> u32 {
+        if let Some(addr) = self.to_store.get(&id) {
+            return *addr;
+        }
+        let addr = funcs.len() as u32;
+        funcs.push(HostFunc {
+            t: functions[id as usize].t.clone(),
+            call: Callee::Export {
+                instance,
+                func_id: id as usize,
+            },
+        });
+        self.insert(id, addr);
+        addr
+    }
+
+    fn local_value<T>(
+        &mut self,
+        value: LocalValue,
+        funcs: &[HostFunc<T>],
+        functions: &mut Vec<Function>,
+        first_id: usize,
+    ) -> LocalValue {
+        match value {
+            LocalValue::FuncRef(Some(addr)) => {
+                LocalValue::FuncRef(Some(self.local(addr, funcs, functions, first_id)))
+            }
+            value => value,
+        }
+    }
+
+    fn store_value<T>(
+        &mut self,
+        instance: usize,
+        value: LocalValue,
+        funcs: &mut Vec<HostFunc<T>>,
+        functions: &[Function],
+    ) -> LocalValue {
+        match value {
+            LocalValue::FuncRef(Some(id)) => {
+                LocalValue::FuncRef(Some(self.store(instance, id, funcs, functions)))
+            }
+            value => value,
+        }
+    }
+}
+
+/// Store addresses of the memory, globals, tables and functions an instance shares
+#[derive(Debug, Clone, Default)]
+struct Links {
+    memory: Option<usize>,
+    /// Global index in the instance and its address
+    globals: Vec<(usize, usize)>,
+    /// Table index in the instance and its address
+    tables: Vec<(usize, usize)>,
+    funcs: FuncMap,
+}
+
+/// The instances of a [`Store`] and the state they share. It lives in the [`Host`] so that
+/// calls from one instance into another can reach it.
+pub(crate) struct Modules<T> {
+    /// `None` while the instance runs
+    instances: Vec<Option<Instance<Host<T>>>>,
+    links: Vec<Links>,
+    //NOTE: Shared memories and tables are moved into the vm of the running instance.
+    //Function references in the store are store function addresses.
+    memories: Vec<Option<MemoryInstance>>,
+    globals: Vec<LocalValue>,
+    tables: Vec<Option<TableInstance>>,
+    /// Running instances, innermost last
+    active: Vec<usize>,
+    trap: Option<RuntimeError>,
+    /// Run calls on the fast tier
+    fast: bool,
+}
+
+impl<T> Default for Modules<T> {
+    fn default() -> Self {
+        Self {
+            instances: vec![],
+            links: vec![],
+            memories: vec![],
+            globals: vec![],
+            tables: vec![],
+            active: vec![],
+            trap: None,
+            fast: false,
+        }
+    }
+}
+
+impl<T> Modules<T> {
+    /// Moves the shared state of `instance` into its vm before it runs
+    fn check_out(&mut self, funcs: &[HostFunc<T>], instance: usize, vm: &mut Vm<Host<T>>) {
+        let links = &mut self.links[instance];
+        if let Some(addr) = links.memory {
+            *vm.memory_slot() = self.memories[addr].take();
+        }
+        for &(id, addr) in &links.globals {
+            let value = self.globals[addr];
+            let value = links.funcs.local_value(value, funcs, vm.functions_mut(), 0);
+            vm.globals_mut()[id] = value;
+        }
+        for &(id, addr) in &links.tables {
+            let mut table = self.tables[addr].take().unwrap();
+            if table.value_type() == ValueType::Funcref {
+                let functions = vm.functions_mut();
+                let elements = table
+                    .elements()
+                    .iter()
+                    .map(|r| r.map(|addr| links.funcs.local(addr, funcs, functions, 0)));
+                table.replace_elements(elements.collect());
+            }
+            vm.tables_mut()[id] = table;
+        }
+    }
+
+    /// Moves the shared state of `instance` back into the store after it ran
+    fn check_in(&mut self, funcs: &mut Vec<HostFunc<T>>, instance: usize, vm: &mut Vm<Host<T>>) {
+        let links = &mut self.links[instance];
+        if let Some(addr) = links.memory {
+            self.memories[addr] = vm.memory_slot().take();
+        }
+        for &(id, addr) in &links.globals {
+            let value = vm.globals()[id];
+            let functions = vm.functions();
+            self.globals[addr] = links.funcs.store_value(instance, value, funcs, functions);
+        }
+        for &(id, addr) in &links.tables {
+            let slot = &mut vm.tables_mut()[id];
+            let placeholder = TableInstance::with_size(slot.value_type(), 0, Some(0));
+            let mut table = std::mem::replace(slot, placeholder);
+            let functions = vm.functions();
+            if table.value_type() == ValueType::Funcref {
+                let elements = table
+                    .elements()
+                    .iter()
+                    .map(|r| r.map(|id| links.funcs.store(instance, id, funcs, functions)));
+                table.replace_elements(elements.collect());
+            }
+            self.tables[addr] = Some(table);
+        }
+    }
+
+    /// Replaces the error code of a failed call into another instance with its trap
+    fn with_callee_trap(&mut self, error: RuntimeError) -> RuntimeError {
+        let Some(callee) = self.trap.take() else {
+            return error;
+        };
+        match error {
+            RuntimeError::Trap {
+                func_id,
+                func_name,
+                instruction,
+                op,
+                error,
+            } if matches!(*error, RuntimeError::NativeFuncCallError(CALLEE_TRAPPED)) => {
+                RuntimeError::Trap {
+                    func_id,
+                    func_name,
+                    instruction,
+                    op,
+                    error: Box::new(callee),
+                }
+            }
+            RuntimeError::NativeFuncCallError(CALLEE_TRAPPED) => callee,
+            error => error,
+        }
+    }
+}
+
+/// Calls `func_id` of `instance` with its shared state checked out. Function references in
+/// `params` and the results are store function addresses.
+fn run<T>(
+    host: &mut Host<T>,
+    instance: usize,
+    func_id: usize,
+    params: &[LocalValue],
+) -> Result<Vec<LocalValue>, RuntimeError> {
+    //NOTE: The vm of a running instance is borrowed by the call running it, so it can't be
+    //entered again through a shared table
+    let Some(mut callee) = host.modules.instances[instance].take() else {
+        return Err(RuntimeError::InstanceRunning(instance));
+    };
+    let vm = callee.vm_mut();
+    host.modules.check_out(&host.funcs, instance, vm);
+    let funcs = &mut host.modules.links[instance].funcs;
+    let params: Vec<_> = params
+        .iter()
+        .map(|v| funcs.local_value(*v, &host.funcs, vm.functions_mut(), 0))
+        .collect();
+    host.modules.active.push(instance);
+    let result = match host.modules.fast {
+        true => {
+            let result;
+            (callee, result) = callee.call_fast(func_id, &params, host);
+            result
+        }
+        false => callee.call(func_id, &params, host),
+    };
+    host.modules.active.pop();
+    let (vm, funcs) = (callee.vm_mut(), &mut host.modules.links[instance].funcs);
+    let result = result.map(|values| {
+        let functions = vm.functions();
+        let values = values.into_iter();
+        values
+            .map(|v| funcs.store_value(instance, v, &mut host.funcs, functions))
+            .collect()
+    });
+    host.modules.check_in(&mut host.funcs, instance, vm);
+    host.modules.instances[instance] = Some(callee);
+    result.map_err(|e| host.modules.with_callee_trap(e))
+}
+
+/// Called by [`Host`] when a running instance calls a function imported from another instance
+pub(crate) fn call_export<T>(
+    host: &mut Host<T>,
+    vm: &mut Vm<Host<T>>,
+    instance: usize,
+    func_id: usize,
+    params: &[LocalValue],
+    results: &mut [LocalValue],
+) -> Result<(), usize> {
+    let caller = *host.modules.active.last().unwrap();
+    let funcs = &mut host.modules.links[caller].funcs;
+    let params: Vec<_> = params
+        .iter()
+        .map(|v| funcs.store_value(caller, *v, &mut host.funcs, vm.functions()))
+        .collect();
+    host.modules.check_in(&mut host.funcs, caller, vm);
+    let result = run(host, instance, func_id, &params);
+    host.modules.check_out(&host.funcs, caller, vm);
+    match result {
+        Ok(values) => {
+            let funcs = &mut host.modules.links[caller].funcs;
+            for (result, value) in results.iter_mut().zip(values) {
+                *result = funcs.local_value(value, &host.funcs, vm.functions_mut(), 0);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            host.modules.trap = Some(e);
+            Err(CALLEE_TRAPPED)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Export {
+    /// Store function address
+    Func(usize),
+    Global {
+        addr: usize,
+        mutable: bool,
+    },
+    Memory(usize),
+    Table(usize),
+}
+
+fn imported_func_id(info: &BytecodeInfo, import_id: usize) -> usize {
+    info.functions
+        .iter()
+        .position(|f| matches!(f.t, FunctionType::Imported { import_id: i } if i == import_id))
+        .unwrap()
+}
+
+fn imported_global_id(info: &BytecodeInfo, import_id: usize) -> usize {
+    info.globals
+        .iter()
+        .position(|g| matches!(g.info, GlobalInfo::Imported { import_id: i } if i == import_id))
+        .unwrap()
+}
+
+fn imported_table_id(info: &BytecodeInfo, import_id: usize) -> usize {
+    info.tables
+        .iter()
+        .position(|t| matches!(t.info, TableInfo::Imported { import_id: i } if i == import_id))
+        .unwrap()
+}
+
+/// Instantiates several modules by name, a module can import the exports of the modules
+/// instantiated before it. Imports no instance exports are taken from the [`Linker`].
+///
+/// Exported memories, globals and tables are shared between the instances importing them.
+/// While no instance using them runs they are held by the store, see [`Self::memory`].
+/// Function references passed to or returned from the store are store function addresses,
+/// so a reference stored in a shared table calls the same function in every instance.
+/// Calling a function of an instance that is already running traps with
+/// [`RuntimeError::InstanceRunning`].
+pub struct Store<T> {
+    linker: Linker<T>,
+    host: Host<T>,
+    names: HashMap<String, usize>,
+    exports: HashMap<(String, String), Export>,
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Store<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store")
+            .field("linker", &self.linker)
+            .field("host", &self.host)
+            .field("names", &self.names)
+            .field("exports", &self.exports)
+            .finish()
+    }
+}
+
+impl<T> Store<T> {
+    pub fn new(linker: Linker<T>, data: T) -> Self {
+        let mut host = Host::new(data);
+        //NOTE: The functions of the host are the store function addresses, the first ones
+        //are the functions of the linker
+        host.funcs = linker.funcs.clone();
+        Self {
+            linker,
+            host,
+            names: HashMap::new(),
+            exports: HashMap::new(),
+        }
+    }
+
+    /// Runs exported functions on the fast tier, start functions still run on the slow vm.
+    /// The code of an instance is lowered for every call into it.
+    pub fn with_fast_vm(mut self) -> Self {
+        self.host.modules.fast = true;
+        self
+    }
+
+    pub fn data(&self) -> &T {
+        self.host.data()
+    }
+
+    pub fn data_mut(&mut self) -> &mut T {
+        self.host.data_mut()
+    }
+
+    pub fn into_data(self) -> T {
+        self.host.into_data()
+    }
+
+    fn definition(&self, export: Export) -> Definition {
+        let modules = &self.host.modules;
+        match export {
+            Export::Func(id) => Definition::Func(id),
+            Export::Global { addr, mutable } => Definition::Global(ExternalGlobal {
+                value: modules.globals[addr],
+                mutable,
+            }),
+            Export::Memory(addr) => Definition::Memory(modules.memories[addr].clone().unwrap()),
+            Export::Table(addr) => Definition::Table(modules.tables[addr].clone().unwrap()),
+        }
+    }
+
+    /// Resolves the imports of `module` from the exports of the instances and the linker.
+    /// Shared memories and tables are copied, the copies replace them once the module
+    /// was instantiated. Function references in shared globals and tables are translated
+    /// to the module, the functions it does not import are returned to be appended to it.
+    fn resolve(
+        &self,
+        module: &ValidateResult,
+    ) -> Result<(Imports, Links, Vec<Function>), LinkError> {
+        let (bytecode, info) = (&module.bytecode, &module.info);
+        let (mut imports, mut links) = (Imports::default(), Links::default());
+        let Some(iter) = bytecode.iter_imports() else {
+            return Ok((imports, links, vec![]));
+        };
+        let mut shared_refs = vec![];
+        for (import_id, import) in iter.enumerate() {
+            let (module, name) = (import.get_mod_name(), import.get_name());
+            let key = (module.to_string(), name.to_string());
+            let export = self.exports.get(&key).copied();
+            let shared;
+            let definition = match export {
+                Some(export) => {
+                    shared = self.definition(export);
+                    &shared
+                }
+                None => self.linker.definition(module, name).ok_or_else(|| {
+                    LinkError::UnknownImport {
+                        module: module.to_string(),
+                        name: name.to_string(),
+                        kind: ImportKind::of(&import.desc.data),
+                    }
+                })?,
+            };
+            link(
+                &self.host.funcs,
+                bytecode,
+                import_id,
+                definition,
+                &mut imports,
+            )?;
+            match (export, definition) {
+                (Some(Export::Memory(addr)), _) => links.memory = Some(addr),
+                (Some(Export::Global { addr, .. }), _) => {
+                    let id = imported_global_id(info, import_id);
+                    links.globals.push((id, addr));
+                    shared_refs.push(import_id);
+                }
+                (Some(Export::Table(addr)), _) => {
+                    let id = imported_table_id(info, import_id);
+                    links.tables.push((id, addr));
+                    shared_refs.push(import_id);
+                }
+                (_, Definition::Func(addr)) => {
+                    let id = imported_func_id(info, import_id);
+                    links.funcs.insert(id as u32, *addr as u32);
+                }
+                _ => (),
+            }
+        }
+        let mut functions = vec![];
+        let first_id = info.functions.len();
+        for import_id in shared_refs {
+            if let Some(global) = imports.globals.get_mut(&import_id) {
+                global.value = (links.funcs).local_value(
+                    global.value,
+                    &self.host.funcs,
+                    &mut functions,
+                    first_id,
+                );
+            }
+            if let Some(table) = imports.tables.get_mut(&import_id)
+                && table.value_type() == ValueType::Funcref
+            {
+                let elements = table.elements().iter().map(|r| {
+                    r.map(|addr| {
+                        links
+                            .funcs
+                            .local(addr, &self.host.funcs, &mut functions, first_id)
+                    })
+                });
+                table.replace_elements(elements.collect());
+            }
+        }
+        Ok((imports, links, functions))
+    }
+
+    /// Gives the memory, globals and tables `instance` exports an address unless they are
+    /// already shared and returns its exports
+    fn share_exports(
+        &mut self,
+        id: usize,
+        instance: &Instance<Host<T>>,
+        links: &mut Links,
+    ) -> Vec<(String, Export)> {
+        let modules = &mut self.host.modules;
+        let module = instance.module();
+        let Some(exports) = module.bytecode.iter_exports() else {
+            return vec![];
+        };
+        exports
+            .map(|e| {
+                let export = match e.desc.data {
+                    ExportDesc::FuncId(func_id) => {
+                        let functions = instance.vm().functions();
+                        let (funcs, func_id) = (&mut self.host.funcs, func_id as u32);
+                        Export::Func(links.funcs.store(id, func_id, funcs, functions) as usize)
+                    }
+                    ExportDesc::MemId(_) => {
+                        let addr = *links.memory.get_or_insert_with(|| {
+                            modules.memories.push(None);
+                            modules.memories.len() - 1
+                        });
+                        Export::Memory(addr)
+                    }
+                    ExportDesc::GlobalId(id) => {
+                        let addr = match links.globals.iter().find(|(g, _)| *g == id) {
+                            Some((_, addr)) => *addr,
+                            None => {
+                                let t = module.info.globals[id].t;
+                                modules.globals.push(LocalValue::init_from_type(t));
+                                links.globals.push((id, modules.globals.len() - 1));
+                                modules.globals.len() - 1
+                            }
+                        };
+                        let mutable = module.info.globals[id].mutable;
+                        Export::Global { addr, mutable }
+                    }
+                    ExportDesc::TableId(id) => {
+                        let addr = match links.tables.iter().find(|(t, _)| *t == id) {
+                            Some((_, addr)) => *addr,
+                            None => {
+                                modules.tables.push(None);
+                                links.tables.push((id, modules.tables.len() - 1));
+                                modules.tables.len() - 1
+                            }
+                        };
+                        Export::Table(addr)
+                    }
+                };
+                (e.name.data.clone(), export)
+            })
+            .collect()
+    }
+
+    /// Instantiates `module` as `name` and runs its start function. Its exports can be
+    /// imported by the modules instantiated after it with `name` as the module name.
+    pub fn instantiate(&mut self, name: &str, module: ValidateResult) -> Result<(), LinkError> {
+        if self.names.contains_key(name) {
+            return Err(LinkError::DuplicateModule(name.to_string()));
+        }
+        let (imports, mut links, functions) = self.resolve(&module)?;
+        let mut vm = Vm::init_with_imports(&module.bytecode, &module.info, imports)
+            .map_err(ExecutionError::from)?;
+        vm.functions_mut().extend(functions);
+
+        let id = self.host.modules.instances.len();
+        let mut instance = Instance::unstarted(module, vm);
+        let exports = self.share_exports(id, &instance, &mut links);
+        let modules = &mut self.host.modules;
+        modules.instances.push(None);
+        modules.links.push(links);
+
+        //NOTE: The vm of a new instance holds all its state, as if it was checked out
+        self.host.modules.active.push(id);
+        let result = instance.run_start(&mut self.host);
+        let modules = &mut self.host.modules;
+        modules.active.pop();
+        modules.check_in(&mut self.host.funcs, id, instance.vm_mut());
+        //NOTE: An instance whose start function trapped stays in the store without a name,
+        //the functions it wrote into shared tables can still be called
+        modules.instances[id] = Some(instance);
+        if let Err(e) = result {
+            let e = match e {
+                ExecutionError::RuntimeError(e) => modules.with_callee_trap(e).into(),
+                e => e,
+            };
+            return Err(e.into());
+        }
+        self.names.insert(name.to_string(), id);
+        for (export_name, export) in exports {
+            self.exports.insert((name.to_string(), export_name), export);
+        }
+        Ok(())
+    }
+
+    /// Makes the exports of `module` importable as `as_name` as well, replacing the exports
+    /// registered under that name before
+    pub fn alias_module(&mut self, module: &str, as_name: &str) -> Result<(), LinkError> {
+        let id = *self
+            .names
+            .get(module)
+            .ok_or_else(|| LinkError::UnknownModule(module.to_string()))?;
+        self.exports.retain(|(m, _), _| m != as_name);
+        let aliases = self
+            .exports
+            .iter()
+            .filter(|((m, _), _)| m == module)
+            .map(|((_, name), export)| ((as_name.to_string(), name.clone()), *export))
+            .collect::<Vec<_>>();
+        self.exports.extend(aliases);
+        self.names.insert(as_name.to_string(), id);
+        Ok(())
+    }
+
+    pub fn instance(&self, module: &str) -> Option<&Instance<Host<T>>> {
+        self.host.modules.instances[*self.names.get(module)?].as_ref()
+    }
+
+    fn export_func(&self, module: &str, name: &str) -> Result<(usize, usize), RuntimeError> {
+        let unknown = || RuntimeError::UnknownExportedFunc(format!("{module}.{name}"));
+        let id = *self.names.get(module).ok_or_else(unknown)?;
+        let instance = self.host.modules.instances[id].as_ref().unwrap();
+        Ok((id, instance.func_id(name).ok_or_else(unknown)?))
+    }
+
+    /// Calls an exported function with untyped values, the params have to match its type
+    pub fn call(
+        &mut self,
+        module: &str,
+        name: &str,
+        params: &[LocalValue],
+    ) -> Result<Vec<LocalValue>, RuntimeError> {
+        let (id, func_id) = self.export_func(module, name)?;
+        run(&mut self.host, id, func_id, params)
+    }
+
+    /// Calls an exported function after checking its signature against `P` and `R`
+    pub fn call_typed<P: WasmTypeList, R: WasmTypeList>(
+        &mut self,
+        module: &str,
+        name: &str,
+        params: P,
+    ) -> Result<R, RuntimeError> {
+        let (id, func_id) = self.export_func(module, name)?;
+        let expected = Type {
+            params: P::types(),
+            results: R::types(),
+        };
+        let got = self.host.modules.instances[id]
+            .as_ref()
+            .and_then(|i| i.func_type(func_id))
+            .unwrap();
+        if *got != expected {
+            return Err(RuntimeError::ExportedFuncTypeMismatch {
+                name: format!("{module}.{name}"),
+                expected,
+                got: got.clone(),
+            });
+        }
+        let results = run(&mut self.host, id, func_id, &params.into_values())?;
+        Ok(R::from_values(&results))
+    }
+
+    pub fn global(&self, module: &str, name: &str) -> Option<LocalValue> {
+        match self.exports.get(&(module.to_string(), name.to_string()))? {
+            Export::Global { addr, .. } => Some(self.host.modules.globals[*addr]),
+            _ => None,
+        }
+    }
+
+    pub fn memory(&self, module: &str, name: &str) -> Option<&MemoryInstance> {
+        match self.exports.get(&(module.to_string(), name.to_string()))? {
+            Export::Memory(addr) => self.host.modules.memories[*addr].as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn memory_mut(&mut self, module: &str, name: &str) -> Option<&mut MemoryInstance> {
+        match self.exports.get(&(module.to_string(), name.to_string()))? {
+            Export::Memory(addr) => self.host.modules.memories[*addr].as_mut(),
+            _ => None,
+        }
+    }
+
+    pub fn table(&self, module: &str, name: &str) -> Option<&TableInstance> {
+        match self.exports.get(&(module.to_string(), name.to_string()))? {
+            Export::Table(addr) => self.host.modules.tables[*addr].as_ref(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::{linker::Caller, slow_vm::InstanceError};
+
+    const LIB: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $heap (export "heap") (mut i32) (i32.const 1024))
+            (func (export "alloc") (param $size i32) (result i32)
+                global.get $heap
+                global.get $heap
+                local.get $size
+                i32.add
+                global.set $heap
+            )
+            (func (export "strlen") (param $ptr i32) (result i32)
+                (local $len i32)
+                block $done
+                    loop $next
+                        local.get $ptr
+                        local.get $len
+                        i32.add
+                        i32.load8_u
+                        i32.eqz
+                        br_if $done
+                        local.get $len
+                        i32.const 1
+                        i32.add
+                        local.set $len
+                        br $next
+                    end
+                end
+                local.get $len
+            )
+        )
+    "#;
+
+    const STUDENT: &str = r#"
+        (module
+            (import "lib" "memory" (memory 1))
+            (import "lib" "heap" (global $heap (mut i32)))
+            (import "lib" "alloc" (func $alloc (param i32) (result i32)))
+            (import "lib" "strlen" (func $strlen (param i32) (result i32)))
+            (import "env" "io_print_sint" (func $print (param i32)))
+            (global $greeting (mut i32) (i32.const 0))
+            (data (i32.const 16) "hello")
+            (func $init
+                i32.const 4
+                call $alloc
+                global.set $greeting
+            )
+            (func (export "main") (result i32)
+                global.get $greeting
+                i32.const 0x216968
+                i32.store
+                global.get $greeting
+                call $strlen
+                call $print
+                i32.const 16
+                call $strlen
+                call $print
+                global.get $heap
+            )
+            (start $init)
+        )
+    "#;
+
+    fn store() -> Store<Vec<i32>> {
+        let mut linker = Linker::new();
+        linker
+            .func_wrap(
+                "env",
+                "io_print_sint",
+                |mut caller: Caller<'_, Vec<i32>>, x: i32| {
+                    caller.data_mut().push(x);
+                },
+            )
+            .unwrap();
+        let mut store = Store::new(linker, vec![]);
+        let lib = read_and_validate_wat(LIB).unwrap();
+        store.instantiate("lib", lib).unwrap();
+        store
+    }
+
+    #[test]
+    fn runtime_library() {
+        let mut store = store();
+        let student = read_and_validate_wat(STUDENT).unwrap();
+        store.instantiate("student", student).unwrap();
+        assert_eq!(store.global("lib", "heap"), Some(LocalValue::I32(1028)));
+
+        let heap = store.call_typed::<(), i32>("student", "main", ()).unwrap();
+        assert_eq!(heap, 1028);
+        let memory = store.memory("lib", "memory").unwrap();
+        assert_eq!(memory.range(1024, 4).unwrap(), b"hi!\0");
+        assert_eq!(memory.range(16, 5).unwrap(), b"hello");
+
+        //NOTE: A second module shares the heap with the first one
+        let other = read_and_validate_wat(STUDENT).unwrap();
+        store.instantiate("other", other).unwrap();
+        assert_eq!(
+            store.call("other", "main", &[]).unwrap(),
+            [LocalValue::I32(1032)]
+        );
+        assert_eq!(
+            store.call("lib", "alloc", &[LocalValue::I32(8)]).unwrap(),
+            [LocalValue::I32(1032)]
+        );
+        assert_eq!(store.global("lib", "heap"), Some(LocalValue::I32(1040)));
+        assert_eq!(store.into_data(), [3, 5, 3, 5]);
+    }
+
+    #[test]
+    fn shared_tables_and_traps() {
+        let a = r#"
+            (module
+                (table (export "table") 2 funcref)
+                (global (export "limit") i32 (i32.const 10))
+                (func (export "div") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.div_u
+                )
+            )
+        "#;
+        let b = r#"
+            (module
+                (import "a" "table" (table 2 funcref))
+                (import "a" "limit" (global $limit i32))
+                (import "a" "div" (func $div (param i32 i32) (result i32)))
+                (func $f)
+                (elem (i32.const 1) $f)
+                (func (export "run") (param i32) (result i32)
+                    global.get $limit
+                    local.get 0
+                    call $div
+                )
+            )
+        "#;
+        let mut store = Store::new(Linker::new(), ());
+        store
+            .instantiate("a", read_and_validate_wat(a).unwrap())
+            .unwrap();
+        store
+            .instantiate("b", read_and_validate_wat(b).unwrap())
+            .unwrap();
+        assert!(store.table("a", "table").unwrap().get(1).unwrap().is_some());
+
+        assert_eq!(store.call_typed::<i32, i32>("b", "run", 2).unwrap(), 5);
+        let err = store.call_typed::<i32, i32>("b", "run", 0).unwrap_err();
+        assert!(matches!(
+            err.trap_cause().trap_cause(),
+            RuntimeError::IntegerDivideByZero
+        ));
+        assert!(matches!(
+            store.call_typed::<(), i32>("b", "run", ()),
+            Err(RuntimeError::ExportedFuncTypeMismatch { .. })
+        ));
+
+        assert!(matches!(
+            store.instantiate("b", read_and_validate_wat(b).unwrap()),
+            Err(LinkError::DuplicateModule(_))
+        ));
+        let missing = r#"(module (import "a" "missing" (func)))"#;
+        assert_eq!(
+            store
+                .instantiate("c", read_and_validate_wat(missing).unwrap())
+                .unwrap_err()
+                .to_string(),
+            "Unknown import: function a.missing is not defined"
+        );
+        let mutable = r#"(module (import "a" "limit" (global (mut i32))))"#;
+        assert!(matches!(
+            store.instantiate("c", read_and_validate_wat(mutable).unwrap()),
+            Err(LinkError::GlobalMutabilityMismatch { expected: true, .. })
+        ));
+    }
+
+    #[test]
+    fn call_indirect_through_shared_table() {
+        let lib = r#"
+            (module
+                (table (export "table") 2 funcref)
+                (func $seven (result i32) i32.const 7)
+                (elem (i32.const 0) $seven)
+                (func (export "call") (param i32) (result i32)
+                    local.get 0
+                    call_indirect (result i32)
+                )
+            )
+        "#;
+        let user = r#"
+            (module
+                (import "lib" "table" (table 2 funcref))
+                (import "lib" "call" (func $call (param i32) (result i32)))
+                (func $three (result i32) i32.const 3)
+                (elem (i32.const 1) $three)
+                (func (export "first") (result i32)
+                    i32.const 0
+                    call_indirect (result i32)
+                )
+                (func (export "second") (result i32)
+                    i32.const 1
+                    call $call
+                )
+            )
+        "#;
+        let stores = [
+            Store::new(Linker::new(), ()),
+            Store::new(Linker::new(), ()).with_fast_vm(),
+        ];
+        for mut store in stores {
+            store
+                .instantiate("lib", read_and_validate_wat(lib).unwrap())
+                .unwrap();
+            store
+                .instantiate("user", read_and_validate_wat(user).unwrap())
+                .unwrap();
+
+            assert_eq!(store.call_typed::<(), i32>("user", "first", ()).unwrap(), 7);
+            assert_eq!(store.call_typed::<i32, i32>("lib", "call", 0).unwrap(), 7);
+            assert_eq!(store.call_typed::<i32, i32>("lib", "call", 1).unwrap(), 3);
+            let err = store
+                .call_typed::<(), i32>("user", "second", ())
+                .unwrap_err();
+            assert!(matches!(
+                err.trap_cause().trap_cause(),
+                RuntimeError::InstanceRunning(1)
+            ));
+
+            store.alias_module("lib", "alias").unwrap();
+            assert_eq!(store.call_typed::<i32, i32>("alias", "call", 1).unwrap(), 3);
+            let importer = r#"(module (import "alias" "table" (table 2 funcref)))"#;
+            store
+                .instantiate("importer", read_and_validate_wat(importer).unwrap())
+                .unwrap();
+            assert!(matches!(
+                store.alias_module("missing", "alias"),
+                Err(LinkError::UnknownModule(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn imported_globals_in_segment_offsets() {
+        let lib = r#"
+            (module
+                (global (export "base") i32 (i32.const 32))
+                (global (export "end") i32 (i32.const 65535))
+                (memory (export "memory") 1)
+            )
+        "#;
+        let user = r#"
+            (module
+                (import "lib" "base" (global $b i32))
+                (import "lib" "memory" (memory 1))
+                (global $next i32 (global.get $b))
+                (table 4 funcref)
+                (func $f)
+                (elem (global.get $b) $f)
+                (data (global.get $b) "hi")
+                (func (export "next") (result i32) global.get $next)
+            )
+        "#;
+        let mut store = Store::new(Linker::new(), ());
+        store
+            .instantiate("lib", read_and_validate_wat(lib).unwrap())
+            .unwrap();
+        //NOTE: The element segment starts at 32, outside of the table
+        assert!(matches!(
+            store.instantiate("user", read_and_validate_wat(user).unwrap()),
+            Err(LinkError::Instantiation(ExecutionError::InstanceError(
+                InstanceError::ElementSegmentOutOfBounds(0)
+            )))
+        ));
+
+        let user = user.replace("(table 4 funcref)", "(table 64 funcref)");
+        store
+            .instantiate("user", read_and_validate_wat(&user).unwrap())
+            .unwrap();
+        assert_eq!(store.call_typed::<(), i32>("user", "next", ()).unwrap(), 32);
+        let memory = store.memory("lib", "memory").unwrap();
+        assert_eq!(memory.range(32, 2).unwrap(), b"hi");
+
+        let overflow = r#"
+            (module
+                (import "lib" "end" (global $end i32))
+                (import "lib" "memory" (memory 1))
+                (data (global.get $end) "hi")
+            )
+        "#;
+        assert!(matches!(
+            store.instantiate("overflow", read_and_validate_wat(overflow).unwrap()),
+            Err(LinkError::Instantiation(ExecutionError::InstanceError(
+                InstanceError::DataSegmentOutOfBounds(0)
+            )))
+        ));
+    }
+}
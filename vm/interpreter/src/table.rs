@@ -0,0 +1,125 @@
+use parser::reader::{TableType, ValueType};
+
+use crate::slow_vm::RuntimeError;
+
+/// A reference stored in a table, `None` is the null reference.
+pub type Ref = Option<u32>;
+
+//NOTE: Tables without a maximum could grow up to u32::MAX entries, we cap them to keep
+//table.grow from allocating gigabytes.
+pub const MAX_TABLE_SIZE: u32 = 10_000_000;
+
+#[derive(Debug, Clone)]
+pub struct TableInstance {
+    t: ValueType,
+    max: Option<u32>,
+    elements: Vec<Ref>,
+}
+
+impl TableInstance {
+    pub fn new(table_type: &TableType) -> Self {
+        let limits = &table_type.limits.data;
+        Self::with_size(
+            table_type.value_type(),
+            limits.min.data,
+            limits.max.as_ref().map(|m| m.data),
+        )
+    }
+
+    /// A table of `min` null references that can grow up to `max` elements
+    pub fn with_size(t: ValueType, min: u32, max: Option<u32>) -> Self {
+        Self {
+            t,
+            max,
+            elements: vec![None; min as usize],
+        }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.t
+    }
+
+    pub fn size(&self) -> u32 {
+        self.elements.len() as u32
+    }
+
+    /// The maximum as declared
+    pub fn max(&self) -> Option<u32> {
+        self.max
+    }
+
+    /// The size the table can at most grow to
+    pub fn max_size(&self) -> u32 {
+        self.max.unwrap_or(MAX_TABLE_SIZE)
+    }
+
+    pub fn elements(&self) -> &[Ref] {
+        &self.elements
+    }
+
+    /// Used to restore snapshots, the caller checks the size against [`Self::max_size`]
+    pub(crate) fn replace_elements(&mut self, elements: Vec<Ref>) {
+        self.elements = elements;
+    }
+
+    pub fn get(&self, i: u32) -> Result<Ref, RuntimeError> {
+        self.elements
+            .get(i as usize)
+            .copied()
+            .ok_or(RuntimeError::TableAddressOutOfScope)
+    }
+
+    pub fn set(&mut self, i: u32, val: Ref) -> Result<(), RuntimeError> {
+        let entry = self
+            .elements
+            .get_mut(i as usize)
+            .ok_or(RuntimeError::TableAddressOutOfScope)?;
+        *entry = val;
+        Ok(())
+    }
+
+    /// Returns the previous size or `None` if the table can not grow by `n`.
+    pub fn grow(&mut self, n: u32, init: Ref) -> Option<u32> {
+        let old_size = self.size();
+        let new_size = old_size.checked_add(n)?;
+        if new_size > self.max_size() {
+            return None;
+        }
+        self.elements.resize(new_size as usize, init);
+        Some(old_size)
+    }
+
+    pub fn range(&self, start: u32, n: u32) -> Result<&[Ref], RuntimeError> {
+        let start = start as usize;
+        self.elements
+            .get(start..start + n as usize)
+            .ok_or(RuntimeError::TableAddressOutOfScope)
+    }
+
+    fn range_mut(&mut self, start: u32, n: u32) -> Result<&mut [Ref], RuntimeError> {
+        let start = start as usize;
+        self.elements
+            .get_mut(start..start + n as usize)
+            .ok_or(RuntimeError::TableAddressOutOfScope)
+    }
+
+    pub fn fill(&mut self, start: u32, val: Ref, n: u32) -> Result<(), RuntimeError> {
+        self.range_mut(start, n)?.fill(val);
+        Ok(())
+    }
+
+    pub fn write(&mut self, start: u32, src: &[Ref]) -> Result<(), RuntimeError> {
+        self.range_mut(start, src.len() as u32)?
+            .copy_from_slice(src);
+        Ok(())
+    }
+
+    pub fn copy_within(&mut self, dst: u32, src: u32, n: u32) -> Result<(), RuntimeError> {
+        _ = self.range(src, n)?;
+        _ = self.range(dst, n)?;
+        let src = src as usize;
+        self.elements
+            .copy_within(src..src + n as usize, dst as usize);
+        Ok(())
+    }
+}
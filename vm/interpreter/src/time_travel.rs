@@ -0,0 +1,457 @@
+use std::{collections::VecDeque, ops::Range};
+
+use crate::{
+    slow_vm::{ActivationFrame, Label, LocalValue},
+    snapshot::Snapshot,
+    stack::StackValue,
+    table::{Ref, TableInstance},
+};
+
+pub const DEFAULT_MAX_UNDO_ENTRIES: usize = 100_000;
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 100_000;
+pub const DEFAULT_MAX_CHECKPOINTS: usize = 8;
+
+/// Everything a single instruction changed, recorded before it executed.
+/// Only the current call can be changed by an instruction, so only its part of the stacks is kept.
+#[derive(Debug, Clone)]
+pub struct UndoEntry {
+    pub(crate) ip: usize,
+    pub(crate) func_id: Option<usize>,
+    pub(crate) local_offset: usize,
+    pub(crate) frames: usize,
+    pub(crate) top_frame: ActivationFrame,
+    pub(crate) stack_start: usize,
+    pub(crate) stack: Vec<StackValue>,
+    pub(crate) labels_start: usize,
+    pub(crate) labels: Vec<Label>,
+    pub(crate) locals_start: usize,
+    pub(crate) locals: Vec<LocalValue>,
+    /// Previous value of the global written by `global.set`
+    pub(crate) global: Option<(usize, LocalValue)>,
+    /// Previous contents of the written memory ranges, in write order
+    pub(crate) memory: Vec<(usize, Vec<u8>)>,
+    /// Memory size in bytes before `memory.grow`
+    pub(crate) memory_len: Option<usize>,
+    /// Tables and element segments before an instruction that changes them
+    pub(crate) tables: Option<(Vec<TableInstance>, Vec<Vec<Ref>>)>,
+}
+
+impl UndoEntry {
+    /// The memory ranges written by the instruction
+    pub fn memory_writes(&self) -> impl Iterator<Item = Range<usize>> {
+        self.memory
+            .iter()
+            .map(|(addr, bytes)| *addr..*addr + bytes.len())
+    }
+
+    pub fn wrote(&self, addr: usize) -> bool {
+        self.memory_writes().any(|range| range.contains(&addr))
+    }
+
+    pub fn global_write(&self) -> Option<usize> {
+        self.global.map(|(id, _)| id)
+    }
+}
+
+/// Records executed instructions so they can be undone with `Vm::step_back`, attached with
+/// `Vm::set_history`. Only available with the `time-travel` feature.
+///
+/// The last instructions are kept in an undo log. For older instructions the vm goes back to a
+/// checkpoint, a [`Snapshot`] taken every few instructions, and executes forward again. Host
+/// functions are called again during that replay, so it is only exact for a deterministic env.
+/// Memory written by host functions through `Vm::get_bytes_from_mem_mut` or
+/// `Caller::memory_mut` is restored when their call is undone.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub(crate) entries: VecDeque<UndoEntry>,
+    pub(crate) current: Option<UndoEntry>,
+    max_entries: usize,
+    /// Oldest first
+    pub(crate) checkpoints: VecDeque<(u64, Snapshot)>,
+    checkpoint_interval: u64,
+    max_checkpoints: usize,
+    /// Number of instructions executed since the history was attached
+    pub(crate) step: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            current: None,
+            max_entries: DEFAULT_MAX_UNDO_ENTRIES,
+            checkpoints: VecDeque::new(),
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            max_checkpoints: DEFAULT_MAX_CHECKPOINTS,
+            step: 0,
+        }
+    }
+
+    /// Limits the undo log, older instructions are only reachable through checkpoints
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    /// Takes a checkpoint every `interval` instructions and keeps the last `max` of them,
+    /// `max` 0 disables checkpoints
+    pub fn with_checkpoints(mut self, interval: u64, max: usize) -> Self {
+        self.checkpoint_interval = interval.max(1);
+        self.max_checkpoints = max;
+        self
+    }
+
+    /// Number of instructions executed since the history was attached
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// The entry of the last executed instruction
+    pub fn last(&self) -> Option<&UndoEntry> {
+        self.entries.back()
+    }
+
+    /// True if the previous instruction can be undone
+    pub fn can_step_back(&self) -> bool {
+        !self.entries.is_empty() || self.checkpoint_before(self.step).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.checkpoints.clear();
+        self.current = None;
+        self.step = 0;
+    }
+
+    pub(crate) fn wants_checkpoint(&self) -> bool {
+        self.max_checkpoints > 0 && self.step.is_multiple_of(self.checkpoint_interval)
+    }
+
+    pub(crate) fn add_checkpoint(&mut self, snapshot: Snapshot) {
+        //NOTE: After stepping back, later checkpoints are replaced on the way forward
+        self.checkpoints.retain(|(step, _)| *step < self.step);
+        self.checkpoints.push_back((self.step, snapshot));
+        if self.checkpoints.len() > self.max_checkpoints {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    /// The latest checkpoint taken before instruction `step` executed
+    pub(crate) fn checkpoint_before(&self, step: u64) -> Option<&(u64, Snapshot)> {
+        self.checkpoints.iter().rev().find(|(s, _)| *s < step)
+    }
+
+    pub(crate) fn commit(&mut self) {
+        if let Some(entry) = self.current.take() {
+            self.entries.push_back(entry);
+            if self.entries.len() > self.max_entries {
+                self.entries.pop_front();
+            }
+            self.step += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::reader::ValueType;
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::{
+        debugger::{Debugger, StopReason},
+        env::{Env, ExternalFunction, ExternalGlobal},
+        linker::{Caller, Host, Linker},
+        slow_vm::{DebugEnv, RuntimeError, Vm},
+    };
+
+    const SRC: &str = r#"
+        (module
+            (memory 1)
+            (global $total (mut i32) (i32.const 0))
+            (func $store (param i32 i32)
+                local.get 0
+                local.get 1
+                i32.store
+            )
+            (func $main (param i32)
+                (local i32)
+                loop $l
+                    i32.const 100
+                    local.get 1
+                    call $store
+                    global.get $total
+                    local.get 1
+                    i32.add
+                    global.set $total
+                    local.get 1
+                    i32.const 1
+                    i32.add
+                    local.tee 1
+                    local.get 0
+                    i32.lt_u
+                    br_if $l
+                end
+                i32.const 1
+                memory.grow
+                drop
+            )
+        )
+    "#;
+
+    /// Steps through `$main` and returns the state before every instruction
+    fn record(vm: &mut Vm<DebugEnv>, bytecode: &parser::reader::Bytecode) -> Vec<Snapshot> {
+        vm.set_func(1, [LocalValue::I32(4)]).unwrap();
+        let mut states = vec![];
+        loop {
+            states.push(vm.snapshot(true));
+            if vm.step(bytecode, &mut DebugEnv {}).unwrap() {
+                return states;
+            }
+        }
+    }
+
+    #[test]
+    fn step_back_restores_every_state() {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_history(Some(History::new()));
+        let states = record(&mut vm, &res.bytecode);
+        assert_eq!(vm.memory().unwrap().size(), 2);
+
+        for state in states.iter().rev() {
+            assert!(
+                vm.step_back(&res.bytecode, &mut DebugEnv {})
+                    .unwrap()
+                    .is_some()
+            );
+            assert_eq!(&vm.snapshot(true), state);
+        }
+        assert!(
+            vm.step_back(&res.bytecode, &mut DebugEnv {})
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn replay_from_checkpoints() {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        let history = History::new().with_max_entries(5).with_checkpoints(10, 100);
+        vm.set_history(Some(history));
+        let states = record(&mut vm, &res.bytecode);
+
+        for state in states.iter().rev() {
+            vm.step_back(&res.bytecode, &mut DebugEnv {}).unwrap();
+            assert_eq!(&vm.snapshot(true), state);
+        }
+        assert!(!vm.history().unwrap().can_step_back());
+    }
+
+    /// Returns 1 and 0 in turns, so a replay takes another path than the recorded run
+    struct Coin(u32);
+
+    impl Env for Coin {
+        fn get_func(_: &str, name: &str) -> Option<ExternalFunction> {
+            (name == "flip").then(|| ExternalFunction {
+                params: vec![],
+                result: vec![ValueType::I32],
+                id: 0,
+            })
+        }
+        fn get_global(_: &str, _: &str) -> Option<ExternalGlobal> {
+            None
+        }
+        fn call(
+            &mut self,
+            _: &mut Vm<Self>,
+            _: &[LocalValue],
+            results: &mut [LocalValue],
+            _: usize,
+        ) -> Result<(), usize> {
+            self.0 ^= 1;
+            results[0] = LocalValue::I32(self.0);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn replay_with_nondeterministic_env() {
+        let src = r#"
+            (module
+                (import "env" "flip" (func $flip (result i32)))
+                (func $main (result i32)
+                    call $flip
+                    if (result i32)
+                        i32.const 1
+                        i32.const 2
+                        i32.add
+                    else
+                        i32.const 0
+                    end
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut env = Coin(0);
+        let mut vm: Vm<Coin> = Vm::init_from_validation_result(&res).unwrap();
+        let history = History::new().with_max_entries(1).with_checkpoints(100, 1);
+        vm.set_history(Some(history));
+        vm.set_func(1, []).unwrap();
+        while !vm.step(&res.bytecode, &mut env).unwrap() {}
+
+        assert!(vm.step_back(&res.bytecode, &mut env).unwrap().is_some());
+        assert!(matches!(
+            vm.step_back(&res.bytecode, &mut env),
+            Err(RuntimeError::ReplayDiverged)
+        ));
+    }
+
+    #[test]
+    fn reverse_to_last_write() {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let (bytecode, info) = (&res.bytecode, &res.info);
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        let mut env = DebugEnv {};
+        vm.set_history(Some(History::new()));
+        vm.set_func(1, [LocalValue::I32(4)]).unwrap();
+        let debugger = Debugger::new();
+        let stop = debugger.resume(&mut vm, bytecode, info, &mut env).unwrap();
+        assert!(matches!(stop, StopReason::Finished(_)));
+        let value = |vm: &Vm<DebugEnv>| vm.memory().unwrap().range(100, 1).unwrap()[0];
+        assert_eq!(value(&vm), 3);
+
+        let stop = debugger
+            .reverse_to_write(&mut vm, bytecode, &mut env, 100)
+            .unwrap();
+        assert!(matches!(stop, StopReason::Step));
+        assert_eq!(vm.position(), Some((0, 2)));
+        assert_eq!(value(&vm), 2);
+        assert_eq!(vm.globals(), &[LocalValue::I32(3)]);
+
+        let stop = debugger
+            .reverse_continue(&mut vm, bytecode, &mut env)
+            .unwrap();
+        assert!(matches!(stop, StopReason::HistoryStart));
+        assert_eq!(vm.position(), Some((1, 0)));
+        assert_eq!(value(&vm), 0);
+    }
+    /// Writes its parameter to address 8 through `get_bytes_from_mem_mut`
+    struct PokeEnv;
+
+    impl Env for PokeEnv {
+        fn get_func(env: &str, name: &str) -> Option<ExternalFunction> {
+            (env == "env" && name == "poke").then(|| ExternalFunction {
+                params: vec![parser::reader::ValueType::I32],
+                result: vec![],
+                id: 0,
+            })
+        }
+        fn get_global(_env: &str, _name: &str) -> Option<ExternalGlobal> {
+            None
+        }
+        fn call(
+            &mut self,
+            vm: &mut Vm<Self>,
+            params: &[LocalValue],
+            _results: &mut [LocalValue],
+            _func_id: usize,
+        ) -> Result<(), usize> {
+            let [LocalValue::I32(v)] = params else {
+                return Err(1);
+            };
+            vm.get_bytes_from_mem_mut(8, 1).map_err(|_| 2usize)?[0] = *v as u8;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn undo_host_memory_writes() {
+        let src = r#"
+            (module
+                (import "env" "poke" (func $poke (param i32)))
+                (memory 1)
+                (func $main
+                    i32.const 7
+                    call $poke
+                    i32.const 9
+                    call $poke
+                )
+            )
+        "#;
+        let res = read_and_validate_wat(src).unwrap();
+        let mut vm: Vm<PokeEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_history(Some(History::new()));
+        vm.set_func(1, []).unwrap();
+        let mut states = vec![];
+        loop {
+            states.push(vm.snapshot(true));
+            if vm.step(&res.bytecode, &mut PokeEnv).unwrap() {
+                break;
+            }
+        }
+        assert_eq!(vm.memory().unwrap().range(8, 1).unwrap(), [9]);
+
+        for state in states.iter().rev() {
+            vm.step_back(&res.bytecode, &mut PokeEnv).unwrap();
+            assert_eq!(&vm.snapshot(true), state);
+        }
+    }
+    #[test]
+    fn undo_caller_memory_writes() {
+        let src = r#"
+            (module
+                (import "env" "fill" (func $fill (param i32)))
+                (import "env" "poke" (func $poke (param i32)))
+                (memory 1)
+                (func $main
+                    i32.const 7
+                    call $fill
+                    i32.const 9
+                    call $poke
+                )
+            )
+        "#;
+        let mut linker = Linker::new();
+        linker
+            .func_wrap("env", "fill", |mut caller: Caller<()>, v: i32| {
+                let memory = caller.memory_mut().unwrap();
+                memory.bytes_mut()[..4].fill(v as u8);
+                memory.grow(1);
+            })
+            .unwrap()
+            .func_wrap("env", "poke", |mut caller: Caller<()>, v: i32| {
+                caller.vm_mut().get_bytes_from_mem_mut(8, 1).unwrap()[0] = v as u8;
+            })
+            .unwrap();
+        let mut host = Host::new(());
+        let res = read_and_validate_wat(src).unwrap();
+        let mut instance = linker.instantiate(res, &mut host).unwrap();
+        let bytecode = read_and_validate_wat(src).unwrap().bytecode;
+        let vm = instance.vm_mut();
+        vm.set_history(Some(History::new()));
+        vm.set_func(2, []).unwrap();
+        let mut states = vec![];
+        loop {
+            states.push(vm.snapshot(true));
+            if vm.step(&bytecode, &mut host).unwrap() {
+                break;
+            }
+        }
+        let memory = vm.memory().unwrap();
+        assert_eq!(memory.range(0, 9).unwrap(), [7, 7, 7, 7, 0, 0, 0, 0, 9]);
+        assert_eq!(memory.size(), 2);
+
+        for state in states.iter().rev() {
+            vm.step_back(&bytecode, &mut host).unwrap();
+            assert_eq!(&vm.snapshot(true), state);
+        }
+    }
+}
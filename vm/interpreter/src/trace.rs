@@ -0,0 +1,311 @@
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    io::{self, Write},
+    ops::Range,
+};
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::json::{json_array, json_string};
+
+/// Identifies binary traces, followed by a version byte
+pub const BINARY_MAGIC: &[u8; 4] = b"WTRC";
+pub const BINARY_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// One JSON object per executed instruction
+    JsonLines,
+    /// [`BINARY_MAGIC`] and version, then per instruction: func id and instruction index as u32,
+    /// the op as u16 length prefixed string, both stacks as u32 count followed by u64 values and
+    /// the memory writes as u32 count followed by u64 address, u32 length and the bytes.
+    /// Everything is little endian.
+    Binary,
+}
+
+/// Restricts which instructions are recorded, an empty filter records everything
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    functions: Option<HashSet<usize>>,
+    instructions: Option<Range<usize>>,
+}
+
+impl TraceFilter {
+    pub fn functions(mut self, functions: impl IntoIterator<Item = usize>) -> Self {
+        self.functions = Some(functions.into_iter().collect());
+        self
+    }
+
+    /// Instruction indices inside the function body
+    pub fn instructions(mut self, range: Range<usize>) -> Self {
+        self.instructions = Some(range);
+        self
+    }
+
+    pub fn matches(&self, func_id: usize, instruction: usize) -> bool {
+        self.functions.as_ref().is_none_or(|f| f.contains(&func_id))
+            && self
+                .instructions
+                .as_ref()
+                .is_none_or(|r| r.contains(&instruction))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemoryWrite {
+    pub addr: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEntry {
+    pub func_id: usize,
+    pub func_name: Option<String>,
+    pub instruction: usize,
+    pub op: String,
+    /// Raw bits of the operand stack of the current call, 32 bit values are zero extended
+    pub stack_before: Vec<u64>,
+    pub stack_after: Vec<u64>,
+    pub memory_writes: Vec<MemoryWrite>,
+}
+
+/// Streams executed instructions to a writer, attached with `Vm::set_tracer`.
+/// Only available with the `trace` feature.
+pub struct TraceRecorder {
+    writer: Box<dyn Write + Send>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    current: Option<TraceEntry>,
+    written: usize,
+    error: Option<io::Error>,
+}
+
+impl std::fmt::Debug for TraceRecorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceRecorder")
+            .field("format", &self.format)
+            .field("filter", &self.filter)
+            .field("written", &self.written)
+            .finish()
+    }
+}
+
+impl TraceRecorder {
+    pub fn new(writer: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        let mut recorder = Self {
+            writer: Box::new(writer),
+            format,
+            filter: TraceFilter::default(),
+            current: None,
+            written: 0,
+            error: None,
+        };
+        if format == TraceFormat::Binary {
+            let res = recorder
+                .writer
+                .write_all(BINARY_MAGIC)
+                .and_then(|_| recorder.writer.write_u8(BINARY_VERSION));
+            recorder.error = res.err();
+        }
+        recorder
+    }
+
+    pub fn with_filter(mut self, filter: TraceFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Number of recorded instructions
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    /// Flushes the writer and reports the first error that occurred while recording.
+    /// Recording stops after an error.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+
+    pub fn begin(
+        &mut self,
+        func_id: usize,
+        func_name: Option<&str>,
+        instruction: usize,
+        op: &impl std::fmt::Display,
+        stack: impl Iterator<Item = u64>,
+    ) {
+        if self.error.is_some() || !self.filter.matches(func_id, instruction) {
+            return;
+        }
+        self.current = Some(TraceEntry {
+            func_id,
+            func_name: func_name.map(str::to_string),
+            instruction,
+            op: op.to_string(),
+            stack_before: stack.collect(),
+            stack_after: vec![],
+            memory_writes: vec![],
+        });
+    }
+
+    /// True while an instruction that passed the filter is executed
+    pub fn is_recording(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn memory_write(&mut self, addr: usize, bytes: &[u8]) {
+        if let Some(entry) = &mut self.current {
+            entry.memory_writes.push(MemoryWrite {
+                addr,
+                bytes: bytes.to_vec(),
+            });
+        }
+    }
+
+    pub fn end(&mut self, stack: impl Iterator<Item = u64>) {
+        let Some(mut entry) = self.current.take() else {
+            return;
+        };
+        entry.stack_after = stack.collect();
+        let res = match self.format {
+            TraceFormat::JsonLines => writeln!(self.writer, "{}", to_json(&entry)),
+            TraceFormat::Binary => write_binary(&mut self.writer, &entry),
+        };
+        match res {
+            Ok(()) => self.written += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
+pub fn to_json(entry: &TraceEntry) -> String {
+    let mut out = String::new();
+    _ = write!(out, "{{\"func_id\":{},\"func_name\":", entry.func_id);
+    match &entry.func_name {
+        Some(name) => json_string(&mut out, name),
+        None => out.push_str("null"),
+    }
+    _ = write!(out, ",\"instruction\":{},\"op\":", entry.instruction);
+    json_string(&mut out, &entry.op);
+    out.push_str(",\"stack_before\":");
+    json_array(&mut out, &entry.stack_before);
+    out.push_str(",\"stack_after\":");
+    json_array(&mut out, &entry.stack_after);
+    out.push_str(",\"memory_writes\":[");
+    for (i, write) in entry.memory_writes.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        _ = write!(out, "{{\"addr\":{},\"bytes\":", write.addr);
+        json_array(&mut out, &write.bytes);
+        out.push('}');
+    }
+    out.push_str("]}");
+    out
+}
+
+fn write_binary(w: &mut impl Write, entry: &TraceEntry) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(entry.func_id as u32)?;
+    w.write_u32::<LittleEndian>(entry.instruction as u32)?;
+    w.write_u16::<LittleEndian>(entry.op.len() as u16)?;
+    w.write_all(entry.op.as_bytes())?;
+    for stack in [&entry.stack_before, &entry.stack_after] {
+        w.write_u32::<LittleEndian>(stack.len() as u32)?;
+        stack
+            .iter()
+            .try_for_each(|v| w.write_u64::<LittleEndian>(*v))?;
+    }
+    w.write_u32::<LittleEndian>(entry.memory_writes.len() as u32)?;
+    for write in &entry.memory_writes {
+        w.write_u64::<LittleEndian>(write.addr as u64)?;
+        w.write_u32::<LittleEndian>(write.bytes.len() as u32)?;
+        w.write_all(&write.bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use validator::validator::read_and_validate_wat;
+
+    use super::*;
+    use crate::slow_vm::{DebugEnv, LocalValue, Vm};
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SRC: &str = r#"
+        (module
+            (memory 1)
+            (func $store (param i32)
+                i32.const 8
+                local.get 0
+                i32.store
+            )
+            (func $main (param i32)
+                local.get 0
+                call $store
+            )
+        )
+    "#;
+
+    fn record(format: TraceFormat, filter: TraceFilter) -> Vec<u8> {
+        let res = read_and_validate_wat(SRC).unwrap();
+        let buffer = SharedBuffer::default();
+        let recorder = TraceRecorder::new(buffer.clone(), format).with_filter(filter);
+        let recorder = Arc::new(Mutex::new(recorder));
+        let mut vm: Vm<DebugEnv> = Vm::init_from_validation_result(&res).unwrap();
+        vm.set_tracer(Some(recorder.clone()));
+        vm.set_func(1, [LocalValue::I32(7)]).unwrap();
+        vm.run_func(&res.bytecode, &res.info, &mut DebugEnv {})
+            .unwrap();
+        recorder.lock().unwrap().finish().unwrap();
+        buffer.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn json_lines() {
+        let trace = record(TraceFormat::JsonLines, TraceFilter::default());
+        let lines = String::from_utf8(trace).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[0],
+            r#"{"func_id":1,"func_name":"main","instruction":0,"op":"local.get 0","stack_before":[],"stack_after":[7],"memory_writes":[]}"#
+        );
+        assert_eq!(
+            lines[4],
+            r#"{"func_id":0,"func_name":"store","instruction":2,"op":"i32.store 0 2","stack_before":[8,7],"stack_after":[],"memory_writes":[{"addr":8,"bytes":[7,0,0,0]}]}"#
+        );
+    }
+
+    #[test]
+    fn filtered_binary() {
+        let filter = TraceFilter::default().functions([0]).instructions(2..3);
+        let trace = record(TraceFormat::Binary, filter);
+        assert_eq!(&trace[..4], BINARY_MAGIC);
+        assert_eq!(trace[4], BINARY_VERSION);
+        let entry = &trace[5..];
+        assert_eq!(&entry[..8], &[0, 0, 0, 0, 2, 0, 0, 0]);
+        //NOTE: The entry ends with the single memory write of the store
+        assert_eq!(
+            &entry[entry.len() - 20..],
+            &[1, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0]
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use gimli::{EndianSlice, LittleEndian, SectionId};
+
+use crate::{leb::Leb, reader::Bytecode};
+
+/// A source location from the DWARF line table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Offset of the instruction relative to the start of the code section
+    pub address: u64,
+    pub file: String,
+    pub line: u64,
+}
+
+/// Maps instructions to source lines, built from the `.debug_line` custom section that
+/// compilers like clang emit with `-g`
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    /// Sorted by address
+    rows: Vec<SourceLine>,
+}
+
+impl LineTable {
+    pub fn new(mut rows: Vec<SourceLine>) -> Self {
+        rows.sort_by_key(|r| r.address);
+        Self { rows }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The row that covers `address`, that is the last row starting at or before it
+    pub fn lookup(&self, address: u64) -> Option<&SourceLine> {
+        let end = self.rows.partition_point(|r| r.address <= address);
+        end.checked_sub(1).map(|i| &self.rows[i])
+    }
+}
+
+impl Bytecode {
+    /// Offset of the code section contents in the file, DWARF addresses are relative to it
+    pub fn code_section_start(&self) -> Option<usize> {
+        let code = &self.code.as_ref()?.data;
+        let mut count = vec![];
+        Leb::write_u32(&mut count, code.len() as u32).ok()?;
+        //NOTE: The section only stores the positions of its items, the first body follows the
+        //function count
+        Some(code.first()?.position.start - count.len())
+    }
+
+    /// DWARF address of the instruction `instruction` of the function body `code_id`
+    pub fn code_address(&self, code_id: usize, instruction: usize) -> Option<u64> {
+        let start = self.code_section_start()?;
+        let op = self.get_code(code_id)?.code.data.ops().get(instruction)?;
+        Some((op.position.start - start) as u64)
+    }
+
+    /// Decodes the DWARF line table if the module has one
+    pub fn parse_line_table(&self) -> Option<Result<LineTable, gimli::Error>> {
+        self.get_custom_section(SectionId::DebugLine.name())?;
+        Some(self.read_line_table())
+    }
+
+    fn read_line_table(&self) -> Result<LineTable, gimli::Error> {
+        let dwarf = gimli::Dwarf::load(|id: SectionId| {
+            let data = self.get_custom_section(id.name()).unwrap_or_default();
+            Ok::<_, gimli::Error>(EndianSlice::new(data, LittleEndian))
+        })?;
+        let mut rows = vec![];
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut program_rows = program.rows();
+            while let Some((header, row)) = program_rows.next_row()? {
+                let Some(line) = row.line().filter(|_| !row.end_sequence()) else {
+                    continue;
+                };
+                let file = match row.file(header) {
+                    Some(file) => {
+                        let name = dwarf.attr_string(&unit, file.path_name())?;
+                        name.to_string_lossy().into_owned()
+                    }
+                    None => String::new(),
+                };
+                rows.push(SourceLine {
+                    address: row.address(),
+                    file,
+                    line: line.get(),
+                });
+            }
+        }
+        Ok(LineTable::new(rows))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use gimli::write::{
+        Address, AttributeValue, DwarfUnit, EndianVec, LineProgram, LineString, Sections,
+    };
+    use gimli::{Encoding, Format, LineEncoding};
+    use itertools::Itertools;
+
+    use crate::reader::{Bytecode, parse_binary};
+
+    /// Builds `.debug_*` custom sections that map each address to a line in `file`
+    fn debug_sections(file: &str, lines: &[(u64, u64)]) -> Vec<(&'static str, Vec<u8>)> {
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: 4,
+        };
+        let mut dwarf = DwarfUnit::new(encoding);
+        let dir = LineString::String(b"/src".to_vec());
+        let name = LineString::String(file.as_bytes().to_vec());
+        let mut program =
+            LineProgram::new(encoding, LineEncoding::default(), dir, name.clone(), None);
+        let dir_id = program.default_directory();
+        let file_id = program.add_file(name, dir_id, None);
+        program.begin_sequence(Some(Address::Constant(0)));
+        for (address, line) in lines {
+            let row = program.row();
+            row.address_offset = *address;
+            row.file = file_id;
+            row.line = *line;
+            program.generate_row();
+        }
+        program.end_sequence(lines.last().map_or(0, |l| l.0) + 1);
+        dwarf.unit.line_program = program;
+        let root = dwarf.unit.root();
+        dwarf.unit.get_mut(root).set(
+            gimli::DW_AT_name,
+            AttributeValue::String(file.as_bytes().to_vec()),
+        );
+
+        let mut sections = Sections::new(EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        let mut res = vec![];
+        sections
+            .for_each(|id, data| {
+                if !data.slice().is_empty() {
+                    res.push((id.name(), data.slice().to_vec()));
+                }
+                Ok::<_, gimli::write::Error>(())
+            })
+            .unwrap();
+        res
+    }
+
+    /// Appends the sections as `@custom` annotations to a wat module
+    fn with_custom_sections(src: &str, sections: &[(&str, Vec<u8>)]) -> Bytecode {
+        let custom = sections
+            .iter()
+            .map(|(name, data)| {
+                let data = data.iter().map(|b| format!("\\{b:02x}")).join("");
+                format!("(@custom \"{name}\" (after code) \"{data}\")")
+            })
+            .join("\n");
+        let end = src.rfind(')').unwrap();
+        let src = format!("{}{custom}\n)", &src[..end]);
+        let binary = wat::parse_str(src).unwrap();
+        parse_binary(&mut Cursor::new(binary)).unwrap()
+    }
+
+    #[test]
+    fn map_instructions_to_lines() {
+        let src = r#"
+            (module
+                (func (result i32)
+                    i32.const 1
+                    i32.const 2
+                    i32.add
+                )
+            )
+        "#;
+        let plain = with_custom_sections(src, &[]);
+        assert!(plain.parse_line_table().is_none());
+
+        let address = |i| plain.code_address(0, i).unwrap();
+        let sections = debug_sections("main.c", &[(address(0), 3), (address(2), 4)]);
+        let bytecode = with_custom_sections(src, &sections);
+        let table = bytecode.parse_line_table().unwrap().unwrap();
+        let line = |i| table.lookup(bytecode.code_address(0, i).unwrap()).unwrap();
+        assert_eq!(line(0).file, "main.c");
+        assert_eq!(line(0).line, 3);
+        assert_eq!(line(1).line, 3);
+        assert_eq!(line(2).line, 4);
+    }
+}
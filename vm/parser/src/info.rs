@@ -1,6 +1,16 @@
-use std::collections::HashMap;
+use std::collections::HashSet;
 
-use crate::reader::{Bytecode, ExportDesc, GlobalType, Limits, SortedImports, ValueType};
+use log::{trace, warn};
+
+use crate::{
+    names::Names,
+    op::Op,
+    printer::WatPrinter,
+    reader::{
+        Bytecode, ElementInit, GlobalType, Import, ImportDesc, Limits, SortedImports, TableType,
+        ValueType,
+    },
+};
 const WASM_PAGE_SIZE: usize = 65536;
 
 #[derive(Debug, Clone)]
@@ -47,23 +57,19 @@ impl Function {
             FunctionType::Imported { .. } => None,
         }
     }
+    /// Prints the function in the WebAssembly text format
     pub fn format(&self, bytecode: &Bytecode) -> String {
-        let t = bytecode.get_type(self.type_id).unwrap();
-        match self.t {
-            FunctionType::Internal { code_id, export_id } => {
-                let code = bytecode.get_code(code_id).unwrap();
-                format!(
-                    "func {}({}): {t} =\n{}",
-                    export_id.unwrap_or(""),
-                    code_id,
-                    code
-                )
-            }
-            FunctionType::Imported { import_id } => {
-                let import = bytecode.get_import(import_id).unwrap();
-                format!("native func {import}: {t}")
-            }
-        }
+        let is_func_import = |i: &Import| matches!(i.desc.data, ImportDesc::TypeIdx(_));
+        let imported = |n: usize| {
+            bytecode.iter_imports().map_or(0, |imports| {
+                imports.take(n).filter(|i| is_func_import(i)).count()
+            })
+        };
+        let func_id = match self.t {
+            FunctionType::Internal { code_id, .. } => imported(usize::MAX) + code_id,
+            FunctionType::Imported { import_id } => imported(import_id),
+        };
+        WatPrinter::new(bytecode).print_function(func_id)
     }
 }
 #[derive(Debug, Clone)]
@@ -109,6 +115,10 @@ pub struct Memory {
 }
 
 impl Memory {
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
     pub fn new_imported(import_id: usize, limits: Limits) -> Memory {
         Memory {
             limits,
@@ -116,17 +126,50 @@ impl Memory {
         }
     }
 }
+#[derive(Debug, Clone)]
+pub enum TableInfo {
+    Internal { table_id: usize },
+    Imported { import_id: usize },
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub t: TableType,
+    pub info: TableInfo,
+}
+
+impl Table {
+    pub fn new_imported(import_id: usize, t: TableType) -> Table {
+        Table {
+            t,
+            info: TableInfo::Imported { import_id },
+        }
+    }
+    pub fn value_type(&self) -> ValueType {
+        self.t.value_type()
+    }
+}
 #[derive(Debug, Default)]
 pub struct BytecodeInfo {
     pub imports: Option<SortedImports>,
     pub functions: Vec<Function>,
+    pub tables: Vec<Table>,
     pub globals: Vec<Global>,
     pub memories: Vec<Memory>,
+    pub names: Names,
+    /// Functions that may be used with `ref.func` inside of function bodies
+    pub declared_refs: HashSet<usize>,
 }
 
 impl BytecodeInfo {
     pub fn new(bytecode: &Bytecode) -> Self {
         let mut info: BytecodeInfo = Default::default();
+        match bytecode.parse_names() {
+            Some(Ok(names)) => info.names = names,
+            //NOTE: The name section is only used for debugging, a broken one is not an error
+            Some(Err(e)) => warn!("Ignoring malformed name section: {e}"),
+            None => {}
+        }
         if let Some(imports) = bytecode.sort_imports() {
             info.functions.extend(
                 imports
@@ -142,6 +185,13 @@ impl BytecodeInfo {
                     .map(|(id, gt)| Global::new_imported(gt, *id)),
             );
 
+            info.tables.extend(
+                imports
+                    .tables
+                    .iter()
+                    .map(|(id, t)| Table::new_imported(*id, t.clone())),
+            );
+
             info.memories.extend(
                 imports
                     .mems
@@ -182,15 +232,54 @@ impl BytecodeInfo {
                     }
                 }));
         }
+        if let Some(tables) = bytecode.iter_tables() {
+            info.tables
+                .extend(tables.cloned().enumerate().map(|(table_id, t)| Table {
+                    t,
+                    info: TableInfo::Internal { table_id },
+                }));
+        }
         if let Some(memories) = bytecode.iter_memories() {
             info.memories.extend(memories.cloned().map(|limits| Memory {
                 limits,
                 info: MemoryInfo::Internal { export_id: None },
             }));
         }
+        info.declared_refs = Self::collect_declared_refs(bytecode);
         info
     }
 
+    //NOTE: A function counts as declared if it is referenced anywhere outside of function bodies
+    //https://webassembly.github.io/spec/core/valid/modules.html#valid-module
+    fn collect_declared_refs(bytecode: &Bytecode) -> HashSet<usize> {
+        let ref_func = |op: &Op| match op {
+            Op::RefFunc(id) => Some(*id),
+            _ => None,
+        };
+        let elements = bytecode.iter_elements().into_iter().flatten();
+        let element_refs = elements.flat_map(|e| {
+            let exprs = match &e.init {
+                ElementInit::Expressions(exprs) => exprs.as_slice(),
+                ElementInit::Functions(_) => &[],
+            };
+            let expr_refs = exprs.iter().flat_map(|expr| expr.data.iter_ops());
+            e.iter_function_ids()
+                .chain(expr_refs.filter_map(|op| ref_func(&op)))
+                .collect::<Vec<_>>()
+        });
+        let globals = bytecode.iter_globals().into_iter().flatten();
+        let global_refs = globals.flat_map(|g| g.iter_init_expr().filter_map(ref_func));
+        let exports = bytecode.iter_exports().into_iter().flatten();
+        let export_refs = exports.filter_map(|e| e.get_function_id());
+
+        element_refs.chain(global_refs).chain(export_refs).collect()
+    }
+
+    /// `$name` of the function if the name section defines one, otherwise its index
+    pub fn describe_function(&self, func_id: usize) -> String {
+        self.names.functions.describe(func_id)
+    }
+
     pub fn imported_function_count(&self) -> usize {
         self.imports.as_ref().map_or(0, |i| i.functions.len())
     }
@@ -224,7 +313,7 @@ impl BytecodeInfo {
     pub fn find_function_by_code_id(&self, id: usize) -> Option<(usize, &Function)> {
         self.functions.iter().enumerate().find_map(|(func_id, f)| {
             let code_id = f.get_code_id()?;
-            println!("searching: {}", code_id);
+            trace!("searching: {}", code_id);
             (code_id == id).then_some((func_id, f))
         })
     }
@@ -1,6 +1,6 @@
-use std::io::Read;
+use std::io::{Read, Write};
 
-use byteorder::ReadBytesExt;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -157,4 +157,43 @@ impl Leb {
         let ashift = 64 - shift;
         Ok((result << ashift) >> ashift)
     }
+
+    pub fn write_u32(writer: &mut impl Write, value: u32) -> Result<(), LebError> {
+        Self::write_u64(writer, u64::from(value))
+    }
+
+    pub fn write_u64(writer: &mut impl Write, mut value: u64) -> Result<(), LebError> {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                writer.write_u8(byte)?;
+                return Ok(());
+            }
+            writer.write_u8(byte | 0x80)?;
+        }
+    }
+
+    pub fn write_i32(writer: &mut impl Write, value: i32) -> Result<(), LebError> {
+        Self::write_i64(writer, i64::from(value))
+    }
+
+    //NOTE: s33 only differs from i64 in the range of valid values, the encoding is the same
+    pub fn write_s33(writer: &mut impl Write, value: i64) -> Result<(), LebError> {
+        Self::write_i64(writer, value)
+    }
+
+    pub fn write_i64(writer: &mut impl Write, mut value: i64) -> Result<(), LebError> {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            //The sign bit of the last byte has to match the sign of the value
+            let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+            if done {
+                writer.write_u8(byte)?;
+                return Ok(());
+            }
+            writer.write_u8(byte | 0x80)?;
+        }
+    }
 }
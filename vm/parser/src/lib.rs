@@ -1,4 +1,8 @@
+pub mod dwarf;
 pub mod info;
 pub mod leb;
+pub mod names;
 pub mod op;
+pub mod printer;
 pub mod reader;
+pub mod writer;
@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, SeekFrom},
+};
+
+use byteorder::ReadBytesExt;
+use log::trace;
+
+use crate::reader::{Bytecode, BytecodeReader, FromBytecode, ParserError};
+
+pub const NAME_SECTION: &str = "name";
+
+/// Maps an index to its name
+#[derive(Debug, Default, Clone)]
+pub struct NameMap(HashMap<usize, String>);
+
+impl NameMap {
+    pub fn get(&self, id: usize) -> Option<&str> {
+        self.0.get(&id).map(|name| name.as_str())
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.0.iter().map(|(id, name)| (*id, name.as_str()))
+    }
+    /// `$name` if the item has a name, otherwise its index
+    pub fn describe(&self, id: usize) -> String {
+        describe(self.get(id), id)
+    }
+}
+
+/// `$name` if there is a name, otherwise the index
+pub fn describe(name: Option<&str>, id: usize) -> String {
+    match name {
+        Some(name) => format!("${name}"),
+        None => id.to_string(),
+    }
+}
+
+impl FromBytecode for NameMap {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        let count: u32 = reader.parse()?;
+        let names = (0..count)
+            .map(|_| Ok((reader.parse::<usize>()?, reader.parse::<String>()?)))
+            .collect::<Result<HashMap<_, _>, ParserError>>()?;
+        Ok(Self(names))
+    }
+}
+
+/// Maps an index to the names of its children, e.g. a function to its locals
+#[derive(Debug, Default, Clone)]
+pub struct IndirectNameMap(HashMap<usize, NameMap>);
+
+impl IndirectNameMap {
+    pub fn get(&self, id: usize) -> Option<&NameMap> {
+        self.0.get(&id)
+    }
+    pub fn get_name(&self, id: usize, inner_id: usize) -> Option<&str> {
+        self.get(id)?.get(inner_id)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &NameMap)> {
+        self.0.iter().map(|(id, names)| (*id, names))
+    }
+}
+
+impl FromBytecode for IndirectNameMap {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        let count: u32 = reader.parse()?;
+        let names = (0..count)
+            .map(|_| Ok((reader.parse::<usize>()?, reader.parse::<NameMap>()?)))
+            .collect::<Result<HashMap<_, _>, ParserError>>()?;
+        Ok(Self(names))
+    }
+}
+
+/// The decoded `name` custom section.
+/// See: https://webassembly.github.io/spec/core/appendix/custom.html#name-section
+/// and https://github.com/WebAssembly/extended-name-section for labels, globals and data
+#[derive(Debug, Default, Clone)]
+pub struct Names {
+    pub module: Option<String>,
+    pub functions: NameMap,
+    pub locals: IndirectNameMap,
+    /// Labels are indexed by the order of `block`, `loop` and `if` in the function body
+    pub labels: IndirectNameMap,
+    pub globals: NameMap,
+    pub data: NameMap,
+}
+
+impl Names {
+    pub fn function(&self, func_id: usize) -> Option<&str> {
+        self.functions.get(func_id)
+    }
+    pub fn local(&self, func_id: usize, local_id: usize) -> Option<&str> {
+        self.locals.get_name(func_id, local_id)
+    }
+    pub fn global(&self, global_id: usize) -> Option<&str> {
+        self.globals.get(global_id)
+    }
+}
+
+impl FromBytecode for Names {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        let mut names = Names::default();
+        loop {
+            let id = match reader.read_u8() {
+                Ok(id) => id,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            };
+            let size: u32 = reader.parse()?;
+            trace!("Reading name subsection {id}");
+            match id {
+                0 => names.module = Some(reader.parse()?),
+                1 => names.functions = reader.parse()?,
+                2 => names.locals = reader.parse()?,
+                3 => names.labels = reader.parse()?,
+                7 => names.globals = reader.parse()?,
+                9 => names.data = reader.parse()?,
+                //NOTE: Unknown subsections are allowed and have to be skipped
+                _ => _ = reader.seek(SeekFrom::Current(size as i64))?,
+            }
+        }
+        Ok(names)
+    }
+}
+
+impl Bytecode {
+    pub fn get_custom_section(&self, name: &str) -> Option<&[u8]> {
+        self.custom_sections
+            .iter()
+            .find(|c| c.data.name.data == name)
+            .map(|c| c.data.data.data.as_slice())
+    }
+
+    /// Decodes the name section if the module has one
+    pub fn parse_names(&self) -> Option<Result<Names, ParserError>> {
+        let data = self.get_custom_section(NAME_SECTION)?;
+        Some(Cursor::new(data).parse())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::reader::{ParserError, parse_binary};
+
+    #[test]
+    fn decode_names() -> Result<(), ParserError> {
+        let src = r#"
+            (module $math
+                (global $counter (mut i32) (i32.const 0))
+                (memory 1)
+                (data $greeting "hi")
+                (func $fib (param $n i32) (result i32)
+                    (local $tmp i32)
+                    (block $out (result i32) (local.get $n))
+                )
+                (func (param i32))
+            )
+        "#;
+        let module = parse_binary(&mut Cursor::new(wat::parse_str(src)?))?;
+        let names = module.parse_names().expect("module has a name section")?;
+        assert_eq!(names.module.as_deref(), Some("math"));
+        assert_eq!(names.function(0), Some("fib"));
+        assert_eq!(names.function(1), None);
+        assert_eq!(names.local(0, 0), Some("n"));
+        assert_eq!(names.local(0, 1), Some("tmp"));
+        assert_eq!(names.labels.get_name(0, 0), Some("out"));
+        assert_eq!(names.global(0), Some("counter"));
+        assert_eq!(names.data.get(0), Some("greeting"));
+        assert_eq!(names.functions.describe(0), "$fib");
+        assert_eq!(names.functions.describe(1), "1");
+        Ok(())
+    }
+}
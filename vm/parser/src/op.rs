@@ -1,9 +1,12 @@
-use byteorder::ReadBytesExt;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use core::fmt;
+use itertools::Itertools;
+use log::trace;
 
 use crate::{
     leb::Leb,
     reader::{BytecodeReader, FromBytecode, ParserError, ValueType},
+    writer::{BytecodeWriter, ToBytecode},
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -24,7 +27,7 @@ impl FromBytecode for Blocktype {
                 //TODO: Finde eine huebschere Loesung!
                 reader.seek(std::io::SeekFrom::Current(-1))?;
                 let value = Leb::read_u32(reader)?;
-                println!("value: {:x?}", value);
+                trace!("value: {:x?}", value);
 
                 Ok(Self::TypeIndex(value.try_into().unwrap()))
             }
@@ -32,6 +35,16 @@ impl FromBytecode for Blocktype {
     }
 }
 
+impl ToBytecode for Blocktype {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        match self {
+            Blocktype::Empty => Ok(writer.write_u8(0x40)?),
+            Blocktype::Value(t) => writer.emit(t),
+            Blocktype::TypeIndex(id) => Ok(Leb::write_s33(writer, i64::from(*id))?),
+        }
+    }
+}
+
 impl fmt::Display for Blocktype {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -56,19 +69,52 @@ impl FromBytecode for Memarg {
         })
     }
 }
+impl ToBytecode for Memarg {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        writer.emit(&self.align)?;
+        writer.emit(&self.offset)
+    }
+}
 impl fmt::Display for Memarg {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} {}", self.offset, self.align)
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrTableTarget {
+    pub label: usize,
+    pub jmp: isize,
+}
+
+impl FromBytecode for BrTableTarget {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        Ok(BrTableTarget {
+            label: reader.parse()?,
+            jmp: 0,
+        })
+    }
+}
+
+impl ToBytecode for BrTableTarget {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        writer.emit(&self.label)
+    }
+}
+
+impl fmt::Display for BrTableTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.label)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum JumpDirection {
     Forward,
     Backward,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Op {
     Unreachable,
     Nop,
@@ -80,9 +126,13 @@ pub enum Op {
     End(bool),
     Br { label: usize, jmp: isize },
     BrIf { label: usize, jmp: isize },
+    BrTable {
+        labels: Vec<BrTableTarget>,
+        default: BrTableTarget,
+    },
     Return,
     Call(usize),
-    CallIndirect { table: usize, type_id: isize },
+    CallIndirect { type_id: usize, table: usize },
     Drop,
     Select(Option<ValueType>),
     LocalGet(usize),
@@ -90,6 +140,8 @@ pub enum Op {
     LocalTee(usize),
     GlobalGet(usize),
     GlobalSet(usize),
+    TableGet(usize),
+    TableSet(usize),
     I32Load(Memarg),
     I64Load(Memarg),
     F32Load(Memarg),
@@ -140,6 +192,24 @@ pub enum Op {
     I64Leu,
     I64Ges,
     I64Geu,
+
+    F32Eq,
+    F32Ne,
+    F32Lt,
+    F32Gt,
+    F32Le,
+    F32Ge,
+
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Gt,
+    F64Le,
+    F64Ge,
+
+    I32Clz,
+    I32Ctz,
+    I32Popcnt,
     I32Add,
     I32Sub,
     I32Mul,
@@ -155,6 +225,9 @@ pub enum Op {
     I32Shru,
     I32Rotl,
     I32Rotr,
+    I64Clz,
+    I64Ctz,
+    I64Popcnt,
     I64Add,
     I64Sub,
     I64Mul,
@@ -170,17 +243,291 @@ pub enum Op {
     I64Shru,
     I64Rotl,
     I64Rotr,
+
+    F32Abs,
+    F32Neg,
+    F32Ceil,
+    F32Floor,
+    F32Trunc,
+    F32Nearest,
+    F32Sqrt,
+    F32Add,
+    F32Sub,
+    F32Mul,
+    F32Div,
+    F32Min,
+    F32Max,
+    F32Copysign,
+
+    F64Abs,
+    F64Neg,
+    F64Ceil,
+    F64Floor,
+    F64Trunc,
+    F64Nearest,
+    F64Sqrt,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Min,
+    F64Max,
+    F64Copysign,
+
     I32WrapI64,
+    I32TruncF32s,
+    I32TruncF32u,
+    I32TruncF64s,
+    I32TruncF64u,
     I64ExtendI32s,
     I64ExtendI32u,
+    I64TruncF32s,
+    I64TruncF32u,
+    I64TruncF64s,
+    I64TruncF64u,
+    F32ConvertI32s,
+    F32ConvertI32u,
+    F32ConvertI64s,
+    F32ConvertI64u,
+    F32DemoteF64,
+    F64ConvertI32s,
+    F64ConvertI32u,
+    F64ConvertI64s,
+    F64ConvertI64u,
+    F64PromoteF32,
+    I32ReinterpretF32,
+    I64ReinterpretF64,
+    F32ReinterpretI32,
+    F64ReinterpretI64,
+
+    I32TruncSatF32s,
+    I32TruncSatF32u,
+    I32TruncSatF64s,
+    I32TruncSatF64u,
+    I64TruncSatF32s,
+    I64TruncSatF32u,
+    I64TruncSatF64s,
+    I64TruncSatF64u,
 
     MemoryCopy { extra_1: usize, extra_2: usize },
     MemoryFill { extra: usize },
     MemoryInit { data_id: usize, extra: usize }, //TODO: (joh): Float ops
+    MemorySize { extra: usize },
     MemoryGrow { extra: usize },
+
+    RefNull(ValueType),
+    RefIsNull,
+    RefFunc(usize),
+
+    TableInit { elem_id: usize, table: usize },
+    ElemDrop(usize),
+    TableCopy { dst: usize, src: usize },
+    TableGrow(usize),
+    TableSize(usize),
+    TableFill(usize),
 }
 
 impl Op {
+    /// The mnemonic of the instruction in the text format
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Unreachable => "unreachable",
+            Op::Nop => "nop",
+            Op::Block(..) => "block",
+            Op::Loop(..) => "loop",
+            Op::If { .. } => "if",
+            Op::Else(..) => "else",
+            Op::End(..) => "end",
+            Op::Br { .. } => "br",
+            Op::BrIf { .. } => "br_if",
+            Op::BrTable { .. } => "br_table",
+            Op::Return => "return",
+            Op::Call(..) => "call",
+            Op::CallIndirect { .. } => "call_indirect",
+            Op::Drop => "drop",
+            Op::Select(..) => "select",
+            Op::LocalGet(..) => "local.get",
+            Op::LocalSet(..) => "local.set",
+            Op::LocalTee(..) => "local.tee",
+            Op::GlobalGet(..) => "global.get",
+            Op::GlobalSet(..) => "global.set",
+            Op::TableGet(..) => "table.get",
+            Op::TableSet(..) => "table.set",
+            Op::I32Load(..) => "i32.load",
+            Op::I64Load(..) => "i64.load",
+            Op::F32Load(..) => "f32.load",
+            Op::F64Load(..) => "f64.load",
+            Op::I32Load8s(..) => "i32.load8_s",
+            Op::I32Load8u(..) => "i32.load8_u",
+            Op::I32Load16s(..) => "i32.load16_s",
+            Op::I32Load16u(..) => "i32.load16_u",
+            Op::I64Load8s(..) => "i64.load8_s",
+            Op::I64Load8u(..) => "i64.load8_u",
+            Op::I64Load16s(..) => "i64.load16_s",
+            Op::I64Load16u(..) => "i64.load16_u",
+            Op::I64Load32s(..) => "i64.load32_s",
+            Op::I64Load32u(..) => "i64.load32_u",
+            Op::I32Store(..) => "i32.store",
+            Op::I64Store(..) => "i64.store",
+            Op::F32Store(..) => "f32.store",
+            Op::F64Store(..) => "f64.store",
+            Op::I32Store8(..) => "i32.store8",
+            Op::I32Store16(..) => "i32.store16",
+            Op::I64Store8(..) => "i64.store8",
+            Op::I64Store16(..) => "i64.store16",
+            Op::I64Store32(..) => "i64.store32",
+            Op::I32Const(..) => "i32.const",
+            Op::I64Const(..) => "i64.const",
+            Op::F32Const(..) => "f32.const",
+            Op::F64Const(..) => "f64.const",
+            Op::I32Eqz => "i32.eqz",
+            Op::I32Eq => "i32.eq",
+            Op::I32Ne => "i32.ne",
+            Op::I32Lts => "i32.lt_s",
+            Op::I32Ltu => "i32.lt_u",
+            Op::I32Gts => "i32.gt_s",
+            Op::I32Gtu => "i32.gt_u",
+            Op::I32Leu => "i32.le_u",
+            Op::I32Les => "i32.le_s",
+            Op::I32Ges => "i32.ge_s",
+            Op::I32Geu => "i32.ge_u",
+            Op::I64Eqz => "i64.eqz",
+            Op::I64Eq => "i64.eq",
+            Op::I64Ne => "i64.ne",
+            Op::I64Lts => "i64.lt_s",
+            Op::I64Ltu => "i64.lt_u",
+            Op::I64Gts => "i64.gt_s",
+            Op::I64Gtu => "i64.gt_u",
+            Op::I64Les => "i64.le_s",
+            Op::I64Leu => "i64.le_u",
+            Op::I64Ges => "i64.ge_s",
+            Op::I64Geu => "i64.ge_u",
+            Op::F32Eq => "f32.eq",
+            Op::F32Ne => "f32.ne",
+            Op::F32Lt => "f32.lt",
+            Op::F32Gt => "f32.gt",
+            Op::F32Le => "f32.le",
+            Op::F32Ge => "f32.ge",
+            Op::F64Eq => "f64.eq",
+            Op::F64Ne => "f64.ne",
+            Op::F64Lt => "f64.lt",
+            Op::F64Gt => "f64.gt",
+            Op::F64Le => "f64.le",
+            Op::F64Ge => "f64.ge",
+            Op::I32Clz => "i32.clz",
+            Op::I32Ctz => "i32.ctz",
+            Op::I32Popcnt => "i32.popcnt",
+            Op::I32Add => "i32.add",
+            Op::I32Sub => "i32.sub",
+            Op::I32Mul => "i32.mul",
+            Op::I32Divs => "i32.div_s",
+            Op::I32Divu => "i32.div_u",
+            Op::I32Rems => "i32.rem_s",
+            Op::I32Remu => "i32.rem_u",
+            Op::I32And => "i32.and",
+            Op::I32Or => "i32.or",
+            Op::I32Xor => "i32.xor",
+            Op::I32Shl => "i32.shl",
+            Op::I32Shrs => "i32.shr_s",
+            Op::I32Shru => "i32.shr_u",
+            Op::I32Rotl => "i32.rotl",
+            Op::I32Rotr => "i32.rotr",
+            Op::I64Clz => "i64.clz",
+            Op::I64Ctz => "i64.ctz",
+            Op::I64Popcnt => "i64.popcnt",
+            Op::I64Add => "i64.add",
+            Op::I64Sub => "i64.sub",
+            Op::I64Mul => "i64.mul",
+            Op::I64Divs => "i64.div_s",
+            Op::I64Divu => "i64.div_u",
+            Op::I64Rems => "i64.rem_s",
+            Op::I64Remu => "i64.rem_u",
+            Op::I64And => "i64.and",
+            Op::I64Or => "i64.or",
+            Op::I64Xor => "i64.xor",
+            Op::I64Shl => "i64.shl",
+            Op::I64Shrs => "i64.shr_s",
+            Op::I64Shru => "i64.shr_u",
+            Op::I64Rotl => "i64.rotl",
+            Op::I64Rotr => "i64.rotr",
+            Op::F32Abs => "f32.abs",
+            Op::F32Neg => "f32.neg",
+            Op::F32Ceil => "f32.ceil",
+            Op::F32Floor => "f32.floor",
+            Op::F32Trunc => "f32.trunc",
+            Op::F32Nearest => "f32.nearest",
+            Op::F32Sqrt => "f32.sqrt",
+            Op::F32Add => "f32.add",
+            Op::F32Sub => "f32.sub",
+            Op::F32Mul => "f32.mul",
+            Op::F32Div => "f32.div",
+            Op::F32Min => "f32.min",
+            Op::F32Max => "f32.max",
+            Op::F32Copysign => "f32.copysign",
+            Op::F64Abs => "f64.abs",
+            Op::F64Neg => "f64.neg",
+            Op::F64Ceil => "f64.ceil",
+            Op::F64Floor => "f64.floor",
+            Op::F64Trunc => "f64.trunc",
+            Op::F64Nearest => "f64.nearest",
+            Op::F64Sqrt => "f64.sqrt",
+            Op::F64Add => "f64.add",
+            Op::F64Sub => "f64.sub",
+            Op::F64Mul => "f64.mul",
+            Op::F64Div => "f64.div",
+            Op::F64Min => "f64.min",
+            Op::F64Max => "f64.max",
+            Op::F64Copysign => "f64.copysign",
+            Op::I32WrapI64 => "i32.wrap_i64",
+            Op::I32TruncF32s => "i32.trunc_f32_s",
+            Op::I32TruncF32u => "i32.trunc_f32_u",
+            Op::I32TruncF64s => "i32.trunc_f64_s",
+            Op::I32TruncF64u => "i32.trunc_f64_u",
+            Op::I64ExtendI32s => "i64.extend_i32_s",
+            Op::I64ExtendI32u => "i64.extend_i32_u",
+            Op::I64TruncF32s => "i64.trunc_f32_s",
+            Op::I64TruncF32u => "i64.trunc_f32_u",
+            Op::I64TruncF64s => "i64.trunc_f64_s",
+            Op::I64TruncF64u => "i64.trunc_f64_u",
+            Op::F32ConvertI32s => "f32.convert_i32_s",
+            Op::F32ConvertI32u => "f32.convert_i32_u",
+            Op::F32ConvertI64s => "f32.convert_i64_s",
+            Op::F32ConvertI64u => "f32.convert_i64_u",
+            Op::F32DemoteF64 => "f32.demote_f64",
+            Op::F64ConvertI32s => "f64.convert_i32_s",
+            Op::F64ConvertI32u => "f64.convert_i32_u",
+            Op::F64ConvertI64s => "f64.convert_i64_s",
+            Op::F64ConvertI64u => "f64.convert_i64_u",
+            Op::F64PromoteF32 => "f64.promote_f32",
+            Op::I32ReinterpretF32 => "i32.reinterpret_f32",
+            Op::I64ReinterpretF64 => "i64.reinterpret_f64",
+            Op::F32ReinterpretI32 => "f32.reinterpret_i32",
+            Op::F64ReinterpretI64 => "f64.reinterpret_i64",
+            Op::I32TruncSatF32s => "i32.trunc_sat_f32_s",
+            Op::I32TruncSatF32u => "i32.trunc_sat_f32_u",
+            Op::I32TruncSatF64s => "i32.trunc_sat_f64_s",
+            Op::I32TruncSatF64u => "i32.trunc_sat_f64_u",
+            Op::I64TruncSatF32s => "i64.trunc_sat_f32_s",
+            Op::I64TruncSatF32u => "i64.trunc_sat_f32_u",
+            Op::I64TruncSatF64s => "i64.trunc_sat_f64_s",
+            Op::I64TruncSatF64u => "i64.trunc_sat_f64_u",
+            Op::MemoryCopy { .. } => "memory.copy",
+            Op::MemoryFill { .. } => "memory.fill",
+            Op::MemoryInit { .. } => "memory.init",
+            Op::MemorySize { .. } => "memory.size",
+            Op::MemoryGrow { .. } => "memory.grow",
+            Op::RefNull(..) => "ref.null",
+            Op::RefIsNull => "ref.is_null",
+            Op::RefFunc(..) => "ref.func",
+            Op::TableInit { .. } => "table.init",
+            Op::ElemDrop(..) => "elem.drop",
+            Op::TableCopy { .. } => "table.copy",
+            Op::TableGrow(..) => "table.grow",
+            Op::TableSize(..) => "table.size",
+            Op::TableFill(..) => "table.fill",
+        }
+    }
+
     pub fn needs_end_terminator(&self) -> bool {
         matches!(self, Op::Block(_) | Op::Loop(_) | Op::If { bt: _, jmp: _ })
     }
@@ -190,7 +537,13 @@ impl Op {
         //testen ob ein Global.Get in der Form Const t ist: https://webassembly.github.io/spec/core/valid/instructions.html#constant-expressions
         matches!(
             self,
-            Self::I32Const(_) | Self::I64Const(_) | Self::GlobalGet(_)
+            Self::I32Const(_)
+                | Self::I64Const(_)
+                | Self::F32Const(_)
+                | Self::F64Const(_)
+                | Self::GlobalGet(_)
+                | Self::RefNull(_)
+                | Self::RefFunc(_)
         )
     }
     pub fn is_terminator(&self) -> bool {
@@ -223,10 +576,220 @@ impl Op {
             _ => None,
         }
     }
+
+    /// Number of values the instruction pops and pushes. `None` for control instructions and
+    /// calls, their effect depends on the enclosing labels or the called function.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        let effect = match self {
+            Self::Block(_)
+            | Self::Loop(_)
+            | Self::If { .. }
+            | Self::Else(_)
+            | Self::End(_)
+            | Self::Br { .. }
+            | Self::BrIf { .. }
+            | Self::BrTable { .. }
+            | Self::Return
+            | Self::Call(_)
+            | Self::CallIndirect { .. } => return None,
+            Self::Unreachable | Self::Nop | Self::ElemDrop(_) => (0, 0),
+            Self::Drop | Self::LocalSet(_) | Self::GlobalSet(_) => (1, 0),
+            Self::Select(_) => (3, 1),
+            Self::LocalGet(_)
+            | Self::GlobalGet(_)
+            | Self::TableSize(_)
+            | Self::MemorySize { .. } => (0, 1),
+            Self::LocalTee(_) | Self::TableGet(_) | Self::MemoryGrow { .. } => (1, 1),
+            Self::TableSet(_) => (2, 0),
+            Self::I32Load(_)
+            | Self::I64Load(_)
+            | Self::F32Load(_)
+            | Self::F64Load(_)
+            | Self::I32Load8s(_)
+            | Self::I32Load8u(_)
+            | Self::I32Load16s(_)
+            | Self::I32Load16u(_)
+            | Self::I64Load8s(_)
+            | Self::I64Load8u(_)
+            | Self::I64Load16s(_)
+            | Self::I64Load16u(_)
+            | Self::I64Load32s(_)
+            | Self::I64Load32u(_) => (1, 1),
+            Self::I32Store(_)
+            | Self::I64Store(_)
+            | Self::F32Store(_)
+            | Self::F64Store(_)
+            | Self::I32Store8(_)
+            | Self::I32Store16(_)
+            | Self::I64Store8(_)
+            | Self::I64Store16(_)
+            | Self::I64Store32(_) => (2, 0),
+            Self::I32Const(_) | Self::I64Const(_) | Self::F32Const(_) | Self::F64Const(_) => (0, 1),
+            Self::RefNull(_) | Self::RefFunc(_) => (0, 1),
+            Self::RefIsNull => (1, 1),
+            Self::MemoryCopy { .. }
+            | Self::MemoryFill { .. }
+            | Self::MemoryInit { .. }
+            | Self::TableInit { .. }
+            | Self::TableCopy { .. }
+            | Self::TableFill(_) => (3, 0),
+            Self::TableGrow(_) => (2, 1),
+            Self::I32Eqz
+            | Self::I64Eqz
+            | Self::I32Clz
+            | Self::I32Ctz
+            | Self::I32Popcnt
+            | Self::I64Clz
+            | Self::I64Ctz
+            | Self::I64Popcnt
+            | Self::F32Abs
+            | Self::F32Neg
+            | Self::F32Ceil
+            | Self::F32Floor
+            | Self::F32Trunc
+            | Self::F32Nearest
+            | Self::F32Sqrt
+            | Self::F64Abs
+            | Self::F64Neg
+            | Self::F64Ceil
+            | Self::F64Floor
+            | Self::F64Trunc
+            | Self::F64Nearest
+            | Self::F64Sqrt
+            | Self::I32WrapI64
+            | Self::I32TruncF32s
+            | Self::I32TruncF32u
+            | Self::I32TruncF64s
+            | Self::I32TruncF64u
+            | Self::I64ExtendI32s
+            | Self::I64ExtendI32u
+            | Self::I64TruncF32s
+            | Self::I64TruncF32u
+            | Self::I64TruncF64s
+            | Self::I64TruncF64u
+            | Self::F32ConvertI32s
+            | Self::F32ConvertI32u
+            | Self::F32ConvertI64s
+            | Self::F32ConvertI64u
+            | Self::F32DemoteF64
+            | Self::F64ConvertI32s
+            | Self::F64ConvertI32u
+            | Self::F64ConvertI64s
+            | Self::F64ConvertI64u
+            | Self::F64PromoteF32
+            | Self::I32ReinterpretF32
+            | Self::I64ReinterpretF64
+            | Self::F32ReinterpretI32
+            | Self::F64ReinterpretI64
+            | Self::I32TruncSatF32s
+            | Self::I32TruncSatF32u
+            | Self::I32TruncSatF64s
+            | Self::I32TruncSatF64u
+            | Self::I64TruncSatF32s
+            | Self::I64TruncSatF32u
+            | Self::I64TruncSatF64s
+            | Self::I64TruncSatF64u => (1, 1),
+            Self::I32Eq
+            | Self::I32Ne
+            | Self::I32Lts
+            | Self::I32Ltu
+            | Self::I32Gts
+            | Self::I32Gtu
+            | Self::I32Leu
+            | Self::I32Les
+            | Self::I32Ges
+            | Self::I32Geu
+            | Self::I64Eq
+            | Self::I64Ne
+            | Self::I64Lts
+            | Self::I64Ltu
+            | Self::I64Gts
+            | Self::I64Gtu
+            | Self::I64Les
+            | Self::I64Leu
+            | Self::I64Ges
+            | Self::I64Geu
+            | Self::F32Eq
+            | Self::F32Ne
+            | Self::F32Lt
+            | Self::F32Gt
+            | Self::F32Le
+            | Self::F32Ge
+            | Self::F64Eq
+            | Self::F64Ne
+            | Self::F64Lt
+            | Self::F64Gt
+            | Self::F64Le
+            | Self::F64Ge
+            | Self::I32Add
+            | Self::I32Sub
+            | Self::I32Mul
+            | Self::I32Divs
+            | Self::I32Divu
+            | Self::I32Rems
+            | Self::I32Remu
+            | Self::I32And
+            | Self::I32Or
+            | Self::I32Xor
+            | Self::I32Shl
+            | Self::I32Shrs
+            | Self::I32Shru
+            | Self::I32Rotl
+            | Self::I32Rotr
+            | Self::I64Add
+            | Self::I64Sub
+            | Self::I64Mul
+            | Self::I64Divs
+            | Self::I64Divu
+            | Self::I64Rems
+            | Self::I64Remu
+            | Self::I64And
+            | Self::I64Or
+            | Self::I64Xor
+            | Self::I64Shl
+            | Self::I64Shrs
+            | Self::I64Shru
+            | Self::I64Rotl
+            | Self::I64Rotr
+            | Self::F32Add
+            | Self::F32Sub
+            | Self::F32Mul
+            | Self::F32Div
+            | Self::F32Min
+            | Self::F32Max
+            | Self::F32Copysign
+            | Self::F64Add
+            | Self::F64Sub
+            | Self::F64Mul
+            | Self::F64Div
+            | Self::F64Min
+            | Self::F64Max
+            | Self::F64Copysign => (2, 1),
+        };
+        Some(effect)
+    }
 }
+//NOTE: The typed select carries a vector of value types, which currently always has length 1
+//https://webassembly.github.io/spec/core/binary/instructions.html#parametric-instructions
+fn read_select_type(reader: &mut impl BytecodeReader) -> Result<ValueType, ParserError> {
+    let types: Vec<ValueType> = reader.parse()?;
+    match types.as_slice() {
+        [t] => Ok(*t),
+        _ => Err(ParserError::InvalidSelectArity(types.len())),
+    }
+}
+
 pub fn read_fc_op(reader: &mut impl BytecodeReader) -> Result<Op, ParserError> {
     let opcode = reader.read_u8()?;
     let instr = match opcode {
+        0x00 => Op::I32TruncSatF32s,
+        0x01 => Op::I32TruncSatF32u,
+        0x02 => Op::I32TruncSatF64s,
+        0x03 => Op::I32TruncSatF64u,
+        0x04 => Op::I64TruncSatF32s,
+        0x05 => Op::I64TruncSatF32u,
+        0x06 => Op::I64TruncSatF64s,
+        0x07 => Op::I64TruncSatF64u,
         0x08 => Op::MemoryInit {
             data_id: reader.parse()?,
             extra: reader.parse()?,
@@ -238,8 +801,20 @@ pub fn read_fc_op(reader: &mut impl BytecodeReader) -> Result<Op, ParserError> {
         11 => Op::MemoryFill {
             extra: reader.parse()?,
         },
+        12 => Op::TableInit {
+            elem_id: reader.parse()?,
+            table: reader.parse()?,
+        },
+        13 => Op::ElemDrop(reader.parse()?),
+        14 => Op::TableCopy {
+            dst: reader.parse()?,
+            src: reader.parse()?,
+        },
+        15 => Op::TableGrow(reader.parse()?),
+        16 => Op::TableSize(reader.parse()?),
+        17 => Op::TableFill(reader.parse()?),
 
-        _ => todo!(),
+        _ => return Err(ParserError::InvalidFcOpcode(opcode)),
     };
     Ok(instr)
 }
@@ -266,20 +841,26 @@ impl FromBytecode for Op {
                 label: reader.parse()?,
                 jmp: 0,
             },
+            0x0E => Self::BrTable {
+                labels: reader.parse()?,
+                default: reader.parse()?,
+            },
             0x0F => Self::Return,
             0x10 => Self::Call(reader.parse()?),
             0x11 => Self::CallIndirect {
-                table: reader.parse()?,
                 type_id: reader.parse()?,
+                table: reader.parse()?,
             },
             0x1A => Self::Drop,
             0x1B => Self::Select(None),
-            0x1C => Self::Select(Some(reader.parse()?)),
+            0x1C => Self::Select(Some(read_select_type(reader)?)),
             0x20 => Self::LocalGet(reader.parse()?),
             0x21 => Self::LocalSet(reader.parse()?),
             0x22 => Self::LocalTee(reader.parse()?),
             0x23 => Self::GlobalGet(reader.parse()?),
             0x24 => Self::GlobalSet(reader.parse()?),
+            0x25 => Self::TableGet(reader.parse()?),
+            0x26 => Self::TableSet(reader.parse()?),
             0x28 => Self::I32Load(reader.parse()?),
             0x29 => Self::I64Load(reader.parse()?),
             0x2A => Self::F32Load(reader.parse()?),
@@ -305,8 +886,8 @@ impl FromBytecode for Op {
             0x3E => Self::I64Store32(reader.parse()?),
             0x41 => Self::I32Const(reader.parse()?),
             0x42 => Self::I64Const(reader.parse()?),
-            0x43 => todo!(), //const f32
-            0x44 => todo!(), //const f64
+            0x43 => Self::F32Const(reader.parse()?),
+            0x44 => Self::F64Const(reader.parse()?),
             0x45 => Op::I32Eqz,
             0x46 => Op::I32Eq,
             0x47 => Op::I32Ne,
@@ -331,6 +912,23 @@ impl FromBytecode for Op {
             0x59 => Op::I64Ges,
             0x5A => Op::I64Geu,
 
+            0x5B => Op::F32Eq,
+            0x5C => Op::F32Ne,
+            0x5D => Op::F32Lt,
+            0x5E => Op::F32Gt,
+            0x5F => Op::F32Le,
+            0x60 => Op::F32Ge,
+
+            0x61 => Op::F64Eq,
+            0x62 => Op::F64Ne,
+            0x63 => Op::F64Lt,
+            0x64 => Op::F64Gt,
+            0x65 => Op::F64Le,
+            0x66 => Op::F64Ge,
+
+            0x67 => Op::I32Clz,
+            0x68 => Op::I32Ctz,
+            0x69 => Op::I32Popcnt,
             0x6A => Op::I32Add,
             0x6B => Op::I32Sub,
             0x6C => Op::I32Mul,
@@ -347,6 +945,9 @@ impl FromBytecode for Op {
             0x77 => Op::I32Rotl,
             0x78 => Op::I32Rotr,
 
+            0x79 => Op::I64Clz,
+            0x7A => Op::I64Ctz,
+            0x7B => Op::I64Popcnt,
             0x7C => Op::I64Add,
             0x7D => Op::I64Sub,
             0x7E => Op::I64Mul,
@@ -359,23 +960,297 @@ impl FromBytecode for Op {
             0x85 => Op::I64Xor,
             0x86 => Op::I64Shl,
             0x87 => Op::I64Shrs,
-            0x89 => Op::I64Shru,
-            0x8A => Op::I64Rotl,
-            0x8B => Op::I64Rotr,
+            0x88 => Op::I64Shru,
+            0x89 => Op::I64Rotl,
+            0x8A => Op::I64Rotr,
+
+            0x8B => Op::F32Abs,
+            0x8C => Op::F32Neg,
+            0x8D => Op::F32Ceil,
+            0x8E => Op::F32Floor,
+            0x8F => Op::F32Trunc,
+            0x90 => Op::F32Nearest,
+            0x91 => Op::F32Sqrt,
+            0x92 => Op::F32Add,
+            0x93 => Op::F32Sub,
+            0x94 => Op::F32Mul,
+            0x95 => Op::F32Div,
+            0x96 => Op::F32Min,
+            0x97 => Op::F32Max,
+            0x98 => Op::F32Copysign,
+
+            0x99 => Op::F64Abs,
+            0x9A => Op::F64Neg,
+            0x9B => Op::F64Ceil,
+            0x9C => Op::F64Floor,
+            0x9D => Op::F64Trunc,
+            0x9E => Op::F64Nearest,
+            0x9F => Op::F64Sqrt,
+            0xA0 => Op::F64Add,
+            0xA1 => Op::F64Sub,
+            0xA2 => Op::F64Mul,
+            0xA3 => Op::F64Div,
+            0xA4 => Op::F64Min,
+            0xA5 => Op::F64Max,
+            0xA6 => Op::F64Copysign,
+
             0xA7 => Op::I32WrapI64,
+            0xA8 => Op::I32TruncF32s,
+            0xA9 => Op::I32TruncF32u,
+            0xAA => Op::I32TruncF64s,
+            0xAB => Op::I32TruncF64u,
             0xAC => Op::I64ExtendI32s,
             0xAD => Op::I64ExtendI32u,
+            0xAE => Op::I64TruncF32s,
+            0xAF => Op::I64TruncF32u,
+            0xB0 => Op::I64TruncF64s,
+            0xB1 => Op::I64TruncF64u,
+            0xB2 => Op::F32ConvertI32s,
+            0xB3 => Op::F32ConvertI32u,
+            0xB4 => Op::F32ConvertI64s,
+            0xB5 => Op::F32ConvertI64u,
+            0xB6 => Op::F32DemoteF64,
+            0xB7 => Op::F64ConvertI32s,
+            0xB8 => Op::F64ConvertI32u,
+            0xB9 => Op::F64ConvertI64s,
+            0xBA => Op::F64ConvertI64u,
+            0xBB => Op::F64PromoteF32,
+            0xBC => Op::I32ReinterpretF32,
+            0xBD => Op::I64ReinterpretF64,
+            0xBE => Op::F32ReinterpretI32,
+            0xBF => Op::F64ReinterpretI64,
+            0xD0 => Op::RefNull(reader.parse()?),
+            0xD1 => Op::RefIsNull,
+            0xD2 => Op::RefFunc(reader.parse()?),
             0xFC => read_fc_op(reader)?, //Memory
+            0x3F => Op::MemorySize {
+                extra: reader.parse()?,
+            },
             0x40 => Op::MemoryGrow {
                 extra: reader.parse()?,
             },
-            _ => panic!("Unimplemented Opcode {:0X}", opcode),
+            _ => return Err(ParserError::InvalidOpcode(opcode)),
         };
 
         Ok(instr)
     }
 }
 
+impl ToBytecode for Op {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        macro_rules! write_op {
+            (0xFC, $opcode: expr $(, $arg: expr)*) => {{
+                writer.write_u8(0xFC)?;
+                writer.emit(&($opcode as u32))?;
+                $(writer.emit($arg)?;)*
+            }};
+            ($opcode: expr $(, $arg: expr)*) => {{
+                writer.write_u8($opcode)?;
+                $(writer.emit($arg)?;)*
+            }};
+        }
+        //NOTE: Jump offsets are filled in by the validator and have no binary representation
+        match self {
+            Op::Block(bt) => write_op!(0x02, bt),
+            Op::Loop(bt) => write_op!(0x03, bt),
+            Op::If { bt, .. } => write_op!(0x04, bt),
+            Op::Else(_) => write_op!(0x05),
+            Op::End(_) => write_op!(0x0B),
+            Op::Br { label, .. } => write_op!(0x0C, label),
+            Op::BrIf { label, .. } => write_op!(0x0D, label),
+            Op::BrTable { labels, default } => write_op!(0x0E, labels, default),
+            Op::Call(func_id) => write_op!(0x10, func_id),
+            Op::CallIndirect { type_id, table } => write_op!(0x11, type_id, table),
+            Op::Select(None) => write_op!(0x1B),
+            Op::Select(Some(t)) => write_op!(0x1C, [*t].as_slice()),
+            Op::LocalGet(id) => write_op!(0x20, id),
+            Op::LocalSet(id) => write_op!(0x21, id),
+            Op::LocalTee(id) => write_op!(0x22, id),
+            Op::GlobalGet(id) => write_op!(0x23, id),
+            Op::GlobalSet(id) => write_op!(0x24, id),
+            Op::TableGet(id) => write_op!(0x25, id),
+            Op::TableSet(id) => write_op!(0x26, id),
+            Op::I32Load(memarg) => write_op!(0x28, memarg),
+            Op::I64Load(memarg) => write_op!(0x29, memarg),
+            Op::F32Load(memarg) => write_op!(0x2A, memarg),
+            Op::F64Load(memarg) => write_op!(0x2B, memarg),
+            Op::I32Load8s(memarg) => write_op!(0x2C, memarg),
+            Op::I32Load8u(memarg) => write_op!(0x2D, memarg),
+            Op::I32Load16s(memarg) => write_op!(0x2E, memarg),
+            Op::I32Load16u(memarg) => write_op!(0x2F, memarg),
+            Op::I64Load8s(memarg) => write_op!(0x30, memarg),
+            Op::I64Load8u(memarg) => write_op!(0x31, memarg),
+            Op::I64Load16s(memarg) => write_op!(0x32, memarg),
+            Op::I64Load16u(memarg) => write_op!(0x33, memarg),
+            Op::I64Load32s(memarg) => write_op!(0x34, memarg),
+            Op::I64Load32u(memarg) => write_op!(0x35, memarg),
+            Op::I32Store(memarg) => write_op!(0x36, memarg),
+            Op::I64Store(memarg) => write_op!(0x37, memarg),
+            Op::F32Store(memarg) => write_op!(0x38, memarg),
+            Op::F64Store(memarg) => write_op!(0x39, memarg),
+            Op::I32Store8(memarg) => write_op!(0x3A, memarg),
+            Op::I32Store16(memarg) => write_op!(0x3B, memarg),
+            Op::I64Store8(memarg) => write_op!(0x3C, memarg),
+            Op::I64Store16(memarg) => write_op!(0x3D, memarg),
+            Op::I64Store32(memarg) => write_op!(0x3E, memarg),
+            Op::MemorySize { extra } => write_op!(0x3F, extra),
+            Op::MemoryGrow { extra } => write_op!(0x40, extra),
+            Op::I32Const(v) => write_op!(0x41, v),
+            Op::I64Const(v) => write_op!(0x42, v),
+            Op::F32Const(v) => write_op!(0x43, v),
+            Op::F64Const(v) => write_op!(0x44, v),
+            Op::RefNull(t) => write_op!(0xD0, t),
+            Op::RefFunc(func_id) => write_op!(0xD2, func_id),
+            Op::I32TruncSatF32s => write_op!(0xFC, 0),
+            Op::I32TruncSatF32u => write_op!(0xFC, 1),
+            Op::I32TruncSatF64s => write_op!(0xFC, 2),
+            Op::I32TruncSatF64u => write_op!(0xFC, 3),
+            Op::I64TruncSatF32s => write_op!(0xFC, 4),
+            Op::I64TruncSatF32u => write_op!(0xFC, 5),
+            Op::I64TruncSatF64s => write_op!(0xFC, 6),
+            Op::I64TruncSatF64u => write_op!(0xFC, 7),
+            Op::MemoryInit { data_id, extra } => write_op!(0xFC, 8, data_id, extra),
+            Op::MemoryCopy { extra_1, extra_2 } => write_op!(0xFC, 10, extra_1, extra_2),
+            Op::MemoryFill { extra } => write_op!(0xFC, 11, extra),
+            Op::TableInit { elem_id, table } => write_op!(0xFC, 12, elem_id, table),
+            Op::ElemDrop(elem_id) => write_op!(0xFC, 13, elem_id),
+            Op::TableCopy { dst, src } => write_op!(0xFC, 14, dst, src),
+            Op::TableGrow(table) => write_op!(0xFC, 15, table),
+            Op::TableSize(table) => write_op!(0xFC, 16, table),
+            Op::TableFill(table) => write_op!(0xFC, 17, table),
+            Op::Unreachable => write_op!(0x00),
+            Op::Nop => write_op!(0x01),
+            Op::Return => write_op!(0x0F),
+            Op::Drop => write_op!(0x1A),
+            Op::I32Eqz => write_op!(0x45),
+            Op::I32Eq => write_op!(0x46),
+            Op::I32Ne => write_op!(0x47),
+            Op::I32Lts => write_op!(0x48),
+            Op::I32Ltu => write_op!(0x49),
+            Op::I32Gts => write_op!(0x4A),
+            Op::I32Gtu => write_op!(0x4B),
+            Op::I32Les => write_op!(0x4C),
+            Op::I32Leu => write_op!(0x4D),
+            Op::I32Ges => write_op!(0x4E),
+            Op::I32Geu => write_op!(0x4F),
+            Op::I64Eqz => write_op!(0x50),
+            Op::I64Eq => write_op!(0x51),
+            Op::I64Ne => write_op!(0x52),
+            Op::I64Lts => write_op!(0x53),
+            Op::I64Ltu => write_op!(0x54),
+            Op::I64Gts => write_op!(0x55),
+            Op::I64Gtu => write_op!(0x56),
+            Op::I64Les => write_op!(0x57),
+            Op::I64Leu => write_op!(0x58),
+            Op::I64Ges => write_op!(0x59),
+            Op::I64Geu => write_op!(0x5A),
+            Op::F32Eq => write_op!(0x5B),
+            Op::F32Ne => write_op!(0x5C),
+            Op::F32Lt => write_op!(0x5D),
+            Op::F32Gt => write_op!(0x5E),
+            Op::F32Le => write_op!(0x5F),
+            Op::F32Ge => write_op!(0x60),
+            Op::F64Eq => write_op!(0x61),
+            Op::F64Ne => write_op!(0x62),
+            Op::F64Lt => write_op!(0x63),
+            Op::F64Gt => write_op!(0x64),
+            Op::F64Le => write_op!(0x65),
+            Op::F64Ge => write_op!(0x66),
+            Op::I32Clz => write_op!(0x67),
+            Op::I32Ctz => write_op!(0x68),
+            Op::I32Popcnt => write_op!(0x69),
+            Op::I32Add => write_op!(0x6A),
+            Op::I32Sub => write_op!(0x6B),
+            Op::I32Mul => write_op!(0x6C),
+            Op::I32Divs => write_op!(0x6D),
+            Op::I32Divu => write_op!(0x6E),
+            Op::I32Rems => write_op!(0x6F),
+            Op::I32Remu => write_op!(0x70),
+            Op::I32And => write_op!(0x71),
+            Op::I32Or => write_op!(0x72),
+            Op::I32Xor => write_op!(0x73),
+            Op::I32Shl => write_op!(0x74),
+            Op::I32Shrs => write_op!(0x75),
+            Op::I32Shru => write_op!(0x76),
+            Op::I32Rotl => write_op!(0x77),
+            Op::I32Rotr => write_op!(0x78),
+            Op::I64Clz => write_op!(0x79),
+            Op::I64Ctz => write_op!(0x7A),
+            Op::I64Popcnt => write_op!(0x7B),
+            Op::I64Add => write_op!(0x7C),
+            Op::I64Sub => write_op!(0x7D),
+            Op::I64Mul => write_op!(0x7E),
+            Op::I64Divs => write_op!(0x7F),
+            Op::I64Divu => write_op!(0x80),
+            Op::I64Rems => write_op!(0x81),
+            Op::I64Remu => write_op!(0x82),
+            Op::I64And => write_op!(0x83),
+            Op::I64Or => write_op!(0x84),
+            Op::I64Xor => write_op!(0x85),
+            Op::I64Shl => write_op!(0x86),
+            Op::I64Shrs => write_op!(0x87),
+            Op::I64Shru => write_op!(0x88),
+            Op::I64Rotl => write_op!(0x89),
+            Op::I64Rotr => write_op!(0x8A),
+            Op::F32Abs => write_op!(0x8B),
+            Op::F32Neg => write_op!(0x8C),
+            Op::F32Ceil => write_op!(0x8D),
+            Op::F32Floor => write_op!(0x8E),
+            Op::F32Trunc => write_op!(0x8F),
+            Op::F32Nearest => write_op!(0x90),
+            Op::F32Sqrt => write_op!(0x91),
+            Op::F32Add => write_op!(0x92),
+            Op::F32Sub => write_op!(0x93),
+            Op::F32Mul => write_op!(0x94),
+            Op::F32Div => write_op!(0x95),
+            Op::F32Min => write_op!(0x96),
+            Op::F32Max => write_op!(0x97),
+            Op::F32Copysign => write_op!(0x98),
+            Op::F64Abs => write_op!(0x99),
+            Op::F64Neg => write_op!(0x9A),
+            Op::F64Ceil => write_op!(0x9B),
+            Op::F64Floor => write_op!(0x9C),
+            Op::F64Trunc => write_op!(0x9D),
+            Op::F64Nearest => write_op!(0x9E),
+            Op::F64Sqrt => write_op!(0x9F),
+            Op::F64Add => write_op!(0xA0),
+            Op::F64Sub => write_op!(0xA1),
+            Op::F64Mul => write_op!(0xA2),
+            Op::F64Div => write_op!(0xA3),
+            Op::F64Min => write_op!(0xA4),
+            Op::F64Max => write_op!(0xA5),
+            Op::F64Copysign => write_op!(0xA6),
+            Op::I32WrapI64 => write_op!(0xA7),
+            Op::I32TruncF32s => write_op!(0xA8),
+            Op::I32TruncF32u => write_op!(0xA9),
+            Op::I32TruncF64s => write_op!(0xAA),
+            Op::I32TruncF64u => write_op!(0xAB),
+            Op::I64ExtendI32s => write_op!(0xAC),
+            Op::I64ExtendI32u => write_op!(0xAD),
+            Op::I64TruncF32s => write_op!(0xAE),
+            Op::I64TruncF32u => write_op!(0xAF),
+            Op::I64TruncF64s => write_op!(0xB0),
+            Op::I64TruncF64u => write_op!(0xB1),
+            Op::F32ConvertI32s => write_op!(0xB2),
+            Op::F32ConvertI32u => write_op!(0xB3),
+            Op::F32ConvertI64s => write_op!(0xB4),
+            Op::F32ConvertI64u => write_op!(0xB5),
+            Op::F32DemoteF64 => write_op!(0xB6),
+            Op::F64ConvertI32s => write_op!(0xB7),
+            Op::F64ConvertI32u => write_op!(0xB8),
+            Op::F64ConvertI64s => write_op!(0xB9),
+            Op::F64ConvertI64u => write_op!(0xBA),
+            Op::F64PromoteF32 => write_op!(0xBB),
+            Op::I32ReinterpretF32 => write_op!(0xBC),
+            Op::I64ReinterpretF64 => write_op!(0xBD),
+            Op::F32ReinterpretI32 => write_op!(0xBE),
+            Op::F64ReinterpretI64 => write_op!(0xBF),
+            Op::RefIsNull => write_op!(0xD1),
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Display for Op {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -388,15 +1263,22 @@ impl fmt::Display for Op {
             Op::End(_) => write!(f, "end"),
             Op::Br { label, jmp } => write!(f, "br {label} (jmp: {jmp})"),
             Op::BrIf { label, jmp } => write!(f, "br_if {label} (jmp: {jmp})"),
+            Op::BrTable { labels, default } => {
+                write!(f, "br_table {} {default}", labels.iter().format(" "))
+            }
             Op::Return => write!(f, "return"),
             Op::Call(func_id) => write!(f, "call {func_id}"),
-            Op::CallIndirect { table, type_id } => write!(f, "call_indirect {table} {type_id}"),
+            Op::CallIndirect { type_id, table } => {
+                write!(f, "call_indirect {table} (type {type_id})")
+            }
             Op::Drop => write!(f, "drop"),
             Op::Select(_) => write!(f, "select"),
             Op::LocalGet(id) => write!(f, "local.get {id}"),
             Op::LocalSet(id) => write!(f, "local.set {id}"),
             Op::LocalTee(id) => write!(f, "local.tee {id}"),
             Op::GlobalGet(id) => write!(f, "global.get {id}"),
+            Op::TableGet(id) => write!(f, "table.get {id}"),
+            Op::TableSet(id) => write!(f, "table.set {id}"),
             Op::GlobalSet(id) => write!(f, "global.set {id}"),
             Op::I32Load(memarg) => write!(f, "i32.load {memarg}"),
             Op::I64Load(memarg) => write!(f, "i64.load {memarg}"),
@@ -447,6 +1329,24 @@ impl fmt::Display for Op {
             Op::I64Leu => write!(f, "i64.leu"),
             Op::I64Ges => write!(f, "i64.ges"),
             Op::I64Geu => write!(f, "i64.geu"),
+            Op::F32Eq => write!(f, "f32.eq"),
+            Op::F32Ne => write!(f, "f32.ne"),
+            Op::F32Lt => write!(f, "f32.lt"),
+            Op::F32Gt => write!(f, "f32.gt"),
+            Op::F32Le => write!(f, "f32.le"),
+            Op::F32Ge => write!(f, "f32.ge"),
+            Op::F64Eq => write!(f, "f64.eq"),
+            Op::F64Ne => write!(f, "f64.ne"),
+            Op::F64Lt => write!(f, "f64.lt"),
+            Op::F64Gt => write!(f, "f64.gt"),
+            Op::F64Le => write!(f, "f64.le"),
+            Op::F64Ge => write!(f, "f64.ge"),
+            Op::I32Clz => write!(f, "i32.clz"),
+            Op::I32Ctz => write!(f, "i32.ctz"),
+            Op::I32Popcnt => write!(f, "i32.popcnt"),
+            Op::I64Clz => write!(f, "i64.clz"),
+            Op::I64Ctz => write!(f, "i64.ctz"),
+            Op::I64Popcnt => write!(f, "i64.popcnt"),
             Op::MemoryCopy { .. } => write!(f, "memory.copy"),
             Op::MemoryFill { .. } => write!(f, "memory.fill"),
             Op::I32Add => write!(f, "i32.add"),
@@ -482,10 +1382,78 @@ impl fmt::Display for Op {
             Op::MemoryInit { data_id, .. } => {
                 write!(f, "memory.init {data_id}")
             }
+            Op::MemorySize { .. } => write!(f, "memory.size"),
             Op::MemoryGrow { .. } => write!(f, "memory.grow"),
+            Op::RefNull(t) => write!(f, "ref.null {t}"),
+            Op::RefIsNull => write!(f, "ref.is_null"),
+            Op::RefFunc(id) => write!(f, "ref.func {id}"),
+            Op::TableInit { elem_id, table } => write!(f, "table.init {table} {elem_id}"),
+            Op::ElemDrop(id) => write!(f, "elem.drop {id}"),
+            Op::TableCopy { dst, src } => write!(f, "table.copy {dst} {src}"),
+            Op::TableGrow(id) => write!(f, "table.grow {id}"),
+            Op::TableSize(id) => write!(f, "table.size {id}"),
+            Op::TableFill(id) => write!(f, "table.fill {id}"),
             Op::I32WrapI64 => write!(f, "i32.wrap_i64"),
             Op::I64ExtendI32s => write!(f, "i64.extend_i32_s"),
             Op::I64ExtendI32u => write!(f, "i64.extend_i32_u"),
+            Op::F32Abs => write!(f, "f32.abs"),
+            Op::F32Neg => write!(f, "f32.neg"),
+            Op::F32Ceil => write!(f, "f32.ceil"),
+            Op::F32Floor => write!(f, "f32.floor"),
+            Op::F32Trunc => write!(f, "f32.trunc"),
+            Op::F32Nearest => write!(f, "f32.nearest"),
+            Op::F32Sqrt => write!(f, "f32.sqrt"),
+            Op::F32Add => write!(f, "f32.add"),
+            Op::F32Sub => write!(f, "f32.sub"),
+            Op::F32Mul => write!(f, "f32.mul"),
+            Op::F32Div => write!(f, "f32.div"),
+            Op::F32Min => write!(f, "f32.min"),
+            Op::F32Max => write!(f, "f32.max"),
+            Op::F32Copysign => write!(f, "f32.copysign"),
+            Op::F64Abs => write!(f, "f64.abs"),
+            Op::F64Neg => write!(f, "f64.neg"),
+            Op::F64Ceil => write!(f, "f64.ceil"),
+            Op::F64Floor => write!(f, "f64.floor"),
+            Op::F64Trunc => write!(f, "f64.trunc"),
+            Op::F64Nearest => write!(f, "f64.nearest"),
+            Op::F64Sqrt => write!(f, "f64.sqrt"),
+            Op::F64Add => write!(f, "f64.add"),
+            Op::F64Sub => write!(f, "f64.sub"),
+            Op::F64Mul => write!(f, "f64.mul"),
+            Op::F64Div => write!(f, "f64.div"),
+            Op::F64Min => write!(f, "f64.min"),
+            Op::F64Max => write!(f, "f64.max"),
+            Op::F64Copysign => write!(f, "f64.copysign"),
+            Op::I32TruncF32s => write!(f, "i32.trunc_f32_s"),
+            Op::I32TruncF32u => write!(f, "i32.trunc_f32_u"),
+            Op::I32TruncF64s => write!(f, "i32.trunc_f64_s"),
+            Op::I32TruncF64u => write!(f, "i32.trunc_f64_u"),
+            Op::I64TruncF32s => write!(f, "i64.trunc_f32_s"),
+            Op::I64TruncF32u => write!(f, "i64.trunc_f32_u"),
+            Op::I64TruncF64s => write!(f, "i64.trunc_f64_s"),
+            Op::I64TruncF64u => write!(f, "i64.trunc_f64_u"),
+            Op::F32ConvertI32s => write!(f, "f32.convert_i32_s"),
+            Op::F32ConvertI32u => write!(f, "f32.convert_i32_u"),
+            Op::F32ConvertI64s => write!(f, "f32.convert_i64_s"),
+            Op::F32ConvertI64u => write!(f, "f32.convert_i64_u"),
+            Op::F32DemoteF64 => write!(f, "f32.demote_f64"),
+            Op::F64ConvertI32s => write!(f, "f64.convert_i32_s"),
+            Op::F64ConvertI32u => write!(f, "f64.convert_i32_u"),
+            Op::F64ConvertI64s => write!(f, "f64.convert_i64_s"),
+            Op::F64ConvertI64u => write!(f, "f64.convert_i64_u"),
+            Op::F64PromoteF32 => write!(f, "f64.promote_f32"),
+            Op::I32ReinterpretF32 => write!(f, "i32.reinterpret_f32"),
+            Op::I64ReinterpretF64 => write!(f, "i64.reinterpret_f64"),
+            Op::F32ReinterpretI32 => write!(f, "f32.reinterpret_i32"),
+            Op::F64ReinterpretI64 => write!(f, "f64.reinterpret_i64"),
+            Op::I32TruncSatF32s => write!(f, "i32.trunc_sat_f32_s"),
+            Op::I32TruncSatF32u => write!(f, "i32.trunc_sat_f32_u"),
+            Op::I32TruncSatF64s => write!(f, "i32.trunc_sat_f64_s"),
+            Op::I32TruncSatF64u => write!(f, "i32.trunc_sat_f64_u"),
+            Op::I64TruncSatF32s => write!(f, "i64.trunc_sat_f32_s"),
+            Op::I64TruncSatF32u => write!(f, "i64.trunc_sat_f32_u"),
+            Op::I64TruncSatF64s => write!(f, "i64.trunc_sat_f64_s"),
+            Op::I64TruncSatF64u => write!(f, "i64.trunc_sat_f64_u"),
         }
     }
 }
@@ -0,0 +1,1059 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display, Write},
+};
+
+use itertools::Itertools;
+use log::warn;
+
+use crate::{
+    names::Names,
+    op::{Blocktype, Memarg, Op},
+    reader::{
+        Bytecode, Data, ElementInit, ElementMode, ExportDesc, GlobalType, ImportDesc, Limits,
+        TableType, Type, ValueType, WithPosition,
+    },
+};
+
+const INDENT: &str = "  ";
+/// Folded instructions longer than this are split over multiple lines
+const MAX_INLINE_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrintMode {
+    /// One instruction per line, blocks are closed with `end`
+    #[default]
+    Flat,
+    /// S-expressions with operands nested into the instruction consuming them
+    Folded,
+}
+
+//NOTE: Names from the name section are arbitrary strings, only those that are valid and unique
+//identifiers can be used as `$id`, everything else is referenced by index
+//https://webassembly.github.io/spec/core/text/values.html#text-id
+fn is_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^_`|~".contains(c)
+}
+
+#[derive(Debug, Default)]
+struct Ids(HashMap<usize, String>);
+
+impl Ids {
+    fn new<'a>(names: impl Iterator<Item = (usize, &'a str)>) -> Self {
+        Self::collect(names, true)
+    }
+
+    /// Label names may repeat, an inner label shadows outer ones with the same name
+    fn with_shadowing<'a>(names: impl Iterator<Item = (usize, &'a str)>) -> Self {
+        Self::collect(names, false)
+    }
+
+    fn collect<'a>(names: impl Iterator<Item = (usize, &'a str)>, unique: bool) -> Self {
+        let mut used = HashSet::new();
+        let ids = names
+            .sorted_by_key(|(id, _)| *id)
+            .filter(|(_, name)| !name.is_empty() && name.chars().all(is_id_char))
+            .filter(|(_, name)| !unique || used.insert(*name))
+            .map(|(id, name)| (id, format!("${name}")))
+            .collect();
+        Self(ids)
+    }
+
+    fn get(&self, id: usize) -> Option<&str> {
+        self.0.get(&id).map(|s| s.as_str())
+    }
+
+    /// The identifier if there is one, otherwise the plain index
+    fn reference(&self, id: usize) -> String {
+        self.get(id)
+            .map_or_else(|| id.to_string(), |s| s.to_string())
+    }
+
+    /// `$id (;index;)` used when declaring an item
+    fn declaration(&self, id: usize) -> String {
+        match self.get(id) {
+            Some(name) => format!(" {name} (;{id};)"),
+            None => format!(" (;{id};)"),
+        }
+    }
+}
+
+fn format_f32(v: f32) -> String {
+    if v.is_nan() {
+        let sign = if v.is_sign_negative() { "-" } else { "" };
+        format!("{sign}nan:0x{:x}", v.to_bits() & 0x7f_ffff)
+    } else if v.is_infinite() {
+        if v < 0.0 { "-inf" } else { "inf" }.to_string()
+    } else {
+        format!("{v:?}")
+    }
+}
+
+fn format_f64(v: f64) -> String {
+    if v.is_nan() {
+        let sign = if v.is_sign_negative() { "-" } else { "" };
+        format!("{sign}nan:0x{:x}", v.to_bits() & 0xf_ffff_ffff_ffff)
+    } else if v.is_infinite() {
+        if v < 0.0 { "-inf" } else { "inf" }.to_string()
+    } else {
+        format!("{v:?}")
+    }
+}
+
+fn format_string(data: &[u8]) -> String {
+    let mut res = String::with_capacity(data.len() + 2);
+    res.push('"');
+    for b in data {
+        match b {
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            0x20..0x7F => res.push(*b as char),
+            _ => _ = write!(res, "\\{b:02x}"),
+        }
+    }
+    res.push('"');
+    res
+}
+
+fn format_limits(limits: &Limits) -> String {
+    match &limits.max {
+        Some(max) => format!("{} {}", limits.min.data, max.data),
+        None => format!("{}", limits.min.data),
+    }
+}
+
+fn format_table_type(table_type: &TableType) -> String {
+    format!(
+        "{} {}",
+        format_limits(&table_type.limits.data),
+        table_type.value_type()
+    )
+}
+
+fn format_global_type(global_type: &GlobalType) -> String {
+    if global_type.is_mut() {
+        format!("(mut {})", global_type.value_type())
+    } else {
+        global_type.value_type().to_string()
+    }
+}
+
+fn format_heap_type(t: ValueType) -> &'static str {
+    match t {
+        ValueType::Externref => "extern",
+        _ => "func",
+    }
+}
+
+//NOTE: The binary format stores the alignment as exponent, the text format as number of bytes
+//and only if it differs from the natural alignment
+fn format_memarg(memarg: &Memarg, natural_align: u32) -> String {
+    let mut res = String::new();
+    if memarg.offset != 0 {
+        _ = write!(res, " offset={}", memarg.offset);
+    }
+    if memarg.align != natural_align {
+        _ = write!(res, " align={}", 1u64 << memarg.align);
+    }
+    res
+}
+
+/// Instructions in a function body, with their operands nested for the folded format
+#[derive(Debug)]
+enum Node<'a> {
+    Instr {
+        text: &'a str,
+        operands: Vec<Node<'a>>,
+        results: usize,
+    },
+    Block {
+        text: &'a str,
+        body: Vec<Node<'a>>,
+        results: usize,
+    },
+    If {
+        text: &'a str,
+        condition: Vec<Node<'a>>,
+        then: Vec<Node<'a>>,
+        otherwise: Option<Vec<Node<'a>>>,
+        results: usize,
+    },
+}
+
+impl Node<'_> {
+    fn results(&self) -> usize {
+        match self {
+            Node::Instr { results, .. }
+            | Node::Block { results, .. }
+            | Node::If { results, .. } => *results,
+        }
+    }
+}
+
+/// State while folding a single function body
+struct FoldContext<'a> {
+    ops: &'a [WithPosition<Op>],
+    /// The formatted instructions, see [`WatPrinter::format_body`]
+    texts: &'a [String],
+    pos: usize,
+    /// Arity of each enclosing label, innermost last
+    labels: Vec<usize>,
+}
+
+pub struct WatPrinter<'a> {
+    bytecode: &'a Bytecode,
+    mode: PrintMode,
+    names: Names,
+    functions: Ids,
+    locals: HashMap<usize, Ids>,
+    labels: HashMap<usize, Ids>,
+    globals: Ids,
+    data: Ids,
+    imported_functions: usize,
+}
+
+impl<'a> WatPrinter<'a> {
+    pub fn new(bytecode: &'a Bytecode) -> Self {
+        let names = match bytecode.parse_names() {
+            Some(Ok(names)) => names,
+            Some(Err(e)) => {
+                //NOTE: A malformed name section must not make the module unusable
+                warn!("Ignoring malformed name section: {e}");
+                Names::default()
+            }
+            None => Names::default(),
+        };
+        let functions = Ids::new(names.functions.iter());
+        let locals = names
+            .locals
+            .iter()
+            .map(|(id, locals)| (id, Ids::new(locals.iter())))
+            .collect();
+        let labels = names
+            .labels
+            .iter()
+            .map(|(id, labels)| (id, Ids::with_shadowing(labels.iter())))
+            .collect();
+        let globals = Ids::new(names.globals.iter());
+        let data = Ids::new(names.data.iter());
+        let imported_functions = bytecode.iter_imports().map_or(0, |imports| {
+            imports
+                .filter(|i| matches!(i.desc.data, ImportDesc::TypeIdx(_)))
+                .count()
+        });
+        Self {
+            bytecode,
+            mode: PrintMode::default(),
+            names,
+            functions,
+            locals,
+            labels,
+            globals,
+            data,
+            imported_functions,
+        }
+    }
+
+    pub fn with_mode(mut self, mode: PrintMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    fn count_imports(&self, f: impl Fn(&ImportDesc) -> bool) -> usize {
+        self.bytecode
+            .iter_imports()
+            .map_or(0, |imports| imports.filter(|i| f(&i.desc.data)).count())
+    }
+
+    fn get_function_type(&self, func_id: usize) -> Option<&Type> {
+        let type_id = if func_id < self.imported_functions {
+            self.bytecode
+                .iter_imports()?
+                .filter_map(|i| match i.desc.data {
+                    ImportDesc::TypeIdx(t) => Some(t),
+                    _ => None,
+                })
+                .nth(func_id)?
+        } else {
+            *self
+                .bytecode
+                .get_function(func_id - self.imported_functions)?
+        };
+        self.bytecode.get_type(type_id)
+    }
+
+    fn local_ref(&self, func_id: usize, local_id: usize) -> String {
+        self.locals
+            .get(&func_id)
+            .map_or_else(|| local_id.to_string(), |ids| ids.reference(local_id))
+    }
+
+    /// A branch target by name, unless the name is shadowed by an inner label
+    fn label_ref(labels: &[Option<&str>], depth: usize) -> String {
+        let name = labels
+            .len()
+            .checked_sub(depth + 1)
+            .and_then(|i| Some((labels[i]?, &labels[i + 1..])));
+        match name {
+            Some((name, inner)) if !inner.contains(&Some(name)) => name.to_string(),
+            _ => depth.to_string(),
+        }
+    }
+
+    fn blocktype_arity(&self, bt: &Blocktype) -> (usize, usize) {
+        match bt {
+            Blocktype::Empty => (0, 0),
+            Blocktype::Value(_) => (0, 1),
+            Blocktype::TypeIndex(id) => self
+                .bytecode
+                .get_type(*id as usize)
+                .map_or((0, 0), |t| (t.params.data.len(), t.results.data.len())),
+        }
+    }
+
+    fn format_blocktype(bt: &Blocktype) -> String {
+        match bt {
+            Blocktype::Empty => String::new(),
+            Blocktype::Value(t) => format!(" (result {t})"),
+            Blocktype::TypeIndex(id) => format!(" (type {id})"),
+        }
+    }
+
+    /// The instruction with its immediates, e.g. `i32.load offset=4`.
+    /// `labels` are the names of the enclosing labels, for blocks the last one is their own.
+    fn format_instr(&self, func_id: usize, op: &Op, labels: &[Option<&str>]) -> String {
+        let name = op.name();
+        let func = |id: &usize| self.functions.reference(*id);
+        let local = |id: &usize| self.local_ref(func_id, *id);
+        let label = |depth: &usize| Self::label_ref(labels, *depth);
+        match op {
+            Op::Block(bt) | Op::Loop(bt) | Op::If { bt, .. } => {
+                match labels.last().copied().flatten() {
+                    Some(id) => format!("{name} {id}{}", Self::format_blocktype(bt)),
+                    None => format!("{name}{}", Self::format_blocktype(bt)),
+                }
+            }
+            Op::Br { label: l, .. } | Op::BrIf { label: l, .. } => format!("{name} {}", label(l)),
+            Op::BrTable { labels, default } => {
+                let labels = labels
+                    .iter()
+                    .chain([default])
+                    .map(|l| label(&l.label))
+                    .join(" ");
+                format!("{name} {labels}")
+            }
+            Op::Call(id) | Op::RefFunc(id) => format!("{name} {}", func(id)),
+            Op::CallIndirect { type_id, table } => {
+                if *table == 0 {
+                    format!("{name} (type {type_id})")
+                } else {
+                    format!("{name} {table} (type {type_id})")
+                }
+            }
+            Op::Select(Some(t)) => format!("{name} (result {t})"),
+            Op::LocalGet(id) | Op::LocalSet(id) | Op::LocalTee(id) => {
+                format!("{name} {}", local(id))
+            }
+            Op::GlobalGet(id) | Op::GlobalSet(id) => {
+                format!("{name} {}", self.globals.reference(*id))
+            }
+            Op::TableGet(id)
+            | Op::TableSet(id)
+            | Op::ElemDrop(id)
+            | Op::TableGrow(id)
+            | Op::TableSize(id)
+            | Op::TableFill(id) => format!("{name} {id}"),
+            Op::I32Load8s(m) | Op::I32Load8u(m) | Op::I64Load8s(m) | Op::I64Load8u(m) => {
+                format!("{name}{}", format_memarg(m, 0))
+            }
+            Op::I32Store8(m) | Op::I64Store8(m) => format!("{name}{}", format_memarg(m, 0)),
+            Op::I32Load16s(m) | Op::I32Load16u(m) | Op::I64Load16s(m) | Op::I64Load16u(m) => {
+                format!("{name}{}", format_memarg(m, 1))
+            }
+            Op::I32Store16(m) | Op::I64Store16(m) => format!("{name}{}", format_memarg(m, 1)),
+            Op::I32Load(m)
+            | Op::F32Load(m)
+            | Op::I64Load32s(m)
+            | Op::I64Load32u(m)
+            | Op::I32Store(m)
+            | Op::F32Store(m)
+            | Op::I64Store32(m) => format!("{name}{}", format_memarg(m, 2)),
+            Op::I64Load(m) | Op::F64Load(m) | Op::I64Store(m) | Op::F64Store(m) => {
+                format!("{name}{}", format_memarg(m, 3))
+            }
+            Op::I32Const(v) => format!("{name} {v}"),
+            Op::I64Const(v) => format!("{name} {v}"),
+            Op::F32Const(v) => format!("{name} {}", format_f32(*v)),
+            Op::F64Const(v) => format!("{name} {}", format_f64(*v)),
+            Op::MemoryInit { data_id, .. } => format!("{name} {}", self.data.reference(*data_id)),
+            Op::RefNull(t) => format!("{name} {}", format_heap_type(*t)),
+            Op::TableInit { elem_id, table } => format!("{name} {table} {elem_id}"),
+            Op::TableCopy { dst, src } => format!("{name} {dst} {src}"),
+            _ => name.to_string(),
+        }
+    }
+
+    /// Number of values an instruction pops and pushes, including branches and calls.
+    /// `None` for block instructions, which are handled while folding.
+    fn stack_effect(&self, op: &Op, labels: &[usize], func_id: usize) -> Option<(usize, usize)> {
+        let label_arity = |label: &usize| {
+            labels
+                .len()
+                .checked_sub(label + 1)
+                .and_then(|i| labels.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+        let call_arity =
+            |t: Option<&Type>| t.map_or((0, 0), |t| (t.params.data.len(), t.results.data.len()));
+        let effect = match op {
+            Op::Block(_) | Op::Loop(_) | Op::If { .. } | Op::Else(_) | Op::End(_) => return None,
+            Op::Br { label, .. } => (label_arity(label), 0),
+            Op::BrIf { label, .. } => (label_arity(label) + 1, label_arity(label)),
+            Op::BrTable { default, .. } => (label_arity(&default.label) + 1, 0),
+            Op::Return => (call_arity(self.get_function_type(func_id)).1, 0),
+            Op::Call(id) => call_arity(self.get_function_type(*id)),
+            Op::CallIndirect { type_id, .. } => {
+                let (params, results) = call_arity(self.bytecode.get_type(*type_id));
+                (params + 1, results)
+            }
+            _ => return op.stack_effect(),
+        };
+        Some(effect)
+    }
+
+    /// Takes the operands of an instruction from the already folded nodes.
+    /// Operands are only folded if each of the last `n` nodes produces exactly one value.
+    fn take_operands<'o>(nodes: &mut Vec<Node<'o>>, n: usize) -> Vec<Node<'o>> {
+        if n == 0 || nodes.len() < n || nodes[nodes.len() - n..].iter().any(|n| n.results() != 1) {
+            Vec::new()
+        } else {
+            nodes.split_off(nodes.len() - n)
+        }
+    }
+
+    /// Folds instructions until the `end` or `else` closing the current block
+    fn fold_block<'o>(&self, ctx: &mut FoldContext<'o>, func_id: usize) -> (Vec<Node<'o>>, bool) {
+        let mut nodes = Vec::new();
+        while let Some(op) = ctx.ops.get(ctx.pos).map(|op| &op.data) {
+            let text = ctx.texts[ctx.pos].as_str();
+            ctx.pos += 1;
+            let node = match op {
+                Op::End(_) => return (nodes, false),
+                Op::Else(_) => return (nodes, true),
+                Op::Block(bt) | Op::Loop(bt) => {
+                    let (params, results) = self.blocktype_arity(bt);
+                    let label_arity = if matches!(op, Op::Loop(_)) {
+                        params
+                    } else {
+                        results
+                    };
+                    ctx.labels.push(label_arity);
+                    let (body, _) = self.fold_block(ctx, func_id);
+                    ctx.labels.pop();
+                    Node::Block {
+                        text,
+                        body,
+                        results,
+                    }
+                }
+                Op::If { bt, .. } => {
+                    let (_, results) = self.blocktype_arity(bt);
+                    let condition = Self::take_operands(&mut nodes, 1);
+                    ctx.labels.push(results);
+                    let (then, has_else) = self.fold_block(ctx, func_id);
+                    let otherwise = has_else.then(|| self.fold_block(ctx, func_id).0);
+                    ctx.labels.pop();
+                    Node::If {
+                        text,
+                        condition,
+                        then,
+                        otherwise,
+                        results,
+                    }
+                }
+                _ => {
+                    let (pops, results) = self
+                        .stack_effect(op, &ctx.labels, func_id)
+                        .unwrap_or((0, 0));
+                    let operands = Self::take_operands(&mut nodes, pops);
+                    Node::Instr {
+                        text,
+                        operands,
+                        results,
+                    }
+                }
+            };
+            nodes.push(node);
+        }
+        (nodes, false)
+    }
+
+    fn render_nodes(&self, nodes: &[Node], func_id: usize) -> Vec<String> {
+        nodes
+            .iter()
+            .flat_map(|n| self.render_node(n, func_id))
+            .collect()
+    }
+
+    fn indented(lines: Vec<String>) -> impl Iterator<Item = String> {
+        lines.into_iter().map(|l| format!("{INDENT}{l}"))
+    }
+
+    fn render_node(&self, node: &Node, func_id: usize) -> Vec<String> {
+        match node {
+            Node::Instr { text, operands, .. } => {
+                let head = format!("({text}");
+                let operands = operands
+                    .iter()
+                    .map(|o| self.render_node(o, func_id))
+                    .collect::<Vec<_>>();
+                let width = head.len()
+                    + operands
+                        .iter()
+                        .flatten()
+                        .map(|o| o.len() + 1)
+                        .sum::<usize>();
+                if operands.iter().all(|o| o.len() == 1) && width < MAX_INLINE_WIDTH {
+                    let operands = operands.into_iter().flatten();
+                    vec![std::iter::once(head).chain(operands).join(" ") + ")"]
+                } else {
+                    let mut lines = vec![head];
+                    lines.extend(operands.into_iter().flat_map(Self::indented));
+                    lines.push(")".to_string());
+                    lines
+                }
+            }
+            Node::Block { text, body, .. } => {
+                let mut lines = vec![format!("({text}")];
+                lines.extend(Self::indented(self.render_nodes(body, func_id)));
+                lines.push(")".to_string());
+                lines
+            }
+            Node::If {
+                text,
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                let mut lines = vec![format!("({text}")];
+                lines.extend(Self::indented(self.render_nodes(condition, func_id)));
+                lines.push(format!("{INDENT}(then"));
+                lines.extend(Self::indented(
+                    Self::indented(self.render_nodes(then, func_id)).collect(),
+                ));
+                lines.push(format!("{INDENT})"));
+                if let Some(otherwise) = otherwise {
+                    lines.push(format!("{INDENT}(else"));
+                    lines.extend(Self::indented(
+                        Self::indented(self.render_nodes(otherwise, func_id)).collect(),
+                    ));
+                    lines.push(format!("{INDENT})"));
+                }
+                lines.push(")".to_string());
+                lines
+            }
+        }
+    }
+
+    /// Formats each instruction of a function body with label names resolved
+    fn format_body(&self, func_id: usize, ops: &[WithPosition<Op>]) -> Vec<String> {
+        let names = self.labels.get(&func_id);
+        let mut labels = Vec::new();
+        let mut next_label = 0;
+        ops.iter()
+            .map(|op| match &op.data {
+                op @ (Op::Block(_) | Op::Loop(_) | Op::If { .. }) => {
+                    labels.push(names.and_then(|n| n.get(next_label)));
+                    next_label += 1;
+                    self.format_instr(func_id, op, &labels)
+                }
+                op @ Op::End(_) => {
+                    labels.pop();
+                    self.format_instr(func_id, op, &labels)
+                }
+                op => self.format_instr(func_id, op, &labels),
+            })
+            .collect()
+    }
+
+    /// The instructions of a function body, without the final `end`
+    fn body_lines(&self, func_id: usize, ops: &[WithPosition<Op>]) -> Vec<String> {
+        let texts = self.format_body(func_id, ops);
+        match self.mode {
+            PrintMode::Flat => Self::flat_lines(ops, &texts),
+            PrintMode::Folded => {
+                let results = self
+                    .get_function_type(func_id)
+                    .map_or(0, |t| t.results.data.len());
+                let mut ctx = FoldContext {
+                    ops,
+                    texts: &texts,
+                    pos: 0,
+                    labels: vec![results],
+                };
+                let (nodes, _) = self.fold_block(&mut ctx, func_id);
+                self.render_nodes(&nodes, func_id)
+            }
+        }
+    }
+
+    fn flat_lines(ops: &[WithPosition<Op>], texts: &[String]) -> Vec<String> {
+        let mut depth = 0;
+        let mut lines = Vec::new();
+        for (op, text) in ops[..ops.len().saturating_sub(1)].iter().zip(texts) {
+            let op = &op.data;
+            if matches!(op, Op::End(_) | Op::Else(_)) {
+                depth -= 1;
+            }
+            lines.push(format!("{}{text}", INDENT.repeat(depth)));
+            if op.needs_end_terminator() || matches!(op, Op::Else(_)) {
+                depth += 1;
+            }
+        }
+        lines
+    }
+
+    /// One indented line per instruction of a function body without the final `end`,
+    /// regardless of the print mode. Used to annotate instructions, e.g. with coverage.
+    pub fn instruction_lines(&self, func_id: usize) -> Vec<String> {
+        let Some(code) = func_id
+            .checked_sub(self.imported_functions)
+            .and_then(|id| self.bytecode.get_code(id))
+        else {
+            return vec![];
+        };
+        let ops = code.code.data.ops();
+        Self::flat_lines(ops, &self.format_body(func_id, ops))
+    }
+
+    /// The declaration of a function without its body, e.g. `(func $add (param i32) (result i32)`
+    pub fn function_header(&self, func_id: usize) -> Option<String> {
+        let code_id = func_id.checked_sub(self.imported_functions)?;
+        let t = self
+            .bytecode
+            .get_type(*self.bytecode.get_function(code_id)?)?;
+        Some(format!(
+            "(func{}{}",
+            self.functions.declaration(func_id),
+            self.format_params(Some(func_id), t)
+        ))
+    }
+
+    /// Constant expressions are always printed folded, e.g. `(i32.const 0)`
+    fn format_const_expr<'o>(&self, ops: impl Iterator<Item = &'o Op>) -> String {
+        ops.map(|op| format!("({})", self.format_instr(0, op, &[])))
+            .join(" ")
+    }
+
+    fn format_params(&self, func_id: Option<usize>, t: &Type) -> String {
+        let locals = func_id.and_then(|id| self.locals.get(&id));
+        let mut res = String::new();
+        for (i, param) in t.iter_params().enumerate() {
+            match locals.and_then(|l| l.get(i)) {
+                Some(name) => _ = write!(res, " (param {name} {param})"),
+                None => _ = write!(res, " (param {param})"),
+            }
+        }
+        if !t.results.data.is_empty() {
+            _ = write!(res, " (result {})", t.iter_results().join(" "));
+        }
+        res
+    }
+
+    fn write_function(&self, f: &mut impl Write, func_id: usize) -> fmt::Result {
+        let code_id = func_id - self.imported_functions;
+        let (Some(type_id), Some(code)) = (
+            self.bytecode.get_function(code_id),
+            self.bytecode.get_code(code_id),
+        ) else {
+            return Ok(());
+        };
+        let t = self.bytecode.get_type(*type_id).ok_or(fmt::Error)?;
+        writeln!(
+            f,
+            "{INDENT}(func{} (type {type_id}){}",
+            self.functions.declaration(func_id),
+            self.format_params(Some(func_id), t)
+        )?;
+
+        let params = t.params.data.len();
+        let locals = self.locals.get(&func_id);
+        for (i, local) in code.iter_locals().enumerate() {
+            match locals.and_then(|l| l.get(params + i)) {
+                Some(name) => writeln!(f, "{INDENT}{INDENT}(local {name} {local})")?,
+                None => writeln!(f, "{INDENT}{INDENT}(local {local})")?,
+            }
+        }
+        for line in self.body_lines(func_id, code.code.data.ops()) {
+            writeln!(f, "{INDENT}{INDENT}{line}")?;
+        }
+        writeln!(f, "{INDENT})")
+    }
+
+    /// Prints a single function declaration, imported functions are printed as import
+    pub fn print_function(&self, func_id: usize) -> String {
+        let mut res = String::new();
+        if func_id < self.imported_functions {
+            _ = self.write_imports(&mut res, Some(func_id));
+        } else {
+            _ = self.write_function(&mut res, func_id);
+        }
+        res
+    }
+
+    fn write_imports(&self, f: &mut impl Write, only_func: Option<usize>) -> fmt::Result {
+        let Some(imports) = self.bytecode.iter_imports() else {
+            return Ok(());
+        };
+        let (mut funcs, mut tables, mut mems, mut globals) = (0, 0, 0, 0);
+        for import in imports {
+            let desc = match &import.desc.data {
+                ImportDesc::TypeIdx(type_id) => {
+                    funcs += 1;
+                    if only_func.is_some_and(|id| id != funcs - 1) {
+                        continue;
+                    }
+                    let t = self.bytecode.get_type(*type_id).ok_or(fmt::Error)?;
+                    format!(
+                        "(func{} (type {type_id}){})",
+                        self.functions.declaration(funcs - 1),
+                        self.format_params(None, t)
+                    )
+                }
+                _ if only_func.is_some() => continue,
+                ImportDesc::TableType(table_type) => {
+                    tables += 1;
+                    format!(
+                        "(table (;{};) {})",
+                        tables - 1,
+                        format_table_type(table_type)
+                    )
+                }
+                ImportDesc::MemType(limits) => {
+                    mems += 1;
+                    format!("(memory (;{};) {})", mems - 1, format_limits(limits))
+                }
+                ImportDesc::GlobalType(global_type) => {
+                    globals += 1;
+                    format!(
+                        "(global{} {})",
+                        self.globals.declaration(globals - 1),
+                        format_global_type(global_type)
+                    )
+                }
+            };
+            writeln!(
+                f,
+                "{INDENT}(import {} {} {desc})",
+                format_string(import.get_mod_name().as_bytes()),
+                format_string(import.get_name().as_bytes())
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_elements(&self, f: &mut impl Write) -> fmt::Result {
+        let Some(elements) = self.bytecode.iter_elements() else {
+            return Ok(());
+        };
+        for (i, element) in elements.enumerate() {
+            let mode = match &element.mode {
+                ElementMode::Passive => String::new(),
+                ElementMode::Declarative => " declare".to_string(),
+                ElementMode::Active { table_id, expr } => format!(
+                    " (table {table_id}) (offset {})",
+                    self.format_const_expr(expr.data.iter().map(|op| &op.data))
+                ),
+            };
+            let init = match &element.init {
+                ElementInit::Functions(ids) => std::iter::once("func".to_string())
+                    .chain(ids.iter().map(|id| self.functions.reference(id.data)))
+                    .join(" "),
+                ElementInit::Expressions(exprs) => std::iter::once(element.t.to_string())
+                    .chain(exprs.iter().map(|expr| {
+                        format!(
+                            "(item {})",
+                            self.format_const_expr(expr.data.ops().iter().map(|op| &op.data))
+                        )
+                    }))
+                    .join(" "),
+            };
+            writeln!(f, "{INDENT}(elem (;{i};){mode} {init})")?;
+        }
+        Ok(())
+    }
+
+    fn write_data(&self, f: &mut impl Write) -> fmt::Result {
+        let Some(data) = self.bytecode.iter_data() else {
+            return Ok(());
+        };
+        for (i, data) in data.enumerate() {
+            let mode = match data {
+                Data::Active { mem_id, expr, .. } => {
+                    let offset = self.format_const_expr(expr.data.iter().map(|op| &op.data));
+                    if *mem_id == 0 {
+                        format!(" (offset {offset})")
+                    } else {
+                        format!(" (memory {mem_id}) (offset {offset})")
+                    }
+                }
+                Data::Passive(_) => String::new(),
+            };
+            writeln!(
+                f,
+                "{INDENT}(data{}{mode} {})",
+                self.data.declaration(i),
+                format_string(data.get_data())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for WatPrinter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytecode = self.bytecode;
+        match self.names.module.as_deref() {
+            Some(name) if !name.is_empty() && name.chars().all(is_id_char) => {
+                writeln!(f, "(module ${name}")?
+            }
+            _ => writeln!(f, "(module")?,
+        }
+        if let Some(types) = bytecode.iter_types() {
+            for (i, t) in types.enumerate() {
+                writeln!(
+                    f,
+                    "{INDENT}(type (;{i};) (func{}))",
+                    self.format_params(None, t)
+                )?;
+            }
+        }
+        self.write_imports(f, None)?;
+
+        let functions = bytecode.iter_functions().map_or(0, |f| f.count());
+        for func_id in self.imported_functions..self.imported_functions + functions {
+            self.write_function(f, func_id)?;
+        }
+
+        let imported_tables = self.count_imports(|i| matches!(i, ImportDesc::TableType(_)));
+        if let Some(tables) = bytecode.iter_tables() {
+            for (i, table) in tables.enumerate() {
+                let id = imported_tables + i;
+                writeln!(f, "{INDENT}(table (;{id};) {})", format_table_type(table))?;
+            }
+        }
+        let imported_mems = self.count_imports(|i| matches!(i, ImportDesc::MemType(_)));
+        if let Some(memories) = bytecode.iter_memories() {
+            for (i, limits) in memories.enumerate() {
+                let id = imported_mems + i;
+                writeln!(f, "{INDENT}(memory (;{id};) {})", format_limits(limits))?;
+            }
+        }
+        let imported_globals = self.count_imports(|i| matches!(i, ImportDesc::GlobalType(_)));
+        if let Some(globals) = bytecode.iter_globals() {
+            for (i, global) in globals.enumerate() {
+                writeln!(
+                    f,
+                    "{INDENT}(global{} {} {})",
+                    self.globals.declaration(imported_globals + i),
+                    format_global_type(&global.t.data),
+                    self.format_const_expr(global.iter_init_expr())
+                )?;
+            }
+        }
+        if let Some(exports) = bytecode.iter_exports() {
+            for export in exports {
+                let desc = match export.desc.data {
+                    ExportDesc::FuncId(id) => format!("func {}", self.functions.reference(id)),
+                    ExportDesc::TableId(id) => format!("table {id}"),
+                    ExportDesc::MemId(id) => format!("memory {id}"),
+                    ExportDesc::GlobalId(id) => format!("global {}", self.globals.reference(id)),
+                };
+                writeln!(
+                    f,
+                    "{INDENT}(export {} ({desc}))",
+                    format_string(export.name.data.as_bytes())
+                )?;
+            }
+        }
+        if let Some(start) = &bytecode.start {
+            writeln!(
+                f,
+                "{INDENT}(start {})",
+                self.functions.reference(start.data as usize)
+            )?;
+        }
+        self.write_elements(f)?;
+        self.write_data(f)?;
+        write!(f, ")")
+    }
+}
+
+impl Bytecode {
+    /// Prints the module in the WebAssembly text format
+    pub fn to_wat(&self, mode: PrintMode) -> String {
+        WatPrinter::new(self).with_mode(mode).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::reader::{Bytecode, ParserError, parse_binary};
+
+    use super::PrintMode;
+
+    fn parse(src: &str) -> Result<Bytecode, ParserError> {
+        parse_binary(&mut Cursor::new(wat::parse_str(src)?))
+    }
+
+    //NOTE: The printed text has to describe the same module, names end up in a new name section
+    //so only the remaining sections are compared
+    fn round_trip(src: &str, mode: PrintMode) -> Result<String, ParserError> {
+        let mut module = parse(src)?;
+        let printed = module.to_wat(mode);
+        let mut reparsed = parse(&printed)?;
+        module.custom_sections.clear();
+        reparsed.custom_sections.clear();
+        assert_eq!(reparsed.encode()?, module.encode()?, "{printed}");
+        Ok(printed)
+    }
+
+    const FIB: &str = r#"
+        (module $math
+            (import "env" "log" (func $log (param i32)))
+            (memory 1)
+            (global $counter (mut i32) (i32.const 0))
+            (func $fib (export "fib") (param $n i32) (result i32)
+                (local $tmp i64)
+                (if (result i32) (i32.lt_u (local.get $n) (i32.const 2))
+                    (then (local.get $n))
+                    (else
+                        (i32.add
+                            (call $fib (i32.sub (local.get $n) (i32.const 1)))
+                            (call $fib (i32.sub (local.get $n) (i32.const 2)))
+                        )
+                    )
+                )
+            )
+            (func (param i32) (result i32)
+                (global.set $counter (i32.shr_s (global.get $counter) (i32.const 1)))
+                (i64.store offset=8 align=4 (i32.const 0) (i64.const -1))
+                (call $log (i32.load8_u offset=3 (local.get 0)))
+                (block $out (result i32)
+                    (loop $again
+                        (br_if $again (i32.eqz (local.get 0)))
+                        (br_table $out $out (i32.const 7) (local.get 0))
+                    )
+                    (i32.const -1)
+                )
+            )
+        )
+    "#;
+
+    #[test]
+    fn flat_round_trip() -> Result<(), ParserError> {
+        let printed = round_trip(FIB, PrintMode::Flat)?;
+        assert!(printed.contains("(module $math"));
+        assert!(printed.contains("(func $fib (;1;) (type 1) (param $n i32) (result i32)"));
+        assert!(printed.contains("(local $tmp i64)"));
+        assert!(printed.contains("call $fib"));
+        assert!(printed.contains("local.get $n"));
+        assert!(printed.contains("i32.shr_s"));
+        assert!(printed.contains("i64.store offset=8 align=4"));
+        assert!(printed.contains("i32.load8_u offset=3\n"));
+        assert!(printed.contains("(global $counter (;0;) (mut i32) (i32.const 0))"));
+        assert!(printed.contains("global.set $counter"));
+        assert!(printed.contains("block $out (result i32)"));
+        assert!(printed.contains("br_if $again"));
+        assert!(printed.contains("br_table $out $out"));
+        Ok(())
+    }
+
+    #[test]
+    fn folded_round_trip() -> Result<(), ParserError> {
+        let printed = round_trip(FIB, PrintMode::Folded)?;
+        assert!(printed.contains("(i32.lt_u (local.get $n) (i32.const 2))"));
+        assert!(printed.contains("(call $log (i32.load8_u offset=3 (local.get 0)))"));
+        assert!(!printed.contains("end"));
+        Ok(())
+    }
+
+    #[test]
+    fn all_sections() -> Result<(), ParserError> {
+        let src = r#"
+            (module
+                (type (func (param i32 i32) (result i32)))
+                (import "env" "g" (global $imported i32))
+                (import "env" "t" (table 1 funcref))
+                (import "env" "mem" (memory 1 2))
+                (global $g (mut i64) (i64.const 42))
+                (global f32 (f32.const -inf))
+                (global f64 (f64.const nan:0x4000000000000))
+                (table $t2 2 10 externref)
+                (elem (i32.const 0) $f)
+                (elem $passive funcref (ref.func $f) (ref.null func))
+                (elem declare func $f)
+                (elem (table $t2) (i32.const 1) externref (ref.null extern))
+                (data (i32.const 8) "hello\00\ff\"\\")
+                (data $d "world")
+                (func $f (export "f")
+                    (memory.init $d (i32.const 0) (i32.const 0) (i32.const 5))
+                    (table.init 0 $passive (i32.const 0) (i32.const 0) (i32.const 1))
+                    (table.copy $t2 $t2 (i32.const 0) (i32.const 0) (i32.const 1))
+                    (elem.drop $passive)
+                    (drop (table.grow $t2 (ref.null extern) (i32.const 1)))
+                    (drop (call_indirect (type 0) (i32.const 1) (i32.const 2) (i32.const 0)))
+                    (drop (select (result f32) (f32.const nan) (f32.const 0.5) (i32.const 1)))
+                    (drop (i32.trunc_sat_f64_u (f64.const 1)))
+                )
+                (start $f)
+            )
+        "#;
+        round_trip(src, PrintMode::Flat)?;
+        round_trip(src, PrintMode::Folded)?;
+        Ok(())
+    }
+
+    #[test]
+    fn shadowed_labels() -> Result<(), ParserError> {
+        let printed = round_trip(
+            r#"
+            (module
+                (func
+                    (block $l
+                        (block $l
+                            (br 1)
+                        )
+                        (br $l)
+                    )
+                )
+            )
+            "#,
+            PrintMode::Flat,
+        )?;
+        assert!(printed.contains("br 1\n"));
+        assert!(printed.contains("br $l\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_names_fall_back_to_indices() -> Result<(), ParserError> {
+        let printed = round_trip(
+            r#"
+            (module
+                (func $"has space" (call $valid))
+                (func $valid (call $"has space"))
+            )
+            "#,
+            PrintMode::Flat,
+        )?;
+        assert!(printed.contains("(func (;0;)"));
+        assert!(printed.contains("call $valid"));
+        assert!(printed.contains("call 0"));
+        Ok(())
+    }
+}
@@ -2,20 +2,20 @@ use core::fmt::{self, Display};
 use std::{
     collections::HashMap,
     io::{Cursor, Read, Seek, SeekFrom},
-    iter::repeat,
     ops::Range,
     string::FromUtf8Error,
     usize,
 };
 
-use byteorder::ReadBytesExt;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use itertools::Itertools;
 use log::{error, info, trace};
-use parser_derive::FromBytecode;
+use parser_derive::{FromBytecode, ToBytecode};
 
 use crate::{
     leb::{Leb, LebError},
     op::Op,
+    writer::{BytecodeWriter, ToBytecode, write_bytes, write_const_expr, write_section},
 };
 use thiserror::Error;
 const TYPE_MAGIC: u8 = 0x60;
@@ -70,11 +70,32 @@ pub enum ParserError {
     #[error("Invalid Data Mode Encoding: Got {0}, expected 0, 1 or 2")]
     InvalidDataMode(u32),
 
+    #[error("Invalid Element Mode Encoding: Got {0}, expected 0..7")]
+    InvalidElementMode(u32),
+
+    #[error("Invalid Element Kind Encoding: Got {0}, expected 0x00")]
+    InvalidElemKind(u8),
+
+    #[error("Invalid select encoding: Expected exactly one value type, got {0}")]
+    InvalidSelectArity(usize),
+
     #[error("Invalid section id: Got {0}, expected 0..11")]
     InvalidSectionId(u8),
 
+    #[error("Invalid opcode: 0x{0:02X}")]
+    InvalidOpcode(u8),
+
+    #[error("Invalid opcode: 0xFC {0}")]
+    InvalidFcOpcode(u8),
+
     #[error("{0}")]
     WatParseError(#[from] wat::Error),
+
+    #[error("Error at {location}: {error}")]
+    At {
+        location: ErrorLocation,
+        error: Box<ParserError>,
+    },
 }
 impl ParserError {
     pub fn is_eof(&self) -> bool {
@@ -84,6 +105,154 @@ impl ParserError {
             false
         }
     }
+
+    /// Returns the error without its location
+    pub fn cause(&self) -> &ParserError {
+        match self {
+            Self::At { error, .. } => error.cause(),
+            _ => self,
+        }
+    }
+
+    pub fn location(&self) -> Option<&ErrorLocation> {
+        match self {
+            Self::At { location, .. } => Some(location),
+            _ => None,
+        }
+    }
+
+    /// Attaches the current reader position to the error, or adds to the location it already has.
+    /// Inner contexts are more precise, `update` should only fill in fields that are still unset.
+    pub fn locate<R: BytecodeReader>(
+        self,
+        reader: &mut R,
+        update: impl FnOnce(&mut ErrorLocation),
+    ) -> ParserError {
+        match self {
+            Self::At {
+                mut location,
+                error,
+            } => {
+                update(&mut location);
+                Self::At { location, error }
+            }
+            error => {
+                let mut location = ErrorLocation::at_reader(reader);
+                update(&mut location);
+                Self::At {
+                    location,
+                    error: Box::new(error),
+                }
+            }
+        }
+    }
+}
+
+/// Number of bytes shown before and after an error
+const ERROR_CONTEXT_BYTES: usize = 8;
+
+/// Where in the binary a parse error was detected
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ErrorLocation {
+    /// Absolute offset of the reader when the error was detected,
+    /// the offending bytes are right in front of it
+    pub offset: usize,
+    pub section: Option<u8>,
+    /// Index of the item inside its section, e.g. the function body
+    pub item: Option<usize>,
+    /// Index of the instruction inside an expression
+    pub instruction: Option<usize>,
+    /// The bytes around `offset`, starting at `bytes_start`
+    pub bytes: Vec<u8>,
+    pub bytes_start: usize,
+}
+
+impl ErrorLocation {
+    fn at_reader<R: BytecodeReader>(reader: &mut R) -> Self {
+        let Ok(offset) = reader.stream_position() else {
+            return Self::default();
+        };
+        let offset = offset as usize;
+        let bytes_start = offset.saturating_sub(ERROR_CONTEXT_BYTES);
+        let mut bytes = Vec::with_capacity(2 * ERROR_CONTEXT_BYTES);
+        //NOTE: The bytes are only informative, the reader is left where the error happened
+        if reader.seek(SeekFrom::Start(bytes_start as u64)).is_ok() {
+            _ = reader
+                .by_ref()
+                .take((offset - bytes_start + ERROR_CONTEXT_BYTES) as u64)
+                .read_to_end(&mut bytes);
+            _ = reader.seek(SeekFrom::Start(offset as u64));
+        }
+        Self {
+            offset,
+            bytes,
+            bytes_start,
+            ..Default::default()
+        }
+    }
+
+    pub fn section_name(&self) -> Option<&'static str> {
+        Some(match self.section? {
+            0 => "custom",
+            1 => "type",
+            2 => "import",
+            3 => "function",
+            4 => "table",
+            5 => "memory",
+            6 => "global",
+            7 => "export",
+            8 => "start",
+            9 => "element",
+            10 => "code",
+            11 => "data",
+            12 => "data count",
+            _ => "unknown",
+        })
+    }
+
+    /// What the items of the section are called, e.g. `function body` in the code section
+    pub fn item_name(&self) -> &'static str {
+        match self.section {
+            Some(1) => "type",
+            Some(2) => "import",
+            Some(3) => "function",
+            Some(4) => "table",
+            Some(5) => "memory",
+            Some(6) => "global",
+            Some(7) => "export",
+            Some(9) => "element segment",
+            Some(10) => "function body",
+            Some(11) => "data segment",
+            _ => "item",
+        }
+    }
+}
+
+impl Display for ErrorLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset 0x{:x}", self.offset)?;
+        if let Some(section) = self.section_name() {
+            write!(f, " in {section} section")?;
+        }
+        if let Some(item) = self.item {
+            write!(f, ", {} {item}", self.item_name())?;
+        }
+        if let Some(instruction) = self.instruction {
+            write!(f, ", instruction {instruction}")?;
+        }
+        if !self.bytes.is_empty() {
+            let (before, after) = self
+                .bytes
+                .split_at((self.offset - self.bytes_start).min(self.bytes.len()));
+            write!(
+                f,
+                " [{:02x} | {:02x}]",
+                before.iter().format(" "),
+                after.iter().format(" ")
+            )?;
+        }
+        Ok(())
+    }
 }
 
 pub trait BytecodeReader: Read + Seek + Sized {
@@ -124,6 +293,17 @@ impl FromBytecode for u64 {
     }
 }
 
+impl FromBytecode for f32 {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        Ok(reader.read_f32::<LittleEndian>()?)
+    }
+}
+impl FromBytecode for f64 {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        Ok(reader.read_f64::<LittleEndian>()?)
+    }
+}
+
 impl FromBytecode for usize {
     fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
         Ok(Leb::read_u32(reader)? as usize)
@@ -263,17 +443,23 @@ pub fn parse_data_with_pos<R: BytecodeReader>(
 pub fn iter_const_expr<R: BytecodeReader>(
     reader: &mut R,
 ) -> impl Iterator<Item = Result<WithPosition<Op>, ParserError>> {
-    repeat(0)
-        .map(|_| reader.parse::<WithPosition<Op>>())
+    (0..)
+        .map(|i| {
+            reader
+                .parse::<WithPosition<Op>>()
+                .map_err(|e| e.locate(reader, |l| _ = l.instruction.get_or_insert(i)))
+        })
         .take_while(|op| op.as_ref().is_ok_and(|op| !op.data.is_terminator()) || op.is_err())
 }
 
 pub fn iter_expr<R: BytecodeReader>(
     reader: &mut R,
 ) -> impl Iterator<Item = Result<WithPosition<Op>, ParserError>> {
-    (0..).scan((0, true), |(depth, cont), _| {
+    (0..).scan((0, true), |(depth, cont), i| {
         if *cont {
-            let op = reader.parse::<WithPosition<Op>>();
+            let op = reader
+                .parse::<WithPosition<Op>>()
+                .map_err(|e| e.locate(reader, |l| _ = l.instruction.get_or_insert(i)));
             Some(op.inspect(|op| {
                 let (new_depth, should_cont) = op.data.continues(*depth);
                 *depth = new_depth;
@@ -332,6 +518,13 @@ impl FromBytecode for Header {
     }
 }
 
+impl ToBytecode for Header {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        writer.write_all(WASM_HEADER_MAGIC)?;
+        Ok(writer.write_all(WASM_HEADER_VERSION)?)
+    }
+}
+
 pub fn read_wasm_header(reader: &mut impl BytecodeReader) -> Result<Header, ParserError> {
     reader.parse()
 }
@@ -426,6 +619,11 @@ impl FromBytecode for ValueType {
         reader.read_u8()?.try_into()
     }
 }
+impl ToBytecode for ValueType {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(writer.write_u8(*self as u8)?)
+    }
+}
 
 #[derive(Debug, Default, Clone)]
 pub struct Type {
@@ -448,11 +646,18 @@ impl FromBytecode for Type {
         }
     }
 }
+impl ToBytecode for Type {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        writer.write_u8(TYPE_MAGIC)?;
+        writer.emit(&self.params)?;
+        writer.emit(&self.results)
+    }
+}
 impl Type {
-    pub fn iter_params(&self) -> impl Iterator<Item = &ValueType> {
+    pub fn iter_params(&self) -> impl DoubleEndedIterator<Item = &ValueType> {
         self.params.data.iter().map(|v| &v.data)
     }
-    pub fn iter_results(&self) -> impl Iterator<Item = &ValueType> {
+    pub fn iter_results(&self) -> impl DoubleEndedIterator<Item = &ValueType> {
         self.results.data.iter().map(|v| &v.data)
     }
 
@@ -472,7 +677,7 @@ impl Display for Type {
     }
 }
 
-#[derive(FromBytecode, Debug, PartialEq, Clone)]
+#[derive(FromBytecode, ToBytecode, Debug, PartialEq, Clone)]
 pub struct GlobalType {
     pub t: WithPosition<ValueType>,
     pub mutable: WithPosition<bool>,
@@ -508,13 +713,22 @@ impl FromBytecode for ConstExpr {
     }
 }
 
+impl ToBytecode for ConstExpr {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        write_const_expr(writer, &self.expr)
+    }
+}
+
 impl ConstExpr {
     pub fn iter_ops(&self) -> impl Iterator<Item = Op> {
-        self.expr.iter().map(|op| op.data)
+        self.expr.iter().map(|op| op.data.clone())
+    }
+    pub fn ops(&self) -> &[WithPosition<Op>] {
+        &self.expr
     }
 }
 
-#[derive(FromBytecode, Debug, Clone)]
+#[derive(FromBytecode, ToBytecode, Debug, Clone)]
 pub struct Global {
     pub t: WithPosition<GlobalType>,
     pub init_expr: WithPosition<ConstExpr>,
@@ -582,6 +796,21 @@ impl FromBytecode for Limits {
         }
     }
 }
+impl ToBytecode for Limits {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        match &self.max {
+            None => {
+                writer.write_u8(0x00)?;
+                writer.emit(&self.min)
+            }
+            Some(max) => {
+                writer.write_u8(0x01)?;
+                writer.emit(&self.min)?;
+                writer.emit(max)
+            }
+        }
+    }
+}
 impl Display for Limits {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.max {
@@ -593,10 +822,27 @@ impl Display for Limits {
         }
     }
 }
+#[derive(FromBytecode, ToBytecode, Debug, PartialEq, Clone)]
+pub struct TableType {
+    pub t: WithPosition<ValueType>,
+    pub limits: WithPosition<Limits>,
+}
+
+impl TableType {
+    pub fn value_type(&self) -> ValueType {
+        self.t.data
+    }
+}
+impl Display for TableType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.t.data, self.limits.data)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum ImportDesc {
     TypeIdx(usize),
-    TableType(Limits),
+    TableType(TableType),
     MemType(Limits),
     GlobalType(GlobalType),
 }
@@ -613,18 +859,40 @@ impl FromBytecode for ImportDesc {
         }
     }
 }
+impl ToBytecode for ImportDesc {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        match self {
+            Self::TypeIdx(id) => {
+                writer.write_u8(0x00)?;
+                writer.emit(id)
+            }
+            Self::TableType(table_type) => {
+                writer.write_u8(0x01)?;
+                writer.emit(table_type)
+            }
+            Self::MemType(limits) => {
+                writer.write_u8(0x02)?;
+                writer.emit(limits)
+            }
+            Self::GlobalType(global_type) => {
+                writer.write_u8(0x03)?;
+                writer.emit(global_type)
+            }
+        }
+    }
+}
 impl Display for ImportDesc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ImportDesc::TypeIdx(i) => write!(f, "{i}"),
-            ImportDesc::TableType(limits) => write!(f, "table {limits}"),
+            ImportDesc::TableType(table_type) => write!(f, "table {table_type}"),
             ImportDesc::MemType(limits) => write!(f, "mem {limits}"),
             ImportDesc::GlobalType(global_type) => write!(f, "{global_type}"),
         }
     }
 }
 
-#[derive(FromBytecode, Debug, Clone)]
+#[derive(FromBytecode, ToBytecode, Debug, Clone)]
 pub struct ImportIdent {
     pub module: WithPosition<String>,
     pub name: WithPosition<String>,
@@ -635,7 +903,7 @@ impl Display for ImportIdent {
     }
 }
 
-#[derive(FromBytecode, Debug, Clone)]
+#[derive(FromBytecode, ToBytecode, Debug, Clone)]
 pub struct Import {
     pub ident: WithPosition<ImportIdent>,
     pub desc: WithPosition<ImportDesc>,
@@ -655,7 +923,7 @@ impl Display for Import {
     }
 }
 
-#[derive(FromBytecode, Debug, Clone, PartialEq)]
+#[derive(FromBytecode, ToBytecode, Debug, Clone, PartialEq)]
 pub struct Locals {
     pub n: u32,
     pub t: ValueType,
@@ -701,6 +969,19 @@ impl FromBytecode for ExportDesc {
     }
 }
 
+impl ToBytecode for ExportDesc {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        let (t, id) = match self {
+            Self::FuncId(id) => (0x00, id),
+            Self::TableId(id) => (0x01, id),
+            Self::MemId(id) => (0x02, id),
+            Self::GlobalId(id) => (0x03, id),
+        };
+        writer.write_u8(t)?;
+        writer.emit(id)
+    }
+}
+
 impl Display for ExportDesc {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -712,7 +993,7 @@ impl Display for ExportDesc {
     }
 }
 
-#[derive(FromBytecode, Debug, Clone)]
+#[derive(FromBytecode, ToBytecode, Debug, Clone)]
 pub struct Export {
     pub name: WithPosition<String>,
     pub desc: WithPosition<ExportDesc>,
@@ -767,7 +1048,7 @@ impl Data {
 
 impl FromBytecode for Data {
     fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
-        println!("Reading data");
+        trace!("Reading data");
         match reader.parse::<u32>()? {
             0 => Data::parse_active(reader, 0),
             1 => Ok(Self::Passive(parse_data_with_pos(reader)?)),
@@ -779,24 +1060,205 @@ impl FromBytecode for Data {
         }
     }
 }
+impl ToBytecode for Data {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        match self {
+            Self::Active { mem_id, expr, data } => {
+                if *mem_id == 0 {
+                    writer.emit(&0u32)?;
+                } else {
+                    writer.emit(&2u32)?;
+                    writer.emit(mem_id)?;
+                }
+                write_const_expr(writer, &expr.data)?;
+                write_bytes(writer, &data.data)
+            }
+            Self::Passive(data) => {
+                writer.emit(&1u32)?;
+                write_bytes(writer, &data.data)
+            }
+        }
+    }
+}
+#[derive(Debug, Clone)]
+pub enum ElementMode {
+    Passive,
+    Active {
+        table_id: usize,
+        expr: WithPosition<Vec<WithPosition<Op>>>,
+    },
+    Declarative,
+}
+
+#[derive(Debug, Clone)]
+pub enum ElementInit {
+    Functions(Vec<WithPosition<usize>>),
+    Expressions(Vec<WithPosition<ConstExpr>>),
+}
+
+//NOTE: The 8 encodings of element segments differ in three bits:
+//bit 0 passive/declarative, bit 1 explicit table id or declarative, bit 2 expressions instead of function ids
+//https://webassembly.github.io/spec/core/binary/modules.html#element-section
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub t: ValueType,
+    pub mode: ElementMode,
+    pub init: ElementInit,
+}
+
+impl Element {
+    fn parse_offset<R: BytecodeReader>(
+        reader: &mut R,
+    ) -> Result<WithPosition<Vec<WithPosition<Op>>>, ParserError> {
+        try_read_with_pos(reader, |r| {
+            iter_const_expr(r).collect::<Result<Vec<_>, _>>()
+        })
+    }
+
+    fn parse_elemkind<R: BytecodeReader>(reader: &mut R) -> Result<ValueType, ParserError> {
+        match reader.read_u8()? {
+            0x00 => Ok(ValueType::Funcref),
+            kind => Err(ParserError::InvalidElemKind(kind)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.init {
+            ElementInit::Functions(ids) => ids.len(),
+            ElementInit::Expressions(exprs) => exprs.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.mode, ElementMode::Active { .. })
+    }
+
+    pub fn is_declarative(&self) -> bool {
+        matches!(self.mode, ElementMode::Declarative)
+    }
+
+    pub fn iter_function_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        let ids = match &self.init {
+            ElementInit::Functions(ids) => Some(ids.iter().map(|id| id.data)),
+            ElementInit::Expressions(_) => None,
+        };
+        ids.into_iter().flatten()
+    }
+}
+
+impl FromBytecode for Element {
+    fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
+        trace!("Reading element segment");
+        let flags = reader.parse::<u32>()?;
+        if flags > 7 {
+            return Err(ParserError::InvalidElementMode(flags));
+        }
+        let has_exprs = flags & 0b100 != 0;
+
+        let mode = match flags & 0b011 {
+            0b000 => ElementMode::Active {
+                table_id: 0,
+                expr: Element::parse_offset(reader)?,
+            },
+            0b010 => {
+                let table_id = reader.parse()?;
+                ElementMode::Active {
+                    table_id,
+                    expr: Element::parse_offset(reader)?,
+                }
+            }
+            0b001 => ElementMode::Passive,
+            _ => ElementMode::Declarative,
+        };
+
+        //NOTE: Segments with implicit table 0 carry neither an elemkind nor a reftype
+        let t = match (flags & 0b011 == 0, has_exprs) {
+            (true, _) => ValueType::Funcref,
+            (false, false) => Element::parse_elemkind(reader)?,
+            (false, true) => reader.parse()?,
+        };
+
+        let init = if has_exprs {
+            ElementInit::Expressions(reader.parse()?)
+        } else {
+            ElementInit::Functions(reader.parse()?)
+        };
+
+        Ok(Self { t, mode, init })
+    }
+}
+
+impl ToBytecode for Element {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        let has_exprs = matches!(self.init, ElementInit::Expressions(_));
+        //NOTE: The short encodings with implicit table 0 are only possible for funcref segments
+        let implicit_table = self.t == ValueType::Funcref
+            && matches!(self.mode, ElementMode::Active { table_id: 0, .. });
+        let mode_flags: u32 = match &self.mode {
+            ElementMode::Active { .. } if implicit_table => 0b000,
+            ElementMode::Active { .. } => 0b010,
+            ElementMode::Passive => 0b001,
+            ElementMode::Declarative => 0b011,
+        };
+        writer.emit(&(mode_flags | if has_exprs { 0b100 } else { 0 }))?;
+
+        if let ElementMode::Active { table_id, expr } = &self.mode {
+            if !implicit_table {
+                writer.emit(table_id)?;
+            }
+            write_const_expr(writer, &expr.data)?;
+        }
+
+        if !implicit_table {
+            if has_exprs {
+                writer.emit(&self.t)?;
+            } else {
+                //elemkind funcref
+                writer.write_u8(0x00)?;
+            }
+        }
+
+        match &self.init {
+            ElementInit::Functions(ids) => writer.emit(ids),
+            ElementInit::Expressions(exprs) => writer.emit(exprs),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Expression {
     data: Vec<WithPosition<Op>>,
 }
 impl Display for Expression {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.data.iter().map(|op| op.data).format("\n"))
+        write!(f, "{}", self.data.iter().map(|op| &op.data).format("\n"))
     }
 }
 impl FromBytecode for Expression {
     fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
-        println!("Reading expression...");
+        trace!("Reading expression...");
         Ok(Self {
             data: iter_expr(reader).collect::<Result<Vec<_>, _>>()?,
         })
     }
 }
 
+impl Expression {
+    pub fn ops(&self) -> &[WithPosition<Op>] {
+        &self.data
+    }
+}
+
+impl ToBytecode for Expression {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        self.data.iter().try_for_each(|op| writer.emit(op))
+    }
+}
+
 #[derive(FromBytecode, Debug, Clone)]
 pub struct Function {
     pub size: usize,
@@ -804,6 +1266,15 @@ pub struct Function {
     pub code: WithPosition<Expression>,
 }
 
+//NOTE: `size` is not written as is, the code might have been modified since it was parsed
+impl ToBytecode for Function {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        let mut body = self.locals.to_bytes()?;
+        self.code.to_writer(&mut body)?;
+        write_bytes(writer, &body)
+    }
+}
+
 impl Display for Function {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Locals\n")?;
@@ -851,7 +1322,7 @@ impl Function {
         let op = self.get_op(ip as usize)?;
         let jmp = op.get_jmp()?;
         let next = (ip + jmp) + offset;
-        println!("jmp: {}", next);
+        trace!("jmp: {}", next);
         let op = self.get_op(next as usize)?;
 
         Some((op, next))
@@ -883,6 +1354,13 @@ impl CustomSection {
     }
 }
 
+impl ToBytecode for CustomSection {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        writer.emit(&self.name)?;
+        Ok(writer.write_all(&self.data.data)?)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum SectionId {
     Type = 1,
@@ -893,6 +1371,7 @@ pub enum SectionId {
     Global = 6,
     Export = 7,
     Start = 8,
+    Element = 9,
     Code = 10,
     Data = 11,
     DataCount = 12,
@@ -901,12 +1380,13 @@ pub enum SectionId {
 pub type Types = Vec<WithPosition<Type>>;
 pub type Imports = Vec<WithPosition<Import>>;
 pub type Functions = Vec<WithPosition<usize>>;
-pub type Tables = Vec<WithPosition<Limits>>;
+pub type Tables = Vec<WithPosition<TableType>>;
 pub type Memories = Vec<WithPosition<Limits>>;
 pub type Globals = Vec<WithPosition<Global>>;
 pub type Exports = Vec<WithPosition<Export>>;
 pub type Start = u32;
 pub type DataCount = u32;
+pub type Elements = Vec<WithPosition<Element>>;
 pub type Code = Vec<WithPosition<Function>>;
 pub type ModuleData = Vec<WithPosition<Data>>;
 
@@ -920,36 +1400,49 @@ pub enum SectionData {
     Global(Globals),
     Export(Exports),
     Start(Start),
+    Element(Elements),
     Code(Code),
     Data(ModuleData),
     DataCount(DataCount),
 }
 macro_rules! impl_match_sec_data {
-    ($reader:ident, $id:ident, $($case:literal => $section_type:path), + $(,)?) => {
+    ($reader:ident, $id:ident, $($case:literal => $section_type:path as $parse:path), + $(,)?) => {
         match $id {
-            $($case => Ok($section_type($reader.parse()?))),+,
+            $($case => Ok($section_type($parse($reader)?))),+,
             num => Err(ParserError::InvalidSectionId(num))
         }
     };
 }
 
+/// Parses the items of a section, errors are annotated with the index of the failing item
+fn parse_section_items<R: BytecodeReader, T: FromBytecode>(
+    reader: &mut R,
+) -> Result<Vec<WithPosition<T>>, ParserError> {
+    let count = Leb::read_u32(reader)?;
+    (0..count as usize)
+        .map(|i| {
+            parse_with_pos(reader).map_err(|e| e.locate(reader, |l| _ = l.item.get_or_insert(i)))
+        })
+        .collect()
+}
+
 impl SectionData {
     pub fn init<R: BytecodeReader>(reader: &mut R, id: u8) -> Result<Self, ParserError> {
         impl_match_sec_data! {
             reader,
             id,
-            0x01 => Self::Type,
-            0x02 => Self::Import,
-            0x03 => Self::Function,
-            0x04 => Self::Table,
-            0x05 => Self::Memory,
-            0x06 => Self::Global,
-            0x07 => Self::Export,
-            0x08 => Self::Start,
-            0x09 => Self::DataCount,
-            0x0A => Self::Code,
-            0x0B => Self::Data,
-            0x0C => Self::DataCount,
+            0x01 => Self::Type as parse_section_items,
+            0x02 => Self::Import as parse_section_items,
+            0x03 => Self::Function as parse_section_items,
+            0x04 => Self::Table as parse_section_items,
+            0x05 => Self::Memory as parse_section_items,
+            0x06 => Self::Global as parse_section_items,
+            0x07 => Self::Export as parse_section_items,
+            0x08 => Self::Start as R::parse,
+            0x09 => Self::Element as parse_section_items,
+            0x0A => Self::Code as parse_section_items,
+            0x0B => Self::Data as parse_section_items,
+            0x0C => Self::DataCount as R::parse,
         }
     }
 }
@@ -992,14 +1485,19 @@ pub enum SectionDataOrCustom {
 impl FromBytecode for Section {
     fn from_reader<R: BytecodeReader>(reader: &mut R) -> Result<Self, ParserError> {
         let id = reader.read_u8()?;
-        let size = reader.parse::<usize>()?;
-        match id {
-            0x00 => Section::new_custom(reader, id, size),
-            _ => {
-                let data = try_read_with_pos(reader, |r| SectionData::init(r, id))?;
-                Ok(Section::new_section(id, size, data))
+        //NOTE: Errors after the section id are located, so that a truncated section is not
+        //mistaken for the end of the module
+        let section = (|| {
+            let size = reader.parse::<usize>()?;
+            match id {
+                0x00 => Section::new_custom(reader, id, size),
+                _ => {
+                    let data = try_read_with_pos(reader, |r| SectionData::init(r, id))?;
+                    Ok(Section::new_section(id, size, data))
+                }
             }
-        }
+        })();
+        section.map_err(|e| e.locate(reader, |l| _ = l.section.get_or_insert(id)))
     }
 }
 
@@ -1020,7 +1518,7 @@ pub fn iter_sections(
 #[derive(Debug, Default)]
 pub struct SortedImports {
     pub functions: Vec<(usize, usize)>,
-    pub tables: Vec<(usize, Limits)>,
+    pub tables: Vec<(usize, TableType)>,
     pub mems: Vec<(usize, Limits)>,
     pub globals: Vec<(usize, GlobalType)>,
 }
@@ -1028,7 +1526,7 @@ impl SortedImports {
     pub fn add(&mut self, import: &Import, id: usize) {
         match &import.desc.data {
             ImportDesc::TypeIdx(t_id) => self.functions.push((id, *t_id)),
-            ImportDesc::TableType(limits) => self.tables.push((id, limits.clone())),
+            ImportDesc::TableType(table_type) => self.tables.push((id, table_type.clone())),
             ImportDesc::MemType(limits) => self.mems.push((id, limits.clone())),
             ImportDesc::GlobalType(gt) => self.globals.push((id, gt.clone())),
         }
@@ -1047,6 +1545,7 @@ pub struct Bytecode {
     pub globals: MaybeAt<Globals>,
     pub exports: MaybeAt<Exports>,
     pub start: MaybeAt<Start>,
+    pub elements: MaybeAt<Elements>,
     pub data_count: MaybeAt<DataCount>,
     pub code: MaybeAt<Code>,
     pub data: MaybeAt<ModuleData>,
@@ -1060,7 +1559,7 @@ macro_rules! impl_add_section {
     };
 }
 
-//TODO: (joh): Finde einen besseren Weg um mit duplizierten Namen umzugehen
+//NOTE: Export names are unique in a validated module
 pub struct ExportMap<'src>(HashMap<&'src str, ExportDesc>);
 
 impl ExportMap<'_> {
@@ -1076,7 +1575,6 @@ impl Bytecode {
         self.iter_exports().map(|exports| {
             let mut result = HashMap::new();
             exports.for_each(|e| {
-                //TODO: Handle doppelte Namen
                 _ = result.insert(e.name.data.as_str(), e.desc.data.clone());
             });
             ExportMap(result)
@@ -1093,6 +1591,7 @@ impl Bytecode {
                 SectionData::Global => self.globals,
                 SectionData::Export => self.exports,
                 SectionData::Start => self.start,
+                SectionData::Element => self.elements,
                 SectionData::DataCount => self.data_count,
                 SectionData::Code => self.code,
                 SectionData::Data => self.data,
@@ -1144,6 +1643,56 @@ impl FromBytecode for Bytecode {
     }
 }
 
+impl ToBytecode for Bytecode {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        writer.emit(&self.header)?;
+
+        //NOTE: Custom sections may appear anywhere, they are placed in front of the first
+        //section that followed them in the parsed module. Everything else goes to the end.
+        let mut custom_sections = self
+            .custom_sections
+            .iter()
+            .sorted_by_key(|c| c.position.start)
+            .peekable();
+
+        macro_rules! write_sections {
+            ($($field:ident => $id:expr),+ $(,)?) => {
+                $(if let Some(section) = &self.$field {
+                    while let Some(custom) =
+                        custom_sections.next_if(|c| c.position.start < section.position.start)
+                    {
+                        write_section(writer, 0x00, &custom.data)?;
+                    }
+                    write_section(writer, $id as u8, &section.data)?;
+                })+
+            };
+        }
+        //NOTE: DataCount has to precede the code section, even though its id is higher
+        write_sections! {
+            types => SectionId::Type,
+            imports => SectionId::Import,
+            functions => SectionId::Function,
+            tables => SectionId::Table,
+            memories => SectionId::Memory,
+            globals => SectionId::Global,
+            exports => SectionId::Export,
+            start => SectionId::Start,
+            elements => SectionId::Element,
+            data_count => SectionId::DataCount,
+            code => SectionId::Code,
+            data => SectionId::Data,
+        }
+        custom_sections.try_for_each(|custom| write_section(writer, 0x00, &custom.data))
+    }
+}
+
+impl Bytecode {
+    /// Encodes the module back to its binary format
+    pub fn encode(&self) -> Result<Vec<u8>, ParserError> {
+        self.to_bytes()
+    }
+}
+
 macro_rules! impl_bytecode_vec_accessor {
     ($($name:ident, $pos_name:ident, $field:ident=> $res_type: ty),+$(,)?) => {
         impl Bytecode {
@@ -1170,10 +1719,11 @@ impl_bytecode_vec_accessor! {
     get_type, get_type_pos, types => &Type,
     get_import, get_import_pos, imports => &Import,
     get_function, get_function_pos, functions => &usize,
-    get_table, get_table_pos, tables => &Limits,
+    get_table, get_table_pos, tables => &TableType,
     get_memory, get_memory_pos, memories => &Limits,
     get_global, get_global_pos, globals => &Global,
     get_export, get_export_pos, exports => &Export,
+    get_element, get_element_pos, elements => &Element,
     get_code, get_code_pos, code => &Function,
     get_data, get_data_pos, data => &Data,
 }
@@ -1181,10 +1731,11 @@ impl_bytecode_iter! {
     iter_types, types => Type,
     iter_imports, imports => Import,
     iter_functions, functions => usize,
-    iter_tables, tables => Limits,
+    iter_tables, tables => TableType,
     iter_memories, memories => Limits,
     iter_globals, globals => Global,
     iter_exports,  exports => Export,
+    iter_elements, elements => Element,
     iter_code, code => Function,
     iter_data, data => Data,
 }
@@ -1237,9 +1788,18 @@ pub fn is_wasm_bytecode(reader: &mut impl BytecodeReader) -> Result<bool, Parser
 }
 #[cfg(test)]
 mod tests {
-    use crate::reader::{ValueType, parse_wat};
+    use std::io::Cursor;
+
+    use crate::op::Op;
+    use crate::reader::{ValueType, parse_binary, parse_wat};
+
+    use super::{Data, ElementInit, ElementMode, ParserError};
 
-    use super::{Data, ParserError};
+    const HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn parse_bytes(sections: &[u8]) -> Result<super::Bytecode, ParserError> {
+        parse_binary(&mut Cursor::new([HEADER, sections].concat()))
+    }
 
     #[test]
     fn empty_module() -> Result<(), ParserError> {
@@ -1254,6 +1814,7 @@ mod tests {
         assert!(module.globals.is_none());
         assert!(module.exports.is_none());
         assert!(module.start.is_none());
+        assert!(module.elements.is_none());
         assert!(module.data_count.is_none());
         assert!(module.code.is_none());
         assert!(module.data.is_none());
@@ -1288,7 +1849,7 @@ mod tests {
             .unwrap()
             .iter_locals()
             .collect::<Vec<_>>();
-        assert_eq!(*locals.get(0).unwrap(), ValueType::F64);
+        assert_eq!(*locals.first().unwrap(), ValueType::F64);
 
         Ok(())
     }
@@ -1332,4 +1893,115 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn float_consts() -> Result<(), ParserError> {
+        let src = r#"
+            (module
+                (func (result f64)
+                    f32.const -1.5
+                    f64.promote_f32
+                    f64.const 0x1p-1074
+                    f64.add
+                )
+            )
+        "#;
+        let module = parse_wat(src)?;
+        let code = module.get_code(0).unwrap();
+        assert_eq!(*code.get_op(0).unwrap(), Op::F32Const(-1.5));
+        assert_eq!(*code.get_op(1).unwrap(), Op::F64PromoteF32);
+        assert_eq!(*code.get_op(2).unwrap(), Op::F64Const(f64::from_bits(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn element_segments() -> Result<(), ParserError> {
+        let src = r#"
+            (module
+                (type $t (func))
+                (table $a 2 funcref)
+                (table $b 1 10 externref)
+                (func $f)
+                (elem (i32.const 1) $f)
+                (elem funcref (ref.func $f) (ref.null func))
+                (elem declare func $f)
+                (func
+                    i32.const 0
+                    call_indirect $a (type $t)
+                )
+            )
+        "#;
+        let module = parse_wat(src)?;
+        let table = module.get_table(1).unwrap();
+        assert_eq!(table.value_type(), ValueType::Externref);
+        assert_eq!(table.limits.data.max.as_ref().unwrap().data, 10);
+
+        let active = module.get_element(0).unwrap();
+        assert!(active.is_active());
+        assert_eq!(active.iter_function_ids().collect::<Vec<_>>(), vec![0]);
+
+        let passive = module.get_element(1).unwrap();
+        assert!(matches!(passive.mode, ElementMode::Passive));
+        assert!(matches!(passive.init, ElementInit::Expressions(_)));
+        assert_eq!(passive.len(), 2);
+
+        assert!(module.get_element(2).unwrap().is_declarative());
+
+        let code = module.get_code(1).unwrap();
+        assert_eq!(
+            *code.get_op(1).unwrap(),
+            Op::CallIndirect {
+                type_id: 0,
+                table: 0
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn error_location_in_section_item() {
+        //NOTE: The second type has an invalid parameter type 0x7A
+        let err =
+            parse_bytes(&[0x01, 0x08, 0x02, 0x60, 0x00, 0x00, 0x60, 0x01, 0x7A, 0x00]).unwrap_err();
+        assert!(matches!(err.cause(), ParserError::InvalidValueTypeId(0x7A)));
+        let location = err.location().unwrap();
+        assert_eq!(location.offset, 17);
+        assert_eq!(location.section, Some(1));
+        assert_eq!(location.item, Some(1));
+        assert_eq!(location.instruction, None);
+        assert_eq!(location.bytes_start, 9);
+        assert_eq!(
+            location.bytes,
+            [0x08, 0x02, 0x60, 0x00, 0x00, 0x60, 0x01, 0x7A, 0x00]
+        );
+        assert!(err.to_string().starts_with(
+            "Error at offset 0x11 in type section, type 1 [08 02 60 00 00 60 01 7a | 00]"
+        ));
+    }
+
+    #[test]
+    fn error_location_in_function_body() {
+        let err = parse_bytes(&[
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section
+            0x03, 0x02, 0x01, 0x00, // function section
+            0x0A, 0x09, 0x01, 0x07, 0x00, 0x41, 0x00, 0x1A, 0x01, 0xFF, 0x0B, // code section
+        ])
+        .unwrap_err();
+        assert!(matches!(err.cause(), ParserError::InvalidOpcode(0xFF)));
+        let location = err.location().unwrap();
+        assert_eq!(location.section, Some(10));
+        assert_eq!(location.item, Some(0));
+        assert_eq!(location.instruction, Some(3));
+        assert!(
+            err.to_string()
+                .contains("in code section, function body 0, instruction 3")
+        );
+    }
+
+    #[test]
+    fn truncated_section_is_an_error() {
+        let err = parse_bytes(&[0x01, 0x04, 0x01, 0x60]).unwrap_err();
+        assert!(matches!(err.cause(), ParserError::Leb(_)));
+        assert_eq!(err.location().unwrap().section, Some(1));
+    }
 }
@@ -0,0 +1,274 @@
+use std::io::Write;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use crate::{
+    leb::Leb,
+    op::Op,
+    reader::{ParserError, WithPosition},
+};
+
+pub trait BytecodeWriter: Write + Sized {
+    fn emit<T: ToBytecode + ?Sized>(&mut self, value: &T) -> Result<(), ParserError> {
+        value.to_writer(self)
+    }
+}
+impl<T: Write> BytecodeWriter for T {}
+
+/// Counterpart to [`crate::reader::FromBytecode`]: writes a value in its binary encoding.
+pub trait ToBytecode {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError>;
+
+    fn to_bytes(&self) -> Result<Vec<u8>, ParserError> {
+        let mut buffer = Vec::new();
+        self.to_writer(&mut buffer)?;
+        Ok(buffer)
+    }
+}
+
+impl<T: ToBytecode> ToBytecode for Vec<T> {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        self.as_slice().to_writer(writer)
+    }
+}
+impl<T: ToBytecode> ToBytecode for [T] {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Leb::write_u32(writer, self.len() as u32)?;
+        self.iter().try_for_each(|v| v.to_writer(writer))
+    }
+}
+impl<T: ToBytecode> ToBytecode for WithPosition<T> {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        self.data.to_writer(writer)
+    }
+}
+
+impl ToBytecode for i32 {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(Leb::write_i32(writer, *self)?)
+    }
+}
+impl ToBytecode for i64 {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(Leb::write_i64(writer, *self)?)
+    }
+}
+impl ToBytecode for u32 {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(Leb::write_u32(writer, *self)?)
+    }
+}
+impl ToBytecode for u64 {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(Leb::write_u64(writer, *self)?)
+    }
+}
+impl ToBytecode for f32 {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(writer.write_f32::<LittleEndian>(*self)?)
+    }
+}
+impl ToBytecode for f64 {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(writer.write_f64::<LittleEndian>(*self)?)
+    }
+}
+impl ToBytecode for usize {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(Leb::write_u32(writer, *self as u32)?)
+    }
+}
+impl ToBytecode for isize {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(Leb::write_u32(writer, *self as u32)?)
+    }
+}
+impl ToBytecode for bool {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        Ok(writer.write_u8(*self as u8)?)
+    }
+}
+impl ToBytecode for String {
+    fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+        write_bytes(writer, self.as_bytes())
+    }
+}
+
+/// Writes a length prefixed byte vector
+pub fn write_bytes<W: BytecodeWriter>(writer: &mut W, bytes: &[u8]) -> Result<(), ParserError> {
+    Leb::write_u32(writer, bytes.len() as u32)?;
+    Ok(writer.write_all(bytes)?)
+}
+
+//NOTE: The parser drops the `end` of constant expressions, so it has to be added back here
+pub fn write_const_expr<W: BytecodeWriter>(
+    writer: &mut W,
+    expr: &[WithPosition<Op>],
+) -> Result<(), ParserError> {
+    expr.iter().try_for_each(|op| op.to_writer(writer))?;
+    writer.emit(&Op::End(false))
+}
+
+/// Writes a section header followed by the section content.
+/// The content has to be buffered since the header contains its size.
+pub fn write_section<W: BytecodeWriter, T: ToBytecode + ?Sized>(
+    writer: &mut W,
+    id: u8,
+    content: &T,
+) -> Result<(), ParserError> {
+    let buffer = content.to_bytes()?;
+    writer.write_u8(id)?;
+    write_bytes(writer, &buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{
+        leb::Leb,
+        reader::{Bytecode, ParserError, parse_binary},
+    };
+
+    fn round_trip(src: &str) -> Result<(), ParserError> {
+        let binary = wat::parse_str(src)?;
+        let module = parse_binary(&mut Cursor::new(binary.clone()))?;
+        let encoded = module.encode()?;
+        assert_eq!(encoded, binary);
+        Ok(())
+    }
+
+    #[test]
+    fn leb_round_trip() -> Result<(), ParserError> {
+        let unsigned = [0, 1, 63, 64, 127, 128, 624485, u32::MAX];
+        for value in unsigned {
+            let mut buffer = Vec::new();
+            Leb::write_u32(&mut buffer, value)?;
+            assert_eq!(Leb::read_u32(&mut Cursor::new(buffer))?, value);
+        }
+        let signed = [0, 1, -1, 63, 64, -64, -65, -123456, i64::MIN, i64::MAX];
+        for value in signed {
+            let mut buffer = Vec::new();
+            Leb::write_i64(&mut buffer, value)?;
+            assert_eq!(Leb::read_i64(&mut Cursor::new(buffer))?, value);
+        }
+        let mut buffer = Vec::new();
+        Leb::write_i32(&mut buffer, -123456)?;
+        assert_eq!(buffer, [0xC0, 0xBB, 0x78]);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_module() -> Result<(), ParserError> {
+        let module = Bytecode::default();
+        assert_eq!(module.encode()?, b"\0asm\x01\0\0\0");
+        round_trip("(module)")
+    }
+
+    #[test]
+    fn functions_and_control_flow() -> Result<(), ParserError> {
+        round_trip(
+            r#"
+            (module
+                (type $sig (func (param i32) (result i32)))
+                (func $fac (export "fac") (type $sig) (local i64 i64) (local f32)
+                    (if (result i32) (i32.eqz (local.get 0))
+                        (then (i32.const 1))
+                        (else
+                            (i32.mul
+                                (local.get 0)
+                                (call $fac (i32.sub (local.get 0) (i32.const 1)))
+                            )
+                        )
+                    )
+                )
+                (func (param i32) (result i32)
+                    (block $a (result i32)
+                        (loop $b
+                            (br_if $b (i32.const 0))
+                            (br_table $a $a (i32.const 7) (local.get 0))
+                        )
+                        (i32.const -1)
+                    )
+                )
+                (func (result i64 f64)
+                    i64.const -9223372036854775808
+                    f64.const nan:0x4000000000000
+                    (select (result i32) (i32.const 1) (i32.const 2) (i32.const 0))
+                    drop
+                )
+                (func (param i32) (param i32) (result i32)
+                    (block (param i32) (result i32) (local.get 1) (i32.add))
+                    (local.get 0)
+                    (select)
+                )
+            )
+            "#,
+        )
+    }
+
+    #[test]
+    fn all_sections() -> Result<(), ParserError> {
+        round_trip(
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (import "env" "g" (global $imported i32))
+                (import "env" "t" (table 1 funcref))
+                (memory (export "mem") 1 2)
+                (global $g (mut i64) (i64.const 42))
+                (global f32 (f32.const 1.5))
+                (table $t2 2 10 externref)
+                (elem (i32.const 0) $f)
+                (elem $passive funcref (ref.func $f) (ref.null func))
+                (elem declare func $f)
+                (elem (table $t2) (i32.const 1) externref (ref.null extern))
+                (data (i32.const 8) "hello")
+                (data $d "world")
+                (func $f (export "f")
+                    (call $log (i32.load offset=8 align=2 (i32.const 0)))
+                    (i64.store8 (i32.const 0) (global.get $g))
+                    (memory.init $d (i32.const 0) (i32.const 0) (i32.const 5))
+                    (table.init 0 $passive (i32.const 0) (i32.const 0) (i32.const 1))
+                    (elem.drop $passive)
+                    (drop (table.grow $t2 (ref.null extern) (i32.const 1)))
+                    (drop (memory.grow (i32.const 1)))
+                    (drop (i32.trunc_sat_f64_u (f64.const 1)))
+                )
+                (start $f)
+            )
+            "#,
+        )
+    }
+
+    #[test]
+    fn custom_sections() -> Result<(), ParserError> {
+        round_trip(
+            r#"
+            (module
+                (@custom "first" (before first) "\01\02")
+                (type (func))
+                (@custom "between" (after type) "abc")
+                (func (type 0))
+                (@custom "last" "")
+            )
+            "#,
+        )
+    }
+
+    #[test]
+    fn modified_function() -> Result<(), ParserError> {
+        let mut module = parse_binary(&mut Cursor::new(wat::parse_str(
+            r#"(module (func (result i32) (i32.const 1)))"#,
+        )?))?;
+        let func = module.iter_code_mut().unwrap().next().unwrap();
+        *func.get_op_mut(0).unwrap() = crate::op::Op::I32Const(100_000);
+
+        let module = parse_binary(&mut Cursor::new(module.encode()?))?;
+        assert_eq!(
+            *module.get_code(0).unwrap().get_op(0).unwrap(),
+            crate::op::Op::I32Const(100_000)
+        );
+        Ok(())
+    }
+}
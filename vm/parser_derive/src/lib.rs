@@ -1,15 +1,15 @@
 use proc_macro2::TokenStream;
 use quote::{quote, quote_spanned};
 use syn::{
-    Data, DeriveInput, GenericParam, Generics, parse_macro_input, parse_quote, spanned::Spanned,
-    token::Token,
+    Data, DeriveInput, GenericParam, Generics, TypeParamBound, parse_macro_input, parse_quote,
+    spanned::Spanned, token::Token,
 };
 
 #[proc_macro_derive(FromBytecode)]
 pub fn derive_from_bytecode(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let name = input.ident;
-    let generics = add_trait_bounds(input.generics);
+    let generics = add_trait_bounds(input.generics, parse_quote!(FromBytecode));
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let struct_impl = from_reader(&input.data);
     let expanded = quote! {
@@ -22,10 +22,27 @@ pub fn derive_from_bytecode(input: proc_macro::TokenStream) -> proc_macro::Token
     proc_macro::TokenStream::from(expanded)
 }
 
-fn add_trait_bounds(mut generics: Generics) -> Generics {
+#[proc_macro_derive(ToBytecode)]
+pub fn derive_to_bytecode(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = input.ident;
+    let generics = add_trait_bounds(input.generics, parse_quote!(ToBytecode));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let struct_impl = to_writer(&input.data);
+    let expanded = quote! {
+        impl #impl_generics ToBytecode for #name #ty_generics #where_clause {
+            fn to_writer<W: BytecodeWriter>(&self, writer: &mut W) -> Result<(), ParserError> {
+                #struct_impl
+            }
+        }
+    };
+    proc_macro::TokenStream::from(expanded)
+}
+
+fn add_trait_bounds(mut generics: Generics, bound: TypeParamBound) -> Generics {
     for param in &mut generics.params {
         if let GenericParam::Type(ref mut type_param) = *param {
-            type_param.bounds.push(parse_quote!(FromBytecode));
+            type_param.bounds.push(bound.clone());
         }
     }
     generics
@@ -43,9 +60,48 @@ fn from_reader(data: &Data) -> TokenStream {
                     Ok(Self {#(#recurse,)*})
                 }
             }
-            syn::Fields::Unnamed(_) => todo!(),
-            syn::Fields::Unit => todo!(),
+            fields => unsupported(
+                fields,
+                "FromBytecode can only be derived for structs with named fields",
+            ),
         },
-        Data::Enum(_) | Data::Union(_) => unimplemented!(),
+        Data::Enum(ref data) => {
+            unsupported(data.enum_token, "FromBytecode cannot be derived for enums")
+        }
+        Data::Union(ref data) => unsupported(
+            data.union_token,
+            "FromBytecode cannot be derived for unions",
+        ),
     }
 }
+
+fn to_writer(data: &Data) -> TokenStream {
+    match *data {
+        Data::Struct(ref data) => match &data.fields {
+            syn::Fields::Named(fields) => {
+                let recurse = fields.named.iter().map(|f| {
+                    let name = &f.ident;
+                    quote! {ToBytecode::to_writer(&self.#name, writer)?}
+                });
+                quote! {
+                    #(#recurse;)*
+                    Ok(())
+                }
+            }
+            fields => unsupported(
+                fields,
+                "ToBytecode can only be derived for structs with named fields",
+            ),
+        },
+        Data::Enum(ref data) => {
+            unsupported(data.enum_token, "ToBytecode cannot be derived for enums")
+        }
+        Data::Union(ref data) => {
+            unsupported(data.union_token, "ToBytecode cannot be derived for unions")
+        }
+    }
+}
+
+fn unsupported(tokens: impl quote::ToTokens, message: &str) -> TokenStream {
+    syn::Error::new_spanned(tokens, message).to_compile_error()
+}
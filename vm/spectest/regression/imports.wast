@@ -0,0 +1,42 @@
+;; Imports from the spectest host module and start functions
+
+(module
+  (import "spectest" "print_i32" (func $print_i32 (param i32)))
+  (import "spectest" "global_i32" (global $g i32))
+  (global $counter (mut i32) (i32.const 0))
+  (global (export "exported") i32 (i32.const 42))
+
+  (func $init
+    (call $print_i32 (global.get $g))
+    (global.set $counter (i32.const 7))
+  )
+  (start $init)
+
+  (func (export "counter") (result i32) (global.get $counter))
+  (func (export "imported") (result i32) (global.get $g))
+  (func (export "bump") (result i32)
+    (global.set $counter (i32.add (global.get $counter) (i32.const 1)))
+    (global.get $counter)
+  )
+)
+
+(assert_return (invoke "counter") (i32.const 7))
+(assert_return (invoke "imported") (i32.const 666))
+(assert_return (get "exported") (i32.const 42))
+(invoke "bump")
+(assert_return (invoke "bump") (i32.const 9))
+
+(module $other
+  (func (export "answer") (result i32) (i32.const 42))
+)
+(register "other" $other)
+(assert_return (invoke $other "answer") (i32.const 42))
+
+(assert_unlinkable
+  (module (import "spectest" "unknown" (func)))
+  "unknown import"
+)
+(assert_invalid
+  (module (import "spectest" "global_i32" (global $g i32)) (func (global.set $g (i32.const 0))))
+  "immutable global"
+)
@@ -0,0 +1,46 @@
+use interpreter::{
+    linker::{Caller, LinkError, Linker},
+    memory::MemoryInstance,
+    slow_vm::LocalValue,
+    table::TableInstance,
+};
+use parser::reader::ValueType;
+
+type Spectest<'a> = Caller<'a, ()>;
+
+fn define_spectest(linker: &mut Linker<()>) -> Result<(), LinkError> {
+    use LocalValue::*;
+    //NOTE: The print functions have no observable effect on the test results
+    linker.func_wrap("spectest", "print", |_: Spectest| {})?;
+    linker.func_wrap("spectest", "print_i32", |_: Spectest, _: i32| {})?;
+    linker.func_wrap("spectest", "print_i64", |_: Spectest, _: i64| {})?;
+    linker.func_wrap("spectest", "print_f32", |_: Spectest, _: f32| {})?;
+    linker.func_wrap("spectest", "print_f64", |_: Spectest, _: f64| {})?;
+    linker.func_wrap(
+        "spectest",
+        "print_i32_f32",
+        |_: Spectest, _: i32, _: f32| {},
+    )?;
+    linker.func_wrap(
+        "spectest",
+        "print_f64_f64",
+        |_: Spectest, _: f64, _: f64| {},
+    )?;
+    linker.global("spectest", "global_i32", I32(666), false)?;
+    linker.global("spectest", "global_i64", I64(666), false)?;
+    linker.global("spectest", "global_f32", F32(666.6), false)?;
+    linker.global("spectest", "global_f64", F64(666.6), false)?;
+    let table = TableInstance::with_size(ValueType::Funcref, 10, Some(20));
+    linker.table("spectest", "table", table)?;
+    linker.memory("spectest", "memory", MemoryInstance::with_pages(1, Some(2)))?;
+    Ok(())
+}
+
+/// The `spectest` host module every spec test script may import from, the modules of a script
+/// are linked against it and against each other.
+/// See: https://github.com/WebAssembly/spec/tree/main/interpreter#spectest-host-module
+pub fn spectest_linker() -> Linker<()> {
+    let mut linker = Linker::new();
+    define_spectest(&mut linker).unwrap();
+    linker
+}
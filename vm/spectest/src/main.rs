@@ -0,0 +1,79 @@
+pub mod env;
+pub mod runner;
+
+use std::{path::PathBuf, process::ExitCode};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use colored::Colorize;
+
+use crate::runner::{WastRunner, collect_wast_files, run_file_with};
+
+/// Runs WebAssembly spec test scripts (.wast) against the interpreter
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Files or directories to run, defaults to the vendored testsuite and the regression scripts
+    paths: Vec<PathBuf>,
+    /// Print every failed directive
+    #[arg(short, long)]
+    verbose: bool,
+    /// Run the tests on the fast interpreter tier
+    #[arg(long)]
+    fast: bool,
+}
+
+fn main() -> Result<ExitCode> {
+    let args = Args::parse();
+    let paths = if args.paths.is_empty() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        vec![dir.join("testsuite"), dir.join("regression")]
+    } else {
+        args.paths
+    };
+    let files = collect_wast_files(&paths).context("Unable to collect .wast files")?;
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for file in files {
+        let runner = match args.fast {
+            true => WastRunner::default().with_fast_vm(),
+            false => WastRunner::default(),
+        };
+        let report = run_file_with(runner, &file);
+        let name = file.display();
+        if let Some(error) = &report.error {
+            println!("{}: {}", name, format!("error: {error}").red());
+            failed += 1;
+            continue;
+        }
+        let summary = format!(
+            "{} passed, {} failed, {} skipped",
+            report.passed(),
+            report.failed(),
+            report.skipped()
+        );
+        if report.is_ok() {
+            println!("{}: {}", name, summary.green());
+        } else {
+            println!("{}: {}", name, summary.red());
+        }
+        if args.verbose {
+            for (result, reason) in report.iter_failures() {
+                println!(
+                    "    {}:{} {}: {}",
+                    result.line, result.col, result.kind, reason
+                );
+            }
+        }
+        passed += report.passed();
+        failed += report.failed();
+        skipped += report.skipped();
+    }
+
+    println!("total: {passed} passed, {failed} failed, {skipped} skipped");
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
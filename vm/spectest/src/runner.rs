@@ -0,0 +1,714 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs,
+    io::Cursor,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use interpreter::{
+    linker::LinkError,
+    slow_vm::{ExecutionError, InstanceError, LocalValue, RuntimeError},
+    store::Store,
+};
+use itertools::Itertools;
+use parser::reader::parse_binary;
+use validator::validator::{ValidateResult, valiadate_and_patch_bytecode};
+use wast::{
+    QuoteWat, Wast, WastArg, WastDirective, WastExecute, WastInvoke, WastRet,
+    core::{AbstractHeapType, HeapType, NanPattern, WastArgCore, WastRetCore},
+    parser::{ParseBuffer, parse},
+    token::Id,
+};
+
+use crate::env::spectest_linker;
+
+#[derive(Debug)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+#[derive(Debug)]
+pub struct DirectiveResult {
+    pub line: usize,
+    pub col: usize,
+    pub kind: &'static str,
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Default)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub results: Vec<DirectiveResult>,
+    /// Set if the script itself could not be read or parsed
+    pub error: Option<String>,
+}
+
+impl FileReport {
+    fn count(&self, f: impl Fn(&Outcome) -> bool) -> usize {
+        self.results.iter().filter(|r| f(&r.outcome)).count()
+    }
+    pub fn passed(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Passed))
+    }
+    pub fn failed(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Failed(_)))
+    }
+    pub fn skipped(&self) -> usize {
+        self.count(|o| matches!(o, Outcome::Skipped(_)))
+    }
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.failed() == 0
+    }
+    pub fn iter_failures(&self) -> impl Iterator<Item = (&DirectiveResult, &str)> {
+        self.results.iter().filter_map(|r| match &r.outcome {
+            Outcome::Failed(reason) => Some((r, reason.as_str())),
+            _ => None,
+        })
+    }
+}
+
+/// The stage at which a module was rejected
+#[derive(Debug)]
+enum ModuleError {
+    Malformed(String),
+    Invalid(String),
+    Unlinkable(String),
+    Instantiation(LinkError),
+}
+
+impl Display for ModuleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleError::Malformed(e) => write!(f, "malformed module: {e}"),
+            ModuleError::Invalid(e) => write!(f, "invalid module: {e}"),
+            ModuleError::Unlinkable(e) => write!(f, "unlinkable module: {e}"),
+            ModuleError::Instantiation(e) => write!(f, "unable to instantiate module: {e}"),
+        }
+    }
+}
+
+impl From<LinkError> for ModuleError {
+    fn from(e: LinkError) -> Self {
+        match e {
+            LinkError::Instantiation(_) => ModuleError::Instantiation(e),
+            e => ModuleError::Unlinkable(e.to_string()),
+        }
+    }
+}
+
+pub struct WastRunner {
+    /// Holds the instances, `register` makes their exports importable by later modules
+    store: Store<()>,
+    /// Store names of the instantiated modules
+    instances: Vec<String>,
+    named: HashMap<String, usize>,
+    /// Number of modules given to the store, used to name them
+    modules: usize,
+}
+
+impl Default for WastRunner {
+    fn default() -> Self {
+        Self {
+            store: Store::new(spectest_linker(), ()),
+            instances: vec![],
+            named: HashMap::new(),
+            modules: 0,
+        }
+    }
+}
+
+fn directive_kind(directive: &WastDirective) -> &'static str {
+    match directive {
+        WastDirective::Module(_) => "module",
+        WastDirective::ModuleDefinition(_) => "module definition",
+        WastDirective::ModuleInstance { .. } => "module instance",
+        WastDirective::AssertMalformed { .. } => "assert_malformed",
+        WastDirective::AssertInvalid { .. } => "assert_invalid",
+        WastDirective::Register { .. } => "register",
+        WastDirective::Invoke(_) => "invoke",
+        WastDirective::AssertTrap { .. } => "assert_trap",
+        WastDirective::AssertReturn { .. } => "assert_return",
+        WastDirective::AssertExhaustion { .. } => "assert_exhaustion",
+        WastDirective::AssertUnlinkable { .. } => "assert_unlinkable",
+        WastDirective::AssertException { .. } => "assert_exception",
+        WastDirective::AssertSuspension { .. } => "assert_suspension",
+        WastDirective::Thread(_) => "thread",
+        WastDirective::Wait { .. } => "wait",
+    }
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Runs `f` with a panic hook that records the message instead of printing it, the default
+/// hook is restored afterwards
+fn catch_panic<R>(f: impl FnOnce() -> R) -> Result<R, String> {
+    let message = Arc::new(Mutex::new(None));
+    let recorded = message.clone();
+    panic::set_hook(Box::new(move |info| {
+        let location = info.location().map(|l| format!(" at {l}"));
+        *recorded.lock().unwrap() = Some(format!(
+            "{}{}",
+            panic_message(info.payload()),
+            location.unwrap_or_default()
+        ));
+    }));
+    let result = panic::catch_unwind(AssertUnwindSafe(f));
+    let _ = panic::take_hook();
+    result.map_err(|payload| {
+        let recorded = message.lock().unwrap().take();
+        recorded.unwrap_or_else(|| panic_message(&*payload))
+    })
+}
+
+/// The message the spec interpreter reports for a trap, `None` if the error is no trap
+fn trap_message(error: &RuntimeError) -> Option<&'static str> {
+    match error {
+        RuntimeError::Trap { error, .. } => trap_message(error),
+        RuntimeError::UnreachableReached => Some("unreachable"),
+        RuntimeError::MemoryAddressOutOfScope => Some("out of bounds memory access"),
+        RuntimeError::TableAddressOutOfScope => Some("out of bounds table access"),
+        RuntimeError::IntegerDivideByZero => Some("integer divide by zero"),
+        RuntimeError::IntegerOverflow => Some("integer overflow"),
+        RuntimeError::InvalidConversionToInteger => Some("invalid conversion to integer"),
+        RuntimeError::UndefinedElement => Some("undefined element"),
+        RuntimeError::UninitializedElement => Some("uninitialized element"),
+        RuntimeError::IndirectCallTypeMismatch { .. } => Some("indirect call type mismatch"),
+        RuntimeError::StackOverflow { .. } => Some("call stack exhausted"),
+        _ => None,
+    }
+}
+
+/// Like [`trap_message`] for the traps while instantiating a module
+fn instantiation_trap_message(error: &LinkError) -> Option<&'static str> {
+    match error {
+        LinkError::Instantiation(ExecutionError::RuntimeError(e)) => trap_message(e),
+        LinkError::Instantiation(ExecutionError::InstanceError(e)) => match e {
+            InstanceError::ElementSegmentOutOfBounds(_) => Some("out of bounds table access"),
+            InstanceError::DataSegmentOutOfBounds(_) => Some("out of bounds memory access"),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+//NOTE: The expected messages may carry details after the spec message, like the spec
+//interpreter only their start is compared
+fn check_trap(got: Option<&str>, expected: &str, error: impl Display) -> Outcome {
+    match got {
+        Some(got) if expected.starts_with(got) => Outcome::Passed,
+        _ => Outcome::Failed(format!("expected trap ({expected}), got {error}")),
+    }
+}
+
+fn arg_to_local(arg: &WastArg) -> Result<LocalValue, String> {
+    let WastArg::Core(arg) = arg else {
+        return Err(format!("unsupported argument: {arg:?}"));
+    };
+    match arg {
+        WastArgCore::I32(v) => Ok(LocalValue::I32(*v as u32)),
+        WastArgCore::I64(v) => Ok(LocalValue::I64(*v as u64)),
+        WastArgCore::F32(v) => Ok(LocalValue::F32(f32::from_bits(v.bits))),
+        WastArgCore::F64(v) => Ok(LocalValue::F64(f64::from_bits(v.bits))),
+        WastArgCore::RefNull(HeapType::Abstract {
+            ty: AbstractHeapType::Func,
+            ..
+        }) => Ok(LocalValue::FuncRef(None)),
+        WastArgCore::RefNull(HeapType::Abstract {
+            ty: AbstractHeapType::Extern,
+            ..
+        }) => Ok(LocalValue::ExternRef(None)),
+        WastArgCore::RefExtern(v) => Ok(LocalValue::ExternRef(Some(*v))),
+        _ => Err(format!("unsupported argument: {arg:?}")),
+    }
+}
+
+//NOTE: A canonical NaN has only the quiet bit set in its payload, an arithmetic NaN may have any
+//payload as long as the quiet bit is set. The sign is ignored in both cases.
+macro_rules! impl_match_float {
+    ($fn_name: ident, $t: ty, $pattern_t: ty, $sign_bit: expr, $canonical: expr, $quiet_bit: expr) => {
+        fn $fn_name(got: $t, expected: &NanPattern<$pattern_t>) -> bool {
+            let bits = got.to_bits();
+            match expected {
+                NanPattern::CanonicalNan => bits & !$sign_bit == $canonical,
+                NanPattern::ArithmeticNan => got.is_nan() && bits & $quiet_bit != 0,
+                NanPattern::Value(v) => bits == v.bits,
+            }
+        }
+    };
+}
+
+impl_match_float!(
+    match_f32,
+    f32,
+    wast::token::F32,
+    0x8000_0000,
+    0x7fc0_0000,
+    0x0040_0000
+);
+impl_match_float!(
+    match_f64,
+    f64,
+    wast::token::F64,
+    0x8000_0000_0000_0000,
+    0x7ff8_0000_0000_0000,
+    0x0008_0000_0000_0000
+);
+
+fn match_result(got: &LocalValue, expected: &WastRet) -> bool {
+    let WastRet::Core(expected) = expected else {
+        return false;
+    };
+    match (got, expected) {
+        (LocalValue::I32(g), WastRetCore::I32(e)) => *g == *e as u32,
+        (LocalValue::S32(g), WastRetCore::I32(e)) => *g == *e,
+        (LocalValue::I64(g), WastRetCore::I64(e)) => *g == *e as u64,
+        (LocalValue::S64(g), WastRetCore::I64(e)) => *g == *e,
+        (LocalValue::F32(g), WastRetCore::F32(e)) => match_f32(*g, e),
+        (LocalValue::F64(g), WastRetCore::F64(e)) => match_f64(*g, e),
+        (LocalValue::FuncRef(r) | LocalValue::ExternRef(r), WastRetCore::RefNull(_)) => r.is_none(),
+        (LocalValue::FuncRef(r), WastRetCore::RefFunc(_)) => r.is_some(),
+        (LocalValue::ExternRef(r), WastRetCore::RefExtern(None)) => r.is_some(),
+        (LocalValue::ExternRef(r), WastRetCore::RefExtern(Some(e))) => *r == Some(*e),
+        _ => false,
+    }
+}
+
+impl WastRunner {
+    /// Invokes functions on the fast interpreter tier, start functions still run on the slow vm
+    pub fn with_fast_vm(self) -> Self {
+        Self {
+            store: self.store.with_fast_vm(),
+            ..self
+        }
+    }
+
+    fn resolve(&self, id: Option<Id>) -> Result<usize, String> {
+        match id {
+            Some(id) => self
+                .named
+                .get(id.name())
+                .copied()
+                .ok_or(format!("unknown module ${}", id.name())),
+            None => self
+                .instances
+                .len()
+                .checked_sub(1)
+                .ok_or("no module instantiated".to_string()),
+        }
+    }
+
+    /// Instantiates `module` in the store and returns its name there
+    fn instantiate(&mut self, module: &mut QuoteWat) -> Result<String, ModuleError> {
+        let binary = module
+            .encode()
+            .map_err(|e| ModuleError::Malformed(e.to_string()))?;
+        let mut bytecode = parse_binary(&mut Cursor::new(binary))
+            .map_err(|e| ModuleError::Malformed(e.to_string()))?;
+        let (jumps, info) = valiadate_and_patch_bytecode(&mut bytecode)
+            .map_err(|e| ModuleError::Invalid(e.to_string()))?;
+        let module = ValidateResult {
+            bytecode,
+            info,
+            jumps,
+        };
+        //NOTE: The name can't clash with the names modules are registered as, which are
+        //valid wasm names
+        let name = format!("#{}", self.modules);
+        self.modules += 1;
+        self.store.instantiate(&name, module)?;
+        Ok(name)
+    }
+
+    fn add_instance(&mut self, id: Option<Id>, name: String) {
+        if let Some(id) = id {
+            self.named
+                .insert(id.name().to_string(), self.instances.len());
+        }
+        self.instances.push(name);
+    }
+
+    fn invoke(
+        &mut self,
+        invoke: &WastInvoke,
+    ) -> Result<Result<Vec<LocalValue>, RuntimeError>, String> {
+        let module = &self.instances[self.resolve(invoke.module)?];
+        self.store
+            .instance(module)
+            .and_then(|i| i.func_id(invoke.name))
+            .ok_or(format!("unknown exported function \"{}\"", invoke.name))?;
+        let args = invoke
+            .args
+            .iter()
+            .map(arg_to_local)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.store.call(module, invoke.name, &args))
+    }
+
+    fn get_global(&self, module: Option<Id>, name: &str) -> Result<LocalValue, String> {
+        let module = &self.instances[self.resolve(module)?];
+        self.store
+            .global(module, name)
+            .ok_or(format!("unknown exported global \"{name}\""))
+    }
+
+    fn execute(
+        &mut self,
+        exec: &mut WastExecute,
+    ) -> Result<Result<Vec<LocalValue>, RuntimeError>, String> {
+        match exec {
+            WastExecute::Invoke(invoke) => self.invoke(invoke),
+            WastExecute::Get { module, global, .. } => {
+                Ok(Ok(vec![self.get_global(*module, global)?]))
+            }
+            WastExecute::Wat(_) => Err("module execution is not supported here".to_string()),
+        }
+    }
+
+    fn run_directive(&mut self, directive: &mut WastDirective) -> Outcome {
+        match directive {
+            WastDirective::Module(module) => {
+                let name = module.name();
+                match self.instantiate(module) {
+                    Ok(instance) => {
+                        self.add_instance(name, instance);
+                        Outcome::Passed
+                    }
+                    Err(e) => Outcome::Failed(e.to_string()),
+                }
+            }
+            WastDirective::AssertMalformed { module, .. } => {
+                if matches!(module, QuoteWat::QuoteModule(..)) && module.encode().is_err() {
+                    return Outcome::Skipped("malformed text format".to_string());
+                }
+                match self.instantiate(module) {
+                    Err(ModuleError::Malformed(_)) => Outcome::Passed,
+                    Err(e) => Outcome::Failed(format!("expected malformed module, got {e}")),
+                    Ok(_) => Outcome::Failed("expected malformed module".to_string()),
+                }
+            }
+            WastDirective::AssertInvalid {
+                module, message, ..
+            } => match self.instantiate(module) {
+                Err(ModuleError::Invalid(_)) => Outcome::Passed,
+                Err(e) => Outcome::Failed(format!("expected invalid module ({message}), got {e}")),
+                Ok(_) => Outcome::Failed(format!("expected invalid module: {message}")),
+            },
+            WastDirective::AssertUnlinkable { module, .. } => {
+                let mut module = QuoteWat::Wat(std::mem::replace(
+                    module,
+                    wast::Wat::Module(wast::core::Module {
+                        span: wast::token::Span::from_offset(0),
+                        id: None,
+                        name: None,
+                        kind: wast::core::ModuleKind::Binary(Vec::new()),
+                    }),
+                ));
+                //NOTE: Older versions of the spec reject segments out of bounds when linking
+                match self.instantiate(&mut module) {
+                    Err(ModuleError::Unlinkable(_) | ModuleError::Instantiation(_)) => {
+                        Outcome::Passed
+                    }
+                    Err(e) => Outcome::Failed(format!("expected unlinkable module, got {e}")),
+                    Ok(_) => Outcome::Failed("expected unlinkable module".to_string()),
+                }
+            }
+            WastDirective::Register { name, module, .. } => {
+                let registered = self.resolve(*module).and_then(|id| {
+                    self.store
+                        .alias_module(&self.instances[id], name)
+                        .map_err(|e| e.to_string())
+                });
+                match registered {
+                    Ok(()) => Outcome::Passed,
+                    Err(e) => Outcome::Failed(e),
+                }
+            }
+            WastDirective::Invoke(invoke) => match self.invoke(invoke) {
+                Ok(Ok(_)) => Outcome::Passed,
+                Ok(Err(e)) => Outcome::Failed(format!("unexpected trap: {e}")),
+                Err(e) => Outcome::Failed(e),
+            },
+            WastDirective::AssertReturn { exec, results, .. } => match self.execute(exec) {
+                Ok(Ok(got)) => {
+                    if got.len() == results.len()
+                        && got
+                            .iter()
+                            .zip(results.iter())
+                            .all(|(g, e)| match_result(g, e))
+                    {
+                        Outcome::Passed
+                    } else {
+                        Outcome::Failed(format!(
+                            "expected [{}], got [{}]",
+                            results.iter().map(|r| format!("{r:?}")).format(", "),
+                            got.iter().format(", ")
+                        ))
+                    }
+                }
+                Ok(Err(e)) => Outcome::Failed(format!("unexpected trap: {e}")),
+                Err(e) => Outcome::Failed(e),
+            },
+            WastDirective::AssertTrap { exec, message, .. } => {
+                if let WastExecute::Wat(wat) = exec {
+                    let mut module = QuoteWat::Wat(std::mem::replace(
+                        wat,
+                        wast::Wat::Module(wast::core::Module {
+                            span: wast::token::Span::from_offset(0),
+                            id: None,
+                            name: None,
+                            kind: wast::core::ModuleKind::Binary(Vec::new()),
+                        }),
+                    ));
+                    return match self.instantiate(&mut module) {
+                        Err(ModuleError::Instantiation(e)) => {
+                            check_trap(instantiation_trap_message(&e), message, e)
+                        }
+                        Err(e) => Outcome::Failed(format!("expected trap ({message}), got {e}")),
+                        Ok(_) => Outcome::Failed(format!("expected trap: {message}")),
+                    };
+                }
+                match self.execute(exec) {
+                    Ok(Err(e)) => check_trap(trap_message(&e), message, e),
+                    Ok(Ok(got)) => Outcome::Failed(format!(
+                        "expected trap ({message}), got [{}]",
+                        got.iter().format(", ")
+                    )),
+                    Err(e) => Outcome::Failed(e),
+                }
+            }
+            WastDirective::AssertExhaustion { call, message, .. } => match self.invoke(call) {
+                Ok(Err(e)) if matches!(e.trap_cause(), RuntimeError::StackOverflow { .. }) => {
+                    Outcome::Passed
+                }
+                Ok(Err(e)) => Outcome::Failed(format!("expected exhaustion ({message}), got {e}")),
+                Ok(Ok(_)) => Outcome::Failed(format!("expected exhaustion: {message}")),
+                Err(e) => Outcome::Failed(e),
+            },
+            other => Outcome::Skipped(format!("{} is not supported", directive_kind(other))),
+        }
+    }
+
+    pub fn run_script(&mut self, path: impl AsRef<Path>, text: &str) -> FileReport {
+        let mut report = FileReport {
+            path: path.as_ref().to_path_buf(),
+            ..Default::default()
+        };
+        let buffer = match ParseBuffer::new(text) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                return report;
+            }
+        };
+        let wast = match parse::<Wast>(&buffer) {
+            Ok(wast) => wast,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                return report;
+            }
+        };
+
+        for mut directive in wast.directives {
+            let (line, col) = directive.span().linecol_in(text);
+            let kind = directive_kind(&directive);
+            //NOTE: Unimplemented opcodes still panic in the parser, these count as failures
+            let outcome = catch_panic(|| self.run_directive(&mut directive))
+                .unwrap_or_else(|message| Outcome::Failed(format!("panicked: {message}")));
+
+            report.results.push(DirectiveResult {
+                line: line + 1,
+                col: col + 1,
+                kind,
+                outcome,
+            });
+        }
+        report
+    }
+}
+
+pub fn run_file(path: impl AsRef<Path>) -> FileReport {
+    run_file_with(WastRunner::default(), path)
+}
+
+pub fn run_file_with(mut runner: WastRunner, path: impl AsRef<Path>) -> FileReport {
+    let path = path.as_ref();
+    match fs::read_to_string(path) {
+        Ok(text) => runner.run_script(path, &text),
+        Err(e) => FileReport {
+            path: path.to_path_buf(),
+            error: Some(e.to_string()),
+            ..Default::default()
+        },
+    }
+}
+
+/// Collects all `.wast` files in the given paths, descending into directories.
+pub fn collect_wast_files(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let entries = fs::read_dir(path)?
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            files.extend(collect_wast_files(&entries)?);
+        } else if path.extension().is_some_and(|e| e == "wast") {
+            files.push(path.clone());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{Outcome, WastRunner, catch_panic, collect_wast_files, run_file, run_file_with};
+
+    #[test]
+    fn reports_failures() {
+        let src = r#"
+            (module
+                (func (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add
+                )
+                (func (export "div") (param i32 i32) (result i32)
+                    (i32.div_u (local.get 0) (local.get 1))
+                )
+                (func $loop (export "loop")
+                    call $loop
+                )
+            )
+            (assert_return (invoke "add" (i32.const 1) (i32.const 2)) (i32.const 3))
+            (assert_return (invoke "add" (i32.const 1) (i32.const 2)) (i32.const 4))
+            (assert_trap (invoke "div" (i32.const 1) (i32.const 0)) "integer divide by zero")
+            (assert_trap (invoke "div" (i32.const 1) (i32.const 0)) "integer overflow")
+            (assert_invalid (module (func (result i32) (i64.const 0))) "type mismatch")
+            (assert_exhaustion (invoke "loop") "call stack exhausted")
+            (assert_exhaustion (invoke "add" (i32.const 1) (i32.const 2)) "call stack exhausted")
+        "#;
+        let report = WastRunner::default().run_script("inline.wast", src);
+        assert!(report.error.is_none());
+        assert_eq!(report.passed(), 5);
+        assert_eq!(report.failed(), 3);
+        assert_eq!(report.skipped(), 0);
+
+        let mut failures = report.iter_failures();
+        let (failure, _) = failures.next().unwrap();
+        assert_eq!(failure.line, 16);
+        assert_eq!(failure.kind, "assert_return");
+        let (failure, reason) = failures.next().unwrap();
+        assert_eq!(failure.kind, "assert_trap");
+        assert!(reason.starts_with("expected trap (integer overflow)"));
+        assert!(matches!(report.results[0].outcome, Outcome::Passed));
+    }
+
+    #[test]
+    fn malformed_and_invalid_are_distinct() {
+        let src = r#"
+            (assert_malformed (module binary "\00asm\02\00\00\00") "unknown binary version")
+            (assert_invalid (module binary "\00asm\02\00\00\00") "unknown binary version")
+            (assert_invalid (module (func (result i32) (i64.const 0))) "type mismatch")
+            (assert_malformed (module (func (result i32) (i64.const 0))) "type mismatch")
+        "#;
+        let report = WastRunner::default().run_script("inline.wast", src);
+        assert!(report.error.is_none());
+        let outcomes = report
+            .results
+            .iter()
+            .map(|r| matches!(r.outcome, Outcome::Passed))
+            .collect::<Vec<_>>();
+        assert_eq!(outcomes, [true, false, true, false]);
+    }
+
+    #[test]
+    fn panics_are_recorded() {
+        let message = catch_panic(|| panic!("boom")).unwrap_err();
+        assert!(message.starts_with("boom at "), "{message}");
+        assert!(message.contains("runner.rs"), "{message}");
+        assert_eq!(catch_panic(|| 7), Ok(7));
+    }
+
+    #[test]
+    fn registered_modules() {
+        let src = r#"
+            (module $lib
+                (global (export "base") (mut i32) (i32.const 8))
+                (func (export "inc")
+                    global.get 0
+                    i32.const 1
+                    i32.add
+                    global.set 0
+                )
+            )
+            (register "lib" $lib)
+            (module
+                (import "lib" "base" (global $base (mut i32)))
+                (import "lib" "inc" (func $inc))
+                (func (export "next") (result i32)
+                    call $inc
+                    global.get $base
+                )
+            )
+            (assert_return (invoke "next") (i32.const 9))
+            (assert_return (get $lib "base") (i32.const 9))
+            (assert_unlinkable (module (import "lib" "missing" (func))) "unknown import")
+            (register "other" $missing)
+        "#;
+        for mut runner in [WastRunner::default(), WastRunner::default().with_fast_vm()] {
+            let report = runner.run_script("inline.wast", src);
+            assert!(report.error.is_none());
+            assert_eq!(report.passed(), 6);
+            assert_eq!(report.failed(), 1);
+        }
+    }
+
+    #[test]
+    fn regression_scripts() {
+        let regression = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("regression");
+        let files = collect_wast_files(&[regression]).unwrap();
+        assert!(!files.is_empty());
+        for file in files {
+            let report = run_file(&file);
+            let failures = report
+                .iter_failures()
+                .map(|(r, reason)| format!("{}:{}: {reason}", r.line, r.col))
+                .collect::<Vec<_>>();
+            assert!(
+                report.is_ok(),
+                "{}: {:?} {:?}",
+                file.display(),
+                report.error,
+                failures
+            );
+        }
+    }
+
+    #[test]
+    fn regression_scripts_on_fast_vm() {
+        let regression = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("regression");
+        for file in collect_wast_files(&[regression]).unwrap() {
+            let report = run_file_with(WastRunner::default().with_fast_vm(), &file);
+            let failures = report
+                .iter_failures()
+                .map(|(r, reason)| format!("{}:{}: {reason}", r.line, r.col))
+                .collect::<Vec<_>>();
+            assert!(report.is_ok(), "{}: {:?}", file.display(), failures);
+        }
+    }
+}
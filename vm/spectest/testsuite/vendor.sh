@@ -0,0 +1,16 @@
+#!/bin/sh
+# Vendors the top level scripts of the official testsuite at a pinned upstream commit:
+#     spectest/testsuite/vendor.sh <commit>
+set -eu
+commit=${1:?usage: vendor.sh <commit>}
+dir=$(cd "$(dirname "$0")" && pwd)
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+git -C "$tmp" init -q
+git -C "$tmp" fetch -q --depth 1 https://github.com/WebAssembly/testsuite "$commit"
+git -C "$tmp" checkout -q FETCH_HEAD
+rm -f "$dir"/*.wast
+cp "$tmp"/*.wast "$dir"/
+git -C "$tmp" rev-parse HEAD > "$dir/UPSTREAM"
+echo "vendored $(ls "$dir"/*.wast | wc -l) scripts at $(cat "$dir/UPSTREAM")"
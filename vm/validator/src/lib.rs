@@ -1,3 +1,4 @@
+pub mod module;
 pub mod validator;
 /*
 use std::io::Cursor;
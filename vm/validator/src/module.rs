@@ -0,0 +1,481 @@
+//! Validation of everything outside of function bodies:
+//! https://webassembly.github.io/spec/core/valid/modules.html
+use std::collections::HashSet;
+
+use parser::{
+    info::BytecodeInfo,
+    op::Op,
+    reader::{Bytecode, Data, ElementInit, ElementMode, ExportDesc, ImportDesc, Limits, ValueType},
+};
+
+use crate::validator::ValidationError;
+
+/// Memories are limited to 4GiB in 32 bit address space
+pub const MAX_MEMORY_PAGES: u32 = 65536;
+
+type ModuleCheck = fn(&Bytecode, &BytecodeInfo) -> Result<(), ValidationError>;
+
+//NOTE: Function types have to be checked first, the function body validation relies on them
+const CHECKS: &[ModuleCheck] = &[
+    validate_functions,
+    validate_imports,
+    validate_tables,
+    validate_memories,
+    validate_globals,
+    validate_elements,
+    validate_data,
+    validate_start,
+    validate_exports,
+];
+
+/// Validates all sections of the module except for the function bodies
+pub fn validate_module(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    CHECKS.iter().try_for_each(|check| check(bytecode, info))
+}
+
+/// Like [`validate_module`], but reports the first error of every section
+pub fn collect_module_errors(bytecode: &Bytecode, info: &BytecodeInfo) -> Vec<ValidationError> {
+    CHECKS
+        .iter()
+        .filter_map(|check| check(bytecode, info).err())
+        .collect()
+}
+
+fn validate_limits(limits: &Limits, max_allowed: u32) -> Result<(), ValidationError> {
+    let min = limits.min.data;
+    let max = limits.max.as_ref().map(|max| max.data);
+    if let Some(got) = [Some(min), max]
+        .into_iter()
+        .flatten()
+        .find(|l| *l > max_allowed)
+    {
+        return Err(ValidationError::LimitOutOfRange {
+            got,
+            max: max_allowed,
+        });
+    }
+    match max {
+        Some(max) if max < min => Err(ValidationError::InvalidLimits { min, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `ops` form a constant expression producing exactly one value of type `expected`.
+/// Only the first `visible_globals` globals may be read.
+fn validate_const_expr<'a>(
+    info: &BytecodeInfo,
+    ops: impl IntoIterator<Item = &'a Op>,
+    expected: ValueType,
+    visible_globals: usize,
+) -> Result<(), ValidationError> {
+    let got = ops
+        .into_iter()
+        .map(|op| match *op {
+            Op::I32Const(_) => Ok(ValueType::I32),
+            Op::I64Const(_) => Ok(ValueType::I64),
+            Op::F32Const(_) => Ok(ValueType::F32),
+            Op::F64Const(_) => Ok(ValueType::F64),
+            Op::RefNull(t) => Ok(t),
+            Op::RefFunc(id) if id < info.functions.len() => Ok(ValueType::Funcref),
+            Op::RefFunc(id) => Err(ValidationError::InvalidFunctionId(id)),
+            Op::GlobalGet(id) => match info.globals.get(id) {
+                Some(global) if id < visible_globals && !global.mutable => Ok(global.t),
+                Some(_) if id < visible_globals => {
+                    Err(ValidationError::MutableGlobalInConstExpr(id))
+                }
+                _ => Err(ValidationError::InvalidGlobalId(id)),
+            },
+            ref op => Err(ValidationError::NonConstantInstruction(op.clone())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if got != [expected] {
+        Err(ValidationError::ConstExprTypeMismatch { got, expected })
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_functions(bytecode: &Bytecode, _info: &BytecodeInfo) -> Result<(), ValidationError> {
+    let type_count = bytecode.iter_types().map_or(0, |t| t.count());
+    if let Some(&t) = bytecode
+        .iter_functions()
+        .into_iter()
+        .flatten()
+        .find(|t| **t >= type_count)
+    {
+        return Err(ValidationError::InvalidFunctionTypeId(t));
+    }
+
+    let functions = bytecode.iter_functions().map_or(0, |f| f.count());
+    let code = bytecode.iter_code().map_or(0, |c| c.count());
+    if functions != code {
+        Err(ValidationError::FunctionCodeCountMismatch { functions, code })
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_imports(bytecode: &Bytecode, _info: &BytecodeInfo) -> Result<(), ValidationError> {
+    let type_count = bytecode.iter_types().map_or(0, |t| t.count());
+    bytecode
+        .iter_imports()
+        .into_iter()
+        .flatten()
+        .try_for_each(|import| match &import.desc.data {
+            ImportDesc::TypeIdx(t) if *t >= type_count => {
+                Err(ValidationError::InvalidFunctionTypeId(*t))
+            }
+            ImportDesc::TableType(table) => validate_limits(&table.limits.data, u32::MAX),
+            ImportDesc::MemType(limits) => validate_limits(limits, MAX_MEMORY_PAGES),
+            _ => Ok(()),
+        })
+}
+
+fn validate_tables(bytecode: &Bytecode, _info: &BytecodeInfo) -> Result<(), ValidationError> {
+    bytecode
+        .iter_tables()
+        .into_iter()
+        .flatten()
+        .try_for_each(|table| validate_limits(&table.limits.data, u32::MAX))
+}
+
+fn validate_memories(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    bytecode
+        .iter_memories()
+        .into_iter()
+        .flatten()
+        .try_for_each(|limits| validate_limits(limits, MAX_MEMORY_PAGES))?;
+
+    //NOTE: Imported memories count as well
+    if info.memories.len() > 1 {
+        Err(ValidationError::MultipleMemories(info.memories.len()))
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_globals(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    //NOTE: Initializers may only read imported globals, module globals are not yet initialized
+    let imported = info.imports.as_ref().map_or(0, |i| i.globals.len());
+    bytecode
+        .iter_globals()
+        .into_iter()
+        .flatten()
+        .try_for_each(|global| {
+            validate_const_expr(info, global.iter_init_expr(), global.value_type(), imported)
+        })
+}
+
+fn validate_elements(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    bytecode
+        .iter_elements()
+        .into_iter()
+        .flatten()
+        .try_for_each(|element| {
+            if let ElementMode::Active { table_id, expr } = &element.mode {
+                let table = info
+                    .tables
+                    .get(*table_id)
+                    .ok_or(ValidationError::InvalidTableId(*table_id))?;
+                if table.value_type() != element.t {
+                    return Err(ValidationError::ElementTypeMismatch {
+                        got: element.t,
+                        expected: table.value_type(),
+                    });
+                }
+                let offset = expr.data.iter().map(|op| &op.data);
+                validate_const_expr(info, offset, ValueType::I32, info.globals.len())?;
+            }
+            match &element.init {
+                ElementInit::Functions(ids) => {
+                    match ids.iter().find(|id| id.data >= info.functions.len()) {
+                        Some(id) => Err(ValidationError::InvalidFunctionId(id.data)),
+                        None => Ok(()),
+                    }
+                }
+                ElementInit::Expressions(exprs) => exprs.iter().try_for_each(|expr| {
+                    validate_const_expr(
+                        info,
+                        expr.data.ops().iter().map(|op| &op.data),
+                        element.t,
+                        info.globals.len(),
+                    )
+                }),
+            }
+        })
+}
+
+fn validate_data(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    bytecode
+        .iter_data()
+        .into_iter()
+        .flatten()
+        .try_for_each(|data| match data {
+            Data::Active { mem_id, .. } if *mem_id >= info.memories.len() => {
+                Err(ValidationError::InvalidMemoryId(*mem_id))
+            }
+            Data::Active { expr, .. } => {
+                let offset = expr.data.iter().map(|op| &op.data);
+                validate_const_expr(info, offset, ValueType::I32, info.globals.len())
+            }
+            Data::Passive(_) => Ok(()),
+        })?;
+
+    let segments = bytecode.iter_data().map_or(0, |d| d.count());
+    match bytecode.data_count.as_ref().map(|c| c.data as usize) {
+        Some(count) if count != segments => {
+            Err(ValidationError::DataCountMismatch { count, segments })
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_start(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    let Some(start) = &bytecode.start else {
+        return Ok(());
+    };
+    let func_id = start.data as usize;
+    let func = info
+        .functions
+        .get(func_id)
+        .ok_or(ValidationError::InvalidFunctionId(func_id))?;
+    let t = bytecode
+        .get_type(func.type_id)
+        .ok_or(ValidationError::InvalidFunctionTypeId(func.type_id))?;
+    if t.iter_params().next().is_some() || t.iter_results().next().is_some() {
+        Err(ValidationError::InvalidStartFunctionType {
+            func_id,
+            t: t.clone(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_exports(bytecode: &Bytecode, info: &BytecodeInfo) -> Result<(), ValidationError> {
+    let mut names = HashSet::new();
+    bytecode
+        .iter_exports()
+        .into_iter()
+        .flatten()
+        .try_for_each(|export| {
+            let name = export.name.data.as_str();
+            if !names.insert(name) {
+                return Err(ValidationError::DuplicateExportName(name.to_string()));
+            }
+            match export.desc.data {
+                ExportDesc::FuncId(id) if id >= info.functions.len() => {
+                    Err(ValidationError::InvalidFunctionId(id))
+                }
+                ExportDesc::TableId(id) if id >= info.tables.len() => {
+                    Err(ValidationError::InvalidTableId(id))
+                }
+                ExportDesc::MemId(id) if id >= info.memories.len() => {
+                    Err(ValidationError::InvalidMemoryId(id))
+                }
+                ExportDesc::GlobalId(id) if id >= info.globals.len() => {
+                    Err(ValidationError::InvalidGlobalId(id))
+                }
+                _ => Ok(()),
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::reader::parse_wat;
+
+    use crate::validator::{
+        ReadAndValidateError, ValidationError, read_and_validate_wat, valiadate_and_patch_bytecode,
+    };
+
+    macro_rules! assert_module_err {
+        ($src: expr, $($err: tt)+) => {
+            let err = read_and_validate_wat($src);
+            let ReadAndValidateError::ValidationError(err) = err.unwrap_err() else {
+                panic!("expected a validation error");
+            };
+            assert!(matches!(&err, $($err)+), "unexpected error: {err}");
+        };
+    }
+
+    #[test]
+    fn valid_module() -> Result<(), ReadAndValidateError> {
+        let src = r#"
+            (module
+                (import "env" "g" (global $g i32))
+                (import "env" "mem" (memory 1 2))
+                (table 2 funcref)
+                (global $h i32 (global.get $g))
+                (global $r funcref (ref.func $start))
+                (func $start)
+                (func $init (param i32)
+                    i32.const 0
+                    i32.const 0
+                    i32.const 1
+                    memory.init $passive
+                    ref.func $start
+                    drop
+                )
+                (elem (table 0) (offset (global.get $g)) funcref (ref.func $init))
+                (data (memory 0) (offset (i32.const 0)) "abc")
+                (data $passive "def")
+                (start $start)
+                (export "start" (func $start))
+                (export "init" (func $init))
+            )
+        "#;
+        _ = read_and_validate_wat(src)?;
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_export() {
+        let src = r#"
+            (module
+                (func $a)
+                (export "a" (func $a))
+                (export "a" (func $a))
+            )
+        "#;
+        assert_module_err!(src, ValidationError::DuplicateExportName(name) if name == "a");
+    }
+
+    #[test]
+    fn start_function_type() {
+        let src = r#"
+            (module
+                (func $main (param i32))
+                (start $main)
+            )
+        "#;
+        assert_module_err!(
+            src,
+            ValidationError::InvalidStartFunctionType { func_id: 0, .. }
+        );
+    }
+
+    #[test]
+    fn const_expr_must_be_constant() {
+        let src = r#"
+            (module
+                (global i32 (i32.add (i32.const 1) (i32.const 2)))
+            )
+        "#;
+        assert_module_err!(
+            src,
+            ValidationError::NonConstantInstruction(parser::op::Op::I32Add)
+        );
+    }
+
+    #[test]
+    fn const_expr_type() {
+        let src = r#"
+            (module
+                (global i64 (i32.const 1))
+            )
+        "#;
+        assert_module_err!(src, ValidationError::ConstExprTypeMismatch { .. });
+    }
+
+    #[test]
+    fn global_init_reads_module_global() {
+        let src = r#"
+            (module
+                (global $a i32 (i32.const 1))
+                (global $b i32 (global.get $a))
+            )
+        "#;
+        assert_module_err!(src, ValidationError::InvalidGlobalId(0));
+    }
+
+    #[test]
+    fn offset_reads_mutable_global() {
+        let src = r#"
+            (module
+                (import "env" "g" (global $g (mut i32)))
+                (memory 1)
+                (data (offset (global.get $g)) "abc")
+            )
+        "#;
+        assert_module_err!(src, ValidationError::MutableGlobalInConstExpr(0));
+    }
+
+    #[test]
+    fn memory_limits() {
+        assert_module_err!(
+            "(module (memory 2 1))",
+            ValidationError::InvalidLimits { min: 2, max: 1 }
+        );
+        assert_module_err!(
+            "(module (memory 65537))",
+            ValidationError::LimitOutOfRange { got: 65537, .. }
+        );
+        assert_module_err!(
+            "(module (table 3 2 funcref))",
+            ValidationError::InvalidLimits { min: 3, max: 2 }
+        );
+    }
+
+    #[test]
+    fn multiple_memories() {
+        let src = r#"
+            (module
+                (import "env" "mem" (memory 1))
+                (memory 1)
+            )
+        "#;
+        assert_module_err!(src, ValidationError::MultipleMemories(2));
+    }
+
+    #[test]
+    fn data_without_memory() {
+        assert_module_err!(
+            r#"(module (data (i32.const 0) "abc"))"#,
+            ValidationError::InvalidMemoryId(0)
+        );
+    }
+
+    #[test]
+    fn undeclared_function_ref() {
+        let src = r#"
+            (module
+                (func $a)
+                (func $b (result funcref)
+                    ref.func $a
+                )
+            )
+        "#;
+        let err = read_and_validate_wat(src).unwrap_err();
+        let ReadAndValidateError::ValidationError(err) = err else {
+            panic!("expected a validation error");
+        };
+        assert!(matches!(
+            err.cause(),
+            ValidationError::UndeclaredFunctionRef(0)
+        ));
+    }
+
+    #[test]
+    fn memory_init_requires_data_count() -> Result<(), ReadAndValidateError> {
+        let src = r#"
+            (module
+                (memory 1)
+                (func
+                    i32.const 0
+                    i32.const 0
+                    i32.const 1
+                    memory.init 0
+                )
+                (data "abc")
+            )
+        "#;
+        let mut bytecode = parse_wat(src)?;
+        assert!(bytecode.data_count.is_some());
+        bytecode.data_count = None;
+        let err = valiadate_and_patch_bytecode(&mut bytecode).unwrap_err();
+        assert!(matches!(err.cause(), ValidationError::MissingDataCount));
+        Ok(())
+    }
+}
@@ -1,10 +1,17 @@
-use std::fmt::{Debug, Display};
+use std::{
+    fmt::{Debug, Display},
+    ops::Range,
+};
 use thiserror::Error;
 
+use crate::module::{collect_module_errors, validate_module};
+
 use itertools::Itertools;
+use log::trace;
 use parser::{
     info::{BytecodeInfo, FunctionType},
-    op::{Blocktype, Memarg, Op},
+    names::describe,
+    op::{Blocktype, BrTableTarget, Memarg, Op},
     reader::{
         self, Bytecode, BytecodeReader, Code, Function, ParserError, Type, ValueType, WithPosition,
         parse_binary, parse_wat,
@@ -41,6 +48,8 @@ pub enum ValidationError {
     InvalidLocalId(usize),
     #[error("Invalid global id: {0}")]
     InvalidGlobalId(usize),
+    #[error("Global {0} is immutable")]
+    ImmutableGlobal(usize),
     #[error("Expected an numeric type")]
     ExpectedNumericType,
     #[error("Got an invalid block type: Got: {0}")]
@@ -68,6 +77,141 @@ pub enum ValidationError {
 
     #[error("Trying to init active data section: {0}")]
     InitActiveDataId(usize),
+
+    #[error("Invalid table id: {0}")]
+    InvalidTableId(usize),
+
+    #[error("Invalid element id: {0}")]
+    InvalidElementId(usize),
+
+    #[error("Expected a reference type, got: {0}")]
+    ExpectedReferenceType(ValueStackType),
+
+    #[error("call_indirect requires a funcref table, table {0} has type {1}")]
+    CallIndirectOnNonFuncTable(usize, ValueType),
+
+    #[error("Element type mismatch. Expected: {expected}, got: {got}")]
+    ElementTypeMismatch { got: ValueType, expected: ValueType },
+
+    #[error("br_table label {label} has arity {got}, but the default label has arity {expected}")]
+    BrTableArityMismatch {
+        label: usize,
+        got: usize,
+        expected: usize,
+    },
+
+    #[error("Invalid memory id: {0}")]
+    InvalidMemoryId(usize),
+
+    #[error("Function section declares {functions} functions, but code section contains {code}")]
+    FunctionCodeCountMismatch { functions: usize, code: usize },
+
+    #[error("Limits are invalid: minimum {min} is larger than maximum {max}")]
+    InvalidLimits { min: u32, max: u32 },
+
+    #[error("Limit {got} exceeds the allowed maximum of {max}")]
+    LimitOutOfRange { got: u32, max: u32 },
+
+    #[error("At most one memory is allowed, module defines {0}")]
+    MultipleMemories(usize),
+
+    #[error("Instruction {0} is not allowed in a constant expression")]
+    NonConstantInstruction(Op),
+
+    #[error("Constant expressions may only read immutable globals, global {0} is mutable")]
+    MutableGlobalInConstExpr(usize),
+
+    #[error(
+        "Constant expression has type [{}], expected [{expected}]",
+        got.iter().format(", ")
+    )]
+    ConstExprTypeMismatch {
+        got: Vec<ValueType>,
+        expected: ValueType,
+    },
+
+    #[error("Start function {func_id} must have type () -> (), got {t}")]
+    InvalidStartFunctionType { func_id: usize, t: Type },
+
+    #[error("Export name {0:?} is used more than once")]
+    DuplicateExportName(String),
+
+    #[error("Data count section declares {count} segments, but data section contains {segments}")]
+    DataCountMismatch { count: usize, segments: usize },
+
+    #[error("memory.init requires a data count section")]
+    MissingDataCount,
+
+    #[error(
+        "Function {0} is referenced with ref.func, but not declared in an element segment or export"
+    )]
+    UndeclaredFunctionRef(usize),
+
+    #[error("In {context}: {error}")]
+    InFunction {
+        context: Box<ValidationContext>,
+        error: Box<ValidationError>,
+    },
+}
+
+impl ValidationError {
+    /// Returns the error inside a function context, or the error itself if it has none.
+    pub fn cause(&self) -> &ValidationError {
+        match self {
+            ValidationError::InFunction { error, .. } => error.cause(),
+            _ => self,
+        }
+    }
+
+    pub fn context(&self) -> Option<&ValidationContext> {
+        match self {
+            ValidationError::InFunction { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+/// The state of the validator when an instruction failed to validate
+#[derive(Debug, Clone)]
+pub struct ValidationContext {
+    /// Index in the function index space, imported functions included
+    pub func_id: usize,
+    pub name: Option<String>,
+    /// Index of the instruction in the function body
+    pub instruction: usize,
+    pub op: Op,
+    /// Byte range of the instruction in the module
+    pub position: Range<usize>,
+    pub type_stack: Vec<ValueStackType>,
+    pub ctrl_stack: Vec<CtrlFrame>,
+}
+
+impl ValidationContext {
+    /// The type stack and the enclosing blocks, innermost last
+    pub fn format_stacks(&self) -> String {
+        let ctrl = self
+            .ctrl_stack
+            .iter()
+            .map(|frame| frame.op().map_or("func", |op| op.name()))
+            .format(" > ");
+        format!(
+            "type stack: [{}], control stack: {ctrl}",
+            self.type_stack.iter().format(", ")
+        )
+    }
+}
+
+impl Display for ValidationContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "function {} at instruction {} ({}, offset 0x{:x})",
+            describe(self.name.as_deref(), self.func_id),
+            self.instruction,
+            self.op.name(),
+            self.position.start
+        )
+    }
 }
 
 impl ValueStackType {
@@ -122,11 +266,24 @@ pub struct CtrlFrame {
     ip: isize,
 }
 impl CtrlFrame {
-    pub fn iter_label_types(&self) -> Option<impl Iterator<Item = ValueType>> {
-        if let Op::Loop(_) = self.op.as_ref()?.data {
-            Some(self.in_types.iter().cloned())
+    /// The block instruction of the frame, `None` for the function itself
+    pub fn op(&self) -> Option<&Op> {
+        self.op.as_ref().map(|op| &op.data)
+    }
+    pub fn in_types(&self) -> &[ValueType] {
+        &self.in_types
+    }
+    pub fn out_types(&self) -> &[ValueType] {
+        &self.out_types
+    }
+    pub fn is_unreachable(&self) -> bool {
+        self.is_unreachable
+    }
+    pub fn iter_label_types(&self) -> impl Iterator<Item = ValueType> {
+        if let Some(Op::Loop(_)) = self.op.as_ref().map(|op| &op.data) {
+            self.in_types.iter().cloned()
         } else {
-            Some(self.out_types.iter().cloned())
+            self.out_types.iter().cloned()
         }
     }
 }
@@ -137,6 +294,8 @@ pub struct JumpTableEntry {
     pub stack_height: usize,
 
     pub out_count: usize,
+    /// Index into the labels of a `br_table`, the default label uses `labels.len()`.
+    pub table_target: Option<usize>,
 }
 
 impl JumpTableEntry {
@@ -146,6 +305,7 @@ impl JumpTableEntry {
             delta_ip: ip,
             stack_height,
             out_count,
+            table_target: None,
         }
     }
 }
@@ -163,7 +323,6 @@ pub fn pop_type(
     unreachable: bool,
 ) -> Result<ValueStackType, ValidationError> {
     if frame_stack_len == stack.len() {
-        println!("blubbi!");
         if unreachable {
             Ok(ValueStackType::Unknown)
         } else {
@@ -171,7 +330,7 @@ pub fn pop_type(
         }
     } else {
         let val = stack.pop().ok_or(ValidationError::TypeStackUnderflow)?;
-        println!("popping {}", val);
+        trace!("popping {}", val);
         Ok(val)
     }
 }
@@ -199,7 +358,7 @@ pub fn pop_values(
     unreachable: bool,
     expected: impl Iterator<Item = impl Into<ValueStackType>>,
 ) -> Result<(), ValidationError> {
-    println!("unreachable: {unreachable}");
+    trace!("unreachable: {unreachable}");
     for val in expected {
         pop_type_expect(stack, frame_stack_len, unreachable, val)?;
     }
@@ -207,7 +366,7 @@ pub fn pop_values(
 }
 pub fn push_type(stack: &mut Vec<ValueStackType>, val: impl Into<ValueStackType>) {
     let val = val.into();
-    println!("Pushing: {}", val);
+    trace!("Pushing: {}", val);
     stack.push(val)
 }
 
@@ -280,7 +439,7 @@ impl ValidatorContext {
         last_ctrl(&self.ctrl_stack)
     }
 
-    fn push_branch_op_jte(&mut self, op: Op, out_type_count: usize) -> Option<usize> {
+    fn push_branch_op_jte(&mut self, op: &Op, out_type_count: usize) -> Option<usize> {
         if op.is_branch() {
             let entry = JumpTableEntry::new(self.ip, self.type_stack.len(), out_type_count);
             self.jump_table.push(entry);
@@ -324,7 +483,7 @@ impl ValidatorContext {
         out_types: Vec<ValueType>,
     ) {
         let jump_table_entry = if let Some(WithPosition {
-            data: op,
+            data: ref op,
             position: _,
         }) = op
         {
@@ -379,6 +538,20 @@ impl ValidatorContext {
         Ok(())
     }
 
+    pub fn validate_unop(&mut self, val_type: ValueType) -> Result<(), ValidationError> {
+        validate_types!(self, [val_type] => [val_type]);
+        Ok(())
+    }
+
+    pub fn validate_cvtop(
+        &mut self,
+        from: ValueType,
+        to: ValueType,
+    ) -> Result<(), ValidationError> {
+        validate_types!(self, [from] => [to]);
+        Ok(())
+    }
+
     pub fn validate_relop(&mut self, val_type: ValueType) -> Result<(), ValidationError> {
         validate_types!(self, [val_type, val_type] => [ValueType::I32]);
         Ok(())
@@ -508,6 +681,9 @@ impl ValidatorContext {
             .globals
             .get(id)
             .ok_or(ValidationError::InvalidGlobalId(id))?;
+        if !global_type.mutable {
+            return Err(ValidationError::ImmutableGlobal(id));
+        }
         self.pop(global_type.t)?;
         Ok(())
     }
@@ -521,14 +697,17 @@ impl ValidatorContext {
                 self.pop(ValueType::I32)?;
                 let t1 = self.pop_numeric()?;
                 let t2 = self.pop_numeric()?;
-                if t1 != t2 {
-                    Err(ValidationError::PoppedUnexpectedType {
-                        got: t2,
-                        expected: t1,
-                    })
-                } else {
-                    Ok(())
+                match (t1, t2) {
+                    (ValueStackType::Unknown, t) | (t, ValueStackType::Unknown) => self.push(t),
+                    (t1, t2) if t1 == t2 => self.push(t1),
+                    (t1, t2) => {
+                        return Err(ValidationError::PoppedUnexpectedType {
+                            got: t2,
+                            expected: t1,
+                        });
+                    }
                 }
+                Ok(())
             }
         }
     }
@@ -562,7 +741,7 @@ impl ValidatorContext {
 
     pub fn validate_else(&mut self, op: WithPosition<Op>) -> Result<(), ValidationError> {
         let ctrl = self.pop_ctrl()?;
-        if let Some(Op::If { bt: _, jmp: _ }) = ctrl.op.as_ref().map(|d| d.data) {
+        if let Some(Op::If { bt: _, jmp: _ }) = ctrl.op.as_ref().map(|d| &d.data) {
             if let Some(jump_id) = ctrl.jump_table_entry {
                 self.get_jump_mut(jump_id)?.delta_ip = (self.ip - ctrl.ip) + 1;
             };
@@ -587,7 +766,7 @@ impl ValidatorContext {
     }
 
     pub fn validate_end(&mut self) -> Result<(), ValidationError> {
-        println!("Validate end");
+        trace!("Validate end");
         let ctrl = self.pop_ctrl()?;
 
         ctrl.out_types.iter().for_each(|t| self.push(t));
@@ -633,12 +812,21 @@ impl ValidatorContext {
     }
 
     fn push_break_jte(&mut self, n: usize) -> Result<(), ValidationError> {
+        self.push_target_jte(n, None)
+    }
+
+    fn push_target_jte(
+        &mut self,
+        n: usize,
+        table_target: Option<usize>,
+    ) -> Result<(), ValidationError> {
         let out_count = peek_ctrl(&self.ctrl_stack, n)?.out_types.len();
         let entry = JumpTableEntry {
             ip: self.ip,
             delta_ip: self.ip,
             stack_height: self.type_stack.len(),
             out_count,
+            table_target,
         };
         let jmp = self.push_jmp(entry);
         self.push_ctrl_jump(n, jmp)
@@ -647,20 +835,23 @@ impl ValidatorContext {
     pub fn pop_label_types(&mut self, label: usize) -> Result<(), ValidationError> {
         let vals = peek_ctrl(&self.ctrl_stack, label)?
             .iter_label_types()
-            .unwrap()
             .collect::<Vec<_>>();
 
-        vals.iter().try_for_each(|t| self.pop(t))?;
+        vals.iter().rev().try_for_each(|t| self.pop(t))?;
         Ok(())
     }
     pub fn push_label_types(&mut self, label: usize) -> Result<(), ValidationError> {
-        let vals = peek_ctrl(&self.ctrl_stack, label)?
-            .iter_label_types()
-            .unwrap();
+        let vals = peek_ctrl(&self.ctrl_stack, label)?.iter_label_types();
         self.type_stack.extend(vals.map_into::<ValueStackType>());
         Ok(())
     }
 
+    fn label_arity(&self, label: usize) -> Result<usize, ValidationError> {
+        Ok(peek_ctrl(&self.ctrl_stack, label)?
+            .iter_label_types()
+            .count())
+    }
+
     pub fn validate_br(&mut self, n: usize) -> Result<(), ValidationError> {
         //TODO: ???
         self.pop_label_types(n)?;
@@ -677,9 +868,34 @@ impl ValidatorContext {
         Ok(())
     }
 
+    pub fn validate_br_table(
+        &mut self,
+        labels: &[BrTableTarget],
+        default: usize,
+    ) -> Result<(), ValidationError> {
+        self.pop(ValueType::I32)?;
+        let expected = self.label_arity(default)?;
+        for (i, target) in labels.iter().enumerate() {
+            let got = self.label_arity(target.label)?;
+            if got != expected {
+                return Err(ValidationError::BrTableArityMismatch {
+                    label: target.label,
+                    got,
+                    expected,
+                });
+            }
+            self.pop_label_types(target.label)?;
+            self.push_target_jte(target.label, Some(i))?;
+            self.push_label_types(target.label)?;
+        }
+        self.pop_label_types(default)?;
+        self.push_target_jte(default, Some(labels.len()))?;
+        self.set_unreachable()
+    }
+
     pub fn validate_return(&mut self, t: &Type) -> Result<(), ValidationError> {
-        println!("func return t: {}", t);
-        t.iter_results().try_for_each(|t| self.pop(t))?;
+        trace!("func return t: {}", t);
+        t.iter_results().rev().try_for_each(|t| self.pop(t))?;
         self.set_unreachable()
     }
 
@@ -693,14 +909,176 @@ impl ValidatorContext {
             .functions
             .get(id)
             .ok_or(ValidationError::InvalidFunctionId(id))?;
+        self.validate_call_type(bytecode, func.type_id)
+    }
+
+    fn validate_call_type(
+        &mut self,
+        bytecode: &Bytecode,
+        type_id: usize,
+    ) -> Result<(), ValidationError> {
         let t = bytecode
-            .get_type(func.type_id)
-            .ok_or(ValidationError::InvalidFunctionTypeId(func.type_id))?;
-        t.iter_params().try_for_each(|t| self.pop(t))?;
+            .get_type(type_id)
+            .ok_or(ValidationError::InvalidFunctionTypeId(type_id))?;
+        t.iter_params().rev().try_for_each(|t| self.pop(t))?;
         t.iter_results().for_each(|t| self.push(t));
         Ok(())
     }
 
+    pub fn validate_call_indirect(
+        &mut self,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        type_id: usize,
+        table: usize,
+    ) -> Result<(), ValidationError> {
+        let t = self.get_table_type(info, table)?;
+        if t != ValueType::Funcref {
+            return Err(ValidationError::CallIndirectOnNonFuncTable(table, t));
+        }
+        self.pop(ValueType::I32)?;
+        self.validate_call_type(bytecode, type_id)
+    }
+
+    fn get_table_type(&self, info: &BytecodeInfo, id: usize) -> Result<ValueType, ValidationError> {
+        info.tables
+            .get(id)
+            .map(|t| t.value_type())
+            .ok_or(ValidationError::InvalidTableId(id))
+    }
+
+    fn get_element_type(
+        &self,
+        bytecode: &Bytecode,
+        id: usize,
+    ) -> Result<ValueType, ValidationError> {
+        bytecode
+            .get_element(id)
+            .map(|e| e.t)
+            .ok_or(ValidationError::InvalidElementId(id))
+    }
+
+    fn expect_element_type(got: ValueType, expected: ValueType) -> Result<(), ValidationError> {
+        if got != expected {
+            Err(ValidationError::ElementTypeMismatch { got, expected })
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn validate_table_get(
+        &mut self,
+        info: &BytecodeInfo,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        let t = self.get_table_type(info, id)?;
+        validate_types!(self, [ValueType::I32] => [t]);
+        Ok(())
+    }
+
+    pub fn validate_table_set(
+        &mut self,
+        info: &BytecodeInfo,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        let t = self.get_table_type(info, id)?;
+        validate_types!(self, [t, ValueType::I32] => []);
+        Ok(())
+    }
+
+    pub fn validate_table_size(
+        &mut self,
+        info: &BytecodeInfo,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        self.get_table_type(info, id)?;
+        self.push(ValueType::I32);
+        Ok(())
+    }
+
+    pub fn validate_table_grow(
+        &mut self,
+        info: &BytecodeInfo,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        let t = self.get_table_type(info, id)?;
+        validate_types!(self, [ValueType::I32, t] => [ValueType::I32]);
+        Ok(())
+    }
+
+    pub fn validate_table_fill(
+        &mut self,
+        info: &BytecodeInfo,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        let t = self.get_table_type(info, id)?;
+        validate_types!(self, [ValueType::I32, t, ValueType::I32] => []);
+        Ok(())
+    }
+
+    pub fn validate_table_copy(
+        &mut self,
+        info: &BytecodeInfo,
+        dst: usize,
+        src: usize,
+    ) -> Result<(), ValidationError> {
+        let dst_t = self.get_table_type(info, dst)?;
+        let src_t = self.get_table_type(info, src)?;
+        Self::expect_element_type(src_t, dst_t)?;
+        validate_types!(self, [ValueType::I32, ValueType::I32, ValueType::I32] => []);
+        Ok(())
+    }
+
+    pub fn validate_table_init(
+        &mut self,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        elem_id: usize,
+        table: usize,
+    ) -> Result<(), ValidationError> {
+        let table_t = self.get_table_type(info, table)?;
+        let elem_t = self.get_element_type(bytecode, elem_id)?;
+        Self::expect_element_type(elem_t, table_t)?;
+        validate_types!(self, [ValueType::I32, ValueType::I32, ValueType::I32] => []);
+        Ok(())
+    }
+
+    pub fn validate_elem_drop(
+        &mut self,
+        bytecode: &Bytecode,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        self.get_element_type(bytecode, id)?;
+        Ok(())
+    }
+
+    pub fn validate_ref_is_null(&mut self) -> Result<(), ValidationError> {
+        let len = self.current_ctrl()?.prev_stack_len;
+        let unreachable = self.current_ctrl()?.is_unreachable;
+        let val = pop_type(&mut self.type_stack, len, unreachable)?;
+        if !val.is_ref() {
+            Err(ValidationError::ExpectedReferenceType(val))
+        } else {
+            self.push(ValueType::I32);
+            Ok(())
+        }
+    }
+
+    pub fn validate_ref_func(
+        &mut self,
+        info: &BytecodeInfo,
+        id: usize,
+    ) -> Result<(), ValidationError> {
+        if id >= info.functions.len() {
+            Err(ValidationError::InvalidFunctionId(id))
+        } else if !info.declared_refs.contains(&id) {
+            Err(ValidationError::UndeclaredFunctionRef(id))
+        } else {
+            self.push(ValueType::Funcref);
+            Ok(())
+        }
+    }
+
     pub fn validate_memory_copy(&mut self, info: &BytecodeInfo) -> Result<(), ValidationError> {
         if !info.has_memory() {
             Err(ValidationError::UnexpectedNoMemories)
@@ -718,6 +1096,8 @@ impl ValidatorContext {
     ) -> Result<(), ValidationError> {
         if !info.has_memory() {
             Err(ValidationError::UnexpectedNoMemories)
+        } else if bytecode.data_count.is_none() {
+            Err(ValidationError::MissingDataCount)
         } else {
             let is_passive = bytecode
                 .get_data(data_id)
@@ -732,6 +1112,14 @@ impl ValidatorContext {
             }
         }
     }
+    pub fn validate_memory_size(&mut self, info: &BytecodeInfo) -> Result<(), ValidationError> {
+        if !info.has_memory() {
+            Err(ValidationError::UnexpectedNoMemories)
+        } else {
+            validate_types!(self, [] => [ValueType::I32]);
+            Ok(())
+        }
+    }
     pub fn validate_memory_grow(&mut self, info: &BytecodeInfo) -> Result<(), ValidationError> {
         if !info.has_memory() {
             Err(ValidationError::UnexpectedNoMemories)
@@ -756,7 +1144,7 @@ impl ValidatorContext {
         op: WithPosition<Op>,
     ) -> Result<(), ValidationError> {
         use ValueType::*;
-        println!("Validating op: {}", op.data);
+        trace!("Validating op: {}", op.data);
         match op.data {
             Op::Unreachable => self.set_unreachable()?,
             Op::Drop => self.pop_any()?,
@@ -771,9 +1159,15 @@ impl ValidatorContext {
             Op::End(_) => self.validate_end()?,
             Op::Br { label, .. } => self.validate_br(label)?,
             Op::BrIf { label, .. } => self.validate_br_if(label)?,
+            Op::BrTable {
+                ref labels,
+                default,
+            } => self.validate_br_table(labels, default.label)?,
             Op::Return => self.validate_return(t)?,
             Op::Call(id) => self.validate_call(bytecode, info, id)?,
-            Op::CallIndirect { .. } => todo!(),
+            Op::CallIndirect { type_id, table } => {
+                self.validate_call_indirect(bytecode, info, type_id, table)?
+            }
             Op::Select(value_type) => self.validate_select(value_type)?,
             Op::LocalGet(id) => self.validate_local_get(id)?,
             Op::LocalSet(id) => self.validate_local_set(id)?,
@@ -829,6 +1223,14 @@ impl ValidatorContext {
             | Op::I64Leu
             | Op::I64Ges
             | Op::I64Geu => self.validate_relop(I64)?,
+            Op::F32Eq | Op::F32Ne | Op::F32Lt | Op::F32Gt | Op::F32Le | Op::F32Ge => {
+                self.validate_relop(F32)?
+            }
+            Op::F64Eq | Op::F64Ne | Op::F64Lt | Op::F64Gt | Op::F64Le | Op::F64Ge => {
+                self.validate_relop(F64)?
+            }
+            Op::I32Clz | Op::I32Ctz | Op::I32Popcnt => self.validate_unop(I32)?,
+            Op::I64Clz | Op::I64Ctz | Op::I64Popcnt => self.validate_unop(I64)?,
             Op::I32Add
             | Op::I32Sub
             | Op::I32Mul
@@ -859,10 +1261,52 @@ impl ValidatorContext {
             | Op::I64Shru
             | Op::I64Rotl
             | Op::I64Rotr => self.validate_binop(I64)?,
+            Op::F32Abs
+            | Op::F32Neg
+            | Op::F32Ceil
+            | Op::F32Floor
+            | Op::F32Trunc
+            | Op::F32Nearest
+            | Op::F32Sqrt => self.validate_unop(F32)?,
+            Op::F32Add
+            | Op::F32Sub
+            | Op::F32Mul
+            | Op::F32Div
+            | Op::F32Min
+            | Op::F32Max
+            | Op::F32Copysign => self.validate_binop(F32)?,
+            Op::F64Abs
+            | Op::F64Neg
+            | Op::F64Ceil
+            | Op::F64Floor
+            | Op::F64Trunc
+            | Op::F64Nearest
+            | Op::F64Sqrt => self.validate_unop(F64)?,
+            Op::F64Add
+            | Op::F64Sub
+            | Op::F64Mul
+            | Op::F64Div
+            | Op::F64Min
+            | Op::F64Max
+            | Op::F64Copysign => self.validate_binop(F64)?,
             Op::MemoryCopy { .. } => self.validate_memory_copy(info)?,
             Op::MemoryFill { .. } => self.validate_memory_fill(info)?,
             Op::MemoryInit { data_id, .. } => self.validate_memory_init(bytecode, info, data_id)?,
+            Op::MemorySize { .. } => self.validate_memory_size(info)?,
             Op::MemoryGrow { .. } => self.validate_memory_grow(info)?,
+            Op::TableGet(id) => self.validate_table_get(info, id)?,
+            Op::TableSet(id) => self.validate_table_set(info, id)?,
+            Op::TableSize(id) => self.validate_table_size(info, id)?,
+            Op::TableGrow(id) => self.validate_table_grow(info, id)?,
+            Op::TableFill(id) => self.validate_table_fill(info, id)?,
+            Op::TableCopy { dst, src } => self.validate_table_copy(info, dst, src)?,
+            Op::TableInit { elem_id, table } => {
+                self.validate_table_init(bytecode, info, elem_id, table)?
+            }
+            Op::ElemDrop(id) => self.validate_elem_drop(bytecode, id)?,
+            Op::RefNull(t) => self.push(t),
+            Op::RefIsNull => self.validate_ref_is_null()?,
+            Op::RefFunc(id) => self.validate_ref_func(info, id)?,
             Op::I32WrapI64 => {
                 validate_types! {self, [ValueType::I64] => [ValueType::I32]}
             }
@@ -873,10 +1317,32 @@ impl ValidatorContext {
             Op::I64ExtendI32u => {
                 validate_types!(self, [ValueType::I32] => [ValueType::I64]);
             }
+            Op::I32TruncF32s | Op::I32TruncF32u | Op::I32TruncSatF32s | Op::I32TruncSatF32u => {
+                self.validate_cvtop(F32, I32)?
+            }
+            Op::I32TruncF64s | Op::I32TruncF64u | Op::I32TruncSatF64s | Op::I32TruncSatF64u => {
+                self.validate_cvtop(F64, I32)?
+            }
+            Op::I64TruncF32s | Op::I64TruncF32u | Op::I64TruncSatF32s | Op::I64TruncSatF32u => {
+                self.validate_cvtop(F32, I64)?
+            }
+            Op::I64TruncF64s | Op::I64TruncF64u | Op::I64TruncSatF64s | Op::I64TruncSatF64u => {
+                self.validate_cvtop(F64, I64)?
+            }
+            Op::F32ConvertI32s | Op::F32ConvertI32u => self.validate_cvtop(I32, F32)?,
+            Op::F32ConvertI64s | Op::F32ConvertI64u => self.validate_cvtop(I64, F32)?,
+            Op::F64ConvertI32s | Op::F64ConvertI32u => self.validate_cvtop(I32, F64)?,
+            Op::F64ConvertI64s | Op::F64ConvertI64u => self.validate_cvtop(I64, F64)?,
+            Op::F32DemoteF64 => self.validate_cvtop(F64, F32)?,
+            Op::F64PromoteF32 => self.validate_cvtop(F32, F64)?,
+            Op::I32ReinterpretF32 => self.validate_cvtop(F32, I32)?,
+            Op::I64ReinterpretF64 => self.validate_cvtop(F64, I64)?,
+            Op::F32ReinterpretI32 => self.validate_cvtop(I32, F32)?,
+            Op::F64ReinterpretI64 => self.validate_cvtop(I64, F64)?,
         };
 
         self.ip += 1;
-        println!("Stack now: {:?}", self.type_stack);
+        trace!("Stack now: {:?}", self.type_stack);
         Ok(())
     }
     pub fn set_locals_from_func_t(&mut self, t: &Type, code: &Function) {
@@ -898,14 +1364,97 @@ impl ValidatorContext {
 
         let results = t.iter_results().cloned().collect::<Vec<_>>();
 
-        println!("=====Validating func with t: {}=====", t);
-        println!("out_count: {}", results.len());
+        trace!("=====Validating func with t: {}=====", t);
+        trace!("out_count: {}", results.len());
         self.push_ctrl(None, Vec::new(), results);
-        code.iter_ops()
-            .try_for_each(|op| self.validate_op(bytecode, t, info, op))?;
+        for (instruction, op) in code.iter_ops().enumerate() {
+            if let Err(error) = self.validate_op(bytecode, t, info, op.clone()) {
+                return Err(self.located_error(info, instruction, op, error));
+            }
+        }
         Ok(self.jump_table)
     }
 
+    /// Like [`Self::validate_code`], but continues after an error and returns all of them.
+    /// After an error the current block is treated as unreachable, so that the following
+    /// instructions are checked against a polymorphic stack instead of reporting follow-up errors.
+    pub fn collect_code_errors(
+        mut self,
+        bytecode: &Bytecode,
+        info: &BytecodeInfo,
+        t: &Type,
+        code: &Function,
+    ) -> Vec<ValidationError> {
+        self.set_locals_from_func_t(t, code);
+        self.push_ctrl(None, Vec::new(), t.iter_results().cloned().collect());
+
+        let mut errors = Vec::new();
+        for (instruction, op) in code.iter_ops().enumerate() {
+            let ctrl_depth = self.ctrl_stack.len();
+            if let Err(error) = self.validate_op(bytecode, t, info, op.clone()) {
+                errors.push(self.located_error(info, instruction, op.clone(), error));
+                if !self.recover(op, ctrl_depth) {
+                    break;
+                }
+            }
+        }
+        errors
+    }
+
+    /// Brings the validator back into a consistent state after `op` failed.
+    /// Returns false if validating the rest of the function makes no sense.
+    fn recover(&mut self, op: WithPosition<Op>, ctrl_depth: usize) -> bool {
+        if let Some(frame) = self.ctrl_stack.last_mut() {
+            self.type_stack.truncate(frame.prev_stack_len);
+            frame.is_unreachable = true;
+        }
+        //NOTE: The nesting of blocks has to stay intact, otherwise every following `end` fails
+        let recovered = match op.data {
+            Op::Block(bt) | Op::Loop(bt) | Op::If { bt, .. }
+                if self.ctrl_stack.len() == ctrl_depth =>
+            {
+                let out_types = match bt {
+                    Blocktype::Value(t) => vec![t],
+                    _ => Vec::new(),
+                };
+                self.push_ctrl(Some(op), Vec::new(), out_types);
+                true
+            }
+            Op::Else(_) if self.ctrl_stack.len() == ctrl_depth => self.validate_else(op).is_ok(),
+            Op::Else(_) => {
+                self.push_ctrl(Some(op), Vec::new(), Vec::new());
+                true
+            }
+            Op::End(_) if self.ctrl_stack.len() == ctrl_depth => self.validate_end().is_ok(),
+            _ => true,
+        };
+        self.ip += 1;
+        recovered && !self.ctrl_stack.is_empty()
+    }
+
+    fn located_error(
+        &self,
+        info: &BytecodeInfo,
+        instruction: usize,
+        op: WithPosition<Op>,
+        error: ValidationError,
+    ) -> ValidationError {
+        let func_id = info.imported_function_count() + self.func_id;
+        let context = ValidationContext {
+            func_id,
+            name: info.names.function(func_id).map(str::to_string),
+            instruction,
+            op: op.data,
+            position: op.position,
+            type_stack: self.type_stack.clone(),
+            ctrl_stack: self.ctrl_stack.clone(),
+        };
+        ValidationError::InFunction {
+            context: Box::new(context),
+            error: Box::new(error),
+        }
+    }
+
     pub fn validate_func(
         bytecode: &Bytecode,
         info: &BytecodeInfo,
@@ -920,7 +1469,7 @@ impl ValidatorContext {
                     ..Default::default()
                 };
                 let t = bytecode.get_type(func.type_id).unwrap();
-                println!("validating function type: {}", t);
+                trace!("validating function type: {}", t);
                 validator.validate_code(bytecode, info, t, code)
             }
             FunctionType::Imported { .. } => Ok(Vec::new()),
@@ -945,10 +1494,40 @@ impl ValidatorContext {
             Ok(Vec::new())
         }
     }
+
+    /// Validates every function independently and returns all errors instead of the first one
+    pub fn collect_all_errors(bytecode: &Bytecode, info: &BytecodeInfo) -> Vec<ValidationError> {
+        //NOTE: Functions with an invalid type id are already reported by the module validation
+        let types = bytecode.iter_functions().into_iter().flatten();
+        types
+            .zip(bytecode.iter_code().into_iter().flatten())
+            .enumerate()
+            .filter_map(|(id, (t, code))| Some((id, (bytecode.get_type(*t)?, code))))
+            .flat_map(|(id, (t, code))| {
+                let validator = ValidatorContext {
+                    func_id: id,
+                    ..Default::default()
+                };
+                validator.collect_code_errors(bytecode, info, t, code)
+            })
+            .collect()
+    }
 }
 
 fn patch_op_jump(op: &Op, jump: &JumpTableEntry, jump_id: usize) -> Result<Op, ValidationError> {
     match op {
+        Op::BrTable { labels, default } => {
+            let target = jump
+                .table_target
+                .ok_or(ValidationError::InvalidJump(jump_id))?;
+            let mut labels = labels.clone();
+            let mut default = *default;
+            match labels.get_mut(target) {
+                Some(t) => t.jmp = jump.delta_ip,
+                None => default.jmp = jump.delta_ip,
+            }
+            Ok(Op::BrTable { labels, default })
+        }
         Op::Else(_) => Ok(Op::Else(jump.delta_ip)),
         Op::If { bt, jmp: _ } => Ok(Op::If {
             bt: *bt,
@@ -991,6 +1570,7 @@ pub fn valiadate_and_patch_bytecode(
     bytecode: &mut Bytecode,
 ) -> Result<(Vec<Vec<JumpTableEntry>>, BytecodeInfo), ValidationError> {
     let info = BytecodeInfo::new(bytecode);
+    validate_module(bytecode, &info)?;
     let jumps = ValidatorContext::validate_all(bytecode, &info)?;
     if let Some(code) = bytecode.iter_code_mut() {
         code.zip(jumps.iter()).try_for_each(|(f, j)| {
@@ -1001,6 +1581,15 @@ pub fn valiadate_and_patch_bytecode(
     Ok((jumps, info))
 }
 
+/// Diagnostic mode: validates the whole module and returns every error found.
+/// The interpreter uses the fail-fast [`valiadate_and_patch_bytecode`] instead.
+pub fn collect_validation_errors(bytecode: &Bytecode) -> Vec<ValidationError> {
+    let info = BytecodeInfo::new(bytecode);
+    let mut errors = collect_module_errors(bytecode, &info);
+    errors.extend(ValidatorContext::collect_all_errors(bytecode, &info));
+    errors
+}
+
 #[derive(Debug)]
 pub struct ValidateResult {
     pub bytecode: Bytecode,
@@ -1017,6 +1606,16 @@ pub enum ReadAndValidateError {
     ValidationError(#[from] ValidationError),
 }
 
+impl ReadAndValidateError {
+    /// Where validation failed, if the error happened inside a function
+    pub fn validation_context(&self) -> Option<&ValidationContext> {
+        match self {
+            ReadAndValidateError::ValidationError(e) => e.context(),
+            ReadAndValidateError::ReadError(_) => None,
+        }
+    }
+}
+
 pub fn read_and_validate(
     reader: &mut impl BytecodeReader,
 ) -> Result<ValidateResult, ReadAndValidateError> {
@@ -1045,12 +1644,18 @@ pub fn read_and_validate_wat(
 
 #[cfg(test)]
 mod tests {
-    use std::io::Read;
+    use std::io::{Cursor, Read};
 
-    use parser::op::Op;
+    use parser::{
+        op::Op,
+        reader::{ValueType, parse_wat},
+    };
     use validator_derive::{test_invalid_wast, test_valid_wast};
 
-    use super::{ReadAndValidateError, ValidationError, read_and_validate_wat};
+    use super::{
+        ReadAndValidateError, ValidationError, ValueStackType, collect_validation_errors,
+        read_and_validate, read_and_validate_wat,
+    };
     macro_rules! expect_src_ok {
         ($src: ident) => {
             return Ok(_ = read_and_validate_wat($src)?);
@@ -1060,10 +1665,10 @@ mod tests {
     macro_rules! assert_validation_err {
         ($src: ident, $err: pat) => {
             let err = read_and_validate_wat($src);
-            assert!(matches!(
-                err.unwrap_err(),
-                ReadAndValidateError::ValidationError($err)
-            ));
+            let ReadAndValidateError::ValidationError(err) = err.unwrap_err() else {
+                panic!("expected a validation error");
+            };
+            assert!(matches!(err.cause(), $err));
         };
     }
     #[test]
@@ -1117,6 +1722,152 @@ mod tests {
         Ok(())
     }
     #[test]
+    fn immutable_global_set() -> Result<(), ReadAndValidateError> {
+        let src = r#"
+            (module
+                (global $g i32 (i32.const 1))
+                (func
+                    i32.const 2
+                    global.set $g
+                )
+            )
+        "#;
+        assert_validation_err!(src, ValidationError::ImmutableGlobal(0));
+        Ok(())
+    }
+    #[test]
+    fn error_names_function() {
+        let src = r#"
+             (module
+                 (import "env" "log" (func $log (param i32)))
+                 (func $fib (param $n i32) (result i32)
+                     local.get $n
+                     i64.const 1
+                     i32.add
+                 )
+             )
+        "#;
+        let Err(ReadAndValidateError::ValidationError(err)) = read_and_validate_wat(src) else {
+            panic!("expected a validation error");
+        };
+        let context = err.context().unwrap();
+        assert_eq!(context.func_id, 1);
+        assert_eq!(context.name.as_deref(), Some("fib"));
+        assert_eq!(context.instruction, 2);
+        assert_eq!(context.op, Op::I32Add);
+        assert_eq!(context.position.len(), 1);
+        assert_eq!(context.type_stack, [ValueStackType::T(ValueType::I32)]);
+        assert_eq!(context.ctrl_stack.len(), 1);
+        assert_eq!(
+            context.format_stacks(),
+            "type stack: [i32], control stack: func"
+        );
+        assert!(
+            err.to_string()
+                .starts_with("In function $fib at instruction 2 (i32.add, offset 0x")
+        );
+        assert!(matches!(
+            err.cause(),
+            ValidationError::PoppedUnexpectedType { .. }
+        ));
+    }
+    #[test]
+    fn error_context_in_nested_blocks() {
+        let src = r#"
+             (module
+                 (import "env" "log" (func $log (param i32)))
+                 (func $first)
+                 (func $deep (param $x i32) (result f32)
+                     block (result f32)
+                         local.get $x
+                         if (result f32)
+                             loop (result f32)
+                                 f32.const 1
+                                 local.get $x
+                                 f32.add
+                             end
+                         else
+                             f32.const 0
+                         end
+                     end
+                 )
+             )
+        "#;
+        let binary = wat::parse_str(src).unwrap();
+        let Err(err) = read_and_validate(&mut Cursor::new(&binary)) else {
+            panic!("expected a validation error");
+        };
+        let context = err.validation_context().unwrap();
+        assert_eq!(context.func_id, 2);
+        assert_eq!(context.name.as_deref(), Some("deep"));
+        assert_eq!(context.instruction, 6);
+        assert_eq!(context.op, Op::F32Add);
+        assert_eq!(binary[context.position.clone()], [0x92]);
+        assert_eq!(context.type_stack, [ValueStackType::T(ValueType::F32)]);
+        let blocks = context
+            .ctrl_stack
+            .iter()
+            .map(|frame| frame.op().map(Op::name))
+            .collect::<Vec<_>>();
+        assert_eq!(blocks, [None, Some("block"), Some("if"), Some("loop")]);
+        assert!(
+            context
+                .ctrl_stack
+                .iter()
+                .all(|frame| frame.out_types() == [ValueType::F32])
+        );
+        assert_eq!(
+            context.format_stacks(),
+            "type stack: [f32], control stack: func > block > if > loop"
+        );
+        assert!(matches!(
+            err,
+            ReadAndValidateError::ValidationError(ref err) if matches!(
+                err.cause(),
+                ValidationError::PoppedUnexpectedType { .. }
+            )
+        ));
+    }
+    #[test]
+    fn collect_all_errors() {
+        let src = r#"
+             (module
+                 (func $a (result i32)
+                     i32.const 1
+                     i64.const 2
+                     i32.add
+                     drop
+                     block (result i32)
+                         f32.const 0
+                     end
+                     i32.const 0
+                     i64.eqz
+                 )
+                 (func $b
+                     local.get 5
+                     drop
+                 )
+                 (func $c (result i32)
+                     i32.const 1
+                 )
+             )
+        "#;
+        let bytecode = parse_wat(src).unwrap();
+        let errors = collect_validation_errors(&bytecode);
+        let found = errors
+            .iter()
+            .map(|err| {
+                let context = err.context().unwrap();
+                (context.name.as_deref().unwrap(), context.instruction)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, [("a", 2), ("a", 6), ("a", 8), ("b", 0)]);
+        assert!(matches!(
+            errors[3].cause(),
+            ValidationError::InvalidLocalId(5)
+        ));
+    }
+    #[test]
     fn valid_local_id_tee() -> Result<(), ReadAndValidateError> {
         let src = r#"
             (module
@@ -1273,7 +2024,7 @@ mod tests {
         let code = read_and_validate_wat(src)?;
         let func = code.bytecode.get_code(0).unwrap();
         let after_block = func.get_op_after(6).unwrap();
-        assert!(matches!(after_block.0, Op::End));
+        assert!(matches!(after_block.0, Op::End(_)));
         Ok(())
     }
 
@@ -1307,7 +2058,7 @@ mod tests {
         ));
 
         let after_else = func.get_op_after(after_block_ip).unwrap();
-        assert!(matches!(after_else.0, Op::End));
+        assert!(matches!(after_else.0, Op::End(_)));
         Ok(())
     }
 
@@ -1333,7 +2084,7 @@ mod tests {
         let func = code.bytecode.get_code(0).unwrap();
         let after_if_op = func.get_op_after(1).unwrap();
         let after_end_op = func.get_op(after_if_op.1 as usize + 1).unwrap();
-        assert!(matches!(after_if_op.0, Op::End));
+        assert!(matches!(after_if_op.0, Op::End(_)));
         assert!(matches!(after_end_op, Op::I32Const(100)));
         Ok(())
     }
@@ -1364,4 +2115,148 @@ mod tests {
         assert!(matches!(jmp2.0, Op::Loop(_)));
         Ok(())
     }
+
+    test_valid_wast! {
+        float_ops,
+            r#"
+             (module
+                 (func (param f32 f64) (result i64)
+                     local.get 0
+                     f32.const 2.5
+                     f32.copysign
+                     f64.promote_f32
+                     local.get 1
+                     f64.max
+                     i64.trunc_f64_s
+                 )
+             )
+         "#
+    }
+
+    test_invalid_wast! {
+        float_op_wrong_type,
+            r#"
+             (module
+                 (func (param f32) (result f32)
+                     local.get 0
+                     f64.const 1
+                     f32.add
+                 )
+             )
+         "#,
+         ValidationError::PoppedUnexpectedType { .. }
+    }
+
+    #[test]
+    pub fn br_table_jumps() -> Result<(), ReadAndValidateError> {
+        let src = r#"
+            (module
+                (func (param i32)
+                    (block $a
+                        (loop $b
+                            local.get 0
+                            br_table $a $b $a
+                        )
+                    )
+                )
+            )
+        "#;
+        let code = read_and_validate_wat(src)?;
+        let func = code.bytecode.get_code(0).unwrap();
+        let Op::BrTable { labels, default } = func.get_op(3).unwrap() else {
+            unreachable!()
+        };
+        assert!(matches!(
+            func.get_op((3 + labels[0].jmp - 1) as usize).unwrap(),
+            Op::End(_)
+        ));
+        assert!(matches!(
+            func.get_op((3 + labels[1].jmp) as usize).unwrap(),
+            Op::Loop(_)
+        ));
+        assert_eq!(labels[0].jmp, default.jmp);
+        Ok(())
+    }
+
+    test_invalid_wast! {
+        br_table_arity_mismatch,
+            r#"
+            (module
+                (func (param i32) (result i32)
+                    (block $a (result i32)
+                        (block $b
+                            i32.const 1
+                            local.get 0
+                            br_table $b $a
+                        )
+                        i32.const 2
+                    )
+                )
+            )
+         "#,
+         ValidationError::BrTableArityMismatch { label: 0, got: 0, expected: 1 }
+    }
+
+    test_valid_wast! {
+        call_indirect_and_table_ops,
+            r#"
+            (module
+                (type $t (func (param i32 f32) (result i32)))
+                (table $funcs 2 funcref)
+                (table $objs 1 externref)
+                (func $f (param i32 f32) (result i32) local.get 0)
+                (elem $e func $f)
+                (elem declare func $f)
+                (func (param i32) (result i32)
+                    i32.const 1
+                    f32.const 2
+                    local.get 0
+                    call_indirect $funcs (type $t)
+                    drop
+                    i32.const 0
+                    ref.func $f
+                    table.set $funcs
+                    (table.grow $objs (ref.null extern) (i32.const 1))
+                    drop
+                    (table.fill $funcs (i32.const 0) (ref.null func) (i32.const 1))
+                    (table.copy $funcs $funcs (i32.const 0) (i32.const 1) (i32.const 1))
+                    (table.init $funcs $e (i32.const 0) (i32.const 0) (i32.const 1))
+                    elem.drop $e
+                    (ref.is_null (table.get $objs (i32.const 0)))
+                    table.size $funcs
+                    i32.add
+                )
+            )
+         "#
+    }
+
+    test_invalid_wast! {
+        call_indirect_wrong_arg,
+            r#"
+            (module
+                (type $t (func (param i64)))
+                (table 1 funcref)
+                (func
+                    i32.const 1
+                    i32.const 0
+                    call_indirect (type $t)
+                )
+            )
+         "#,
+         ValidationError::PoppedUnexpectedType { .. }
+    }
+
+    test_invalid_wast! {
+        table_init_type_mismatch,
+            r#"
+            (module
+                (table 1 externref)
+                (elem $e funcref)
+                (func
+                    (table.init 0 $e (i32.const 0) (i32.const 0) (i32.const 0))
+                )
+            )
+         "#,
+         ValidationError::ElementTypeMismatch { .. }
+    }
 }
@@ -62,8 +62,10 @@ pub fn test_invalid_wast(input: TokenStream) -> TokenStream {
         #[test]
         fn #fn_name() -> Result<(), ReadAndValidateError> {
             let src = #source;
-            let err = read_and_validate_wat(src);
-            assert!(matches!(err.unwrap_err(), ReadAndValidateError::ValidationError(#err)));
+            let ReadAndValidateError::ValidationError(err) = read_and_validate_wat(src).unwrap_err() else {
+                panic!("expected a validation error");
+            };
+            assert!(matches!(err.cause(), #err));
             Ok(())
         }
     };